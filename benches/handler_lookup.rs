@@ -0,0 +1,65 @@
+//! Requests/sec through the request handler lookup with many registered methods
+//! run with `cargo bench --bench handler_lookup`
+use async_mcp::protocol::{Protocol, RequestOptions};
+use async_mcp::transport::{ClientInMemoryTransport, ServerInMemoryTransport, Transport};
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use std::sync::Arc;
+
+const METHODS: usize = 64;
+const CALLERS: usize = 8;
+const REQUESTS_PER_CALLER: usize = 50;
+
+fn server(transport: ServerInMemoryTransport) -> Protocol<ServerInMemoryTransport> {
+    let mut builder = Protocol::builder(transport);
+    for i in 0..METHODS {
+        builder = builder
+            .request_handler(&format!("vendor/method_{}", i), |req: serde_json::Value| {
+                Box::pin(async move { Ok(req) })
+            });
+    }
+    builder.build()
+}
+
+fn handler_lookup(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let client = runtime.block_on(async {
+        let transport = ClientInMemoryTransport::new(|t| {
+            tokio::spawn(async move {
+                let _ = server(t).listen().await;
+            })
+        });
+        transport.open().await.unwrap();
+        let client = Arc::new(Protocol::builder(transport).build());
+        let listener = client.clone();
+        tokio::spawn(async move { listener.listen().await });
+        client
+    });
+
+    let mut group = c.benchmark_group("handler_lookup");
+    group.throughput(Throughput::Elements((CALLERS * REQUESTS_PER_CALLER) as u64));
+    group.bench_function(format!("{}_callers_{}_methods", CALLERS, METHODS), |b| {
+        b.to_async(&runtime).iter(|| async {
+            let callers = (0..CALLERS).map(|caller| {
+                let client = client.clone();
+                tokio::spawn(async move {
+                    for i in 0..REQUESTS_PER_CALLER {
+                        let method = format!("vendor/method_{}", (caller * 7 + i) % METHODS);
+                        client
+                            .request(
+                                &method,
+                                Some(serde_json::json!({ "i": i })),
+                                RequestOptions::default(),
+                            )
+                            .await
+                            .unwrap();
+                    }
+                })
+            });
+            futures::future::join_all(callers).await;
+        });
+    });
+    group.finish();
+}
+
+criterion_group!(benches, handler_lookup);
+criterion_main!(benches);
@@ -0,0 +1,78 @@
+//! Notifications/sec delivered to handlers while 16 senders notify concurrently,
+//! with handlers registered through the shared map while deliveries are in flight
+//! run with `cargo bench --bench notification_dispatch`
+use async_mcp::protocol::{Handlers, Protocol};
+use async_mcp::transport::{ClientInMemoryTransport, ServerInMemoryTransport, Transport};
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use std::sync::Arc;
+use tokio::sync::{mpsc, Mutex};
+
+const SENDERS: usize = 16;
+const NOTIFICATIONS_PER_SENDER: usize = 50;
+const METHODS: usize = 16;
+
+fn register(handlers: &Handlers, method: &str, delivered: mpsc::UnboundedSender<()>) {
+    handlers.set_notification_handler(method, move |_: serde_json::Value| {
+        let _ = delivered.send(());
+        Box::pin(async move { Ok(()) })
+    });
+}
+
+fn notification_dispatch(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let (delivered_tx, delivered_rx) = mpsc::unbounded_channel();
+    let delivered = Arc::new(Mutex::new(delivered_rx));
+    let (client, handlers) = runtime.block_on(async {
+        let (handlers_tx, mut handlers_rx) = mpsc::channel(1);
+        let transport = ClientInMemoryTransport::new(move |t: ServerInMemoryTransport| {
+            let handlers_tx = handlers_tx.clone();
+            tokio::spawn(async move {
+                let server = Protocol::builder(t).build();
+                let _ = handlers_tx.send(server.handlers()).await;
+                let _ = server.listen().await;
+            })
+        });
+        transport.open().await.unwrap();
+        let handlers = handlers_rx.recv().await.unwrap();
+        for i in 0..METHODS {
+            register(
+                &handlers,
+                &format!("notifications/vendor_{}", i),
+                delivered_tx.clone(),
+            );
+        }
+        (Arc::new(Protocol::builder(transport).build()), handlers)
+    });
+
+    let mut group = c.benchmark_group("notification_dispatch");
+    group.throughput(Throughput::Elements(
+        (SENDERS * NOTIFICATIONS_PER_SENDER) as u64,
+    ));
+    group.bench_function(format!("{}_senders", SENDERS), |b| {
+        b.to_async(&runtime).iter(|| async {
+            let senders = (0..SENDERS).map(|sender| {
+                let client = client.clone();
+                tokio::spawn(async move {
+                    for i in 0..NOTIFICATIONS_PER_SENDER {
+                        let method = format!("notifications/vendor_{}", (sender + i) % METHODS);
+                        client
+                            .notify(&method, Some(serde_json::json!({ "i": i })))
+                            .await
+                            .unwrap();
+                    }
+                })
+            });
+            // Re-registering takes the write lock between lookups
+            register(&handlers, "notifications/vendor_0", delivered_tx.clone());
+            futures::future::join_all(senders).await;
+            let mut delivered = delivered.lock().await;
+            for _ in 0..SENDERS * NOTIFICATIONS_PER_SENDER {
+                delivered.recv().await.unwrap();
+            }
+        });
+    });
+    group.finish();
+}
+
+criterion_group!(benches, notification_dispatch);
+criterion_main!(benches);
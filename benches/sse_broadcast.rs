@@ -0,0 +1,49 @@
+//! Cost of sending a ~2MB tools/list response over SSE to several subscribers
+//! built from the tavily-search tool of the SSE parsing fixtures
+//! run with `cargo bench -p async-mcp --bench sse_broadcast`
+use async_mcp::transport::{
+    JsonRpcMessage, JsonRpcResponse, JsonRpcVersion, ServerSseTransport, Transport,
+};
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use tokio::sync::broadcast;
+
+const TAVILY_SEARCH: &str = r#"{"description":"A powerful web search tool that provides comprehensive, real-time results using Tavily's AI search engine. Returns relevant web content with customizable parameters for result count, content type, and domain filtering. Ideal for gathering current information, news, and detailed web content analysis.","inputSchema":{"properties":{"days":{"default":3,"description":"The number of days back from the current date to include in the search results. This specifies the time frame of data to be retrieved. Please note that this feature is only available when using the 'news' search topic","type":"number"}}},"name":"tavily-search"}"#;
+const TOOLS: usize = 3000;
+const SUBSCRIBERS: usize = 4;
+
+fn tools_list() -> JsonRpcMessage {
+    let tool: serde_json::Value = serde_json::from_str(TAVILY_SEARCH).unwrap();
+    JsonRpcMessage::Response(JsonRpcResponse {
+        id: 0,
+        result: Some(serde_json::json!({ "tools": vec![tool; TOOLS] })),
+        error: None,
+        jsonrpc: JsonRpcVersion::default(),
+    })
+}
+
+fn sse_broadcast(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let message = tools_list();
+    let (sse_tx, _) = broadcast::channel(16);
+    let transport = ServerSseTransport::new(sse_tx.clone());
+
+    let mut group = c.benchmark_group("sse_broadcast");
+    group.throughput(Throughput::Bytes(
+        serde_json::to_string(&message).unwrap().len() as u64,
+    ));
+    group.bench_function("large_tools_list", |b| {
+        b.to_async(&runtime).iter(|| async {
+            let mut subscribers: Vec<_> = (0..SUBSCRIBERS).map(|_| sse_tx.subscribe()).collect();
+            transport.send(&message).await.unwrap();
+            // What sse_handler does for each connected stream
+            for rx in &mut subscribers {
+                let json = rx.recv().await.unwrap();
+                std::hint::black_box(format!("data: {}\n\n", json));
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, sse_broadcast);
+criterion_main!(benches);
@@ -0,0 +1,44 @@
+//! Cost of gzip framing a ~2MB tools/list response for the stdio transports
+//! built from the tavily-search tool of the SSE parsing fixtures, like sse_broadcast
+//! run with `cargo bench -p async-mcp --bench stdio_compression`
+use async_mcp::transport::{
+    decode_frame, encode_frame, JsonRpcMessage, JsonRpcResponse, JsonRpcVersion,
+};
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+const TAVILY_SEARCH: &str = r#"{"description":"A powerful web search tool that provides comprehensive, real-time results using Tavily's AI search engine. Returns relevant web content with customizable parameters for result count, content type, and domain filtering. Ideal for gathering current information, news, and detailed web content analysis.","inputSchema":{"properties":{"days":{"default":3,"description":"The number of days back from the current date to include in the search results. This specifies the time frame of data to be retrieved. Please note that this feature is only available when using the 'news' search topic","type":"number"}}},"name":"tavily-search"}"#;
+const TOOLS: usize = 3000;
+
+fn tools_list() -> String {
+    let tool: serde_json::Value = serde_json::from_str(TAVILY_SEARCH).unwrap();
+    serde_json::to_string(&JsonRpcMessage::Response(JsonRpcResponse {
+        id: 0,
+        result: Some(serde_json::json!({ "tools": vec![tool; TOOLS] })),
+        error: None,
+        jsonrpc: JsonRpcVersion::default(),
+    }))
+    .unwrap()
+}
+
+fn stdio_compression(c: &mut Criterion) {
+    let json = tools_list();
+    let frame = encode_frame(&json).unwrap();
+    println!(
+        "tools/list: {} bytes as JSON, {} bytes as a gzip frame",
+        json.len(),
+        frame.len()
+    );
+
+    let mut group = c.benchmark_group("stdio_compression");
+    group.throughput(Throughput::Bytes(json.len() as u64));
+    group.bench_function("encode_large_tools_list", |b| {
+        b.iter(|| std::hint::black_box(encode_frame(&json).unwrap()))
+    });
+    group.bench_function("decode_large_tools_list", |b| {
+        b.iter(|| std::hint::black_box(decode_frame(&frame, usize::MAX - 1).unwrap().len()))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, stdio_compression);
+criterion_main!(benches);
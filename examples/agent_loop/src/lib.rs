@@ -0,0 +1,480 @@
+use std::collections::VecDeque;
+
+use anyhow::Result;
+use async_mcp::prelude::*;
+use serde_json::{json, Value};
+
+/// A tool call picked by the model, `arguments` is the JSON string OpenAI sends
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolCall {
+    pub id: String,
+    pub name: String,
+    pub arguments: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reply {
+    ToolCalls(Vec<ToolCall>),
+    Text(String),
+}
+
+/// The LLM side of the loop, sees the chat transcript and the available functions
+pub trait ChatModel {
+    fn complete(&mut self, transcript: &[Value], functions: &[Value]) -> Result<Reply>;
+}
+
+/// Deterministic stand-in for an LLM that replays a fixed list of replies
+pub struct ScriptedModel {
+    replies: VecDeque<Reply>,
+}
+
+impl ScriptedModel {
+    pub fn new(replies: Vec<Reply>) -> Self {
+        Self {
+            replies: replies.into(),
+        }
+    }
+}
+
+impl ChatModel for ScriptedModel {
+    fn complete(&mut self, _transcript: &[Value], functions: &[Value]) -> Result<Reply> {
+        let reply = self
+            .replies
+            .pop_front()
+            .ok_or_else(|| anyhow::anyhow!("Script exhausted"))?;
+        // A real model can only call functions it was offered
+        if let Reply::ToolCalls(calls) = &reply {
+            for call in calls {
+                if !functions
+                    .iter()
+                    .any(|f| f["function"]["name"] == call.name.as_str())
+                {
+                    anyhow::bail!("Script calls unknown function {}", call.name);
+                }
+            }
+        }
+        Ok(reply)
+    }
+}
+
+/// MCP tool as an OpenAI function definition
+pub fn tool_to_function(tool: &Tool) -> Value {
+    json!({
+        "type": "function",
+        "function": {
+            "name": tool.name,
+            "description": tool.description.clone().unwrap_or_default(),
+            "parameters": tool.input_schema,
+        }
+    })
+}
+
+/// Chat API tool results are sent back to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChatFormat {
+    OpenAi,
+    Ollama,
+}
+
+/// What to do with the image items of a tool result
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ImagePolicy {
+    /// Replaced by a placeholder with the mime type and size
+    #[default]
+    Describe,
+    /// Left out of the message
+    Omit,
+    /// Sent as image data where the format has a place for it (Ollama `images`),
+    /// described otherwise since OpenAI tool messages only carry text
+    Attach,
+}
+
+/// Separator between the rendered items of a tool result
+pub const CONTENT_SEPARATOR: &str = "\n";
+
+/// Tool content converted for a chat message
+/// `manifest` has one entry per content item, in content order
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConvertedContent {
+    pub text: String,
+    /// Base64 image data to attach to the message
+    pub images: Vec<String>,
+    pub manifest: Vec<Value>,
+}
+
+impl ConvertedContent {
+    /// The text, followed by a `{"mcpContent": [...]}` line when any item wasn't plain text
+    pub fn render(&self) -> String {
+        if self.manifest.iter().all(|item| item["action"] == "text") {
+            return self.text.clone();
+        }
+        let footer = json!({ "mcpContent": self.manifest });
+        if self.text.is_empty() {
+            footer.to_string()
+        } else {
+            format!("{}{}{}", self.text, CONTENT_SEPARATOR, footer)
+        }
+    }
+}
+
+/// Convert tool content item by item in content order, the output only depends on the content
+pub fn convert_tool_content(
+    content: &[ToolResponseContent],
+    format: ChatFormat,
+    images: ImagePolicy,
+) -> ConvertedContent {
+    let mut texts = Vec::new();
+    let mut attached = Vec::new();
+    let mut manifest = Vec::with_capacity(content.len());
+    for (index, item) in content.iter().enumerate() {
+        let (kind, action) = match item {
+            ToolResponseContent::Text { text } => {
+                texts.push(text.clone());
+                ("text", "text")
+            }
+            ToolResponseContent::Image { data, mime_type } => match (images, format) {
+                (ImagePolicy::Omit, _) => ("image", "omitted"),
+                (ImagePolicy::Attach, ChatFormat::Ollama) => {
+                    attached.push(data.clone());
+                    ("image", "attached")
+                }
+                (ImagePolicy::Describe | ImagePolicy::Attach, _) => {
+                    texts.push(format!(
+                        "[image {} ({} bytes base64)]",
+                        mime_type,
+                        data.len()
+                    ));
+                    ("image", "described")
+                }
+            },
+            ToolResponseContent::Resource { resource } => {
+                texts.push(format!("[resource {}]", resource.uri));
+                ("resource", "described")
+            }
+        };
+        manifest.push(json!({"index": index, "type": kind, "action": action}));
+    }
+    ConvertedContent {
+        text: texts.join(CONTENT_SEPARATOR),
+        images: attached,
+        manifest,
+    }
+}
+
+/// Flatten tool content into the text of a `tool` message, in content order
+/// images and resources can't be sent back as tool output so they are described instead
+pub fn tool_content_to_text(content: &[ToolResponseContent]) -> String {
+    convert_tool_content(content, ChatFormat::OpenAi, ImagePolicy::Describe).text
+}
+
+/// The `tool` message answering `call` in the given chat format
+pub fn tool_result_message(
+    format: ChatFormat,
+    call: &ToolCall,
+    response: &CallToolResponse,
+    images: ImagePolicy,
+) -> Value {
+    let converted = convert_tool_content(&response.content, format, images);
+    let mut content = converted.render();
+    if response.is_error == Some(true) {
+        content = format!("Error: {}", content);
+    }
+    match format {
+        ChatFormat::OpenAi => json!({
+            "role": "tool",
+            "tool_call_id": call.id,
+            "content": content,
+        }),
+        ChatFormat::Ollama if converted.images.is_empty() => json!({
+            "role": "tool",
+            "tool_name": call.name,
+            "content": content,
+        }),
+        ChatFormat::Ollama => json!({
+            "role": "tool",
+            "tool_name": call.name,
+            "content": content,
+            "images": converted.images,
+        }),
+    }
+}
+
+pub async fn list_functions<T: Transport>(client: &Client<T>) -> Result<Vec<Value>> {
+    let response: ToolsListResponse = client
+        .request_typed(
+            "tools/list",
+            ListRequest {
+                cursor: None,
+                meta: None,
+            },
+            RequestOptions::default(),
+        )
+        .await?;
+    Ok(response.tools.iter().map(tool_to_function).collect())
+}
+
+/// Run `call` through `tools/call` and turn the result into an OpenAI `tool` message
+pub async fn execute_tool_call<T: Transport>(client: &Client<T>, call: &ToolCall) -> Result<Value> {
+    execute_tool_call_as(client, call, ChatFormat::OpenAi, ImagePolicy::Describe).await
+}
+
+/// Like [`execute_tool_call`], for any chat format and image policy
+pub async fn execute_tool_call_as<T: Transport>(
+    client: &Client<T>,
+    call: &ToolCall,
+    format: ChatFormat,
+    images: ImagePolicy,
+) -> Result<Value> {
+    let arguments = match call.arguments.trim() {
+        "" => None,
+        arguments => Some(serde_json::from_str(arguments)?),
+    };
+    let request = CallToolRequest {
+        name: call.name.clone(),
+        arguments,
+        meta: None,
+    };
+    let response = match client
+        .request_typed::<_, CallToolResponse>("tools/call", request, RequestOptions::default())
+        .await
+    {
+        Ok(response) => response,
+        // Protocol errors are reported to the model so it can recover
+        Err(e) => CallToolResponse::error(e.to_string()),
+    };
+    Ok(tool_result_message(format, call, &response, images))
+}
+
+/// Loop until the model answers with text or `max_steps` model turns are used
+/// returns the full transcript including the final assistant message
+pub async fn run_agent<T: Transport>(
+    client: &Client<T>,
+    model: &mut impl ChatModel,
+    prompt: &str,
+    max_steps: usize,
+) -> Result<Vec<Value>> {
+    let functions = list_functions(client).await?;
+    let mut transcript = vec![json!({"role": "user", "content": prompt})];
+    for _ in 0..max_steps {
+        match model.complete(&transcript, &functions)? {
+            Reply::Text(text) => {
+                transcript.push(json!({"role": "assistant", "content": text}));
+                return Ok(transcript);
+            }
+            Reply::ToolCalls(calls) => {
+                let tool_calls: Vec<Value> = calls
+                    .iter()
+                    .map(|call| {
+                        json!({
+                            "id": call.id,
+                            "type": "function",
+                            "function": {"name": call.name, "arguments": call.arguments},
+                        })
+                    })
+                    .collect();
+                transcript.push(json!({"role": "assistant", "tool_calls": tool_calls}));
+                for call in &calls {
+                    transcript.push(execute_tool_call(client, call).await?);
+                }
+            }
+        }
+    }
+    anyhow::bail!("No answer after {} steps", max_steps)
+}
+
+/// The scripted conversation run by the binary and the test
+pub fn ping_script() -> ScriptedModel {
+    let ping = |id: &str| ToolCall {
+        id: id.to_string(),
+        name: "ping".to_string(),
+        arguments: "{}".to_string(),
+    };
+    ScriptedModel::new(vec![
+        Reply::ToolCalls(vec![ping("call_1")]),
+        Reply::ToolCalls(vec![ping("call_2")]),
+        Reply::Text("The server answered pong twice.".to_string()),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pingpong::inmemory_server;
+
+    #[tokio::test]
+    async fn test_agent_loop_transcript() -> Result<()> {
+        let transport = ClientInMemoryTransport::new(|t| tokio::spawn(inmemory_server(t)));
+        transport.open().await?;
+        let client = ClientBuilder::new(transport.clone()).build();
+        let client_clone = client.clone();
+        tokio::spawn(async move { client_clone.start().await });
+
+        let mut model = ping_script();
+        let transcript = run_agent(&client, &mut model, "Ping the server twice", 3).await?;
+
+        let call = |id: &str| {
+            json!({"role": "assistant", "tool_calls": [{
+                "id": id,
+                "type": "function",
+                "function": {"name": "ping", "arguments": "{}"},
+            }]})
+        };
+        assert_eq!(
+            transcript,
+            vec![
+                json!({"role": "user", "content": "Ping the server twice"}),
+                call("call_1"),
+                json!({"role": "tool", "tool_call_id": "call_1", "content": "pong"}),
+                call("call_2"),
+                json!({"role": "tool", "tool_call_id": "call_2", "content": "pong"}),
+                json!({"role": "assistant", "content": "The server answered pong twice."}),
+            ]
+        );
+
+        // Unknown tools come back as an error the model can read
+        let missing = ToolCall {
+            id: "call_3".to_string(),
+            name: "pong".to_string(),
+            arguments: String::new(),
+        };
+        let message = execute_tool_call(&client, &missing).await?;
+        assert!(message["content"].as_str().unwrap().starts_with("Error:"));
+
+        transport.close().await?;
+        Ok(())
+    }
+
+    fn mixed_response() -> CallToolResponse {
+        CallToolResponse {
+            content: vec![
+                ToolResponseContent::Text {
+                    text: "3 builds failed".to_string(),
+                },
+                ToolResponseContent::Image {
+                    data: "aGVsbG8=".to_string(),
+                    mime_type: "image/png".to_string(),
+                },
+                ToolResponseContent::Text {
+                    text: "details: timeout".to_string(),
+                },
+            ],
+            is_error: None,
+            meta: None,
+        }
+    }
+
+    #[test]
+    fn test_multi_item_tool_results() {
+        let call = ToolCall {
+            id: "call_1".to_string(),
+            name: "builds".to_string(),
+            arguments: "{}".to_string(),
+        };
+        let footer = |image_action: &str| {
+            format!(
+                r#"{{"mcpContent":[{{"action":"text","index":0,"type":"text"}},{{"action":"{}","index":1,"type":"image"}},{{"action":"text","index":2,"type":"text"}}]}}"#,
+                image_action
+            )
+        };
+        let described = format!(
+            "3 builds failed\n[image image/png (8 bytes base64)]\ndetails: timeout\n{}",
+            footer("described")
+        );
+        let omitted = format!("3 builds failed\ndetails: timeout\n{}", footer("omitted"));
+        let attached = format!("3 builds failed\ndetails: timeout\n{}", footer("attached"));
+
+        let message =
+            |format, images| tool_result_message(format, &call, &mixed_response(), images);
+        for images in [ImagePolicy::Describe, ImagePolicy::Attach] {
+            assert_eq!(
+                message(ChatFormat::OpenAi, images),
+                json!({"role": "tool", "tool_call_id": "call_1", "content": described})
+            );
+        }
+        assert_eq!(
+            message(ChatFormat::OpenAi, ImagePolicy::Omit),
+            json!({"role": "tool", "tool_call_id": "call_1", "content": omitted})
+        );
+        assert_eq!(
+            message(ChatFormat::Ollama, ImagePolicy::Describe),
+            json!({"role": "tool", "tool_name": "builds", "content": described})
+        );
+        assert_eq!(
+            message(ChatFormat::Ollama, ImagePolicy::Omit),
+            json!({"role": "tool", "tool_name": "builds", "content": omitted})
+        );
+        assert_eq!(
+            message(ChatFormat::Ollama, ImagePolicy::Attach),
+            json!({
+                "role": "tool",
+                "tool_name": "builds",
+                "content": attached,
+                "images": ["aGVsbG8="],
+            })
+        );
+    }
+
+    #[test]
+    fn test_rendering_ignores_non_content_fields() {
+        // Every ordering of the `_meta` keys, with and without extra fields on the call
+        let keys = ["a", "b", "c", "d"];
+        let mut orders = vec![vec![]];
+        for _ in 0..keys.len() {
+            orders = orders
+                .into_iter()
+                .flat_map(|order: Vec<&str>| {
+                    keys.iter()
+                        .filter(|key| !order.contains(key))
+                        .map(|key| {
+                            let mut next = order.clone();
+                            next.push(key);
+                            next
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .collect();
+        }
+        assert_eq!(orders.len(), 24);
+
+        let expected = convert_tool_content(
+            &mixed_response().content,
+            ChatFormat::Ollama,
+            ImagePolicy::Describe,
+        );
+        for (i, order) in orders.iter().enumerate() {
+            let mut meta = serde_json::Map::new();
+            for (rank, key) in order.iter().enumerate() {
+                meta.insert(key.to_string(), json!(rank));
+            }
+            let response = CallToolResponse {
+                meta: Some(Value::Object(meta)),
+                ..mixed_response()
+            };
+            let call = ToolCall {
+                id: format!("call_{}", i),
+                name: "builds".to_string(),
+                arguments: format!(r#"{{"attempt":{}}}"#, i),
+            };
+            let message =
+                tool_result_message(ChatFormat::Ollama, &call, &response, ImagePolicy::Describe);
+            assert_eq!(message["content"], expected.render());
+        }
+    }
+
+    #[test]
+    fn test_tool_content_to_text() {
+        let content = vec![
+            ToolResponseContent::Text {
+                text: "chart:".to_string(),
+            },
+            ToolResponseContent::Image {
+                data: "aGVsbG8=".to_string(),
+                mime_type: "image/png".to_string(),
+            },
+        ];
+        assert_eq!(
+            tool_content_to_text(&content),
+            "chart:\n[image image/png (8 bytes base64)]"
+        );
+    }
+}
@@ -0,0 +1,24 @@
+use agent_loop::{ping_script, run_agent};
+use anyhow::Result;
+use async_mcp::prelude::*;
+use pingpong::inmemory_server;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let transport = ClientInMemoryTransport::new(|t| tokio::spawn(inmemory_server(t)));
+    transport.open().await?;
+
+    let client = ClientBuilder::new(transport.clone()).build();
+    let client_clone = client.clone();
+    tokio::spawn(async move { client_clone.start().await });
+
+    // Swap ScriptedModel for a real LLM client implementing ChatModel
+    let mut model = ping_script();
+    let transcript = run_agent(&client, &mut model, "Ping the server twice", 3).await?;
+    for message in &transcript {
+        println!("{}", message);
+    }
+
+    transport.close().await?;
+    Ok(())
+}
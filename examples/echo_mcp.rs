@@ -0,0 +1,83 @@
+//! Minimal MCP server over stdio spawned by the cross-process tests in `tests/stdio.rs`
+//! serves one `echo` tool, the behaviors under test are selected with environment variables:
+//! - `ECHO_MCP_DELAY_MS`: wait this long before answering a tool call
+//! - `ECHO_MCP_PROGRESS`: send a `notifications/progress` for calls that ask for progress
+//! - `ECHO_MCP_CRASH_AFTER`: exit with status 101 when the request after the first N arrives
+//! - `ECHO_MCP_GARBAGE`: print lines that aren't JSON-RPC before serving
+use anyhow::Result;
+use async_mcp::server::Server;
+use async_mcp::transport::ServerStdioTransport;
+use async_mcp::types::{
+    CallToolRequest, CallToolResponse, ServerCapabilities, Tool, ToolResponseContent,
+};
+use serde_json::json;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+fn env_number(name: &str) -> Option<u64> {
+    std::env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    if std::env::var_os("ECHO_MCP_GARBAGE").is_some() {
+        println!("not json");
+        println!(r#"{{"jsonrpc":"1.0","id":1,"method":"ping"}}"#);
+        println!();
+    }
+
+    let mut builder = Server::builder(ServerStdioTransport::default())
+        .name("echo_mcp")
+        .version("0.1.0")
+        .capabilities(ServerCapabilities {
+            tools: Some(json!({})),
+            ..Default::default()
+        });
+    if let Some(limit) = env_number("ECHO_MCP_CRASH_AFTER") {
+        let seen = AtomicU64::new(0);
+        builder = builder.request_interceptor(move |_, _, _| {
+            if seen.fetch_add(1, Ordering::SeqCst) >= limit {
+                std::process::exit(101);
+            }
+            Ok(())
+        });
+    }
+
+    let delay = Duration::from_millis(env_number("ECHO_MCP_DELAY_MS").unwrap_or(0));
+    let progress = std::env::var_os("ECHO_MCP_PROGRESS").is_some();
+    builder.register_tool_with_context(
+        Tool {
+            name: "echo".to_string(),
+            description: Some("Echo the text argument".to_string()),
+            input_schema: json!({
+                "type": "object",
+                "properties": {"text": {"type": "string"}},
+                "required": ["text"]
+            }),
+            output_schema: None,
+        },
+        move |req: CallToolRequest, ctx| {
+            Box::pin(async move {
+                if progress {
+                    ctx.report_progress(1.0, "echoing").await?;
+                }
+                tokio::time::sleep(delay).await;
+                let text = req
+                    .arguments
+                    .as_ref()
+                    .and_then(|arguments| arguments.get("text"))
+                    .and_then(|text| text.as_str())
+                    .ok_or_else(|| anyhow::anyhow!("Missing text argument"))?
+                    .to_string();
+                Ok(CallToolResponse {
+                    content: vec![ToolResponseContent::Text { text }],
+                    is_error: None,
+                    meta: None,
+                })
+            })
+        },
+    );
+    builder.build().listen().await
+}
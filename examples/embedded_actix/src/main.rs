@@ -0,0 +1,49 @@
+use actix_web::{middleware::Logger, web, App, HttpResponse, HttpServer};
+use anyhow::Result;
+use async_mcp::prelude::*;
+use async_mcp::sse::http_server::{configure_mcp, McpOptions, SessionState};
+
+/// Mounts the MCP endpoints under `/mcp` next to the application's own routes
+/// connect an SSE client to http://127.0.0.1:3005/mcp/sse, `/healthz` answers as before
+#[actix_web::main]
+async fn main() -> Result<()> {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::DEBUG)
+        .init();
+
+    // Created once, every worker shares its sessions
+    let session_state = SessionState::from_fn(|transport, _, _| async move {
+        let mut builder = Server::builder(transport).capabilities(ServerCapabilities {
+            tools: Some(serde_json::json!({})),
+            ..Default::default()
+        });
+        builder.register_tool(
+            Tool {
+                name: "echo".to_string(),
+                description: Some("Returns its arguments".to_string()),
+                input_schema: serde_json::json!({"type": "object"}),
+                output_schema: None,
+            },
+            |req| Box::pin(async move { Ok(CallToolResponse::json(req.arguments)) }),
+        );
+        Ok(builder.build())
+    });
+    let reaper = session_state.spawn_idle_reaper();
+    let options = McpOptions::default().prefix("/mcp");
+
+    HttpServer::new(move || {
+        let session_state = session_state.clone();
+        App::new()
+            .wrap(Logger::default())
+            .route("/healthz", web::get().to(HttpResponse::Ok))
+            .configure(|cfg| configure_mcp(cfg, &options, session_state))
+    })
+    .bind(("127.0.0.1", 3005))?
+    .run()
+    .await?;
+
+    if let Some(reaper) = reaper {
+        reaper.abort();
+    }
+    Ok(())
+}
@@ -1,29 +1,56 @@
-use std::sync::{Arc, Mutex};
-
-use async_mcp::{
-    server::{Server, ServerBuilder},
-    transport::ServerStdioTransport,
-    types::{CallToolRequest, CallToolResponse, ServerCapabilities, Tool, ToolResponseContent},
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
 };
+
+use async_mcp::blob::{BlobStore, LocalBlobStore};
+use async_mcp::prelude::*;
+use async_mcp::types::Schema;
+use clap::Parser;
 use serde_json::json;
-use types::{AddObservationParams, DeleteObservationParams, Entity, KnowledgeGraph, Relation};
+use types::{
+    AddObservationParams, DeleteObservationParams, Entity, ImportMode, KnowledgeGraph, Relation,
+};
 
 use anyhow::Result;
 mod types;
 
+/// How long exported graphs stay readable through `resources/read`
+const EXPORT_TTL: Duration = Duration::from_secs(3600);
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Knowledge graph memory MCP server")]
+struct Cli {
+    /// JSONL file the graph is loaded from and saved to, created on first write
+    #[arg(long, env = "MEMORY_FILE_PATH", default_value = "kb_memory.json")]
+    memory_file: PathBuf,
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
+    let cli = Cli::parse();
+
     tracing_subscriber::fmt()
         .with_max_level(tracing::Level::DEBUG)
         // needs to be stderr due to stdio transport
         .with_writer(std::io::stderr)
         .init();
 
-    let mut server = Server::builder(ServerStdioTransport).capabilities(ServerCapabilities {
-        tools: Some(json!({})),
-        ..Default::default()
-    });
-    register_tools(&mut server)?;
+    let blob_store = Arc::new(LocalBlobStore::new(EXPORT_TTL));
+    let mut server = Server::builder(ServerStdioTransport::default())
+        .capabilities(ServerCapabilities {
+            tools: Some(json!({})),
+            resources: Some(ResourceCapabilities::default()),
+            ..Default::default()
+        })
+        .blob_store(blob_store.clone())
+        .with_state(Memory {
+            graph: Mutex::new(KnowledgeGraph::load_from_file(&cli.memory_file)?),
+            path: cli.memory_file,
+            exports: blob_store,
+        });
+    register_tools(&mut server);
 
     let server = server.build();
     server
@@ -33,55 +60,40 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
-fn register_tools(server: &mut ServerBuilder<ServerStdioTransport>) -> Result<()> {
-    let memory_file_path = "kb_memory.json";
-    let kg = KnowledgeGraph::load_from_file(memory_file_path)?;
-    let kg = Arc::new(Mutex::new(kg));
+/// Shared by every tool through `State<Memory>`
+struct Memory {
+    graph: Mutex<KnowledgeGraph>,
+    /// Where the graph is saved after every change
+    path: PathBuf,
+    /// Holds exported graphs until they expire
+    exports: Arc<LocalBlobStore>,
+}
 
-    let description = Tool {
-        name: "create_entities".to_string(),
-        description: Some("Create multiple new entities".to_string()),
-        input_schema: json!({
-           "type":"object",
-           "properties":{
-              "entities":{
-                 "type":"array",
-                 "items":{
-                    "type":"object",
-                    "properties":{
-                       "name":{"type":"string"},
-                       "entityType":{"type":"string"},
-                       "observations":{
-                          "type":"array", "items":{"type":"string"}
-                       }
-                    },
-                    "required":["name","entityType","observations"]
-                 }
-              }
-           },
-           "required":["entities"]
-        }),
-        output_schema: None,
-    };
+fn register_tools(server: &mut ServerBuilder<ServerStdioTransport>) {
+    let entity = Schema::object()
+        .property("name", Schema::string())
+        .property("entityType", Schema::string())
+        .property("observations", Schema::array_of(Schema::string()))
+        .required(["name", "entityType", "observations"]);
+    let description = ToolBuilder::new("create_entities")
+        .description("Create multiple new entities")
+        .input_schema(
+            Schema::object()
+                .property("entities", Schema::array_of(entity))
+                .required(["entities"]),
+        )
+        .build();
 
-    let kg_clone = kg.clone();
-    server.register_tool(description, move |req: CallToolRequest| {
-        let kg_clone = kg_clone.clone();
+    server.register_tool_with_state(description, |req, memory: State<Memory>| {
         Box::pin(async move {
             let args = req.arguments.unwrap_or_default();
             let entities = args
                 .get("entities")
                 .ok_or(anyhow::anyhow!("missing arguments `entities`"))?;
             let entities: Vec<Entity> = serde_json::from_value(entities.clone())?;
-            let created = kg_clone.lock().unwrap().create_entities(entities)?;
-            kg_clone.lock().unwrap().save_to_file(memory_file_path)?;
-            Ok(CallToolResponse {
-                content: vec![ToolResponseContent::Text {
-                    text: json!(created).to_string(),
-                }],
-                is_error: None,
-                meta: None,
-            })
+            let created = memory.graph.lock().unwrap().create_entities(entities)?;
+            memory.graph.lock().unwrap().save_to_file(&memory.path)?;
+            Ok(CallToolResponse::json(created))
         })
     });
 
@@ -108,24 +120,16 @@ fn register_tools(server: &mut ServerBuilder<ServerStdioTransport>) -> Result<()
         }),
         output_schema: None,
     };
-    let kg_clone = kg.clone();
-    server.register_tool(description, move |req: CallToolRequest| {
-        let kg_clone = kg_clone.clone();
+    server.register_tool_with_state(description, |req, memory: State<Memory>| {
         Box::pin(async move {
             let args = req.arguments.unwrap_or_default();
             let relations = args
                 .get("relations")
                 .ok_or(anyhow::anyhow!("missing arguments `relations`"))?;
             let relations: Vec<Relation> = serde_json::from_value(relations.clone())?;
-            let created = kg_clone.lock().unwrap().create_relations(relations)?;
-            kg_clone.lock().unwrap().save_to_file(memory_file_path)?;
-            Ok(CallToolResponse {
-                content: vec![ToolResponseContent::Text {
-                    text: json!(created).to_string(),
-                }],
-                is_error: None,
-                meta: None,
-            })
+            let created = memory.graph.lock().unwrap().create_relations(relations)?;
+            memory.graph.lock().unwrap().save_to_file(&memory.path)?;
+            Ok(CallToolResponse::json(created))
         })
     });
 
@@ -154,9 +158,7 @@ fn register_tools(server: &mut ServerBuilder<ServerStdioTransport>) -> Result<()
         }),
         output_schema: None,
     };
-    let kg_clone = kg.clone();
-    server.register_tool(description, move |req: CallToolRequest| {
-        let kg_clone = kg_clone.clone();
+    server.register_tool_with_state(description, |req, memory: State<Memory>| {
         Box::pin(async move {
             let args = req.arguments.unwrap_or_default();
             let observations = args
@@ -164,52 +166,36 @@ fn register_tools(server: &mut ServerBuilder<ServerStdioTransport>) -> Result<()
                 .ok_or(anyhow::anyhow!("missing arguments `observations`"))?;
             let observations: Vec<AddObservationParams> =
                 serde_json::from_value(observations.clone())?;
-            let results = kg_clone.lock().unwrap().add_observations(observations)?;
-            kg_clone.lock().unwrap().save_to_file(memory_file_path)?;
-            Ok(CallToolResponse {
-                content: vec![ToolResponseContent::Text {
-                    text: json!(results).to_string(),
-                }],
-                is_error: None,
-                meta: None,
-            })
+            let results = memory
+                .graph
+                .lock()
+                .unwrap()
+                .add_observations(observations)?;
+            memory.graph.lock().unwrap().save_to_file(&memory.path)?;
+            Ok(CallToolResponse::json(results))
         })
     });
 
-    let description = Tool {
-        name: "delete_entities".to_string(),
-        description: Some("Delete multiple entities and their relations".to_string()),
-        input_schema: json!({
-            "type": "object",
-            "properties": {
-                "entityNames": {
-                    "type": "array",
-                    "items": {"type": "string"}
-                }
-            },
-            "required": ["entityNames"]
-        }),
-        output_schema: None,
-    };
-    let kg_clone = kg.clone();
-    server.register_tool(description, move |req: CallToolRequest| {
-        let kg_clone = kg_clone.clone();
+    let description = ToolBuilder::new("delete_entities")
+        .description("Delete multiple entities and their relations")
+        .arg_array(
+            "entityNames",
+            "Names of the entities to delete",
+            json!({"type": "string"}),
+            true,
+        )
+        .build();
+    server.register_tool_with_state(description, |req, memory: State<Memory>| {
         Box::pin(async move {
             let args = req.arguments.unwrap_or_default();
             let entity_names = args
                 .get("entityNames")
                 .ok_or(anyhow::anyhow!("missing arguments `entityNames`"))?;
             let entity_names: Vec<String> = serde_json::from_value(entity_names.clone())?;
-            let mut kg_guard = kg_clone.lock().unwrap();
+            let mut kg_guard = memory.graph.lock().unwrap();
             kg_guard.delete_entities(entity_names)?;
-            kg_guard.save_to_file(memory_file_path)?;
-            Ok(CallToolResponse {
-                content: vec![ToolResponseContent::Text {
-                    text: "Entities deleted successfully".to_string(),
-                }],
-                is_error: None,
-                meta: None,
-            })
+            kg_guard.save_to_file(&memory.path)?;
+            Ok(CallToolResponse::text("Entities deleted successfully"))
         })
     });
 
@@ -238,9 +224,7 @@ fn register_tools(server: &mut ServerBuilder<ServerStdioTransport>) -> Result<()
         }),
         output_schema: None,
     };
-    let kg_clone = kg.clone();
-    server.register_tool(description, move |req: CallToolRequest| {
-        let kg_clone = kg_clone.clone();
+    server.register_tool_with_state(description, |req, memory: State<Memory>| {
         Box::pin(async move {
             let args = req.arguments.unwrap_or_default();
             let deletions = args
@@ -248,16 +232,10 @@ fn register_tools(server: &mut ServerBuilder<ServerStdioTransport>) -> Result<()
                 .ok_or(anyhow::anyhow!("missing arguments `deletions`"))?;
             let deletions: Vec<DeleteObservationParams> =
                 serde_json::from_value(deletions.clone())?;
-            let mut kg_guard = kg_clone.lock().unwrap();
+            let mut kg_guard = memory.graph.lock().unwrap();
             kg_guard.delete_observations(deletions)?;
-            kg_guard.save_to_file(memory_file_path)?;
-            Ok(CallToolResponse {
-                content: vec![ToolResponseContent::Text {
-                    text: "Observations deleted successfully".to_string(),
-                }],
-                is_error: None,
-                meta: None,
-            })
+            kg_guard.save_to_file(&memory.path)?;
+            Ok(CallToolResponse::text("Observations deleted successfully"))
         })
     });
 
@@ -284,66 +262,36 @@ fn register_tools(server: &mut ServerBuilder<ServerStdioTransport>) -> Result<()
         }),
         output_schema: None,
     };
-    let kg_clone = kg.clone();
-    server.register_tool(description, move |req: CallToolRequest| {
-        let kg_clone = kg_clone.clone();
+    server.register_tool_with_state(description, |req, memory: State<Memory>| {
         Box::pin(async move {
             let args = req.arguments.unwrap_or_default();
             let relations = args
                 .get("relations")
                 .ok_or(anyhow::anyhow!("missing arguments `relations`"))?;
             let relations: Vec<Relation> = serde_json::from_value(relations.clone())?;
-            let mut kg_guard = kg_clone.lock().unwrap();
+            let mut kg_guard = memory.graph.lock().unwrap();
             kg_guard.delete_relations(relations)?;
-            kg_guard.save_to_file(memory_file_path)?;
-            Ok(CallToolResponse {
-                content: vec![ToolResponseContent::Text {
-                    text: "Relations deleted successfully".to_string(),
-                }],
-                is_error: None,
-                meta: None,
-            })
+            kg_guard.save_to_file(&memory.path)?;
+            Ok(CallToolResponse::text("Relations deleted successfully"))
         })
     });
 
-    let description = Tool {
-        name: "read_graph".to_string(),
-        description: Some("Read the entire knowledge graph".to_string()),
-        input_schema: json!({
-            "type": "object",
-            "properties": {}
-        }),
-        output_schema: None,
-    };
-    let kg_clone = kg.clone();
-    server.register_tool(description, move |_req: CallToolRequest| {
-        let kg_clone = kg_clone.clone();
-        Box::pin(async move {
-            Ok(CallToolResponse {
-                content: vec![ToolResponseContent::Text {
-                    text: json!(*kg_clone.lock().unwrap()).to_string(),
-                }],
-                is_error: None,
-                meta: None,
-            })
-        })
+    let description = ToolBuilder::new("read_graph")
+        .description("Read the entire knowledge graph")
+        .build();
+    server.register_tool_with_state(description, |_req, memory: State<Memory>| {
+        Box::pin(async move { Ok(CallToolResponse::json(&*memory.graph.lock().unwrap())) })
     });
 
-    let description = Tool {
-        name: "search_nodes".to_string(),
-        description: Some("Search for nodes in the knowledge graph".to_string()),
-        input_schema: json!({
-            "type": "object",
-            "properties": {
-                "query": {"type": "string"}
-            },
-            "required": ["query"]
-        }),
-        output_schema: None,
-    };
-    let kg_clone = kg.clone();
-    server.register_tool(description, move |req: CallToolRequest| {
-        let kg_clone = kg_clone.clone();
+    let description = ToolBuilder::new("search_nodes")
+        .description("Search for nodes in the knowledge graph")
+        .arg_string(
+            "query",
+            "Text to match against names, types and observations",
+            true,
+        )
+        .build();
+    server.register_tool_with_state(description, |req, memory: State<Memory>| {
         Box::pin(async move {
             let args = req.arguments.unwrap_or_default();
             let query = args
@@ -351,51 +299,95 @@ fn register_tools(server: &mut ServerBuilder<ServerStdioTransport>) -> Result<()
                 .ok_or(anyhow::anyhow!("missing argument `query`"))?
                 .as_str()
                 .ok_or(anyhow::anyhow!("query must be a string"))?;
-            let results = kg_clone.lock().unwrap().search_nodes(query)?;
-            Ok(CallToolResponse {
-                content: vec![ToolResponseContent::Text {
-                    text: json!(results).to_string(),
-                }],
-                is_error: None,
-                meta: None,
-            })
+            let results = memory.graph.lock().unwrap().search_nodes(query)?;
+            Ok(CallToolResponse::json(results))
         })
     });
 
-    let description = Tool {
-        name: "open_nodes".to_string(),
-        description: Some("Open specific nodes by their names".to_string()),
-        input_schema: json!({
-            "type": "object",
-            "properties": {
-                "names": {
-                    "type": "array",
-                    "items": {"type": "string"}
-                }
-            },
-            "required": ["names"]
-        }),
-        output_schema: None,
-    };
-    let kg_clone = kg.clone();
-    server.register_tool(description, move |req: CallToolRequest| {
-        let kg_clone = kg_clone.clone();
+    let description = ToolBuilder::new("open_nodes")
+        .description("Open specific nodes by their names")
+        .arg_array(
+            "names",
+            "Names of the nodes to open",
+            json!({"type": "string"}),
+            true,
+        )
+        .build();
+    server.register_tool_with_state(description, |req, memory: State<Memory>| {
         Box::pin(async move {
             let args = req.arguments.unwrap_or_default();
             let names = args
                 .get("names")
                 .ok_or(anyhow::anyhow!("missing arguments `names`"))?;
             let names: Vec<String> = serde_json::from_value(names.clone())?;
-            let results = kg_clone.lock().unwrap().open_nodes(names)?;
+            let results = memory.graph.lock().unwrap().open_nodes(names)?;
+            Ok(CallToolResponse::json(results))
+        })
+    });
+
+    let description = ToolBuilder::new("export_graph")
+        .description(
+            "Export the knowledge graph as JSONL, returned as a resource link to read the file from",
+        )
+        .build();
+    server.register_tool_with_state(description, |_req, memory: State<Memory>| {
+        Box::pin(async move {
+            let (jsonl, entities, relations) = {
+                let kg = memory.graph.lock().unwrap();
+                (kg.to_jsonl()?, kg.entities.len(), kg.relations.len())
+            };
+            let link = memory
+                .exports
+                .store_blob(jsonl.into_bytes(), "application/jsonl")
+                .await?;
             Ok(CallToolResponse {
-                content: vec![ToolResponseContent::Text {
-                    text: json!(results).to_string(),
-                }],
+                content: vec![
+                    ToolResponseContent::Text {
+                        text: format!("Exported {} entities and {} relations", entities, relations),
+                    },
+                    link,
+                ],
                 is_error: None,
                 meta: None,
             })
         })
     });
 
-    Ok(())
+    let description = ToolBuilder::new("import_graph")
+        .description(
+            "Import entities and relations from JSONL as produced by export_graph, \
+             nothing is imported if any line is invalid",
+        )
+        .arg_string("content", "JSONL, one entity or relation per line", true)
+        .arg(
+            "mode",
+            "`merge` (default) unions the observations of existing entities, `replace` discards the current graph",
+            json!({"type": "string", "enum": ["merge", "replace"]}),
+            false,
+        )
+        .build();
+    server.register_tool_with_state(description, |req, memory: State<Memory>| {
+        Box::pin(async move {
+            let args = req.arguments.unwrap_or_default();
+            let content = args
+                .get("content")
+                .ok_or(anyhow::anyhow!("missing argument `content`"))?
+                .as_str()
+                .ok_or(anyhow::anyhow!("content must be a string"))?;
+            let mode: ImportMode = match args.get("mode") {
+                Some(mode) => serde_json::from_value(mode.clone())?,
+                None => ImportMode::default(),
+            };
+            let mut kg_guard = memory.graph.lock().unwrap();
+            let result = kg_guard.import_jsonl(content, mode)?;
+            if !result.errors.is_empty() {
+                return Ok(CallToolResponse {
+                    is_error: Some(true),
+                    ..CallToolResponse::json(result)
+                });
+            }
+            kg_guard.save_to_file(&memory.path)?;
+            Ok(CallToolResponse::json(result))
+        })
+    });
 }
@@ -1,16 +1,12 @@
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
-use std::{
-    fs::File,
-    io::{BufRead, BufReader, Write},
-    path::Path,
-};
+use std::{collections::HashMap, path::Path};
 
 // -----------------------------------------------------------------------------
 // Data Structures
 // -----------------------------------------------------------------------------
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct Entity {
     pub name: String,
     #[serde(rename = "entityType")]
@@ -18,7 +14,7 @@ pub struct Entity {
     pub observations: Vec<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct Relation {
     pub from: String,
     pub to: String,
@@ -26,77 +22,158 @@ pub struct Relation {
     pub relation_type: String,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct KnowledgeGraph {
     pub entities: Vec<Entity>,
     pub relations: Vec<Relation>,
 }
 
 impl KnowledgeGraph {
-    pub fn load_from_file(memory_file_path: &str) -> Result<Self> {
-        if !Path::new(memory_file_path).exists() {
+    pub fn load_from_file(memory_file_path: &Path) -> Result<Self> {
+        if !memory_file_path.exists() {
             return Ok(Self {
                 entities: vec![],
                 relations: vec![],
             });
         }
 
-        let file = File::open(memory_file_path)?;
-        let reader = BufReader::new(file);
+        let content = std::fs::read_to_string(memory_file_path)?;
+        let (kg, errors) = Self::parse_jsonl(&content);
+        if let Some(error) = errors.first() {
+            anyhow::bail!(
+                "{}:{}: {}",
+                memory_file_path.display(),
+                error.line,
+                error.message
+            );
+        }
+        Ok(kg)
+    }
+
+    /// Creates the parent directories of the file if needed
+    pub fn save_to_file(&self, memory_file_path: &Path) -> Result<()> {
+        if let Some(parent) = memory_file_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(memory_file_path, self.to_jsonl()?)?;
+        Ok(())
+    }
+
+    /// One line per entity then one per relation, tagged with `"type"`
+    pub fn to_jsonl(&self) -> Result<String> {
+        let mut jsonl = String::new();
+        let entities = self
+            .entities
+            .iter()
+            .map(|e| ("entity", serde_json::to_value(e)));
+        let relations = self
+            .relations
+            .iter()
+            .map(|r| ("relation", serde_json::to_value(r)));
+        for (kind, value) in entities.chain(relations) {
+            let mut map = value?;
+            if let Some(obj) = map.as_object_mut() {
+                obj.insert("type".to_string(), kind.into());
+            }
+            jsonl.push_str(&serde_json::to_string(&map)?);
+            jsonl.push('\n');
+        }
+        Ok(jsonl)
+    }
+
+    /// Parse JSONL as written by [`KnowledgeGraph::to_jsonl`], invalid lines are reported
+    /// with their 1-based line number and left out of the graph
+    pub fn parse_jsonl(content: &str) -> (Self, Vec<LineError>) {
         let mut kg = KnowledgeGraph {
             entities: vec![],
             relations: vec![],
         };
+        let mut errors = Vec::new();
+        let mut entity_lines = HashMap::new();
 
-        for line_res in reader.lines() {
-            let line = line_res?;
+        for (index, line) in content.lines().enumerate() {
+            let line_number = index + 1;
             if line.trim().is_empty() {
                 continue;
             }
-            let json_val: serde_json::Value = serde_json::from_str(&line)?;
-            if let Some(t) = json_val.get("type").and_then(|v| v.as_str()) {
-                match t {
-                    "entity" => {
-                        let entity: Entity = serde_json::from_value(json_val)?;
-                        kg.entities.push(entity);
-                    }
-                    "relation" => {
-                        let relation: Relation = serde_json::from_value(json_val)?;
-                        kg.relations.push(relation);
-                    }
-                    _ => {}
+            let mut error = |message: String| {
+                errors.push(LineError {
+                    line: line_number,
+                    message,
+                })
+            };
+            let json_val: serde_json::Value = match serde_json::from_str(line) {
+                Ok(value) => value,
+                Err(e) => {
+                    error(format!("invalid JSON: {}", e));
+                    continue;
                 }
+            };
+            match json_val.get("type").and_then(|v| v.as_str()) {
+                Some("entity") => match serde_json::from_value::<Entity>(json_val) {
+                    Ok(entity) => match entity_lines.get(&entity.name) {
+                        Some(first) => error(format!(
+                            "duplicate entity `{}`, first defined on line {}",
+                            entity.name, first
+                        )),
+                        None => {
+                            entity_lines.insert(entity.name.clone(), line_number);
+                            kg.entities.push(entity);
+                        }
+                    },
+                    Err(e) => error(format!("invalid entity: {}", e)),
+                },
+                Some("relation") => match serde_json::from_value::<Relation>(json_val) {
+                    Ok(relation) => kg.relations.push(relation),
+                    Err(e) => error(format!("invalid relation: {}", e)),
+                },
+                Some(other) => error(format!("unknown type `{}`", other)),
+                None => error("missing `type`".to_string()),
             }
         }
 
-        Ok(kg)
+        (kg, errors)
     }
 
-    pub fn save_to_file(&self, memory_file_path: &str) -> Result<()> {
-        let mut file = File::create(memory_file_path)?;
-        for entity in &self.entities {
-            let mut map = serde_json::to_value(entity)?;
-            if let Some(obj) = map.as_object_mut() {
-                obj.insert(
-                    "type".to_string(),
-                    serde_json::Value::String("entity".into()),
-                );
-            }
-            let line = serde_json::to_string(&map)?;
-            writeln!(file, "{}", line)?;
+    /// Import JSONL, nothing changes when any line is invalid
+    /// merging unions the observations of entities that already exist and keeps their type
+    pub fn import_jsonl(&mut self, content: &str, mode: ImportMode) -> Result<ImportResult> {
+        let (imported, errors) = Self::parse_jsonl(content);
+        if !errors.is_empty() {
+            return Ok(ImportResult {
+                errors,
+                ..Default::default()
+            });
         }
-        for relation in &self.relations {
-            let mut map = serde_json::to_value(relation)?;
-            if let Some(obj) = map.as_object_mut() {
-                obj.insert(
-                    "type".to_string(),
-                    serde_json::Value::String("relation".into()),
-                );
+
+        if mode == ImportMode::Replace {
+            *self = imported;
+            return Ok(ImportResult {
+                entities_added: self.entities.len(),
+                relations_added: self.relations.len(),
+                ..Default::default()
+            });
+        }
+
+        let mut result = ImportResult::default();
+        for entity in imported.entities {
+            match self.entities.iter_mut().find(|e| e.name == entity.name) {
+                Some(existing) => {
+                    for observation in entity.observations {
+                        if !existing.observations.contains(&observation) {
+                            existing.observations.push(observation);
+                        }
+                    }
+                    result.merged_entities.push(entity.name);
+                }
+                None => {
+                    self.entities.push(entity);
+                    result.entities_added += 1;
+                }
             }
-            let line = serde_json::to_string(&map)?;
-            writeln!(file, "{}", line)?;
         }
-        Ok(())
+        result.relations_added = self.create_relations(imported.relations)?.len();
+        Ok(result)
     }
 
     pub fn create_entities(&mut self, entities: Vec<Entity>) -> Result<Vec<Entity>> {
@@ -260,3 +337,118 @@ pub struct DeleteObservationParams {
     pub entity_name: String,
     pub observations: Vec<String>,
 }
+
+/// How `import_graph` combines the imported graph with the current one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportMode {
+    #[default]
+    Merge,
+    Replace,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct LineError {
+    pub line: usize,
+    pub message: String,
+}
+
+#[derive(Debug, Default, Serialize, PartialEq)]
+pub struct ImportResult {
+    #[serde(rename = "entitiesAdded")]
+    pub entities_added: usize,
+    #[serde(rename = "relationsAdded")]
+    pub relations_added: usize,
+    /// Imported entities that already existed, their observations were merged
+    #[serde(rename = "mergedEntities")]
+    pub merged_entities: Vec<String>,
+    pub errors: Vec<LineError>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entity(name: &str, observations: &[&str]) -> Entity {
+        Entity {
+            name: name.to_string(),
+            entity_type: "person".to_string(),
+            observations: observations.iter().map(|o| o.to_string()).collect(),
+        }
+    }
+
+    fn relation(from: &str, to: &str) -> Relation {
+        Relation {
+            from: from.to_string(),
+            to: to.to_string(),
+            relation_type: "knows".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_export_import_round_trip() -> Result<()> {
+        let kg = KnowledgeGraph {
+            entities: vec![entity("alice", &["likes tea"]), entity("bob", &[])],
+            relations: vec![relation("alice", "bob")],
+        };
+        let jsonl = kg.to_jsonl()?;
+
+        let mut imported = KnowledgeGraph {
+            entities: vec![entity("carol", &[])],
+            relations: vec![],
+        };
+        let result = imported.import_jsonl(&jsonl, ImportMode::Replace)?;
+        assert_eq!((result.entities_added, result.relations_added), (2, 1));
+        assert_eq!(imported, kg);
+        assert_eq!(imported.to_jsonl()?, jsonl);
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_conflicts() -> Result<()> {
+        let mut kg = KnowledgeGraph {
+            entities: vec![entity("alice", &["likes tea"])],
+            relations: vec![],
+        };
+        let import = KnowledgeGraph {
+            entities: vec![
+                entity("alice", &["likes tea", "plays chess"]),
+                entity("bob", &[]),
+            ],
+            relations: vec![relation("alice", "bob")],
+        }
+        .to_jsonl()?;
+
+        let result = kg.import_jsonl(&import, ImportMode::Merge)?;
+        assert_eq!(
+            result,
+            ImportResult {
+                entities_added: 1,
+                relations_added: 1,
+                merged_entities: vec!["alice".to_string()],
+                errors: vec![],
+            }
+        );
+        assert_eq!(
+            kg.entities[0],
+            entity("alice", &["likes tea", "plays chess"])
+        );
+
+        // Duplicate names within the import reject it as a whole
+        let before = kg.clone();
+        let duplicated = format!(
+            "{}\n{}\nnot json\n{{\"type\":\"edge\"}}\n",
+            r#"{"type":"entity","name":"carol","entityType":"person","observations":[]}"#,
+            r#"{"type":"entity","name":"carol","entityType":"robot","observations":[]}"#,
+        );
+        let result = kg.import_jsonl(&duplicated, ImportMode::Replace)?;
+        let lines: Vec<_> = result.errors.iter().map(|e| e.line).collect();
+        assert_eq!(lines, vec![2, 3, 4]);
+        assert_eq!(
+            result.errors[0].message,
+            "duplicate entity `carol`, first defined on line 1"
+        );
+        assert_eq!(kg, before);
+        Ok(())
+    }
+}
@@ -1,12 +1,7 @@
 use std::time::Duration;
 
 use anyhow::Result;
-use async_mcp::{
-    protocol::RequestOptions,
-    transport::{
-        ClientInMemoryTransport, ClientSseTransportBuilder, ClientStdioTransport, Transport,
-    },
-};
+use async_mcp::prelude::*;
 use clap::{Parser, ValueEnum};
 use pingpong::inmemory_server;
 use serde_json::json;
@@ -36,83 +31,39 @@ async fn main() -> Result<()> {
 
     let cli = Cli::parse();
 
-    let response = match cli.transport {
+    let transport: BoxedTransport = match cli.transport {
         TransportType::Stdio => {
             // Build the server first
             // cargo build --bin pingpong_server
-            let transport = ClientStdioTransport::new("./target/debug/pingpong", &[], None)?;
-            transport.open().await?;
-            // Create and start client
-            let client = async_mcp::client::ClientBuilder::new(transport.clone()).build();
-            let client_clone = client.clone();
-            let _client_handle = tokio::spawn(async move { client_clone.start().await });
-
-            tokio::time::sleep(Duration::from_millis(100)).await;
-            // Make a request
-            client
-                .request(
-                    "tools/call",
-                    Some(json!({"name": "ping", "arguments": {}})),
-                    RequestOptions::default().timeout(Duration::from_secs(5)),
-                )
-                .await?
-        }
-        TransportType::Sse => {
-            let transport =
-                ClientSseTransportBuilder::new("http://localhost:3004".to_string()).build();
-            transport.open().await?;
-            // Create and start client
-            let client = async_mcp::client::ClientBuilder::new(transport.clone()).build();
-            let client_clone = client.clone();
-            let _client_handle = tokio::spawn(async move { client_clone.start().await });
-
-            // Make a request
-            client
-                .request(
-                    "tools/call",
-                    Some(json!({"name": "ping", "arguments": {}})),
-                    RequestOptions::default().timeout(Duration::from_secs(5)),
-                )
-                .await?
+            ClientStdioTransport::new("./target/debug/pingpong", &[], None)?.into()
         }
+        TransportType::Sse => ClientSseTransport::new("http://localhost:3004".to_string()).into(),
         TransportType::InMemory => {
-            let client_transport =
-                ClientInMemoryTransport::new(|t| tokio::spawn(inmemory_server(t)));
-            client_transport.open().await?;
-            let client = async_mcp::client::ClientBuilder::new(client_transport.clone()).build();
-            let client_clone = client.clone();
-            let _client_handle = tokio::spawn(async move { client_clone.start().await });
-
-            // Make a request
-            client
-                .request(
-                    "tools/call",
-                    Some(json!({"name": "ping", "arguments": {}})),
-                    RequestOptions::default().timeout(Duration::from_secs(5)),
-                )
-                .await?
-        }
-        TransportType::Ws => {
-            let transport = async_mcp::transport::ClientWsTransportBuilder::new(
-                "ws://localhost:3004/ws".to_string(),
-            )
-            .build();
-            transport.open().await?;
-            // Create and start client
-            let client = async_mcp::client::ClientBuilder::new(transport.clone()).build();
-            let client_clone = client.clone();
-            let _client_handle = tokio::spawn(async move { client_clone.start().await });
-
-            // Make a request
-            client
-                .request(
-                    "tools/call",
-                    Some(json!({"name": "ping", "arguments": {}})),
-                    RequestOptions::default().timeout(Duration::from_secs(5)),
-                )
-                .await?
+            ClientInMemoryTransport::new(|t| tokio::spawn(inmemory_server(t))).into()
         }
+        TransportType::Ws => ClientWsTransportBuilder::new("ws://localhost:3004/ws".to_string())
+            .build()
+            .into(),
     };
+    transport.open().await?;
+
+    // Create and start client
+    let client = ClientBuilder::new(transport.clone()).build();
+    let client_clone = client.clone();
+    let _client_handle = tokio::spawn(async move { client_clone.start().await });
+
+    // Give the spawned server process time to start
+    if cli.transport == TransportType::Stdio {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    // Make a request
+    let response = client
+        .request(
+            "tools/call",
+            Some(json!({"name": "ping", "arguments": {}})),
+            RequestOptions::default().timeout(Duration::from_secs(5)),
+        )
+        .await?;
     info!("response: {response}");
     Ok(())
 }
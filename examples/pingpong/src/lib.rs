@@ -1,4 +1,4 @@
-use async_mcp::transport::ServerInMemoryTransport;
+use async_mcp::prelude::*;
 use server::build_server;
 
 pub mod server;
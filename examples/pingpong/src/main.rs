@@ -1,5 +1,5 @@
 use anyhow::Result;
-use async_mcp::{run_http_server, transport::ServerStdioTransport};
+use async_mcp::prelude::*;
 use clap::{Parser, ValueEnum};
 use pingpong::server::build_server;
 
@@ -29,7 +29,7 @@ async fn main() -> Result<()> {
 
     match cli.transport {
         TransportType::Stdio => {
-            let server = build_server(ServerStdioTransport);
+            let server = build_server(ServerStdioTransport::default());
             server
                 .listen()
                 .await
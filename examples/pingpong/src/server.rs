@@ -1,10 +1,5 @@
 use anyhow::Result;
-use async_mcp::server::Server;
-use async_mcp::transport::Transport;
-use async_mcp::types::{
-    CallToolRequest, CallToolResponse, ListRequest, ResourcesListResponse, ServerCapabilities,
-    ToolResponseContent, ToolsListResponse,
-};
+use async_mcp::prelude::*;
 use serde_json::json;
 
 pub fn build_server<T: Transport>(t: T) -> Server<T> {
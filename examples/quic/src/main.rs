@@ -0,0 +1,117 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_mcp::prelude::*;
+use async_mcp::transport::{
+    self_signed_certificate, ClientQuicTransport, QuicServer, ServerQuicTransport,
+};
+use tokio::sync::mpsc;
+
+/// Runs an MCP server over QUIC and a client calling its `echo` tool, then ends the
+/// session from the server side and calls it again on a new stream
+#[tokio::main]
+async fn main() -> Result<()> {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .init();
+
+    let (cert, key) = self_signed_certificate(&["localhost"])?;
+    let quic = Arc::new(QuicServer::bind(
+        "127.0.0.1:0".parse()?,
+        cert.as_bytes(),
+        key.as_bytes(),
+    )?);
+    let addr = quic.local_addr()?;
+    println!("QUIC MCP server listening on {}", addr);
+    let (sessions_tx, mut sessions) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        while let Some(transport) = quic.accept().await {
+            let _ = sessions_tx.send(transport.clone());
+            tokio::spawn(async move { echo_server(transport).listen().await });
+        }
+    });
+
+    // The self-signed certificate has to be trusted explicitly
+    let transport = ClientQuicTransport::builder(addr, "localhost")
+        .with_root_certificate(cert.as_bytes())
+        .build();
+
+    let session = connect(&transport).await?;
+    println!("{}", session.call("hello").await?);
+
+    // The server ends the session, the client's stream is lost
+    sessions.recv().await.unwrap().close().await?;
+    session.ended.await??;
+    println!("Stream lost, reopening");
+
+    // open starts a new stream, reconnecting first if the connection is gone too
+    let session = connect(&transport).await?;
+    println!("{}", session.call("hello again").await?);
+
+    transport.close().await?;
+    Ok(())
+}
+
+fn echo_server(transport: ServerQuicTransport) -> Server<ServerQuicTransport> {
+    let mut builder = Server::builder(transport).capabilities(ServerCapabilities {
+        tools: Some(serde_json::json!({})),
+        ..Default::default()
+    });
+    builder.register_tool(
+        ToolBuilder::new("echo")
+            .description("Echoes its text argument")
+            .build(),
+        |req| {
+            Box::pin(async move {
+                let text = req
+                    .arguments
+                    .and_then(|args| args.get("text").and_then(|v| v.as_str()).map(String::from))
+                    .unwrap_or_default();
+                Ok(CallToolResponse::text(text))
+            })
+        },
+    );
+    builder.build()
+}
+
+struct Session {
+    client: Client<ClientQuicTransport>,
+    ended: tokio::task::JoinHandle<Result<()>>,
+}
+
+impl Session {
+    async fn call(&self, text: &str) -> Result<String> {
+        let response: CallToolResponse = self
+            .client
+            .request_typed(
+                "tools/call",
+                CallToolRequest {
+                    name: "echo".to_string(),
+                    arguments: Some([("text".to_string(), text.into())].into()),
+                    meta: None,
+                },
+                RequestOptions::default(),
+            )
+            .await?;
+        Ok(response
+            .content
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join("\n"))
+    }
+}
+
+async fn connect(transport: &ClientQuicTransport) -> Result<Session> {
+    transport.open().await?;
+    let client = ClientBuilder::new(transport.clone()).build();
+    let started = client.clone();
+    let ended = tokio::spawn(async move { started.start().await });
+    client
+        .initialize(Implementation {
+            name: "quic-example".to_string(),
+            version: "0.1.0".to_string(),
+        })
+        .await?;
+    Ok(Session { client, ended })
+}
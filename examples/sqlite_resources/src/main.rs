@@ -0,0 +1,59 @@
+use anyhow::Result;
+use async_mcp::prelude::*;
+use async_mcp::sql::SqlResourceProvider;
+use rusqlite::Connection;
+
+/// Serves the rows of a SQLite database as `sqlite://{table}/{id}` resources
+/// pass a database path, otherwise an in-memory demo database is used
+#[tokio::main]
+async fn main() -> Result<()> {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::DEBUG)
+        // needs to be stderr due to stdio transport
+        .with_writer(std::io::stderr)
+        .init();
+
+    let conn = match std::env::args().nth(1) {
+        Some(path) => Connection::open(path)?,
+        None => demo_database()?,
+    };
+    let provider = SqlResourceProvider::new(conn)
+        .table("books", "id")?
+        .table("authors", "id")?;
+
+    let mut server = Server::builder(ServerStdioTransport::default())
+        .capabilities(ServerCapabilities {
+            resources: Some(ResourceCapabilities::default()),
+            ..Default::default()
+        })
+        // Also list the rows in resources/list, not only the templates
+        .list_template_resources(true)
+        .list_page_size(50);
+    provider.register(&mut server);
+
+    server
+        .build()
+        .listen()
+        .await
+        .map_err(|e| anyhow::anyhow!("Server error: {}", e))?;
+    Ok(())
+}
+
+fn demo_database() -> Result<Connection> {
+    let conn = Connection::open_in_memory()?;
+    conn.execute_batch(
+        "CREATE TABLE authors (id INTEGER PRIMARY KEY, name TEXT NOT NULL);
+         CREATE TABLE books (
+             id INTEGER PRIMARY KEY,
+             title TEXT NOT NULL,
+             author_id INTEGER REFERENCES authors(id),
+             published INTEGER
+         );
+         INSERT INTO authors VALUES (1, 'Ursula K. Le Guin'), (2, 'Stanisław Lem');
+         INSERT INTO books VALUES
+             (1, 'The Dispossessed', 1, 1974),
+             (2, 'The Left Hand of Darkness', 1, 1969),
+             (3, 'Solaris', 2, 1961);",
+    )?;
+    Ok(conn)
+}
@@ -0,0 +1,14 @@
+#!/bin/bash
+# Checks that the default build keeps OpenSSL and native-tls out of the dependency tree
+# and that the SSE and WS client tests pass with rustls only
+set -e
+
+for crate in openssl-sys native-tls; do
+  if cargo tree -p async-mcp -e normal -i "$crate" >/dev/null 2>&1; then
+    echo "$crate is in the dependency tree:"
+    cargo tree -p async-mcp -e normal -i "$crate"
+    exit 1
+  fi
+done
+
+cargo test -p async-mcp --lib -- transport::sse_transport transport::ws_transport
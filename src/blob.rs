@@ -0,0 +1,243 @@
+//! Content-addressable storage for large binary payloads
+//! tools store artifacts here and return a `blob://` resource link instead of inlining them,
+//! clients fetch the bytes on demand through `resources/read`
+use crate::types::{
+    BlobResourceContents, ReadResourceResponse, ResourceContent, ResourceContents,
+    ToolResponseContent,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use base64::Engine;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use url::Url;
+
+/// URI scheme of blobs served by the server
+pub const BLOB_SCHEME: &str = "blob";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Blob {
+    pub bytes: Vec<u8>,
+    pub mime_type: String,
+}
+
+#[async_trait]
+pub trait BlobStore: Send + Sync + 'static {
+    /// Store the bytes and return the `blob://` URI referencing them
+    async fn put(&self, bytes: Vec<u8>, mime_type: &str) -> Result<Url>;
+
+    /// Fetch a blob, `None` if unknown or expired
+    async fn get(&self, uri: &Url) -> Result<Option<Blob>>;
+
+    /// Store the bytes and return a resource link to use in a tool response
+    async fn store_blob(&self, bytes: Vec<u8>, mime_type: &str) -> Result<ToolResponseContent> {
+        let uri = self.put(bytes, mime_type).await?;
+        Ok(ToolResponseContent::Resource {
+            resource: ResourceContents {
+                uri,
+                mime_type: Some(mime_type.to_string()),
+            },
+        })
+    }
+}
+
+/// Blob store backed by a local directory
+/// entries expire after `ttl` and are cleaned up lazily on access
+pub struct LocalBlobStore {
+    dir: PathBuf,
+    ttl: Duration,
+    entries: Mutex<HashMap<String, (String, Instant)>>,
+}
+
+impl LocalBlobStore {
+    /// Store blobs in a fresh directory under the system temp dir
+    pub fn new(ttl: Duration) -> Self {
+        let dir = std::env::temp_dir().join(format!("async-mcp-blobs-{}", uuid::Uuid::new_v4()));
+        Self::with_dir(dir, ttl)
+    }
+
+    pub fn with_dir(dir: impl Into<PathBuf>, ttl: Duration) -> Self {
+        Self {
+            dir: dir.into(),
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Remove all expired blobs
+    pub async fn purge_expired(&self) -> Result<()> {
+        let expired: Vec<String> = {
+            let mut entries = self
+                .entries
+                .lock()
+                .map_err(|_| anyhow::anyhow!("Lock poisoned"))?;
+            let expired = entries
+                .iter()
+                .filter(|(_, (_, created))| created.elapsed() >= self.ttl)
+                .map(|(key, _)| key.clone())
+                .collect::<Vec<_>>();
+            for key in &expired {
+                entries.remove(key);
+            }
+            expired
+        };
+        for key in expired {
+            let _ = tokio::fs::remove_file(self.dir.join(key)).await;
+        }
+        Ok(())
+    }
+
+    fn key(uri: &Url) -> Option<&str> {
+        (uri.scheme() == BLOB_SCHEME)
+            .then(|| uri.host_str())
+            .flatten()
+    }
+}
+
+#[async_trait]
+impl BlobStore for LocalBlobStore {
+    async fn put(&self, bytes: Vec<u8>, mime_type: &str) -> Result<Url> {
+        self.purge_expired().await?;
+        let key = format!("{:x}", Sha256::digest(&bytes));
+        tokio::fs::create_dir_all(&self.dir).await?;
+        tokio::fs::write(self.dir.join(&key), &bytes).await?;
+        self.entries
+            .lock()
+            .map_err(|_| anyhow::anyhow!("Lock poisoned"))?
+            .insert(key.clone(), (mime_type.to_string(), Instant::now()));
+        Ok(Url::parse(&format!("{BLOB_SCHEME}://{key}"))?)
+    }
+
+    async fn get(&self, uri: &Url) -> Result<Option<Blob>> {
+        self.purge_expired().await?;
+        let Some(key) = Self::key(uri) else {
+            return Ok(None);
+        };
+        let mime_type = match self
+            .entries
+            .lock()
+            .map_err(|_| anyhow::anyhow!("Lock poisoned"))?
+            .get(key)
+        {
+            Some((mime_type, _)) => mime_type.clone(),
+            None => return Ok(None),
+        };
+        let bytes = tokio::fs::read(self.dir.join(key)).await?;
+        Ok(Some(Blob { bytes, mime_type }))
+    }
+}
+
+/// `resources/read` backed by a blob store
+pub(crate) async fn read_blob(store: &dyn BlobStore, uri: Url) -> Result<ReadResourceResponse> {
+    let blob = store
+        .get(&uri)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("Resource not found: {}", uri))?;
+    Ok(ReadResourceResponse {
+        contents: vec![ResourceContent::Blob(BlobResourceContents {
+            uri,
+            mime_type: Some(blob.mime_type),
+            blob: base64::engine::general_purpose::STANDARD.encode(blob.bytes),
+        })],
+        meta: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::ClientBuilder;
+    use crate::protocol::RequestOptions;
+    use crate::server::Server;
+    use crate::transport::{ClientInMemoryTransport, ServerInMemoryTransport, Transport};
+    use crate::types::{CallToolResponse, Tool};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_blob_roundtrip_and_expiry() -> Result<()> {
+        let artifact: Vec<u8> = (0..5 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+        let expected = artifact.clone();
+
+        let transport = ClientInMemoryTransport::new(move |t: ServerInMemoryTransport| {
+            let artifact = artifact.clone();
+            tokio::spawn(async move {
+                let store = Arc::new(LocalBlobStore::new(Duration::from_millis(300)));
+                let tool_store = store.clone();
+                let mut builder = Server::builder(t).blob_store(store);
+                builder.register_tool(
+                    Tool {
+                        name: "render".to_string(),
+                        description: None,
+                        input_schema: serde_json::json!({"type": "object"}),
+                        output_schema: None,
+                    },
+                    move |_| {
+                        let store = tool_store.clone();
+                        let artifact = artifact.clone();
+                        Box::pin(async move {
+                            let content = store.store_blob(artifact, "application/pdf").await?;
+                            Ok(CallToolResponse {
+                                content: vec![content],
+                                is_error: None,
+                                meta: None,
+                            })
+                        })
+                    },
+                );
+                builder.build().listen().await.unwrap();
+            })
+        });
+        transport.open().await?;
+        let client = ClientBuilder::new(transport.clone()).build();
+        let client_clone = client.clone();
+        tokio::spawn(async move { client_clone.start().await });
+
+        let response = client
+            .request(
+                "tools/call",
+                Some(serde_json::json!({"name": "render"})),
+                RequestOptions::default(),
+            )
+            .await?;
+        assert!(serde_json::to_string(&response)?.len() < 1024);
+        let response: CallToolResponse = serde_json::from_value(response)?;
+        let uri = match &response.content[0] {
+            ToolResponseContent::Resource { resource } => resource.uri.clone(),
+            other => panic!("Expected resource link, got {:?}", other),
+        };
+        assert_eq!(uri.scheme(), BLOB_SCHEME);
+
+        let read = client
+            .request(
+                "resources/read",
+                Some(serde_json::json!({ "uri": uri })),
+                RequestOptions::default(),
+            )
+            .await?;
+        let read: ReadResourceResponse = serde_json::from_value(read)?;
+        match &read.contents[0] {
+            ResourceContent::Blob(blob) => {
+                let bytes = base64::engine::general_purpose::STANDARD.decode(&blob.blob)?;
+                assert_eq!(bytes, expected);
+                assert_eq!(blob.mime_type.as_deref(), Some("application/pdf"));
+            }
+            other => panic!("Expected blob contents, got {:?}", other),
+        }
+
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        let expired = client
+            .request(
+                "resources/read",
+                Some(serde_json::json!({ "uri": uri })),
+                RequestOptions::default(),
+            )
+            .await;
+        assert!(expired.is_err());
+
+        transport.close().await?;
+        Ok(())
+    }
+}
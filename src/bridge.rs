@@ -0,0 +1,424 @@
+//! Answer `sampling/createMessage` with an LLM provider, register the handlers with
+//! [`ClientBuilder::sampling_handler`](crate::client::ClientBuilder::sampling_handler).
+//! Only text is forwarded, requests with images are rejected with `InvalidParams`
+use crate::transport::JsonRpcError;
+use crate::types::{
+    CreateMessageRequest, CreateMessageResult, ErrorCode, Role, SamplingContent, StopReason,
+};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+pub const OPENAI_BASE_URL: &str = "https://api.openai.com/v1";
+pub const OLLAMA_BASE_URL: &str = "http://localhost:11434";
+
+type SamplingFuture = Pin<Box<dyn Future<Output = Result<CreateMessageResult>> + Send>>;
+
+/// POSTs a JSON body and returns the JSON response, implemented by `reqwest::Client`
+#[async_trait]
+pub trait JsonHttp: Send + Sync + 'static {
+    async fn post_json(&self, url: &str, bearer: Option<&str>, body: Value) -> Result<Value>;
+}
+
+#[async_trait]
+impl JsonHttp for reqwest::Client {
+    async fn post_json(&self, url: &str, bearer: Option<&str>, body: Value) -> Result<Value> {
+        let mut request = self.post(url).json(&body);
+        if let Some(token) = bearer {
+            request = request.bearer_auth(token);
+        }
+        Ok(request.send().await?.error_for_status()?.json().await?)
+    }
+}
+
+/// Where [`openai_sampling_handler`] sends chat completions
+#[derive(Debug, Clone)]
+pub struct OpenAiConfig {
+    pub base_url: String,
+    pub api_key: Option<String>,
+    /// Models the request's hints choose from, the first when no hint matches
+    pub models: Vec<String>,
+}
+
+impl OpenAiConfig {
+    pub fn new(api_key: impl Into<String>, model: impl Into<String>) -> Self {
+        Self {
+            base_url: OPENAI_BASE_URL.to_string(),
+            api_key: Some(api_key.into()),
+            models: vec![model.into()],
+        }
+    }
+
+    /// An OpenAI-compatible API, e.g. a local proxy
+    pub fn base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = base_url.into();
+        self
+    }
+
+    /// Another model a hint may pick
+    pub fn model(mut self, model: impl Into<String>) -> Self {
+        self.models.push(model.into());
+        self
+    }
+}
+
+/// Where [`ollama_sampling_handler`] sends chats
+#[derive(Debug, Clone)]
+pub struct OllamaConfig {
+    pub base_url: String,
+    /// Models the request's hints choose from, the first when no hint matches
+    pub models: Vec<String>,
+}
+
+impl OllamaConfig {
+    pub fn new(model: impl Into<String>) -> Self {
+        Self {
+            base_url: OLLAMA_BASE_URL.to_string(),
+            models: vec![model.into()],
+        }
+    }
+
+    pub fn base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = base_url.into();
+        self
+    }
+
+    /// Another model a hint may pick
+    pub fn model(mut self, model: impl Into<String>) -> Self {
+        self.models.push(model.into());
+        self
+    }
+}
+
+/// Forward sampling requests to OpenAI's chat completions
+pub fn openai_sampling_handler(
+    config: OpenAiConfig,
+) -> impl Fn(CreateMessageRequest) -> SamplingFuture + Send + Sync + 'static {
+    openai_sampling_handler_with(config, Arc::new(reqwest::Client::new()))
+}
+
+/// Like [`openai_sampling_handler`] over `http`
+pub fn openai_sampling_handler_with(
+    config: OpenAiConfig,
+    http: Arc<dyn JsonHttp>,
+) -> impl Fn(CreateMessageRequest) -> SamplingFuture + Send + Sync + 'static {
+    let config = Arc::new(config);
+    move |request| {
+        let config = config.clone();
+        let http = http.clone();
+        Box::pin(async move {
+            let mut body = json!({
+                "model": pick_model(&config.models, &request),
+                "messages": chat_messages(&request, "OpenAI")?,
+                "max_tokens": request.max_tokens,
+            });
+            if let Some(temperature) = request.temperature {
+                body["temperature"] = json!(temperature);
+            }
+            if let Some(stop) = &request.stop_sequences {
+                body["stop"] = json!(stop);
+            }
+            let url = format!("{}/chat/completions", config.base_url.trim_end_matches('/'));
+            let response = http
+                .post_json(&url, config.api_key.as_deref(), body)
+                .await?;
+            let choice = &response["choices"][0];
+            Ok(CreateMessageResult {
+                role: Role::Assistant,
+                content: SamplingContent::Text {
+                    text: choice["message"]["content"]
+                        .as_str()
+                        .context("OpenAI response without message content")?
+                        .to_string(),
+                },
+                model: response["model"].as_str().unwrap_or_default().to_string(),
+                stop_reason: choice["finish_reason"].as_str().map(stop_reason),
+            })
+        })
+    }
+}
+
+/// Forward sampling requests to Ollama's `/api/chat`
+pub fn ollama_sampling_handler(
+    config: OllamaConfig,
+) -> impl Fn(CreateMessageRequest) -> SamplingFuture + Send + Sync + 'static {
+    ollama_sampling_handler_with(config, Arc::new(reqwest::Client::new()))
+}
+
+/// Like [`ollama_sampling_handler`] over `http`
+pub fn ollama_sampling_handler_with(
+    config: OllamaConfig,
+    http: Arc<dyn JsonHttp>,
+) -> impl Fn(CreateMessageRequest) -> SamplingFuture + Send + Sync + 'static {
+    let config = Arc::new(config);
+    move |request| {
+        let config = config.clone();
+        let http = http.clone();
+        Box::pin(async move {
+            let mut options = json!({ "num_predict": request.max_tokens });
+            if let Some(temperature) = request.temperature {
+                options["temperature"] = json!(temperature);
+            }
+            if let Some(stop) = &request.stop_sequences {
+                options["stop"] = json!(stop);
+            }
+            let body = json!({
+                "model": pick_model(&config.models, &request),
+                "messages": chat_messages(&request, "Ollama")?,
+                "stream": false,
+                "options": options,
+            });
+            let url = format!("{}/api/chat", config.base_url.trim_end_matches('/'));
+            let response = http.post_json(&url, None, body).await?;
+            Ok(CreateMessageResult {
+                role: Role::Assistant,
+                content: SamplingContent::Text {
+                    text: response["message"]["content"]
+                        .as_str()
+                        .context("Ollama response without message content")?
+                        .to_string(),
+                },
+                model: response["model"].as_str().unwrap_or_default().to_string(),
+                stop_reason: response["done_reason"].as_str().map(stop_reason),
+            })
+        })
+    }
+}
+
+/// The first hint that is part of a model's name picks it, hints are in order of preference
+fn pick_model<'a>(models: &'a [String], request: &CreateMessageRequest) -> &'a str {
+    let hints = request
+        .model_preferences
+        .iter()
+        .flat_map(|preferences| preferences.hints.iter().flatten())
+        .filter_map(|hint| hint.name.as_deref());
+    for hint in hints {
+        if let Some(model) = models.iter().find(|model| model.contains(hint)) {
+            return model;
+        }
+    }
+    models.first().map(String::as_str).unwrap_or_default()
+}
+
+/// Chat messages of both APIs, the system prompt first
+fn chat_messages(request: &CreateMessageRequest, provider: &str) -> Result<Vec<Value>> {
+    let mut messages = Vec::with_capacity(request.messages.len() + 1);
+    if let Some(system) = &request.system_prompt {
+        messages.push(json!({ "role": "system", "content": system }));
+    }
+    for message in &request.messages {
+        let SamplingContent::Text { text } = &message.content else {
+            return Err(JsonRpcError::new(
+                ErrorCode::InvalidParams,
+                format!("The {} sampling bridge only forwards text", provider),
+            )
+            .into());
+        };
+        messages.push(json!({ "role": message.role, "content": text }));
+    }
+    Ok(messages)
+}
+
+/// `stop` and `length` as both providers report them
+fn stop_reason(reason: &str) -> StopReason {
+    match reason {
+        "stop" => StopReason::EndTurn,
+        "length" => StopReason::MaxTokens,
+        other => StopReason::Other(other.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    // Records the requests and answers each with `response`
+    struct StubHttp {
+        response: Value,
+        requests: Mutex<Vec<(String, Option<String>, Value)>>,
+    }
+
+    impl StubHttp {
+        fn new(response: Value) -> Arc<Self> {
+            Arc::new(Self {
+                response,
+                requests: Mutex::new(Vec::new()),
+            })
+        }
+    }
+
+    #[async_trait]
+    impl JsonHttp for StubHttp {
+        async fn post_json(&self, url: &str, bearer: Option<&str>, body: Value) -> Result<Value> {
+            self.requests
+                .lock()
+                .unwrap()
+                .push((url.to_string(), bearer.map(str::to_string), body));
+            Ok(self.response.clone())
+        }
+    }
+
+    fn request() -> CreateMessageRequest {
+        CreateMessageRequest::builder()
+            .system_prompt("Be brief")
+            .user_message("Hi")
+            .assistant_message("Hello")
+            .user_message("Count to three")
+            .temperature(0.2)
+            .max_tokens(5)
+            .stop_sequence("4")
+            .model_hint("mini")
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_openai_bridge() -> Result<()> {
+        let http = StubHttp::new(json!({
+            "model": "gpt-4o-mini-2024-07-18",
+            "choices": [{"message": {"role": "assistant", "content": "1, 2"}, "finish_reason": "length"}]
+        }));
+        let handler = openai_sampling_handler_with(
+            OpenAiConfig::new("key", "gpt-4o")
+                .model("gpt-4o-mini")
+                .base_url("http://proxy/v1/"),
+            http.clone(),
+        );
+
+        let result = handler(request()).await?;
+        assert_eq!(result.role, Role::Assistant);
+        assert_eq!(
+            result.content,
+            SamplingContent::Text {
+                text: "1, 2".to_string()
+            }
+        );
+        assert_eq!(result.model, "gpt-4o-mini-2024-07-18");
+        assert_eq!(result.stop_reason, Some(StopReason::MaxTokens));
+
+        let (url, bearer, body) = http.requests.lock().unwrap().remove(0);
+        assert_eq!(url, "http://proxy/v1/chat/completions");
+        assert_eq!(bearer.as_deref(), Some("key"));
+        assert_eq!(
+            body,
+            json!({
+                "model": "gpt-4o-mini",
+                "messages": [
+                    {"role": "system", "content": "Be brief"},
+                    {"role": "user", "content": "Hi"},
+                    {"role": "assistant", "content": "Hello"},
+                    {"role": "user", "content": "Count to three"}
+                ],
+                "max_tokens": 5,
+                "temperature": 0.2,
+                "stop": ["4"]
+            })
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_ollama_bridge() -> Result<()> {
+        let http = StubHttp::new(json!({
+            "model": "llama3.2",
+            "message": {"role": "assistant", "content": "1, 2, 3"},
+            "done": true,
+            "done_reason": "stop"
+        }));
+        let handler = ollama_sampling_handler_with(OllamaConfig::new("llama3.2"), http.clone());
+
+        let result = handler(request()).await?;
+        assert_eq!(result.model, "llama3.2");
+        assert_eq!(result.stop_reason, Some(StopReason::EndTurn));
+
+        let (url, bearer, body) = http.requests.lock().unwrap().remove(0);
+        assert_eq!(url, "http://localhost:11434/api/chat");
+        assert_eq!(bearer, None);
+        // No configured model matches the hint
+        assert_eq!(body["model"], "llama3.2");
+        assert_eq!(body["stream"], false);
+        assert_eq!(
+            body["options"],
+            json!({"num_predict": 5, "temperature": 0.2, "stop": ["4"]})
+        );
+        assert_eq!(body["messages"].as_array().unwrap().len(), 4);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_images_rejected() {
+        let http = StubHttp::new(json!({}));
+        let handler =
+            openai_sampling_handler_with(OpenAiConfig::new("key", "gpt-4o"), http.clone());
+        let request = CreateMessageRequest::builder()
+            .message(
+                Role::User,
+                SamplingContent::Image {
+                    data: "aGk=".to_string(),
+                    mime_type: "image/png".to_string(),
+                },
+            )
+            .max_tokens(5)
+            .build()
+            .unwrap();
+
+        let err = handler(request).await.unwrap_err();
+        let err = err.downcast_ref::<JsonRpcError>().unwrap();
+        assert_eq!(err.code, ErrorCode::InvalidParams as i32);
+        assert!(http.requests.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_client_answers_sampling() -> Result<()> {
+        use crate::client::ClientBuilder;
+        use crate::registry::require_sampling;
+        use crate::server::Server;
+        use crate::transport::{ClientInMemoryTransport, ServerInMemoryTransport, Transport};
+        use crate::types::{CallToolResponse, Implementation, ToolBuilder};
+
+        let transport = ClientInMemoryTransport::new(|t: ServerInMemoryTransport| {
+            let mut builder = Server::builder(t);
+            builder.register_tool_with_context(ToolBuilder::new("ask").build(), |_, ctx| {
+                Box::pin(async move {
+                    let request = CreateMessageRequest::builder()
+                        .user_message("Hi")
+                        .max_tokens(5)
+                        .build()?;
+                    let result = require_sampling(&ctx)?.create_message(request).await?;
+                    Ok(CallToolResponse::text(result.content.to_string()))
+                })
+            });
+            tokio::spawn(async move { builder.build().listen().await.unwrap() })
+        });
+        transport.open().await?;
+        let http = StubHttp::new(json!({
+            "model": "gpt-4o",
+            "choices": [{"message": {"content": "Hello"}, "finish_reason": "stop"}]
+        }));
+        let client = ClientBuilder::new(transport.clone())
+            .sampling_handler(openai_sampling_handler_with(
+                OpenAiConfig::new("key", "gpt-4o"),
+                http,
+            ))
+            .build();
+        let client_clone = client.clone();
+        tokio::spawn(async move { client_clone.start().await });
+        client.initialize(Implementation::default()).await?;
+
+        let response: CallToolResponse = client
+            .request_typed(
+                "tools/call",
+                json!({"name": "ask"}),
+                crate::protocol::RequestOptions::default(),
+            )
+            .await?;
+        assert_eq!(serde_json::to_value(&response.content)?[0]["text"], "Hello");
+
+        transport.close().await?;
+        Ok(())
+    }
+}
@@ -1,46 +1,115 @@
 use crate::{
-    protocol::{Protocol, ProtocolBuilder, RequestOptions},
-    transport::Transport,
+    protocol::{
+        Protocol, ProtocolBuilder, RequestOptions, ResponseTiming, DEFAULT_REQUEST_TIMEOUT_MSEC,
+    },
+    transport::{BoxedTransport, JsonRpcError, JsonRpcMessage, JsonRpcResponse, Transport},
     types::{
-        ClientCapabilities, Implementation, InitializeRequest, InitializeResponse,
-        RootCapabilities, LATEST_PROTOCOL_VERSION,
+        ClientCapabilities, CreateMessageRequest, CreateMessageResult, Implementation,
+        InitializeRequest, InitializeResponse, ReadResourceRequest, ReadResourceResponse,
+        ResourceContent, RootCapabilities, LATEST_PROTOCOL_VERSION,
     },
+    validation::{BuildError, BuildIssue, IssueCode},
 };
 
 use anyhow::Result;
+use base64::Engine;
+use serde::{de::DeserializeOwned, Serialize};
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
 use tracing::debug;
+use url::Url;
+
+/// Chunks of a streamed read queued for a writer that is behind
+const READ_CHUNKS_BUFFERED: usize = 4;
 
 #[derive(Clone)]
 pub struct Client<T: Transport> {
     protocol: Protocol<T>,
+    connect_timeout: Option<Duration>,
+}
+
+/// Each timeout fails with its own [`ErrorCode`](crate::types::ErrorCode), so callers can tell
+/// a peer that can't be reached from a slow request or a dead connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeoutPolicy {
+    /// Limit on [`Client::open`], fails with `ConnectionTimeout`
+    pub connect_timeout: Option<Duration>,
+    /// Used when [`RequestOptions`] set no timeout, fails with `RequestTimeout`
+    pub default_request_timeout: Duration,
+    /// Disconnect when nothing is received for this long, pending requests fail with
+    /// `IdleTimeout`. Relies on the server sending keep-alives while the connection is idle
+    pub idle_disconnect: Option<Duration>,
+}
+
+impl Default for TimeoutPolicy {
+    fn default() -> Self {
+        Self {
+            connect_timeout: None,
+            default_request_timeout: Duration::from_millis(DEFAULT_REQUEST_TIMEOUT_MSEC),
+            idle_disconnect: None,
+        }
+    }
 }
 
+impl TimeoutPolicy {
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    pub fn default_request_timeout(mut self, timeout: Duration) -> Self {
+        self.default_request_timeout = timeout;
+        self
+    }
+
+    pub fn idle_disconnect(mut self, after: Duration) -> Self {
+        self.idle_disconnect = Some(after);
+        self
+    }
+}
+
+/// Client over a transport chosen at runtime
+pub type DynClient = Client<BoxedTransport>;
+
 impl<T: Transport> Client<T> {
     pub fn builder(transport: T) -> ClientBuilder<T> {
         ClientBuilder::new(transport)
     }
 
+    /// Open the transport, within the connect timeout of the [`TimeoutPolicy`] if any
+    pub async fn open(&self) -> Result<()> {
+        let open = self.protocol.transport().open();
+        match self.connect_timeout {
+            Some(limit) => tokio::time::timeout(limit, open)
+                .await
+                .map_err(|_| JsonRpcError::connection_timeout(limit))?,
+            None => open.await,
+        }
+    }
+
+    /// Advertises sampling and roots when there is a handler answering them
     pub async fn initialize(&self, client_info: Implementation) -> Result<InitializeResponse> {
+        let handlers = self.protocol.handlers();
         let request = InitializeRequest {
             protocol_version: LATEST_PROTOCOL_VERSION.to_string(),
             capabilities: ClientCapabilities {
                 experimental: Some(serde_json::json!({})),
-                sampling: Some(serde_json::json!({})),
-                roots: Some(RootCapabilities {
-                    list_changed: Some(false),
-                }),
+                sampling: handlers
+                    .has_request_handler("sampling/createMessage")
+                    .then(|| serde_json::json!({})),
+                roots: handlers
+                    .has_request_handler("roots/list")
+                    .then_some(RootCapabilities {
+                        list_changed: Some(false),
+                    }),
             },
             client_info,
         };
-        let response = self
-            .request(
-                "initialize",
-                Some(serde_json::to_value(request)?),
-                RequestOptions::default(),
-            )
+        let response: InitializeResponse = self
+            .request_typed("initialize", request, RequestOptions::default())
             .await?;
-        let response: InitializeResponse = serde_json::from_value(response)
-            .map_err(|e| anyhow::anyhow!("Failed to parse response: {}", e))?;
 
         if response.protocol_version != LATEST_PROTOCOL_VERSION {
             return Err(anyhow::anyhow!(
@@ -65,10 +134,147 @@ impl<T: Transport> Client<T> {
         params: Option<serde_json::Value>,
         options: RequestOptions,
     ) -> Result<serde_json::Value> {
-        let response = self.protocol.request(method, params, options).await?;
-        response
-            .result
-            .ok_or_else(|| anyhow::anyhow!("Request failed: {:?}", response.error))
+        self.protocol
+            .request(method, params, options)
+            .await?
+            .error_or_result()
+    }
+
+    /// Advanced: send a notification or response as is, for methods the typed API doesn't cover
+    /// requests are rejected, use [`Client::request_raw`] so the response finds its way back
+    pub async fn send_raw(&self, message: &JsonRpcMessage) -> Result<()> {
+        self.protocol.send_raw(message).await
+    }
+
+    /// Advanced: like [`Client::request`] but returns the response as received,
+    /// an error response is not turned into an `Err`
+    pub async fn request_raw(
+        &self,
+        method: &str,
+        params: Option<serde_json::Value>,
+        options: RequestOptions,
+    ) -> Result<JsonRpcResponse> {
+        self.protocol.request(method, params, options).await
+    }
+
+    /// Send the requests as one JSON-RPC batch, results are in request order
+    /// the outer error is a failure to send the batch, the inner ones are per request
+    pub async fn request_batch(
+        &self,
+        requests: Vec<(&str, Option<serde_json::Value>)>,
+        options: RequestOptions,
+    ) -> Result<Vec<Result<serde_json::Value>>> {
+        let requests = requests
+            .into_iter()
+            .map(|(method, params)| (method.to_string(), params))
+            .collect();
+        let responses = self.protocol.request_batch(requests, options).await?;
+        Ok(responses
+            .into_iter()
+            .map(|response| response?.error_or_result())
+            .collect())
+    }
+
+    /// Typed variant of [`Client::request`]
+    /// error responses are returned as a [`JsonRpcError`](crate::transport::JsonRpcError)
+    pub async fn request_typed<Req, Resp>(
+        &self,
+        method: &str,
+        req: Req,
+        options: RequestOptions,
+    ) -> Result<Resp>
+    where
+        Req: Serialize,
+        Resp: DeserializeOwned,
+    {
+        let params = serde_json::to_value(req)?;
+        let response = self.protocol.request(method, Some(params), options).await?;
+        response.result_as()
+    }
+
+    /// Like [`Client::request_typed`], also returning the server timing when reported
+    pub async fn request_typed_with_timing<Req, Resp>(
+        &self,
+        method: &str,
+        req: Req,
+        options: RequestOptions,
+    ) -> Result<(Resp, Option<ResponseTiming>)>
+    where
+        Req: Serialize,
+        Resp: DeserializeOwned,
+    {
+        let params = serde_json::to_value(req)?;
+        let response = self.protocol.request(method, Some(params), options).await?;
+        Ok((
+            response.result_as()?,
+            ResponseTiming::from_response(&response),
+        ))
+    }
+
+    /// Read `length` bytes of a resource starting at `offset`
+    /// the served range is reported in the response `_meta` as a `ResourceRangeResult`
+    pub async fn read_resource_range(
+        &self,
+        uri: Url,
+        offset: u64,
+        length: u64,
+    ) -> Result<ReadResourceResponse> {
+        self.request_typed(
+            "resources/read",
+            ReadResourceRequest::new(uri).range(offset, length),
+            RequestOptions::default(),
+        )
+        .await
+    }
+
+    /// Read a resource into `writer`, streamed in chunks when the server supports it for the
+    /// resource and inline otherwise. Returns the number of bytes written
+    /// `options` apply to the whole read, a large resource may need a longer timeout.
+    /// A slow writer holds up reading from the transport, other responses wait meanwhile
+    pub async fn read_resource_to<W: AsyncWrite + Unpin>(
+        &self,
+        uri: Url,
+        writer: &mut W,
+        options: RequestOptions,
+    ) -> Result<u64> {
+        let (tx, mut rx) = tokio::sync::mpsc::channel(READ_CHUNKS_BUFFERED);
+        let options = options.resource_chunks(tx);
+        let read = self.request_typed::<_, ReadResourceResponse>(
+            "resources/read",
+            ReadResourceRequest::new(uri).stream(),
+            options,
+        );
+        tokio::pin!(read);
+
+        let mut written = 0;
+        let response = loop {
+            tokio::select! {
+                Some(chunk) = rx.recv() => {
+                    let bytes = chunk.bytes()?;
+                    writer.write_all(&bytes).await?;
+                    written += bytes.len() as u64;
+                }
+                response = &mut read => break response?,
+            }
+        };
+        // Chunks are handled before the response, some may still be queued
+        while let Ok(chunk) = rx.try_recv() {
+            let bytes = chunk.bytes()?;
+            writer.write_all(&bytes).await?;
+            written += bytes.len() as u64;
+        }
+        for content in response.contents {
+            let bytes = match content {
+                ResourceContent::Text(text) => text.text.into_bytes(),
+                ResourceContent::Blob(blob) => {
+                    base64::engine::general_purpose::STANDARD.decode(blob.blob)?
+                }
+            };
+            writer.write_all(&bytes).await?;
+            written += bytes.len() as u64;
+        }
+        writer.flush().await?;
+        Ok(written)
     }
 
     pub async fn start(&self) -> Result<()> {
@@ -78,18 +284,500 @@ impl<T: Transport> Client<T> {
 
 pub struct ClientBuilder<T: Transport> {
     protocol: ProtocolBuilder<T>,
+    connect_timeout: Option<Duration>,
+    issues: Vec<BuildIssue>,
+    strict_validation: bool,
 }
 
 impl<T: Transport> ClientBuilder<T> {
     pub fn new(transport: T) -> Self {
         Self {
             protocol: ProtocolBuilder::new(transport),
+            connect_timeout: None,
+            issues: Vec::new(),
+            strict_validation: false,
         }
     }
 
-    pub fn build(self) -> Client<T> {
-        Client {
+    pub fn timeouts(mut self, policy: TimeoutPolicy) -> Self {
+        for (name, timeout) in [
+            ("connect_timeout", policy.connect_timeout),
+            (
+                "default_request_timeout",
+                Some(policy.default_request_timeout),
+            ),
+            ("idle_disconnect", policy.idle_disconnect),
+        ] {
+            if timeout == Some(Duration::ZERO) {
+                self.issues.push(BuildIssue::warning(
+                    IssueCode::ZeroTimeout,
+                    format!("{} is 0, every attempt times out", name),
+                    format!("set a positive {}, or leave it unset", name),
+                ));
+            }
+        }
+        self.connect_timeout = policy.connect_timeout;
+        self.protocol = self
+            .protocol
+            .default_request_timeout(policy.default_request_timeout);
+        if let Some(after) = policy.idle_disconnect {
+            self.protocol = self.protocol.idle_timeout(after);
+        }
+        self
+    }
+
+    /// Answer a request the server sends, e.g. `roots/list`
+    pub fn request_handler<Req, Resp>(
+        mut self,
+        method: &str,
+        handler: impl Fn(Req) -> Pin<Box<dyn Future<Output = Result<Resp>> + Send>>
+            + Send
+            + Sync
+            + 'static,
+    ) -> Self
+    where
+        Req: DeserializeOwned + Send + Sync + 'static,
+        Resp: Serialize + Send + Sync + 'static,
+    {
+        self.protocol = self.protocol.request_handler(method, handler);
+        self
+    }
+
+    /// Handle a notification the server sends, e.g. `notifications/resources/updated`
+    pub fn notification_handler<N>(
+        mut self,
+        method: &str,
+        handler: impl Fn(N) -> Pin<Box<dyn Future<Output = Result<()>> + Send>> + Send + Sync + 'static,
+    ) -> Self
+    where
+        N: DeserializeOwned + Send + Sync + 'static,
+    {
+        self.protocol = self.protocol.notification_handler(method, handler);
+        self
+    }
+
+    /// Handle a notification next to the handlers `method` already has, all of them run
+    pub fn add_notification_handler<N>(
+        mut self,
+        method: &str,
+        handler: impl Fn(N) -> Pin<Box<dyn Future<Output = Result<()>> + Send>> + Send + Sync + 'static,
+    ) -> Self
+    where
+        N: DeserializeOwned + Send + Sync + 'static,
+    {
+        self.protocol = self.protocol.add_notification_handler(method, handler);
+        self
+    }
+
+    /// Called with the error of a failing notification handler, the other handlers still run
+    pub fn on_notification_error(
+        mut self,
+        callback: impl Fn(&str, &anyhow::Error) + Send + Sync + 'static,
+    ) -> Self {
+        self.protocol = self.protocol.on_notification_error(callback);
+        self
+    }
+
+    /// Run the handlers of a notification concurrently instead of in registration order
+    pub fn concurrent_notification_handlers(mut self, enabled: bool) -> Self {
+        self.protocol = self.protocol.concurrent_notification_handlers(enabled);
+        self
+    }
+
+    /// Answer `sampling/createMessage`, e.g. with [`crate::bridge::openai_sampling_handler`]
+    pub fn sampling_handler(
+        self,
+        handler: impl Fn(
+                CreateMessageRequest,
+            ) -> Pin<Box<dyn Future<Output = Result<CreateMessageResult>> + Send>>
+            + Send
+            + Sync
+            + 'static,
+    ) -> Self {
+        self.request_handler("sampling/createMessage", handler)
+    }
+
+    /// Turn every configuration warning of [`try_build`](Self::try_build) into an error
+    pub fn strict_validation(mut self, strict: bool) -> Self {
+        self.strict_validation = strict;
+        self
+    }
+
+    /// Like [`build`](Self::build), failing with every configuration problem found
+    /// warnings are logged when there is no error
+    pub fn try_build(self) -> std::result::Result<Client<T>, BuildError> {
+        BuildError::check(self.issues, self.strict_validation)?;
+        Ok(Client {
             protocol: self.protocol.build(),
+            connect_timeout: self.connect_timeout,
+        })
+    }
+
+    /// # Panics
+    /// On the errors [`try_build`](Self::try_build) returns
+    pub fn build(self) -> Client<T> {
+        self.try_build().unwrap_or_else(|e| panic!("{}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::McpError;
+    use crate::server::Server;
+    use crate::transport::{
+        ClientInMemoryTransport, JsonRpcNotification, JsonRpcRequest, ServerInMemoryTransport,
+    };
+    use crate::types::ErrorCode;
+    use async_trait::async_trait;
+
+    // Scripted transport: opening hangs for `open_delay` and the peer never answers
+    #[derive(Clone)]
+    struct SilentTransport {
+        open_delay: Duration,
+    }
+
+    #[async_trait]
+    impl Transport for SilentTransport {
+        async fn send(&self, _message: &JsonRpcMessage) -> Result<()> {
+            Ok(())
         }
+        async fn receive(&self) -> Result<Option<JsonRpcMessage>> {
+            futures::future::pending().await
+        }
+        async fn open(&self) -> Result<()> {
+            tokio::time::sleep(self.open_delay).await;
+            Ok(())
+        }
+        async fn close(&self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn error_code(error: &anyhow::Error) -> i32 {
+        error.json_rpc_error().expect("JSON-RPC error").code
+    }
+
+    #[tokio::test]
+    async fn test_timeout_policy() -> Result<()> {
+        let silent = |open_delay: Duration, policy: TimeoutPolicy| {
+            ClientBuilder::new(SilentTransport { open_delay })
+                .timeouts(policy)
+                .build()
+        };
+
+        let client = silent(
+            Duration::from_secs(60),
+            TimeoutPolicy::default().connect_timeout(Duration::from_millis(20)),
+        );
+        let error = client.open().await.unwrap_err();
+        assert_eq!(error_code(&error), ErrorCode::ConnectionTimeout as i32);
+        assert!(error.is_retriable());
+
+        // The policy's default applies unless the request sets its own timeout
+        let client = silent(
+            Duration::ZERO,
+            TimeoutPolicy::default().default_request_timeout(Duration::from_millis(20)),
+        );
+        client.open().await?;
+        let started = std::time::Instant::now();
+        let error = client
+            .request("ping", None, RequestOptions::default())
+            .await
+            .unwrap_err();
+        assert_eq!(error_code(&error), ErrorCode::RequestTimeout as i32);
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        // Requests still in flight fail when the connection goes idle, and listening stops
+        let client = silent(
+            Duration::ZERO,
+            TimeoutPolicy::default().idle_disconnect(Duration::from_millis(50)),
+        );
+        let listen = tokio::spawn({
+            let client = client.clone();
+            async move { client.start().await }
+        });
+        let error = client
+            .request("ping", None, RequestOptions::default())
+            .await
+            .unwrap_err();
+        assert_eq!(error_code(&error), ErrorCode::IdleTimeout as i32);
+        let error = listen.await?.unwrap_err();
+        assert_eq!(error_code(&error), ErrorCode::IdleTimeout as i32);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_request_batch() -> Result<()> {
+        let transport = ClientInMemoryTransport::new(|t: ServerInMemoryTransport| {
+            tokio::spawn(async move { Server::builder(t).build().listen().await.unwrap() })
+        });
+        transport.open().await?;
+        let client = ClientBuilder::new(transport.clone()).build();
+        let client_clone = client.clone();
+        tokio::spawn(async move { client_clone.start().await });
+
+        let results = client
+            .request_batch(
+                vec![
+                    (
+                        "initialize",
+                        Some(serde_json::to_value(InitializeRequest::default())?),
+                    ),
+                    ("tools/list", Some(serde_json::json!({}))),
+                    ("missing", None),
+                ],
+                RequestOptions::default(),
+            )
+            .await?;
+        assert_eq!(results.len(), 3);
+        let init: InitializeResponse =
+            serde_json::from_value(results[0].as_ref().unwrap().clone())?;
+        assert_eq!(init.protocol_version, LATEST_PROTOCOL_VERSION);
+        assert!(results[1].as_ref().unwrap()["tools"].is_array());
+        assert!(results[2].is_err());
+
+        transport.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_initialize_advertises_handled_features() -> Result<()> {
+        for sampling in [false, true] {
+            let (server_tx, mut server_rx) = tokio::sync::mpsc::channel(1);
+            let transport = ClientInMemoryTransport::new(move |t: ServerInMemoryTransport| {
+                let server = Server::builder(t).build();
+                let _ = server_tx.try_send(server.clone());
+                tokio::spawn(async move { server.listen().await.unwrap() })
+            });
+            transport.open().await?;
+            let server = server_rx.recv().await.unwrap();
+            let mut builder = ClientBuilder::new(transport.clone());
+            if sampling {
+                builder = builder
+                    .sampling_handler(|_| Box::pin(async move { Err(anyhow::anyhow!("unused")) }));
+            }
+            let client = builder.build();
+            let client_clone = client.clone();
+            tokio::spawn(async move { client_clone.start().await });
+
+            client.initialize(Implementation::default()).await?;
+            let capabilities = server.get_client_capabilities().unwrap();
+            assert_eq!(capabilities.sampling.is_some(), sampling);
+            assert!(capabilities.roots.is_none());
+
+            drop(server);
+            transport.close().await?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_try_build_reports_zero_timeouts() {
+        use crate::validation::{IssueCode, Severity};
+
+        let policy = TimeoutPolicy::default()
+            .connect_timeout(Duration::ZERO)
+            .default_request_timeout(Duration::ZERO);
+        let builder = || {
+            ClientBuilder::new(SilentTransport {
+                open_delay: Duration::ZERO,
+            })
+        };
+        assert!(builder().timeouts(policy).try_build().is_ok());
+
+        let error = builder()
+            .timeouts(policy)
+            .strict_validation(true)
+            .try_build()
+            .err()
+            .unwrap();
+        assert_eq!(error.codes(), vec![IssueCode::ZeroTimeout; 2]);
+        assert!(error
+            .issues
+            .iter()
+            .all(|issue| issue.severity == Severity::Error));
+        assert!(error.issues[1].message.contains("default_request_timeout"));
+    }
+
+    #[tokio::test]
+    async fn test_raw_messages() -> Result<()> {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let transport = ClientInMemoryTransport::new(move |t: ServerInMemoryTransport| {
+            let tx = tx.clone();
+            let server = Server::builder(t)
+                .notification_handler("vendor/heartbeat", move |params: serde_json::Value| {
+                    let _ = tx.send(params);
+                    Box::pin(async { Ok(()) })
+                })
+                .build();
+            tokio::spawn(async move { server.listen().await.unwrap() })
+        });
+        transport.open().await?;
+        let client = ClientBuilder::new(transport.clone()).build();
+        let client_clone = client.clone();
+        tokio::spawn(async move { client_clone.start().await });
+
+        client
+            .send_raw(&JsonRpcMessage::Notification(JsonRpcNotification {
+                method: "vendor/heartbeat".to_string(),
+                params: Some(serde_json::json!({"seq": 1})),
+                ..Default::default()
+            }))
+            .await?;
+        assert_eq!(rx.recv().await, Some(serde_json::json!({"seq": 1})));
+
+        // The error response comes back as is
+        let response = client
+            .request_raw("vendor/missing", None, RequestOptions::default())
+            .await?;
+        assert_eq!(
+            response.error.map(|e| e.code),
+            Some(ErrorCode::MethodNotFound as i32)
+        );
+
+        let request = JsonRpcMessage::Request(JsonRpcRequest {
+            id: 7,
+            method: "ping".to_string(),
+            ..Default::default()
+        });
+        assert!(client.send_raw(&request).await.is_err());
+        assert!(client
+            .send_raw(&JsonRpcMessage::Batch(vec![request]))
+            .await
+            .is_err());
+
+        transport.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_request_batch_out_of_order() -> Result<()> {
+        // Answers every request of a batch with its method name, in reverse order
+        let transport = ClientInMemoryTransport::new(|t: ServerInMemoryTransport| {
+            tokio::spawn(async move {
+                while let Ok(Some(JsonRpcMessage::Batch(messages))) = t.receive().await {
+                    let responses = messages
+                        .into_iter()
+                        .rev()
+                        .filter_map(|message| match message {
+                            JsonRpcMessage::Request(request) => {
+                                Some(JsonRpcMessage::Response(JsonRpcResponse {
+                                    id: request.id,
+                                    result: Some(serde_json::json!(request.method)),
+                                    ..Default::default()
+                                }))
+                            }
+                            _ => None,
+                        })
+                        .collect();
+                    t.send(&JsonRpcMessage::Batch(responses)).await.unwrap();
+                }
+            })
+        });
+        transport.open().await?;
+        let client = ClientBuilder::new(transport.clone()).build();
+        let client_clone = client.clone();
+        tokio::spawn(async move { client_clone.start().await });
+
+        let results = client
+            .request_batch(
+                vec![("a", None), ("b", None), ("c", None)],
+                RequestOptions::default(),
+            )
+            .await?;
+        let results: Vec<_> = results.into_iter().collect::<Result<_>>()?;
+        assert_eq!(results, vec!["a", "b", "c"]);
+
+        transport.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_resource_to_slow_writer() -> Result<()> {
+        use crate::registry::{ResourceStream, RESOURCE_CHUNK_BYTES};
+        use crate::types::Resource;
+        use std::sync::atomic::{AtomicU64, Ordering};
+        use std::sync::Arc;
+        use std::task::{Context, Poll};
+        use tokio::io::{AsyncRead, AsyncReadExt, ReadBuf};
+
+        // Counts the bytes the server read from the resource
+        struct CountingReader<R> {
+            inner: R,
+            read: Arc<AtomicU64>,
+        }
+
+        impl<R: AsyncRead + Unpin> AsyncRead for CountingReader<R> {
+            fn poll_read(
+                mut self: Pin<&mut Self>,
+                cx: &mut Context<'_>,
+                buf: &mut ReadBuf<'_>,
+            ) -> Poll<std::io::Result<()>> {
+                let before = buf.filled().len();
+                let polled = Pin::new(&mut self.inner).poll_read(cx, buf);
+                let read = (buf.filled().len() - before) as u64;
+                self.read.fetch_add(read, Ordering::SeqCst);
+                polled
+            }
+        }
+
+        let total = 150 * RESOURCE_CHUNK_BYTES as u64;
+        let read = Arc::new(AtomicU64::new(0));
+        let server_read = read.clone();
+        let uri = Url::parse("mem://large")?;
+        let resource_uri = uri.clone();
+        let transport = ClientInMemoryTransport::new(move |t: ServerInMemoryTransport| {
+            let mut builder = Server::builder(t);
+            let read = server_read.clone();
+            builder.register_resource_stream(
+                Resource {
+                    uri: resource_uri.clone(),
+                    name: "large".to_string(),
+                    description: None,
+                    mime_type: None,
+                },
+                move |_req, _ctx| {
+                    let read = read.clone();
+                    Box::pin(async move {
+                        let inner = tokio::io::repeat(b'x').take(total);
+                        Ok(ResourceStream::new(CountingReader { inner, read }))
+                    })
+                },
+            );
+            tokio::spawn(async move { builder.build().listen().await.unwrap() })
+        });
+        transport.open().await?;
+        let client = ClientBuilder::new(transport.clone()).build();
+        let client_clone = client.clone();
+        tokio::spawn(async move { client_clone.start().await });
+
+        // The writer stalls once the pipe is full, until the other end is drained
+        let (mut writer, mut drain) = tokio::io::duplex(64 * 1024);
+        let reader = client.clone();
+        let reading = tokio::spawn(async move {
+            reader
+                .read_resource_to(uri, &mut writer, RequestOptions::default())
+                .await
+        });
+        let mut last = 0;
+        loop {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            let now = read.load(Ordering::SeqCst);
+            if now == last {
+                break;
+            }
+            last = now;
+        }
+        // Held up by the writer instead of piling up in the client
+        assert!(last > 0 && last < total, "read {} of {}", last, total);
+
+        let mut sink = tokio::io::sink();
+        let (written, drained) = tokio::join!(reading, tokio::io::copy(&mut drain, &mut sink));
+        assert_eq!(written??, total);
+        assert_eq!(drained?, total);
+
+        transport.close().await?;
+        Ok(())
     }
 }
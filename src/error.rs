@@ -0,0 +1,140 @@
+//! Classify request failures so hosts can retry automatically
+use crate::transport::JsonRpcError;
+use crate::types::{ErrorCode, ErrorData};
+
+pub trait McpError {
+    /// The JSON-RPC error returned by the peer, if the failure was an error response
+    fn json_rpc_error(&self) -> Option<&JsonRpcError>;
+
+    /// Whether retrying the request may succeed
+    /// uses the `retriable` flag of the error data when present, otherwise timeouts, closed
+    /// connections and transport failures are retriable and everything else is not
+    fn is_retriable(&self) -> bool;
+
+    fn error_data(&self) -> Option<ErrorData> {
+        self.json_rpc_error()?.error_data()
+    }
+}
+
+impl McpError for JsonRpcError {
+    fn json_rpc_error(&self) -> Option<&JsonRpcError> {
+        Some(self)
+    }
+
+    fn is_retriable(&self) -> bool {
+        match self.error_data() {
+            Some(data) => data.retriable,
+            None => [
+                ErrorCode::RequestTimeout,
+                ErrorCode::ConnectionTimeout,
+                ErrorCode::IdleTimeout,
+                ErrorCode::ConnectionClosed,
+            ]
+            .iter()
+            .any(|code| self.code == *code as i32),
+        }
+    }
+}
+
+impl McpError for anyhow::Error {
+    fn json_rpc_error(&self) -> Option<&JsonRpcError> {
+        self.chain().find_map(|e| e.downcast_ref::<JsonRpcError>())
+    }
+
+    fn is_retriable(&self) -> bool {
+        if let Some(error) = self.json_rpc_error() {
+            return error.is_retriable();
+        }
+        self.chain().any(|e| {
+            e.downcast_ref::<std::io::Error>().is_some()
+                || e.downcast_ref::<reqwest::Error>().is_some()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::ClientBuilder;
+    use crate::protocol::RequestOptions;
+    use crate::server::Server;
+    use crate::transport::{ClientInMemoryTransport, ServerInMemoryTransport, Transport};
+    use crate::types::{CallToolResponse, Tool};
+    use anyhow::Result;
+    use std::time::Duration;
+
+    fn tool(name: &str) -> Tool {
+        Tool {
+            name: name.to_string(),
+            description: None,
+            input_schema: serde_json::json!({"type": "object"}),
+            output_schema: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retriable_errors() -> Result<()> {
+        let transport = ClientInMemoryTransport::new(|t: ServerInMemoryTransport| {
+            tokio::spawn(async move {
+                let mut builder = Server::builder(t);
+                builder.register_tool(tool("slow"), |_| {
+                    Box::pin(async move {
+                        tokio::time::sleep(Duration::from_millis(500)).await;
+                        Ok(CallToolResponse {
+                            content: vec![],
+                            is_error: None,
+                            meta: None,
+                        })
+                    })
+                });
+                builder.register_tool(tool("busy"), |_| {
+                    Box::pin(async move {
+                        Err(JsonRpcError::tool_busy("busy", Duration::from_millis(250)).into())
+                    })
+                });
+                builder.build().listen().await.unwrap();
+            })
+        });
+        transport.open().await?;
+        let client = ClientBuilder::new(transport.clone()).build();
+        let client_clone = client.clone();
+        tokio::spawn(async move { client_clone.start().await });
+
+        let call = |name: &str, options: RequestOptions| {
+            client.request(
+                "tools/call",
+                Some(serde_json::json!({ "name": name })),
+                options,
+            )
+        };
+
+        let timeout = call(
+            "slow",
+            RequestOptions::default().timeout(Duration::from_millis(50)),
+        )
+        .await
+        .unwrap_err();
+        assert!(timeout.is_retriable());
+        assert_eq!(timeout.error_data().unwrap().kind, ErrorData::TIMEOUT);
+
+        let bad_params = call("missing", RequestOptions::default())
+            .await
+            .unwrap_err();
+        assert!(!bad_params.is_retriable());
+        assert_eq!(
+            bad_params.json_rpc_error().unwrap().code,
+            ErrorCode::InvalidParams as i32
+        );
+
+        let busy = call("busy", RequestOptions::default()).await.unwrap_err();
+        assert!(busy.is_retriable());
+        assert_eq!(busy.error_data().unwrap().retry_after_ms, Some(250));
+
+        // Without error data the code decides
+        assert!(!JsonRpcError::new(ErrorCode::InternalError, "boom").is_retriable());
+        assert!(JsonRpcError::new(ErrorCode::ConnectionClosed, "closed").is_retriable());
+
+        transport.close().await?;
+        Ok(())
+    }
+}
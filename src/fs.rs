@@ -0,0 +1,423 @@
+//! Serve the files of a local directory as resources
+//! reads honour the `offset`/`length` range extension with a seek and a bounded read,
+//! a file is served as text or blob as a whole, so every range of it has the same kind
+use crate::registry::ResourceStream;
+use crate::types::{
+    BlobResourceContents, ReadResourceResponse, Resource, ResourceContent, ResourceRange,
+    ResourceRangeResult, TextResourceContents,
+};
+use anyhow::Result;
+use base64::Engine;
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio::sync::OnceCell;
+use url::Url;
+
+/// List every file below `dir` as a `file://` resource, with the path to read it from
+/// symlinks are never followed into directories, a link to a file is listed when the file is
+/// inside `dir` and read from its target
+pub(crate) fn directory_resources(dir: &Path) -> Result<Vec<(Resource, PathBuf)>> {
+    let root = std::fs::canonicalize(dir)?;
+    let mut resources = Vec::new();
+    let mut pending = vec![root.clone()];
+    while let Some(dir) = pending.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            let path = entry.path();
+            if file_type.is_dir() {
+                pending.push(path);
+                continue;
+            }
+            let target = if file_type.is_symlink() {
+                match std::fs::canonicalize(&path) {
+                    Ok(target) if target.starts_with(&root) && target.is_file() => target,
+                    _ => continue,
+                }
+            } else if file_type.is_file() {
+                path.clone()
+            } else {
+                continue;
+            };
+            let uri = Url::from_file_path(&path)
+                .map_err(|_| anyhow::anyhow!("Invalid file path: {}", path.display()))?;
+            let name = path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            resources.push((
+                Resource {
+                    uri,
+                    name,
+                    description: None,
+                    mime_type: None,
+                },
+                target,
+            ));
+        }
+    }
+    Ok(resources)
+}
+
+/// Read a file as a resource, optionally limited to a byte range
+/// ranges starting at or past the end of the file return no contents and `eof` in `_meta`.
+/// A UTF-8 file is read as text, its ranges are moved to character boundaries and `_meta`
+/// reports the range returned
+pub async fn read_file(
+    path: &Path,
+    uri: Url,
+    range: Option<ResourceRange>,
+) -> Result<ReadResourceResponse> {
+    read_file_cached(path, uri, range, &OnceCell::new()).await
+}
+
+/// Like [`read_file`], remembering in `is_text` whether the file is text across reads
+pub(crate) async fn read_file_cached(
+    path: &Path,
+    uri: Url,
+    range: Option<ResourceRange>,
+    is_text: &OnceCell<bool>,
+) -> Result<ReadResourceResponse> {
+    let Some(range) = range else {
+        let bytes = tokio::fs::read(path).await?;
+        let _ = is_text.set(std::str::from_utf8(&bytes).is_ok());
+        return Ok(ReadResourceResponse::new(vec![file_content(uri, bytes)]));
+    };
+    let text = *is_text.get_or_try_init(|| is_utf8(path)).await?;
+
+    let mut file = tokio::fs::File::open(path).await?;
+    let size = file.metadata().await?.len();
+    if range.offset >= size {
+        return Ok(ReadResourceResponse {
+            contents: vec![],
+            meta: Some(serde_json::to_value(ResourceRangeResult {
+                offset: range.offset,
+                length: 0,
+                eof: true,
+            })?),
+        });
+    }
+
+    file.seek(std::io::SeekFrom::Start(range.offset)).await?;
+    let mut bytes = Vec::new();
+    (&mut file)
+        .take(range.length)
+        .read_to_end(&mut bytes)
+        .await?;
+    let mut offset = range.offset;
+    let (content, length) = if text {
+        // Skip the rest of a character the range starts in, the previous range returned it
+        let skipped = bytes.iter().take_while(|b| is_continuation(**b)).count();
+        bytes.drain(..skipped);
+        offset += skipped as u64;
+        // Finish a character the range ends in, so the next range starts on a boundary
+        let mut rest = Vec::new();
+        if !bytes.is_empty() {
+            (&mut file).take(3).read_to_end(&mut rest).await?;
+        }
+        let missing = match std::str::from_utf8(&bytes) {
+            Err(e) if e.error_len().is_none() => {
+                rest.iter().take_while(|b| is_continuation(**b)).count()
+            }
+            _ => 0,
+        };
+        bytes.extend_from_slice(&rest[..missing]);
+        let length = bytes.len() as u64;
+        // Changed since it was found to be text if it isn't UTF-8 anymore
+        (file_content(uri, bytes), length)
+    } else {
+        let length = bytes.len() as u64;
+        let blob = ResourceContent::Blob(BlobResourceContents {
+            uri,
+            mime_type: None,
+            blob: base64::engine::general_purpose::STANDARD.encode(bytes),
+        });
+        (blob, length)
+    };
+    Ok(
+        ReadResourceResponse::new(vec![content]).meta(serde_json::to_value(ResourceRangeResult {
+            offset,
+            length,
+            eof: offset + length >= size,
+        })?),
+    )
+}
+
+fn is_continuation(byte: u8) -> bool {
+    byte & 0b1100_0000 == 0b1000_0000
+}
+
+/// Whether the whole file is UTF-8, read in chunks so large files aren't loaded at once
+async fn is_utf8(path: &Path) -> Result<bool> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut buf = vec![0; 64 * 1024];
+    // Bytes of a character split across chunks, moved to the front of the buffer
+    let mut pending = 0;
+    loop {
+        let read = file.read(&mut buf[pending..]).await?;
+        if read == 0 {
+            return Ok(pending == 0);
+        }
+        let filled = pending + read;
+        match std::str::from_utf8(&buf[..filled]) {
+            Ok(_) => pending = 0,
+            Err(e) if e.error_len().is_none() => {
+                buf.copy_within(e.valid_up_to()..filled, 0);
+                pending = filled - e.valid_up_to();
+            }
+            Err(_) => return Ok(false),
+        }
+    }
+}
+
+/// Open a file for a streamed read, limited to `range` if one was requested
+pub(crate) async fn open_file(path: &Path, range: Option<ResourceRange>) -> Result<ResourceStream> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let Some(range) = range else {
+        return Ok(ResourceStream::new(file));
+    };
+    file.seek(std::io::SeekFrom::Start(range.offset)).await?;
+    Ok(ResourceStream::new(file.take(range.length)))
+}
+
+/// UTF-8 data is returned as text, anything else as base64 blob
+pub(crate) fn file_content(uri: Url, bytes: Vec<u8>) -> ResourceContent {
+    match String::from_utf8(bytes) {
+        Ok(text) => ResourceContent::Text(TextResourceContents {
+            uri,
+            mime_type: None,
+            text,
+        }),
+        Err(err) => ResourceContent::Blob(BlobResourceContents {
+            uri,
+            mime_type: None,
+            blob: base64::engine::general_purpose::STANDARD.encode(err.into_bytes()),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::ClientBuilder;
+    use crate::server::Server;
+    use crate::transport::{ClientInMemoryTransport, ServerInMemoryTransport, Transport};
+
+    fn text(response: &ReadResourceResponse) -> &str {
+        match &response.contents[0] {
+            ResourceContent::Text(contents) => &contents.text,
+            other => panic!("Expected text contents, got {:?}", other),
+        }
+    }
+
+    fn range_meta(response: &ReadResourceResponse) -> ResourceRangeResult {
+        serde_json::from_value(response.meta.clone().unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_read_resource_range() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("async-mcp-fs-{}", uuid::Uuid::new_v4()));
+        tokio::fs::create_dir_all(&dir).await?;
+        let size: u64 = 10 * 1024 * 1024;
+        let data: Vec<u8> = (0..size).map(|i| b'a' + (i % 26) as u8).collect();
+        let path = dir.join("app.log");
+        tokio::fs::write(&path, &data).await?;
+        let uri = Url::from_file_path(std::fs::canonicalize(&path)?).unwrap();
+
+        let server_dir = dir.clone();
+        let transport = ClientInMemoryTransport::new(move |t: ServerInMemoryTransport| {
+            let dir = server_dir.clone();
+            tokio::spawn(async move {
+                let mut builder = Server::builder(t);
+                builder.serve_directory(&dir).unwrap();
+                builder.build().listen().await.unwrap();
+            })
+        });
+        transport.open().await?;
+        let client = ClientBuilder::new(transport.clone()).build();
+        let client_clone = client.clone();
+        tokio::spawn(async move { client_clone.start().await });
+
+        // middle of the file
+        let response = client
+            .read_resource_range(uri.clone(), 5_000_000, 1000)
+            .await?;
+        assert_eq!(text(&response).as_bytes(), &data[5_000_000..5_001_000]);
+        assert_eq!(
+            range_meta(&response),
+            ResourceRangeResult {
+                offset: 5_000_000,
+                length: 1000,
+                eof: false
+            }
+        );
+
+        // straddling the end of the file is truncated
+        let response = client
+            .read_resource_range(uri.clone(), size - 10, 100)
+            .await?;
+        assert_eq!(text(&response).as_bytes(), &data[(size - 10) as usize..]);
+        assert_eq!(
+            range_meta(&response),
+            ResourceRangeResult {
+                offset: size - 10,
+                length: 10,
+                eof: true
+            }
+        );
+
+        // zero length
+        let response = client.read_resource_range(uri.clone(), 42, 0).await?;
+        assert_eq!(text(&response), "");
+        assert_eq!(range_meta(&response).length, 0);
+
+        // beyond the end of the file
+        let response = client
+            .read_resource_range(uri.clone(), size + 1, 10)
+            .await?;
+        assert!(response.contents.is_empty());
+        assert!(range_meta(&response).eof);
+
+        transport.close().await?;
+        tokio::fs::remove_dir_all(&dir).await?;
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_directory_symlinks() -> Result<()> {
+        let base = std::env::temp_dir().join(format!("async-mcp-fs-{}", uuid::Uuid::new_v4()));
+        let (dir, outside) = (base.join("served"), base.join("outside"));
+        std::fs::create_dir_all(dir.join("sub"))?;
+        std::fs::create_dir_all(&outside)?;
+        std::fs::write(dir.join("sub/notes.md"), "notes")?;
+        std::fs::write(outside.join("secret.txt"), "secret")?;
+        // A cycle, a link out of the directory and a link to a file inside it
+        std::os::unix::fs::symlink(&dir, dir.join("sub/loop"))?;
+        std::os::unix::fs::symlink(outside.join("secret.txt"), dir.join("secret.txt"))?;
+        std::os::unix::fs::symlink(dir.join("sub/notes.md"), dir.join("latest.md"))?;
+
+        let mut listed: Vec<_> = directory_resources(&dir)?
+            .into_iter()
+            .map(|(resource, path)| (resource.name, path))
+            .collect();
+        listed.sort();
+        let notes = std::fs::canonicalize(dir.join("sub/notes.md"))?;
+        assert_eq!(
+            listed,
+            vec![
+                ("latest.md".to_string(), notes.clone()),
+                ("notes.md".to_string(), notes)
+            ]
+        );
+
+        std::fs::remove_dir_all(&base)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_range_splitting_a_character() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("async-mcp-fs-{}", uuid::Uuid::new_v4()));
+        tokio::fs::create_dir_all(&dir).await?;
+        let path = dir.join("greeting.txt");
+        // `é` is two bytes, at offsets 1 and 2
+        tokio::fs::write(&path, "héllo").await?;
+        let uri = Url::from_file_path(&path).unwrap();
+        let is_text = OnceCell::new();
+        let read = |offset, length| {
+            read_file_cached(
+                &path,
+                uri.clone(),
+                Some(ResourceRange { offset, length }),
+                &is_text,
+            )
+        };
+
+        // Ending inside the character returns all of it
+        let response = read(0, 2).await?;
+        assert_eq!(text(&response), "hé");
+        assert_eq!(range_meta(&response).length, 3);
+        // Starting inside it skips to the next character, still as text
+        let response = read(2, 4).await?;
+        assert_eq!(text(&response), "llo");
+        assert_eq!(
+            range_meta(&response),
+            ResourceRangeResult {
+                offset: 3,
+                length: 3,
+                eof: true
+            }
+        );
+
+        tokio::fs::remove_dir_all(&dir).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_stream_file() -> Result<()> {
+        use crate::protocol::RequestOptions;
+        use crate::registry::RESOURCE_CHUNK_BYTES;
+        use crate::types::{ReadResourceRequest, ResourceStreamResult};
+        use std::sync::{Arc, Mutex};
+
+        let dir = std::env::temp_dir().join(format!("async-mcp-fs-{}", uuid::Uuid::new_v4()));
+        tokio::fs::create_dir_all(&dir).await?;
+        // Not UTF-8, so an inline read would be a single base64 blob
+        let data: Vec<u8> = (0..RESOURCE_CHUNK_BYTES * 2 + 1000)
+            .map(|i| (i % 251) as u8 | 0x80)
+            .collect();
+        let path = dir.join("image.bin");
+        tokio::fs::write(&path, &data).await?;
+        let uri = Url::from_file_path(std::fs::canonicalize(&path)?).unwrap();
+
+        let server_dir = dir.clone();
+        let transport = ClientInMemoryTransport::new(move |t: ServerInMemoryTransport| {
+            let dir = server_dir.clone();
+            tokio::spawn(async move {
+                let mut builder = Server::builder(t);
+                builder.serve_directory(&dir).unwrap();
+                builder.build().listen().await.unwrap();
+            })
+        });
+        transport.open().await?;
+        let client = ClientBuilder::new(transport.clone()).build();
+        let client_clone = client.clone();
+        tokio::spawn(async move { client_clone.start().await });
+
+        let mut streamed = Vec::new();
+        let written = client
+            .read_resource_to(uri.clone(), &mut streamed, RequestOptions::default())
+            .await?;
+        assert_eq!(written, data.len() as u64);
+        assert_eq!(streamed, data);
+
+        // One notification per chunk in order, the response only describes the stream
+        let offsets = Arc::new(Mutex::new(Vec::new()));
+        let handle = offsets.clone();
+        let options = RequestOptions::default()
+            .on_resource_chunk(move |chunk| handle.lock().unwrap().push(chunk.offset));
+        let response: ReadResourceResponse = client
+            .request_typed(
+                "resources/read",
+                ReadResourceRequest::new(uri.clone())
+                    .range(1000, RESOURCE_CHUNK_BYTES as u64)
+                    .stream(),
+                options,
+            )
+            .await?;
+        assert!(response.contents.is_empty());
+        assert_eq!(
+            serde_json::from_value::<ResourceStreamResult>(response.meta.unwrap())?,
+            ResourceStreamResult {
+                length: RESOURCE_CHUNK_BYTES as u64,
+                chunks: 1,
+                mime_type: None
+            }
+        );
+        assert_eq!(*offsets.lock().unwrap(), vec![0]);
+
+        transport.close().await?;
+        tokio::fs::remove_dir_all(&dir).await?;
+        Ok(())
+    }
+}
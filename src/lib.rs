@@ -1,8 +1,23 @@
+pub mod blob;
+pub mod bridge;
 pub mod client;
+pub mod error;
+pub mod fs;
+mod log_limit;
+mod pagination;
+pub mod prelude;
 pub mod protocol;
 pub mod registry;
+pub mod resource_cache;
+pub mod result_limit;
 pub mod server;
+#[cfg(feature = "sqlite")]
+pub mod sql;
 pub mod sse;
-pub use sse::http_server::run_http_server;
+pub mod subscriptions;
+mod token_bucket;
+pub mod tool_source;
+pub use sse::http_server::{run_http_server, run_sse_server};
 pub mod transport;
 pub mod types;
+pub mod validation;
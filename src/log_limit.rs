@@ -0,0 +1,75 @@
+//! Rate limit for log notifications
+//! a token bucket per session caps `notifications/message`, records over the limit are dropped
+//! and counted, and the count is reported once the bucket has refilled
+use crate::sse::limits::RateLimit;
+use crate::token_bucket::TokenBucket;
+use crate::types::{LoggingLevel, LoggingMessageParams};
+use parking_lot::Mutex;
+use std::time::Duration;
+
+/// What happens to a log record offered to a [`LogThrottle`]
+#[derive(Debug, PartialEq)]
+pub(crate) enum Verdict {
+    Send,
+    Dropped,
+    /// Dropped as the first of a burst, the summary is due after the delay
+    Throttled(Duration),
+}
+
+pub(crate) struct LogThrottle {
+    limit: RateLimit,
+    state: Mutex<State>,
+}
+
+struct State {
+    bucket: TokenBucket,
+    dropped: u64,
+    // The summary takes the level of the most severe record it stands for
+    most_severe: Option<LoggingLevel>,
+}
+
+impl LogThrottle {
+    pub(crate) fn new(burst: u32, per_second: f64) -> Self {
+        let limit = RateLimit::new(per_second, burst);
+        Self {
+            limit,
+            state: Mutex::new(State {
+                bucket: TokenBucket::new(&limit),
+                dropped: 0,
+                most_severe: None,
+            }),
+        }
+    }
+
+    pub(crate) fn admit(&self, level: LoggingLevel) -> Verdict {
+        let mut state = self.state.lock();
+        let delay = match state.bucket.take(&self.limit) {
+            Ok(()) => return Verdict::Send,
+            Err(delay) => delay,
+        };
+        state.dropped += 1;
+        state.most_severe = state.most_severe.max(Some(level));
+        if state.dropped > 1 {
+            return Verdict::Dropped;
+        }
+        Verdict::Throttled(delay)
+    }
+
+    /// The record reporting what was dropped since throttling started, it takes a token
+    pub(crate) fn summary(&self) -> Option<LoggingMessageParams> {
+        let mut state = self.state.lock();
+        let level = state.most_severe.take()?;
+        let dropped = std::mem::take(&mut state.dropped);
+        state.bucket.spend(&self.limit);
+        Some(
+            LoggingMessageParams::new(
+                level,
+                serde_json::json!({
+                    "message": format!("Dropped {} log messages over the rate limit", dropped),
+                    "dropped": dropped,
+                }),
+            )
+            .logger(env!("CARGO_PKG_NAME")),
+        )
+    }
+}
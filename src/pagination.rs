@@ -0,0 +1,181 @@
+//! Opaque, signed cursors for the `*/list` methods
+//! a cursor encodes the offset and the registry generation it was issued for, signed with a
+//! per-process key, so tampered cursors, cursors from another server instance and cursors
+//! issued before the registry changed are all rejected instead of returning wrong slices
+use crate::transport::JsonRpcError;
+use crate::types::{ErrorCode, ErrorData};
+use anyhow::Result;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::hash::{Hash, Hasher};
+use std::sync::OnceLock;
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub(crate) const INVALID_CURSOR: &str = "invalid_cursor";
+pub(crate) const CURSOR_EXPIRED: &str = "cursor_expired";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct Cursor {
+    offset: usize,
+    generation: u64,
+}
+
+fn key() -> &'static [u8] {
+    static KEY: OnceLock<Vec<u8>> = OnceLock::new();
+    KEY.get_or_init(|| {
+        [uuid::Uuid::new_v4(), uuid::Uuid::new_v4()]
+            .iter()
+            .flat_map(|id| id.into_bytes())
+            .collect()
+    })
+}
+
+fn mac(list: &str, payload: &[u8]) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(key()).expect("HMAC accepts any key length");
+    // Bind the cursor to the list it was issued for
+    mac.update(list.as_bytes());
+    mac.update(b"\0");
+    mac.update(payload);
+    mac
+}
+
+fn encode(list: &str, cursor: Cursor) -> String {
+    let engine = &base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    let payload = serde_json::to_vec(&cursor).unwrap_or_default();
+    let checksum = mac(list, &payload).finalize().into_bytes();
+    format!("{}.{}", engine.encode(&payload), engine.encode(checksum))
+}
+
+fn invalid_cursor() -> JsonRpcError {
+    JsonRpcError::with_error_data(
+        ErrorCode::InvalidParams,
+        "invalid cursor",
+        ErrorData::new(INVALID_CURSOR, false),
+    )
+}
+
+fn decode(list: &str, cursor: &str, generation: u64) -> Result<usize, JsonRpcError> {
+    let engine = &base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    let (payload, checksum) = cursor.split_once('.').ok_or_else(invalid_cursor)?;
+    let payload = engine.decode(payload).map_err(|_| invalid_cursor())?;
+    let checksum = engine.decode(checksum).map_err(|_| invalid_cursor())?;
+    mac(list, &payload)
+        .verify_slice(&checksum)
+        .map_err(|_| invalid_cursor())?;
+    let cursor: Cursor = serde_json::from_slice(&payload).map_err(|_| invalid_cursor())?;
+    if cursor.generation != generation {
+        return Err(JsonRpcError::with_error_data(
+            ErrorCode::InvalidParams,
+            "cursor expired, restart listing",
+            ErrorData::new(CURSOR_EXPIRED, false),
+        ));
+    }
+    Ok(cursor.offset)
+}
+
+/// Generation of a list computed on every request, changes whenever its keys do
+/// so cursors expire if e.g. a template listing changed between pages
+pub(crate) fn listing_generation<'a>(keys: impl IntoIterator<Item = &'a str>) -> u64 {
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    for key in keys {
+        key.hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+/// Return the page of `items` starting at `cursor` and the cursor of the next page
+/// without a page size everything is returned at once
+pub(crate) fn paginate<T>(
+    list: &str,
+    items: Vec<T>,
+    cursor: Option<&str>,
+    generation: u64,
+    page_size: Option<usize>,
+) -> Result<(Vec<T>, Option<String>)> {
+    let offset = match cursor {
+        Some(cursor) => decode(list, cursor, generation)?,
+        None => 0,
+    };
+    if offset > items.len() {
+        return Err(invalid_cursor().into());
+    }
+    let end = match page_size {
+        Some(page_size) => offset.saturating_add(page_size.max(1)).min(items.len()),
+        None => items.len(),
+    };
+    let next_cursor = (end < items.len()).then(|| {
+        encode(
+            list,
+            Cursor {
+                offset: end,
+                generation,
+            },
+        )
+    });
+    Ok((
+        items.into_iter().skip(offset).take(end - offset).collect(),
+        next_cursor,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kind(err: anyhow::Error) -> String {
+        let err = err.downcast::<JsonRpcError>().unwrap();
+        assert_eq!(err.code, ErrorCode::InvalidParams as i32);
+        err.error_data().unwrap().kind
+    }
+
+    #[test]
+    fn test_paginate() -> Result<()> {
+        let items: Vec<u32> = (0..5).collect();
+        let (page, cursor) = paginate("tools", items.clone(), None, 1, Some(2))?;
+        assert_eq!(page, vec![0, 1]);
+        let (page, cursor) = paginate("tools", items.clone(), cursor.as_deref(), 1, Some(2))?;
+        assert_eq!(page, vec![2, 3]);
+        let (page, cursor) = paginate("tools", items.clone(), cursor.as_deref(), 1, Some(2))?;
+        assert_eq!(page, vec![4]);
+        assert!(cursor.is_none());
+
+        let (page, cursor) = paginate("tools", items, None, 1, None)?;
+        assert_eq!(page.len(), 5);
+        assert!(cursor.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejects_bad_cursors() -> Result<()> {
+        let items: Vec<u32> = (0..5).collect();
+        let (_, cursor) = paginate("tools", items.clone(), None, 1, Some(2))?;
+        let cursor = cursor.unwrap();
+
+        // tampered offset with the original signature
+        let (_, checksum) = cursor.split_once('.').unwrap();
+        let forged_payload = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .encode(br#"{"offset":4,"generation":1}"#);
+        let tampered = format!("{}.{}", forged_payload, checksum);
+        let err = paginate("tools", items.clone(), Some(&tampered), 1, Some(2)).unwrap_err();
+        assert_eq!(kind(err), INVALID_CURSOR);
+
+        // truncated
+        let truncated = &cursor[..cursor.len() - 3];
+        let err = paginate("tools", items.clone(), Some(truncated), 1, Some(2)).unwrap_err();
+        assert_eq!(kind(err), INVALID_CURSOR);
+        let err = paginate("tools", items.clone(), Some("garbage"), 1, Some(2)).unwrap_err();
+        assert_eq!(kind(err), INVALID_CURSOR);
+
+        // issued for another list
+        let err = paginate("prompts", items.clone(), Some(&cursor), 1, Some(2)).unwrap_err();
+        assert_eq!(kind(err), INVALID_CURSOR);
+
+        // registry changed since the cursor was issued
+        let err = paginate("tools", items, Some(&cursor), 2, Some(2)).unwrap_err();
+        assert_eq!(kind(err), CURSOR_EXPIRED);
+        Ok(())
+    }
+}
@@ -0,0 +1,22 @@
+//! The common surface of the crate, `use async_mcp::prelude::*;`
+//!
+//! Items are added here once they are stable, removing or renaming one is a breaking change,
+//! `tests/prelude.rs` fails when that happens by accident
+
+pub use crate::client::{Client, ClientBuilder};
+pub use crate::protocol::RequestOptions;
+pub use crate::registry::{ServerContext, State};
+pub use crate::server::{DynServer, Server, ServerBuilder};
+pub use crate::sse::http_server::run_http_server;
+pub use crate::transport::{
+    BoxedTransport, ClientInMemoryTransport, ClientSseTransport, ClientSseTransportBuilder,
+    ClientStdioTransport, ClientWsTransport, ClientWsTransportBuilder, JsonRpcError,
+    ServerInMemoryTransport, ServerStdioTransport, Transport,
+};
+pub use crate::types::{
+    CallToolRequest, CallToolResponse, ClientCapabilities, ErrorCode, GetPromptRequest,
+    GetPromptResult, Implementation, ListRequest, MessageContent, Prompt, PromptArgument,
+    PromptCapabilities, PromptMessage, ReadResourceRequest, ReadResourceResponse, Resource,
+    ResourceCapabilities, ResourceContent, ResourcesListResponse, Role, ServerCapabilities, Tool,
+    ToolBuilder, ToolResponseContent, ToolsListResponse,
+};
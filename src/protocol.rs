@@ -1,32 +1,77 @@
 use super::transport::{
-    JsonRpcError, JsonRpcMessage, JsonRpcNotification, JsonRpcRequest, JsonRpcResponse, Transport,
+    JsonRpcError, JsonRpcMessage, JsonRpcNotification, JsonRpcRequest, JsonRpcResponse,
+    JsonRpcUnidentifiedError, Transport, MESSAGE_HEADERS,
 };
-use super::types::ErrorCode;
+use super::types::{
+    ErrorCode, ErrorData, ProgressParams, ProgressToken, ResourceChunk, RESOURCE_CHUNK_METHOD,
+};
+use crate::error::McpError;
 use anyhow::anyhow;
 use anyhow::Result;
 use async_trait::async_trait;
+use parking_lot::RwLock;
 use serde::de::DeserializeOwned;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
 use std::pin::Pin;
 use std::sync::atomic::Ordering;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, VecDeque},
     sync::{atomic::AtomicU64, Arc},
 };
-use tokio::sync::oneshot;
 use tokio::sync::Mutex;
+use tokio::sync::{mpsc, oneshot};
 use tokio::time::timeout;
 use tracing::debug;
 
-#[derive(Clone)]
+/// Messages sent through a protocol instance (requests, notifications and responses)
+/// go through a single FIFO, so everything emitted by a handler reaches the peer in emission order.
+/// Inbound requests are handled one at a time in arrival order, so responses leave in request
+/// order even when a later request would finish first; the requests of a batch run concurrently
+/// and are answered together. Responses and cancellations keep being read while a request is
+/// handled, so a handler can wait on a request it sent to the peer, or be cancelled
 pub struct Protocol<T: Transport> {
     transport: Arc<T>,
+    outbound: Arc<Mutex<()>>,
+    emit_timing_meta: bool,
 
     request_id: Arc<AtomicU64>,
     pending_requests: Arc<Mutex<HashMap<u64, oneshot::Sender<JsonRpcResponse>>>>,
-    request_handlers: Arc<Mutex<HashMap<String, Box<dyn RequestHandler>>>>,
-    notification_handlers: Arc<Mutex<HashMap<String, Box<dyn NotificationHandler>>>>,
+    progress_callbacks: Arc<Mutex<HashMap<ProgressToken, ProgressCallback>>>,
+    chunk_callbacks: Arc<Mutex<HashMap<ProgressToken, ChunkReceiver>>>,
+    handlers: Handlers,
+    handler_timeouts: Arc<HashMap<String, Duration>>,
+    default_request_timeout: Duration,
+    idle_timeout: Option<Duration>,
+    on_malformed_notification: Option<MalformedNotificationFn>,
+    malformed_notifications: Arc<AtomicU64>,
+    on_notification_error: Option<NotificationErrorFn>,
+    concurrent_notification_handlers: bool,
+    request_interceptor: Option<RequestInterceptorFn>,
+}
+
+impl<T: Transport> Clone for Protocol<T> {
+    fn clone(&self) -> Self {
+        Self {
+            transport: self.transport.clone(),
+            outbound: self.outbound.clone(),
+            emit_timing_meta: self.emit_timing_meta,
+            request_id: self.request_id.clone(),
+            pending_requests: self.pending_requests.clone(),
+            progress_callbacks: self.progress_callbacks.clone(),
+            chunk_callbacks: self.chunk_callbacks.clone(),
+            handlers: self.handlers.clone(),
+            handler_timeouts: self.handler_timeouts.clone(),
+            default_request_timeout: self.default_request_timeout,
+            idle_timeout: self.idle_timeout,
+            on_malformed_notification: self.on_malformed_notification.clone(),
+            malformed_notifications: self.malformed_notifications.clone(),
+            on_notification_error: self.on_notification_error.clone(),
+            concurrent_notification_handlers: self.concurrent_notification_handlers,
+            request_interceptor: self.request_interceptor.clone(),
+        }
+    }
 }
 
 impl<T: Transport> Protocol<T> {
@@ -34,6 +79,20 @@ impl<T: Transport> Protocol<T> {
         ProtocolBuilder::new(transport)
     }
 
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    /// Handle to register or replace handlers while the protocol is running
+    pub fn handlers(&self) -> Handlers {
+        self.handlers.clone()
+    }
+
+    /// Notifications dropped because their params didn't match the handler's type
+    pub fn malformed_notification_count(&self) -> u64 {
+        self.malformed_notifications.load(Ordering::Relaxed)
+    }
+
     pub async fn notify(&self, method: &str, params: Option<serde_json::Value>) -> Result<()> {
         let notification = JsonRpcNotification {
             method: method.to_string(),
@@ -41,10 +100,34 @@ impl<T: Transport> Protocol<T> {
             ..Default::default()
         };
         let msg = JsonRpcMessage::Notification(notification);
-        self.transport.send(&msg).await?;
+        self.send(&msg).await?;
         Ok(())
     }
 
+    /// Advanced: send a message as is, bypassing the typed layers but not the outbound FIFO
+    /// requests are rejected, even inside a batch, since their response would have no pending
+    /// entry to go to. Send them with [`Protocol::request`] instead
+    pub async fn send_raw(&self, message: &JsonRpcMessage) -> Result<()> {
+        fn has_request(message: &JsonRpcMessage) -> bool {
+            match message {
+                JsonRpcMessage::Request(_) => true,
+                JsonRpcMessage::Batch(messages) => messages.iter().any(has_request),
+                _ => false,
+            }
+        }
+        if has_request(message) {
+            anyhow::bail!("Raw requests aren't tracked, send them with request_raw");
+        }
+        self.send(message).await
+    }
+
+    /// Send a message through the outbound FIFO
+    /// tokio's Mutex is fair, so sends complete in the order they were issued
+    async fn send(&self, message: &JsonRpcMessage) -> Result<()> {
+        let _guard = self.outbound.lock().await;
+        self.transport.send(message).await
+    }
+
     pub async fn request(
         &self,
         method: &str,
@@ -62,6 +145,57 @@ impl<T: Transport> Protocol<T> {
             pending.insert(id, tx);
         }
 
+        // The request id doubles as progress token, params that aren't an object can't carry one
+        let mut params = params;
+        let progress_token = if options.on_progress.is_some() || options.on_resource_chunk.is_some()
+        {
+            let token = ProgressToken::Number(id as i64);
+            match attach_progress_token(params.take(), &token) {
+                Ok(with_token) => {
+                    params = Some(with_token);
+                    if let Some(callback) = options.on_progress {
+                        self.progress_callbacks
+                            .lock()
+                            .await
+                            .insert(token.clone(), callback);
+                    }
+                    if let Some(receiver) = options.on_resource_chunk {
+                        self.chunk_callbacks
+                            .lock()
+                            .await
+                            .insert(token.clone(), receiver);
+                    }
+                    Some(token)
+                }
+                Err(original) => {
+                    params = original;
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        let timeout_after = options.timeout.unwrap_or(self.default_request_timeout);
+        let response = self
+            .send_and_wait(id, method, params, options.headers, timeout_after, rx)
+            .await;
+        if let Some(token) = progress_token {
+            self.progress_callbacks.lock().await.remove(&token);
+            self.chunk_callbacks.lock().await.remove(&token);
+        }
+        response
+    }
+
+    async fn send_and_wait(
+        &self,
+        id: u64,
+        method: &str,
+        params: Option<serde_json::Value>,
+        headers: HashMap<String, String>,
+        timeout_after: Duration,
+        rx: oneshot::Receiver<JsonRpcResponse>,
+    ) -> Result<JsonRpcResponse> {
         // Send the request
         let msg = JsonRpcMessage::Request(JsonRpcRequest {
             id,
@@ -69,13 +203,25 @@ impl<T: Transport> Protocol<T> {
             params,
             ..Default::default()
         });
-        self.transport.send(&msg).await?;
+        if let Err(e) = MESSAGE_HEADERS.scope(headers, self.send(&msg)).await {
+            self.pending_requests.lock().await.remove(&id);
+            return Err(e);
+        }
 
         // Wait for response with timeout
-        match timeout(options.timeout, rx)
-            .await
-            .map_err(|_| anyhow!("Request timed out"))?
-        {
+        let response = match timeout(timeout_after, rx).await {
+            Ok(response) => response,
+            Err(_) => {
+                self.pending_requests.lock().await.remove(&id);
+                return Err(JsonRpcError::with_error_data(
+                    ErrorCode::RequestTimeout,
+                    "Request timed out",
+                    ErrorData::new(ErrorData::TIMEOUT, true),
+                )
+                .into());
+            }
+        };
+        match response {
             Ok(response) => Ok(response),
             Err(_) => {
                 // Clean up the pending request if receiver was dropped
@@ -86,13 +232,99 @@ impl<T: Transport> Protocol<T> {
         }
     }
 
+    /// Send several requests as one JSON-RPC batch and wait for all their responses
+    /// responses are matched by id and returned in request order, whatever order they arrive in
+    pub async fn request_batch(
+        &self,
+        requests: Vec<(String, Option<serde_json::Value>)>,
+        options: RequestOptions,
+    ) -> Result<Vec<Result<JsonRpcResponse>>> {
+        let mut ids = Vec::with_capacity(requests.len());
+        let mut receivers = Vec::with_capacity(requests.len());
+        let mut messages = Vec::with_capacity(requests.len());
+        {
+            let mut pending = self.pending_requests.lock().await;
+            for (method, params) in requests {
+                let id = self.request_id.fetch_add(1, Ordering::SeqCst);
+                let (tx, rx) = oneshot::channel();
+                pending.insert(id, tx);
+                ids.push(id);
+                receivers.push(rx);
+                messages.push(JsonRpcMessage::Request(JsonRpcRequest {
+                    id,
+                    method,
+                    params,
+                    ..Default::default()
+                }));
+            }
+        }
+
+        let sent = MESSAGE_HEADERS
+            .scope(options.headers, self.send(&JsonRpcMessage::Batch(messages)))
+            .await;
+        if let Err(e) = sent {
+            let mut pending = self.pending_requests.lock().await;
+            for id in &ids {
+                pending.remove(id);
+            }
+            return Err(e);
+        }
+
+        // All responses share one deadline
+        let deadline =
+            tokio::time::Instant::now() + options.timeout.unwrap_or(self.default_request_timeout);
+        let mut responses = Vec::with_capacity(ids.len());
+        for (id, rx) in ids.into_iter().zip(receivers) {
+            let response = match tokio::time::timeout_at(deadline, rx).await {
+                Ok(Ok(response)) => Ok(response),
+                Ok(Err(_)) => Err(anyhow!("Request cancelled")),
+                Err(_) => {
+                    self.pending_requests.lock().await.remove(&id);
+                    Err(JsonRpcError::with_error_data(
+                        ErrorCode::RequestTimeout,
+                        "Request timed out",
+                        ErrorData::new(ErrorData::TIMEOUT, true),
+                    )
+                    .into())
+                }
+            };
+            responses.push(response);
+        }
+        Ok(responses)
+    }
+
     pub async fn listen(&self) -> Result<()> {
         debug!("Listening for requests");
+        // One receive stays in flight across iterations, so reading can go on while a request
+        // is handled without dropping a partially read message
+        let mut next = self.transport.receive();
+        let mut received = VecDeque::new();
         loop {
-            let message = self.transport.receive().await;
+            let message = match received.pop_front() {
+                Some(message) => message,
+                None => {
+                    let message = match self.idle_timeout {
+                        // Receiving is only abandoned to disconnect
+                        Some(limit) => match timeout(limit, next.as_mut()).await {
+                            Ok(message) => message,
+                            Err(_) => return Err(self.disconnect_idle(limit).await),
+                        },
+                        None => next.as_mut().await,
+                    };
+                    next = self.transport.receive();
+                    message
+                }
+            };
 
             let message = match message {
                 Ok(msg) => msg,
+                // The peer closed the connection, nothing more will arrive
+                Err(e)
+                    if e.json_rpc_error()
+                        .is_some_and(|e| e.code == ErrorCode::ConnectionClosed as i32) =>
+                {
+                    return Err(e);
+                }
                 Err(e) => {
                     tracing::error!("Failed to parse message: {:?}", e);
                     continue;
@@ -104,102 +336,653 @@ impl<T: Transport> Protocol<T> {
                 break;
             }
 
-            match message.unwrap() {
-                JsonRpcMessage::Request(request) => self.handle_request(request).await?,
+            let handled = match message.unwrap() {
+                JsonRpcMessage::Request(request) => {
+                    self.while_reading(
+                        self.handle_request(request, Instant::now()),
+                        &mut next,
+                        &mut received,
+                    )
+                    .await
+                }
                 JsonRpcMessage::Response(response) => {
-                    let id = response.id;
-                    let mut pending = self.pending_requests.lock().await;
-                    if let Some(tx) = pending.remove(&id) {
-                        let _ = tx.send(response);
-                    }
+                    self.handle_response(response).await;
+                    Some(Ok(()))
                 }
                 JsonRpcMessage::Notification(notification) => {
-                    let handlers = self.notification_handlers.lock().await;
-                    if let Some(handler) = handlers.get(&notification.method) {
-                        handler.handle(notification).await?;
-                    }
+                    Some(self.handle_notification(notification).await)
+                }
+                JsonRpcMessage::Batch(messages) => {
+                    self.while_reading(
+                        self.handle_batch(messages, Instant::now()),
+                        &mut next,
+                        &mut received,
+                    )
+                    .await
+                }
+                JsonRpcMessage::UnidentifiedError(error) => {
+                    debug!("Peer reported an error without id: {:?}", error.error);
+                    Some(Ok(()))
+                }
+            };
+            match handled {
+                Some(result) => result?,
+                None => {
+                    debug!("Transport closed, aborted the in-flight request");
+                    break;
                 }
             }
         }
         Ok(())
     }
 
-    async fn handle_request(&self, request: JsonRpcRequest) -> Result<()> {
-        let handlers = self.request_handlers.lock().await;
-        if let Some(handler) = handlers.get(&request.method) {
-            match handler.handle(request.clone()).await {
-                Ok(response) => {
-                    let msg = JsonRpcMessage::Response(response);
-                    self.transport.send(&msg).await?;
+    /// Fail every pending request with an `IdleTimeout` error and close the transport
+    async fn disconnect_idle(&self, limit: Duration) -> anyhow::Error {
+        let error = JsonRpcError::idle_timeout(limit);
+        tracing::warn!("{}", error.message);
+        for (id, tx) in self.pending_requests.lock().await.drain() {
+            let _ = tx.send(JsonRpcResponse {
+                id,
+                error: Some(error.clone()),
+                ..Default::default()
+            });
+        }
+        if let Err(e) = self.transport.close().await {
+            debug!("Failed to close the idle transport: {:?}", e);
+        }
+        error.into()
+    }
+
+    /// Handle a request while reading on, responses are dispatched right away so a handler can
+    /// wait for the peer to answer a request it sent, and `notifications/cancelled` so it can
+    /// reach the request it cancels. Everything else is kept in `received`, to be handled in
+    /// arrival order once the request is done
+    async fn while_reading<'a, F: Future<Output = Result<()>>>(
+        &'a self,
+        handling: F,
+        next: &mut Receiving<'a>,
+        received: &mut VecDeque<Result<Option<JsonRpcMessage>>>,
+    ) -> Option<Result<()>> {
+        let handling = self.until_closed(handling);
+        tokio::pin!(handling);
+        loop {
+            // Past the end of the stream or the buffer limit, wait for the handler alone
+            let reading = received.len() < MAX_RECEIVED_WHILE_HANDLING
+                && !matches!(received.back(), Some(Ok(None) | Err(_)));
+            tokio::select! {
+                handled = &mut handling => return handled,
+                message = next.as_mut(), if reading => {
+                    *next = self.transport.receive();
+                    match message {
+                        Ok(Some(JsonRpcMessage::Response(response))) => {
+                            self.handle_response(response).await
+                        }
+                        Ok(Some(JsonRpcMessage::Notification(notification)))
+                            if notification.method == "notifications/cancelled" =>
+                        {
+                            if let Err(e) = self.handle_notification(notification).await {
+                                return Some(Err(e));
+                            }
+                        }
+                        other => received.push_back(other),
+                    }
                 }
-                Err(e) => {
-                    let error_response = JsonRpcResponse {
-                        id: request.id,
-                        result: None,
-                        error: Some(JsonRpcError {
-                            code: ErrorCode::InternalError as i32,
-                            message: e.to_string(),
-                            data: None,
-                        }),
-                        ..Default::default()
+            }
+        }
+    }
+
+    /// Run `handling` unless the peer goes away first, dropping it aborts the handler
+    /// work a handler spawned onto other tasks is not cancelled
+    async fn until_closed<F: Future<Output = Result<()>>>(
+        &self,
+        handling: F,
+    ) -> Option<Result<()>> {
+        tokio::select! {
+            result = handling => Some(result),
+            _ = self.transport.closed() => None,
+        }
+    }
+
+    async fn handle_request(&self, request: JsonRpcRequest, received_at: Instant) -> Result<()> {
+        let response = self.process_request(request, received_at).await;
+        self.send(&JsonRpcMessage::Response(response)).await
+    }
+
+    async fn process_request(
+        &self,
+        request: JsonRpcRequest,
+        received_at: Instant,
+    ) -> JsonRpcResponse {
+        if let Some(intercept) = &self.request_interceptor {
+            if let Err(error) = intercept(&request) {
+                debug!(method = %request.method, "Request rejected by the interceptor");
+                return JsonRpcResponse {
+                    id: request.id,
+                    error: Some(error),
+                    ..Default::default()
+                };
+            }
+        }
+        let Some(handler) = self.handlers.request(&request.method) else {
+            return JsonRpcResponse {
+                id: request.id,
+                error: Some(JsonRpcError::with_error_data(
+                    ErrorCode::MethodNotFound,
+                    format!("Method not found: {}", request.method),
+                    ErrorData::new(ErrorData::METHOD_NOT_FOUND, false),
+                )),
+                ..Default::default()
+            };
+        };
+        let started_at = Instant::now();
+        let handled = match self.handler_timeouts.get(&request.method) {
+            Some(limit) => match timeout(*limit, handler.handle(request.clone())).await {
+                Ok(handled) => handled,
+                Err(_) => {
+                    tracing::warn!(method = %request.method, "Request handler timed out");
+                    Err(JsonRpcError::with_error_data(
+                        ErrorCode::RequestTimeout,
+                        format!("Handler for {} timed out", request.method),
+                        ErrorData::new(ErrorData::TIMEOUT, true),
+                    )
+                    .into())
+                }
+            },
+            None => handler.handle(request.clone()).await,
+        };
+        match handled {
+            Ok(mut response) => {
+                if self.emit_timing_meta {
+                    let timing = ResponseTiming {
+                        duration_ms: started_at.elapsed().as_millis() as u64,
+                        queued_ms: started_at.duration_since(received_at).as_millis() as u64,
                     };
-                    let msg = JsonRpcMessage::Response(error_response);
-                    self.transport.send(&msg).await?;
+                    timing.merge_into(&mut response);
                 }
+                response
             }
-        } else {
-            self.transport
-                .send(&JsonRpcMessage::Response(JsonRpcResponse {
+            Err(e) => {
+                // Handlers return a `JsonRpcError` to pick the code, anything else is internal
+                let error = match e.downcast::<JsonRpcError>() {
+                    Ok(error) => error,
+                    Err(e) => JsonRpcError::with_error_data(
+                        ErrorCode::InternalError,
+                        e.to_string(),
+                        ErrorData::new(ErrorData::INTERNAL, false),
+                    ),
+                };
+                JsonRpcResponse {
                     id: request.id,
-                    error: Some(JsonRpcError {
-                        code: ErrorCode::MethodNotFound as i32,
-                        message: format!("Method not found: {}", request.method),
-                        data: None,
-                    }),
+                    result: None,
+                    error: Some(error),
                     ..Default::default()
-                }))
-                .await?;
+                }
+            }
+        }
+    }
+
+    /// Dispatch every message of a batch, responses to its requests go back as one batch
+    async fn handle_batch(
+        &self,
+        messages: Vec<JsonRpcMessage>,
+        received_at: Instant,
+    ) -> Result<()> {
+        if messages.is_empty() {
+            let error = JsonRpcError::new(ErrorCode::InvalidRequest, "empty batch");
+            return self
+                .send(&JsonRpcMessage::UnidentifiedError(
+                    JsonRpcUnidentifiedError::new(error),
+                ))
+                .await;
+        }
+        let mut requests = Vec::new();
+        for message in messages {
+            match message {
+                JsonRpcMessage::Request(request) => requests.push(request),
+                JsonRpcMessage::Response(response) => self.handle_response(response).await,
+                JsonRpcMessage::Notification(notification) => {
+                    self.handle_notification(notification).await?
+                }
+                JsonRpcMessage::Batch(_) => debug!("Ignoring nested batch"),
+                JsonRpcMessage::UnidentifiedError(error) => {
+                    debug!("Peer reported an error without id: {:?}", error.error)
+                }
+            }
+        }
+        // The requests of a batch are independent, run them concurrently
+        let responses: Vec<_> = futures::future::join_all(
+            requests
+                .into_iter()
+                .map(|request| self.process_request(request, received_at)),
+        )
+        .await
+        .into_iter()
+        .map(JsonRpcMessage::Response)
+        .collect();
+        if !responses.is_empty() {
+            self.send(&JsonRpcMessage::Batch(responses)).await?;
         }
         Ok(())
     }
+
+    async fn handle_response(&self, response: JsonRpcResponse) {
+        let mut pending = self.pending_requests.lock().await;
+        if let Some(tx) = pending.remove(&response.id) {
+            let _ = tx.send(response);
+        }
+    }
+
+    async fn handle_notification(&self, notification: JsonRpcNotification) -> Result<()> {
+        // Progress for a request sent with `on_progress` goes to its callback,
+        // unknown tokens fall through to the `notifications/progress` handler
+        if notification.method == "notifications/progress" {
+            if let Some(params) = notification
+                .params
+                .clone()
+                .and_then(|params| serde_json::from_value::<ProgressParams>(params).ok())
+            {
+                let callback = self
+                    .progress_callbacks
+                    .lock()
+                    .await
+                    .get(&params.progress_token)
+                    .cloned();
+                if let Some(callback) = callback {
+                    callback(params);
+                    return Ok(());
+                }
+            }
+        }
+        if notification.method == RESOURCE_CHUNK_METHOD {
+            if let Some(chunk) = notification
+                .params
+                .clone()
+                .and_then(|params| serde_json::from_value::<ResourceChunk>(params).ok())
+            {
+                let receiver = self
+                    .chunk_callbacks
+                    .lock()
+                    .await
+                    .get(&chunk.progress_token)
+                    .cloned();
+                match receiver {
+                    Some(ChunkReceiver::Callback(callback)) => {
+                        callback(chunk);
+                        return Ok(());
+                    }
+                    // Waiting for room stops reading the transport, so the peer slows down too
+                    Some(ChunkReceiver::Channel(tx)) => {
+                        let _ = tx.send(chunk).await;
+                        return Ok(());
+                    }
+                    None => {}
+                }
+            }
+        }
+        // Every handler sees the notification, a failing one doesn't stop the others
+        let handlers = self.handlers.notification(&notification.method);
+        let results = if self.concurrent_notification_handlers {
+            futures::future::join_all(handlers.iter().map(|h| h.handle(&notification))).await
+        } else {
+            let mut results = Vec::with_capacity(handlers.len());
+            for handler in &handlers {
+                results.push(handler.handle(&notification).await);
+            }
+            results
+        };
+        let mut malformed = None;
+        for e in results.into_iter().filter_map(Result::err) {
+            match e.downcast::<MalformedParams>() {
+                Ok(MalformedParams(error)) => {
+                    malformed.get_or_insert(error);
+                }
+                Err(error) => {
+                    tracing::warn!(
+                        method = %notification.method,
+                        error = %error,
+                        "Notification handler failed"
+                    );
+                    if let Some(on_error) = &self.on_notification_error {
+                        on_error(&notification.method, &error);
+                    }
+                }
+            }
+        }
+        if let Some(error) = malformed {
+            self.malformed_notifications.fetch_add(1, Ordering::Relaxed);
+            tracing::warn!(
+                method = %notification.method,
+                error = %error,
+                "Dropped notification with malformed params"
+            );
+            if let Some(on_malformed) = &self.on_malformed_notification {
+                let params = notification.params.unwrap_or_default();
+                on_malformed(&notification.method, &params, &error);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Messages read ahead while a request is handled, beyond that the peer waits as before
+const MAX_RECEIVED_WHILE_HANDLING: usize = 64;
+
+type Receiving<'a> = Pin<Box<dyn Future<Output = Result<Option<JsonRpcMessage>>> + Send + 'a>>;
+
+/// Server-side processing time reported in `result._meta` when timing meta is enabled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResponseTiming {
+    /// Time spent in the handler
+    pub duration_ms: u64,
+    /// Time between receiving the request and starting the handler
+    pub queued_ms: u64,
+}
+
+impl ResponseTiming {
+    /// Read the timing from a response, if the server emitted it
+    pub fn from_response(response: &JsonRpcResponse) -> Option<Self> {
+        let meta = response.result.as_ref()?.get("_meta")?;
+        serde_json::from_value(meta.clone()).ok()
+    }
+
+    // Keys already set by the handler are kept
+    fn merge_into(&self, response: &mut JsonRpcResponse) {
+        let Some(serde_json::Value::Object(result)) = response.result.as_mut() else {
+            return;
+        };
+        let meta = result
+            .entry("_meta")
+            .or_insert_with(|| serde_json::Value::Object(Default::default()));
+        if let serde_json::Value::Object(meta) = meta {
+            meta.entry("durationMs")
+                .or_insert_with(|| self.duration_ms.into());
+            meta.entry("queuedMs")
+                .or_insert_with(|| self.queued_ms.into());
+        }
+    }
+}
+
+/// Set `_meta.progressToken` on the params, giving them back unchanged if they aren't an object
+fn attach_progress_token(
+    params: Option<serde_json::Value>,
+    token: &ProgressToken,
+) -> std::result::Result<serde_json::Value, Option<serde_json::Value>> {
+    let mut params = match params {
+        None | Some(serde_json::Value::Null) => serde_json::Map::new(),
+        Some(serde_json::Value::Object(params)) => params,
+        other => return Err(other),
+    };
+    let meta = params
+        .entry("_meta")
+        .or_insert_with(|| serde_json::Value::Object(Default::default()));
+    match meta {
+        serde_json::Value::Object(meta) => {
+            meta.insert(
+                "progressToken".to_string(),
+                serde_json::to_value(token).unwrap_or_default(),
+            );
+        }
+        _ => return Err(Some(params.into())),
+    }
+    Ok(params.into())
+}
+
+/// Called with every `notifications/progress` whose token matches the request
+pub type ProgressCallback = Arc<dyn Fn(ProgressParams) + Send + Sync>;
+
+/// Called with every `notifications/resources/chunk` of a streamed read, in arrival order
+pub type ResourceChunkCallback = Arc<dyn Fn(ResourceChunk) + Send + Sync>;
+
+#[derive(Clone)]
+enum ChunkReceiver {
+    Callback(ResourceChunkCallback),
+    Channel(mpsc::Sender<ResourceChunk>),
 }
 
 /// The default request timeout, in milliseconds
 pub const DEFAULT_REQUEST_TIMEOUT_MSEC: u64 = 60000;
+#[derive(Default)]
 pub struct RequestOptions {
-    timeout: Duration,
+    timeout: Option<Duration>,
+    headers: HashMap<String, String>,
+    on_progress: Option<ProgressCallback>,
+    on_resource_chunk: Option<ChunkReceiver>,
 }
 
 impl RequestOptions {
+    /// Overrides the default request timeout of the protocol for this request
     pub fn timeout(self, timeout: Duration) -> Self {
-        Self { timeout }
+        Self {
+            timeout: Some(timeout),
+            ..self
+        }
+    }
+
+    /// Attach a header to this request only
+    /// used by transports that carry headers per message (e.g. SSE POSTs)
+    pub fn header(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.insert(key.into(), value.into());
+        self
+    }
+
+    /// Ask the peer for progress on this request, matching `notifications/progress` go to
+    /// `callback` until the response arrives. Ignored by batches and for non-object params
+    pub fn on_progress(
+        mut self,
+        callback: impl Fn(ProgressParams) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_progress = Some(Arc::new(callback));
+        self
+    }
+
+    /// Receive the chunks of a streamed `resources/read` until the response arrives,
+    /// see [`crate::types::ReadResourceRequest::stream`]
+    pub fn on_resource_chunk(
+        mut self,
+        callback: impl Fn(ResourceChunk) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_resource_chunk = Some(ChunkReceiver::Callback(Arc::new(callback)));
+        self
+    }
+
+    /// Like [`RequestOptions::on_resource_chunk`], sending the chunks to `tx`. While it is full
+    /// nothing else is read from the transport
+    pub(crate) fn resource_chunks(mut self, tx: mpsc::Sender<ResourceChunk>) -> Self {
+        self.on_resource_chunk = Some(ChunkReceiver::Channel(tx));
+        self
     }
 }
 
-impl Default for RequestOptions {
-    fn default() -> Self {
+/// Receives the method, params and parse error of a notification its handler couldn't accept
+pub type MalformedNotificationFn =
+    Arc<dyn Fn(&str, &serde_json::Value, &serde_json::Error) + Send + Sync>;
+
+/// Receives the method and error of a notification handler that failed, the other handlers
+/// of the notification still run
+pub type NotificationErrorFn = Arc<dyn Fn(&str, &anyhow::Error) + Send + Sync>;
+
+/// Runs before the handler of every incoming request, an error is sent back instead of handling it
+pub type RequestInterceptorFn =
+    Arc<dyn Fn(&JsonRpcRequest) -> std::result::Result<(), JsonRpcError> + Send + Sync>;
+
+/// Sends notifications through the outbound FIFO of a protocol without keeping its handlers
+/// alive, so handlers can report to the peer while their request is in flight
+pub struct Notifier<T: Transport> {
+    transport: Arc<T>,
+    outbound: Arc<Mutex<()>>,
+}
+
+impl<T: Transport> Clone for Notifier<T> {
+    fn clone(&self) -> Self {
         Self {
-            timeout: Duration::from_millis(DEFAULT_REQUEST_TIMEOUT_MSEC),
+            transport: self.transport.clone(),
+            outbound: self.outbound.clone(),
         }
     }
 }
 
+impl<T: Transport> Notifier<T> {
+    pub async fn notify(&self, method: &str, params: Option<serde_json::Value>) -> Result<()> {
+        let notification = JsonRpcMessage::Notification(JsonRpcNotification {
+            method: method.to_string(),
+            params,
+            ..Default::default()
+        });
+        let _guard = self.outbound.lock().await;
+        self.transport.send(&notification).await
+    }
+}
+
+type NotificationHandlers = Vec<Arc<dyn NotificationHandler>>;
+
+/// Request and notification handlers shared by a protocol and every clone of this handle.
+/// A notification method can have several handlers, each gets every notification.
+/// Lookups clone the handler out of the map and release the read lock before it runs, so a
+/// handler may register or replace handlers, its own method included, without deadlocking
+#[derive(Clone, Default)]
+pub struct Handlers {
+    requests: Arc<RwLock<HashMap<String, Arc<dyn RequestHandler>>>>,
+    notifications: Arc<RwLock<HashMap<String, NotificationHandlers>>>,
+}
+
+impl Handlers {
+    /// Register or replace a typed request handler, requests already running keep the old one
+    pub fn set_request_handler<Req, Resp>(
+        &self,
+        method: &str,
+        handler: impl Fn(Req) -> Pin<Box<dyn std::future::Future<Output = Result<Resp>> + Send>>
+            + Send
+            + Sync
+            + 'static,
+    ) where
+        Req: DeserializeOwned + Send + Sync + 'static,
+        Resp: Serialize + Send + Sync + 'static,
+    {
+        let handler = TypedRequestHandler {
+            handler: Box::new(handler),
+            _phantom: std::marker::PhantomData,
+        };
+        self.requests
+            .write()
+            .insert(method.to_string(), Arc::new(handler));
+    }
+
+    /// Register a typed notification handler, replacing all handlers of `method`
+    pub fn set_notification_handler<N>(
+        &self,
+        method: &str,
+        handler: impl Fn(N) -> Pin<Box<dyn std::future::Future<Output = Result<()>> + Send>>
+            + Send
+            + Sync
+            + 'static,
+    ) where
+        N: DeserializeOwned + Send + Sync + 'static,
+    {
+        self.notifications.write().insert(
+            method.to_string(),
+            vec![typed_notification_handler(handler)],
+        );
+    }
+
+    /// Register a typed notification handler next to those `method` already has,
+    /// they run in the order they were added
+    pub fn add_notification_handler<N>(
+        &self,
+        method: &str,
+        handler: impl Fn(N) -> Pin<Box<dyn std::future::Future<Output = Result<()>> + Send>>
+            + Send
+            + Sync
+            + 'static,
+    ) where
+        N: DeserializeOwned + Send + Sync + 'static,
+    {
+        self.notifications
+            .write()
+            .entry(method.to_string())
+            .or_default()
+            .push(typed_notification_handler(handler));
+    }
+
+    /// Returns whether a handler was registered for `method`
+    pub fn remove_request_handler(&self, method: &str) -> bool {
+        self.requests.write().remove(method).is_some()
+    }
+
+    /// Removes every handler of `method`, returns whether there was one
+    pub fn remove_notification_handler(&self, method: &str) -> bool {
+        self.notifications.write().remove(method).is_some()
+    }
+
+    pub fn has_request_handler(&self, method: &str) -> bool {
+        self.requests.read().contains_key(method)
+    }
+
+    pub fn has_notification_handler(&self, method: &str) -> bool {
+        self.notifications.read().contains_key(method)
+    }
+
+    // The guard is a temporary of the statement, it is gone before the caller awaits the handler
+    fn request(&self, method: &str) -> Option<Arc<dyn RequestHandler>> {
+        self.requests.read().get(method).cloned()
+    }
+
+    fn notification(&self, method: &str) -> NotificationHandlers {
+        self.notifications
+            .read()
+            .get(method)
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+fn typed_notification_handler<N>(
+    handler: impl Fn(N) -> Pin<Box<dyn std::future::Future<Output = Result<()>> + Send>>
+        + Send
+        + Sync
+        + 'static,
+) -> Arc<dyn NotificationHandler>
+where
+    N: DeserializeOwned + Send + Sync + 'static,
+{
+    Arc::new(TypedNotificationHandler {
+        handler: Box::new(handler),
+        _phantom: std::marker::PhantomData,
+    })
+}
+
 pub struct ProtocolBuilder<T: Transport> {
-    transport: T,
-    request_handlers: HashMap<String, Box<dyn RequestHandler>>,
-    notification_handlers: HashMap<String, Box<dyn NotificationHandler>>,
+    transport: Arc<T>,
+    outbound: Arc<Mutex<()>>,
+    emit_timing_meta: bool,
+    handlers: Handlers,
+    handler_timeouts: HashMap<String, Duration>,
+    default_request_timeout: Duration,
+    idle_timeout: Option<Duration>,
+    on_malformed_notification: Option<MalformedNotificationFn>,
+    on_notification_error: Option<NotificationErrorFn>,
+    concurrent_notification_handlers: bool,
+    request_interceptor: Option<RequestInterceptorFn>,
 }
 impl<T: Transport> ProtocolBuilder<T> {
     pub fn new(transport: T) -> Self {
         Self {
-            transport,
-            request_handlers: HashMap::new(),
-            notification_handlers: HashMap::new(),
+            transport: Arc::new(transport),
+            outbound: Arc::new(Mutex::new(())),
+            emit_timing_meta: false,
+            handlers: Handlers::default(),
+            handler_timeouts: HashMap::new(),
+            default_request_timeout: Duration::from_millis(DEFAULT_REQUEST_TIMEOUT_MSEC),
+            idle_timeout: None,
+            on_malformed_notification: None,
+            on_notification_error: None,
+            concurrent_notification_handlers: false,
+            request_interceptor: None,
         }
     }
     /// Register a typed request handler
     pub fn request_handler<Req, Resp>(
-        mut self,
+        self,
         method: &str,
         handler: impl Fn(Req) -> Pin<Box<dyn std::future::Future<Output = Result<Resp>> + Send>>
             + Send
@@ -210,22 +993,54 @@ impl<T: Transport> ProtocolBuilder<T> {
         Req: DeserializeOwned + Send + Sync + 'static,
         Resp: Serialize + Send + Sync + 'static,
     {
-        let handler = TypedRequestHandler {
-            handler: Box::new(handler),
-            _phantom: std::marker::PhantomData,
-        };
+        self.handlers.set_request_handler(method, handler);
+        self
+    }
+
+    /// Add `durationMs`/`queuedMs` to `result._meta` of every successful response
+    pub fn emit_timing_meta(mut self, enabled: bool) -> Self {
+        self.emit_timing_meta = enabled;
+        self
+    }
+
+    /// Abort handling `method` after `limit`, answering with a `RequestTimeout` error
+    pub fn handler_timeout(mut self, method: &str, limit: Duration) -> Self {
+        self.handler_timeouts.insert(method.to_string(), limit);
+        self
+    }
 
-        self.request_handlers
-            .insert(method.to_string(), Box::new(handler));
+    /// Timeout of requests whose [`RequestOptions`] don't set one
+    pub fn default_request_timeout(mut self, timeout: Duration) -> Self {
+        self.default_request_timeout = timeout;
         self
     }
 
+    /// Close the transport and fail pending requests when nothing is received for `limit`,
+    /// the peer is expected to send pings or other traffic more often than that
+    pub fn idle_timeout(mut self, limit: Duration) -> Self {
+        self.idle_timeout = Some(limit);
+        self
+    }
+
+    /// Handle for handlers to send notifications on the protocol being built
+    pub fn notifier(&self) -> Notifier<T> {
+        Notifier {
+            transport: self.transport.clone(),
+            outbound: self.outbound.clone(),
+        }
+    }
+
+    /// Handle to register handlers after the protocol is built, e.g. from inside a handler
+    pub fn handlers(&self) -> Handlers {
+        self.handlers.clone()
+    }
+
     pub fn has_request_handler(&self, method: &str) -> bool {
-        self.request_handlers.contains_key(method)
+        self.handlers.has_request_handler(method)
     }
 
     pub fn notification_handler<N>(
-        mut self,
+        self,
         method: &str,
         handler: impl Fn(N) -> Pin<Box<dyn std::future::Future<Output = Result<()>> + Send>>
             + Send
@@ -235,23 +1050,83 @@ impl<T: Transport> ProtocolBuilder<T> {
     where
         N: DeserializeOwned + Send + Sync + 'static,
     {
-        self.notification_handlers.insert(
-            method.to_string(),
-            Box::new(TypedNotificationHandler {
-                handler: Box::new(handler),
-                _phantom: std::marker::PhantomData,
-            }),
-        );
+        self.handlers.set_notification_handler(method, handler);
+        self
+    }
+
+    /// Register a notification handler without replacing those `method` already has,
+    /// every handler gets each notification
+    pub fn add_notification_handler<N>(
+        self,
+        method: &str,
+        handler: impl Fn(N) -> Pin<Box<dyn std::future::Future<Output = Result<()>> + Send>>
+            + Send
+            + Sync
+            + 'static,
+    ) -> Self
+    where
+        N: DeserializeOwned + Send + Sync + 'static,
+    {
+        self.handlers.add_notification_handler(method, handler);
+        self
+    }
+
+    /// Called with the error of a notification handler that failed, failures are logged either way
+    pub fn on_notification_error(
+        mut self,
+        callback: impl Fn(&str, &anyhow::Error) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_notification_error = Some(Arc::new(callback));
+        self
+    }
+
+    /// Run the handlers of a notification concurrently instead of one after the other,
+    /// the next message is still read once all of them finished
+    pub fn concurrent_notification_handlers(mut self, enabled: bool) -> Self {
+        self.concurrent_notification_handlers = enabled;
+        self
+    }
+
+    /// Called instead of the typed handler when a notification's params fail to deserialize
+    pub fn on_malformed_notification(
+        mut self,
+        callback: impl Fn(&str, &serde_json::Value, &serde_json::Error) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_malformed_notification = Some(Arc::new(callback));
+        self
+    }
+
+    /// Check every incoming request before its handler runs, also requests for unknown methods
+    /// and those of a batch. Replaces a previously set interceptor
+    pub fn request_interceptor(
+        mut self,
+        interceptor: impl Fn(&JsonRpcRequest) -> std::result::Result<(), JsonRpcError>
+            + Send
+            + Sync
+            + 'static,
+    ) -> Self {
+        self.request_interceptor = Some(Arc::new(interceptor));
         self
     }
 
     pub fn build(self) -> Protocol<T> {
         Protocol {
-            transport: Arc::new(self.transport),
-            request_handlers: Arc::new(Mutex::new(self.request_handlers)),
-            notification_handlers: Arc::new(Mutex::new(self.notification_handlers)),
+            transport: self.transport,
+            outbound: self.outbound,
+            emit_timing_meta: self.emit_timing_meta,
+            handlers: self.handlers,
+            handler_timeouts: Arc::new(self.handler_timeouts),
+            default_request_timeout: self.default_request_timeout,
+            idle_timeout: self.idle_timeout,
+            on_malformed_notification: self.on_malformed_notification,
+            malformed_notifications: Arc::new(AtomicU64::new(0)),
+            on_notification_error: self.on_notification_error,
+            concurrent_notification_handlers: self.concurrent_notification_handlers,
+            request_interceptor: self.request_interceptor,
             request_id: Arc::new(AtomicU64::new(0)),
             pending_requests: Arc::new(Mutex::new(HashMap::new())),
+            progress_callbacks: Arc::new(Mutex::new(HashMap::new())),
+            chunk_callbacks: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 }
@@ -264,20 +1139,32 @@ trait RequestHandler: Send + Sync {
 
 #[async_trait]
 trait NotificationHandler: Send + Sync {
-    async fn handle(&self, notification: JsonRpcNotification) -> Result<()>;
+    /// Fails with [`MalformedParams`] without calling the handler when params don't parse
+    async fn handle(&self, notification: &JsonRpcNotification) -> Result<()>;
 }
 
+#[derive(Debug)]
+struct MalformedParams(serde_json::Error);
+
+impl std::fmt::Display for MalformedParams {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Malformed notification params: {}", self.0)
+    }
+}
+
+impl std::error::Error for MalformedParams {}
+
+type HandlerFn<Req, Resp> = Box<
+    dyn Fn(Req) -> Pin<Box<dyn std::future::Future<Output = Result<Resp>> + Send>> + Send + Sync,
+>;
+
 // Update the TypedRequestHandler to use async handlers
 struct TypedRequestHandler<Req, Resp>
 where
     Req: DeserializeOwned + Send + Sync + 'static,
     Resp: Serialize + Send + Sync + 'static,
 {
-    handler: Box<
-        dyn Fn(Req) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<Resp>> + Send>>
-            + Send
-            + Sync,
-    >,
+    handler: HandlerFn<Req, Resp>,
     _phantom: std::marker::PhantomData<(Req, Resp)>,
 }
 
@@ -288,11 +1175,11 @@ where
     Resp: Serialize + Send + Sync + 'static,
 {
     async fn handle(&self, request: JsonRpcRequest) -> Result<JsonRpcResponse> {
-        let params: Req = if request.params.is_none() || request.params.as_ref().unwrap().is_null()
-        {
-            serde_json::from_value(serde_json::Value::Null)?
-        } else {
-            serde_json::from_value(request.params.unwrap())?
+        let params: Req = match request.params {
+            None | Some(serde_json::Value::Null) => {
+                serde_json::from_value(serde_json::Value::Null)?
+            }
+            Some(params) => serde_json::from_value(params)?,
         };
         let result = (self.handler)(params).await?;
         Ok(JsonRpcResponse {
@@ -308,11 +1195,7 @@ struct TypedNotificationHandler<N>
 where
     N: DeserializeOwned + Send + Sync + 'static,
 {
-    handler: Box<
-        dyn Fn(N) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<()>> + Send>>
-            + Send
-            + Sync,
-    >,
+    handler: HandlerFn<N, ()>,
     _phantom: std::marker::PhantomData<N>,
 }
 
@@ -321,29 +1204,662 @@ impl<N> NotificationHandler for TypedNotificationHandler<N>
 where
     N: DeserializeOwned + Send + Sync + 'static,
 {
-    async fn handle(&self, notification: JsonRpcNotification) -> Result<()> {
-        let params: N =
-            if notification.params.is_none() || notification.params.as_ref().unwrap().is_null() {
-                serde_json::from_value(serde_json::Value::Null)?
-            } else {
-                match &notification.params {
-                    Some(params) => {
-                        let res = serde_json::from_value(params.clone());
-                        match res {
-                            Ok(r) => r,
-                            Err(e) => {
-                                tracing::warn!(
-                                    "Failed to parse notification params: {:?}. Params: {:?}",
-                                    e,
-                                    notification.params
-                                );
-                                serde_json::from_value(serde_json::Value::Null)?
-                            }
-                        }
-                    }
-                    None => serde_json::from_value(serde_json::Value::Null)?,
-                }
-            };
+    async fn handle(&self, notification: &JsonRpcNotification) -> Result<()> {
+        // Missing params parse as `null`, so unit params need no special casing
+        let params = notification.params.clone().unwrap_or_default();
+        let params: N = serde_json::from_value(params).map_err(MalformedParams)?;
         (self.handler)(params).await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::{ClientInMemoryTransport, ServerInMemoryTransport, ServerSseTransport};
+    use std::sync::OnceLock;
+    use tokio::sync::broadcast;
+
+    const PAIRS: u64 = 100;
+
+    // Each request emits a notification before its response
+    fn build_protocol<T: Transport + Clone>(transport: T) -> Protocol<T> {
+        let protocol: Arc<OnceLock<Protocol<T>>> = Arc::new(OnceLock::new());
+        let handle = protocol.clone();
+        let built = Protocol::builder(transport)
+            .request_handler("work", move |req: serde_json::Value| {
+                let protocol = handle.get().cloned().expect("protocol built");
+                Box::pin(async move {
+                    protocol
+                        .notify("notifications/progress", Some(req.clone()))
+                        .await?;
+                    Ok(req)
+                })
+            })
+            .build();
+        let _ = protocol.set(built.clone());
+        built
+    }
+
+    fn request(id: u64) -> JsonRpcMessage {
+        JsonRpcMessage::Request(JsonRpcRequest {
+            id,
+            method: "work".to_string(),
+            params: Some(serde_json::json!({ "id": id })),
+            ..Default::default()
+        })
+    }
+
+    fn assert_ordered(received: Vec<JsonRpcMessage>) {
+        assert_eq!(received.len() as u64, PAIRS * 2);
+        for (id, pair) in received.chunks(2).enumerate() {
+            match (&pair[0], &pair[1]) {
+                (JsonRpcMessage::Notification(n), JsonRpcMessage::Response(r)) => {
+                    assert_eq!(n.params, Some(serde_json::json!({ "id": id })));
+                    assert_eq!(r.id, id as u64);
+                }
+                other => panic!("Unexpected ordering: {:?}", other),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_inmemory_notification_ordering() -> Result<()> {
+        let transport = ClientInMemoryTransport::new(|t: ServerInMemoryTransport| {
+            tokio::spawn(async move {
+                build_protocol(t).listen().await.unwrap();
+            })
+        });
+        transport.open().await?;
+
+        for id in 0..PAIRS {
+            transport.send(&request(id)).await?;
+        }
+        let mut received = Vec::new();
+        while (received.len() as u64) < PAIRS * 2 {
+            received.push(transport.receive().await?.expect("message"));
+        }
+        assert_ordered(received);
+
+        transport.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sse_notification_ordering() -> Result<()> {
+        let (sse_tx, mut sse_rx) = broadcast::channel(1024);
+        let transport = ServerSseTransport::new(sse_tx);
+        let protocol = build_protocol(transport.clone());
+        tokio::spawn(async move { protocol.listen().await });
+
+        for id in 0..PAIRS {
+            transport.send_message(request(id)).await?;
+        }
+        let mut received = Vec::new();
+        while (received.len() as u64) < PAIRS * 2 {
+            received.push(serde_json::from_str(&sse_rx.recv().await?)?);
+        }
+        assert_ordered(received);
+        Ok(())
+    }
+
+    async fn roundtrip(emit_timing_meta: bool) -> Result<JsonRpcResponse> {
+        let transport = ClientInMemoryTransport::new(move |t: ServerInMemoryTransport| {
+            tokio::spawn(async move {
+                Protocol::builder(t)
+                    .emit_timing_meta(emit_timing_meta)
+                    .request_handler("slow", |_: serde_json::Value| {
+                        Box::pin(async move {
+                            tokio::time::sleep(Duration::from_millis(20)).await;
+                            Ok(serde_json::json!({"ok": true, "_meta": {"traceId": "abc"}}))
+                        })
+                    })
+                    .build()
+                    .listen()
+                    .await
+                    .unwrap();
+            })
+        });
+        transport.open().await?;
+        transport
+            .send(&JsonRpcMessage::Request(JsonRpcRequest {
+                id: 1,
+                method: "slow".to_string(),
+                ..Default::default()
+            }))
+            .await?;
+        let response = match transport.receive().await? {
+            Some(JsonRpcMessage::Response(response)) => response,
+            other => panic!("Unexpected message: {:?}", other),
+        };
+        transport.close().await?;
+        Ok(response)
+    }
+
+    #[tokio::test]
+    async fn test_timing_meta() -> Result<()> {
+        let response = roundtrip(true).await?;
+        let timing = ResponseTiming::from_response(&response).expect("timing meta");
+        assert!(timing.duration_ms >= 20 && timing.duration_ms < 5000);
+        assert!(timing.queued_ms < 5000);
+        // Handler provided meta is preserved
+        assert_eq!(response.result.unwrap()["_meta"]["traceId"], "abc");
+
+        let response = roundtrip(false).await?;
+        assert!(ResponseTiming::from_response(&response).is_none());
+        assert_eq!(
+            serde_json::to_string(&response)?,
+            r#"{"id":1,"result":{"_meta":{"traceId":"abc"},"ok":true},"jsonrpc":"2.0"}"#
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_slow_handlers_run_concurrently() -> Result<()> {
+        let protocol = Protocol::builder(ServerInMemoryTransport::default())
+            .request_handler("slow", |_: serde_json::Value| {
+                Box::pin(async move {
+                    tokio::time::sleep(Duration::from_millis(200)).await;
+                    Ok(serde_json::json!({}))
+                })
+            })
+            .build();
+        let slow = |id| JsonRpcRequest {
+            id,
+            method: "slow".to_string(),
+            ..Default::default()
+        };
+
+        let started = Instant::now();
+        let (first, second) = tokio::join!(
+            protocol.process_request(slow(1), started),
+            protocol.process_request(slow(2), started)
+        );
+        assert!(first.error.is_none() && second.error.is_none());
+        assert!(started.elapsed() < Duration::from_millis(350));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_progress_routed_to_request() -> Result<()> {
+        let transport = ClientInMemoryTransport::new(|t: ServerInMemoryTransport| {
+            tokio::spawn(async move {
+                let protocol: Arc<OnceLock<Protocol<ServerInMemoryTransport>>> =
+                    Arc::new(OnceLock::new());
+                let handle = protocol.clone();
+                let built = Protocol::builder(t)
+                    .request_handler("work", move |req: serde_json::Value| {
+                        let protocol = handle.get().cloned().expect("protocol built");
+                        Box::pin(async move {
+                            let token = req["_meta"]["progressToken"].clone();
+                            for (token, progress) in
+                                [(&token, 1), (&"other".into(), 5), (&token, 2)]
+                            {
+                                let params = serde_json::json!({
+                                    "progressToken": token, "progress": progress, "total": 2
+                                });
+                                protocol
+                                    .notify("notifications/progress", Some(params))
+                                    .await?;
+                            }
+                            Ok(serde_json::json!({"input": req["input"]}))
+                        })
+                    })
+                    .build();
+                let _ = protocol.set(built.clone());
+                built.listen().await.unwrap();
+            })
+        });
+        transport.open().await?;
+
+        let unmatched = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let unmatched_handle = unmatched.clone();
+        let client = Protocol::builder(transport.clone())
+            .notification_handler("notifications/progress", move |p: ProgressParams| {
+                unmatched_handle.lock().unwrap().push(p.progress_token);
+                Box::pin(async move { Ok(()) })
+            })
+            .build();
+        let client_clone = client.clone();
+        let listen = tokio::spawn(async move { client_clone.listen().await });
+
+        let progress = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let progress_handle = progress.clone();
+        let options = RequestOptions::default().on_progress(move |p| {
+            progress_handle.lock().unwrap().push((p.progress, p.total));
+        });
+        let response = client
+            .request("work", Some(serde_json::json!({"input": 7})), options)
+            .await?;
+        // The caller's params are kept next to the token
+        assert_eq!(response.result, Some(serde_json::json!({"input": 7})));
+        assert_eq!(
+            *progress.lock().unwrap(),
+            vec![(1.0, Some(2.0)), (2.0, Some(2.0))]
+        );
+        assert_eq!(
+            *unmatched.lock().unwrap(),
+            vec![ProgressToken::String("other".to_string())]
+        );
+        assert!(client.progress_callbacks.lock().await.is_empty());
+
+        // The server protocol references itself through its handler, stop reading to close
+        listen.abort();
+        transport.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_malformed_notification_skips_handler() -> Result<()> {
+        use crate::types::CancelledParams;
+
+        let cancelled = Arc::new(AtomicU64::new(0));
+        let malformed = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let (handler_cancelled, hook_malformed) = (cancelled.clone(), malformed.clone());
+        let protocol = Protocol::builder(ServerInMemoryTransport::default())
+            .notification_handler("notifications/cancelled", move |_: CancelledParams| {
+                handler_cancelled.fetch_add(1, Ordering::SeqCst);
+                Box::pin(async move { Ok(()) })
+            })
+            .notification_handler("notifications/initialized", |_: ()| {
+                Box::pin(async move { Ok(()) })
+            })
+            .on_malformed_notification(move |method, params, _error| {
+                hook_malformed
+                    .lock()
+                    .unwrap()
+                    .push((method.to_string(), params.clone()));
+            })
+            .build();
+        let notification = |method: &str, params| JsonRpcNotification {
+            method: method.to_string(),
+            params,
+            ..Default::default()
+        };
+
+        let bad = serde_json::json!({"requestId": "not a number"});
+        protocol
+            .handle_notification(notification("notifications/cancelled", Some(bad.clone())))
+            .await?;
+        assert_eq!(cancelled.load(Ordering::SeqCst), 0);
+        assert_eq!(
+            *malformed.lock().unwrap(),
+            vec![("notifications/cancelled".to_string(), bad)]
+        );
+        assert_eq!(protocol.malformed_notification_count(), 1);
+
+        // Well formed and unit params still reach their handlers
+        let good = serde_json::json!({"requestId": 3});
+        protocol
+            .handle_notification(notification("notifications/cancelled", Some(good)))
+            .await?;
+        protocol
+            .handle_notification(notification("notifications/initialized", None))
+            .await?;
+        assert_eq!(cancelled.load(Ordering::SeqCst), 1);
+        assert_eq!(protocol.malformed_notification_count(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_notification_fans_out_to_every_handler() -> Result<()> {
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let errors = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let (metrics, ui, hook_errors) = (seen.clone(), seen.clone(), errors.clone());
+        let protocol = Protocol::builder(ServerInMemoryTransport::default())
+            .notification_handler("notifications/progress", |_: ProgressParams| {
+                Box::pin(async move { Err(anyhow::anyhow!("metrics backend down")) })
+            })
+            .add_notification_handler("notifications/progress", move |p: ProgressParams| {
+                metrics.lock().unwrap().push(("metrics", p.progress));
+                Box::pin(async move { Ok(()) })
+            })
+            .add_notification_handler("notifications/progress", move |p: ProgressParams| {
+                ui.lock().unwrap().push(("ui", p.progress));
+                Box::pin(async move { Ok(()) })
+            })
+            .on_notification_error(move |method, error| {
+                hook_errors
+                    .lock()
+                    .unwrap()
+                    .push((method.to_string(), error.to_string()));
+            })
+            .build();
+        let progress = |progress: f64| JsonRpcNotification {
+            method: "notifications/progress".to_string(),
+            params: Some(serde_json::json!({"progressToken": "t", "progress": progress})),
+            ..Default::default()
+        };
+
+        // The failing handler neither stops the others nor the protocol
+        protocol.handle_notification(progress(0.5)).await?;
+        assert_eq!(*seen.lock().unwrap(), vec![("metrics", 0.5), ("ui", 0.5)]);
+        assert_eq!(
+            *errors.lock().unwrap(),
+            vec![(
+                "notifications/progress".to_string(),
+                "metrics backend down".to_string()
+            )]
+        );
+
+        // `notification_handler` still replaces every handler of the method
+        protocol
+            .handlers()
+            .set_notification_handler("notifications/progress", |_: ProgressParams| {
+                Box::pin(async move { Ok(()) })
+            });
+        protocol.handle_notification(progress(1.0)).await?;
+        assert_eq!(seen.lock().unwrap().len(), 2);
+        assert_eq!(errors.lock().unwrap().len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_notification_handlers() -> Result<()> {
+        // Each handler waits for the other, so this only finishes when they run at once
+        let barrier = Arc::new(tokio::sync::Barrier::new(2));
+        let (first, second) = (barrier.clone(), barrier.clone());
+        let protocol = Protocol::builder(ServerInMemoryTransport::default())
+            .concurrent_notification_handlers(true)
+            .add_notification_handler("vendor/tick", move |_: ()| {
+                let barrier = first.clone();
+                Box::pin(async move {
+                    barrier.wait().await;
+                    Ok(())
+                })
+            })
+            .add_notification_handler("vendor/tick", move |_: ()| {
+                let barrier = second.clone();
+                Box::pin(async move {
+                    barrier.wait().await;
+                    Ok(())
+                })
+            })
+            .build();
+        timeout(
+            Duration::from_secs(5),
+            protocol.handle_notification(JsonRpcNotification {
+                method: "vendor/tick".to_string(),
+                ..Default::default()
+            }),
+        )
+        .await??;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_responses_in_request_order() -> Result<()> {
+        let transport = ClientInMemoryTransport::new(|t: ServerInMemoryTransport| {
+            tokio::spawn(async move {
+                Protocol::builder(t)
+                    .request_handler("slow", |_: serde_json::Value| {
+                        Box::pin(async move {
+                            tokio::time::sleep(Duration::from_millis(100)).await;
+                            Ok(serde_json::json!({}))
+                        })
+                    })
+                    .request_handler("fast", |_: serde_json::Value| {
+                        Box::pin(async move { Ok(serde_json::json!({})) })
+                    })
+                    .build()
+                    .listen()
+                    .await
+                    .unwrap();
+            })
+        });
+        transport.open().await?;
+        for (id, method) in [(1, "slow"), (2, "fast")] {
+            transport
+                .send(&JsonRpcMessage::Request(JsonRpcRequest {
+                    id,
+                    method: method.to_string(),
+                    ..Default::default()
+                }))
+                .await?;
+        }
+
+        let mut ids = Vec::new();
+        for _ in 0..2 {
+            match transport.receive().await? {
+                Some(JsonRpcMessage::Response(response)) => ids.push(response.id),
+                other => panic!("Unexpected message: {:?}", other),
+            }
+        }
+        assert_eq!(ids, vec![1, 2]);
+
+        transport.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_empty_batch_is_invalid_request() -> Result<()> {
+        let transport = ClientInMemoryTransport::new(|t: ServerInMemoryTransport| {
+            tokio::spawn(async move { Protocol::builder(t).build().listen().await.unwrap() })
+        });
+        transport.open().await?;
+        let batch: JsonRpcMessage = serde_json::from_str("[]")?;
+        transport.send(&batch).await?;
+
+        let error = transport.receive().await?.unwrap();
+        assert!(matches!(
+            &error,
+            JsonRpcMessage::UnidentifiedError(error)
+                if error.error.code == ErrorCode::InvalidRequest as i32
+        ));
+        let json = serde_json::to_value(&error)?;
+        assert_eq!(json["id"], serde_json::Value::Null);
+        assert_eq!(json["jsonrpc"], "2.0");
+        // The peer decodes it back
+        assert_eq!(serde_json::from_value::<JsonRpcMessage>(json)?, error);
+
+        transport.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cancellation_reaches_running_request() -> Result<()> {
+        use crate::types::CancelledParams;
+
+        let transport = ClientInMemoryTransport::new(|t: ServerInMemoryTransport| {
+            tokio::spawn(async move {
+                let cancel = Arc::new(tokio::sync::Notify::new());
+                let cancelled = cancel.clone();
+                Protocol::builder(t)
+                    .request_handler("slow", move |_: serde_json::Value| {
+                        let cancelled = cancelled.clone();
+                        Box::pin(async move {
+                            cancelled.notified().await;
+                            Ok(serde_json::json!("cancelled"))
+                        })
+                    })
+                    .notification_handler("notifications/cancelled", move |_: CancelledParams| {
+                        cancel.notify_one();
+                        Box::pin(async move { Ok(()) })
+                    })
+                    .build()
+                    .listen()
+                    .await
+                    .unwrap();
+            })
+        });
+        transport.open().await?;
+        transport
+            .send(&JsonRpcMessage::Request(JsonRpcRequest {
+                id: 1,
+                method: "slow".to_string(),
+                ..Default::default()
+            }))
+            .await?;
+        transport
+            .send(&JsonRpcMessage::Notification(JsonRpcNotification {
+                method: "notifications/cancelled".to_string(),
+                params: Some(serde_json::json!({ "requestId": 1 })),
+                ..Default::default()
+            }))
+            .await?;
+
+        // Held back until the request is done, the notification would never end it
+        match tokio::time::timeout(Duration::from_secs(5), transport.receive()).await?? {
+            Some(JsonRpcMessage::Response(response)) => {
+                assert_eq!(response.result, Some(serde_json::json!("cancelled")))
+            }
+            other => panic!("Unexpected message: {:?}", other),
+        }
+
+        transport.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_handler_timeout() -> Result<()> {
+        let sleep_for = |duration| {
+            move |_: serde_json::Value| -> Pin<Box<dyn Future<Output = Result<serde_json::Value>> + Send>> {
+                Box::pin(async move {
+                    tokio::time::sleep(duration).await;
+                    Ok(serde_json::json!({}))
+                })
+            }
+        };
+        let protocol = Protocol::builder(ServerInMemoryTransport::default())
+            .request_handler("stuck", sleep_for(Duration::from_secs(60)))
+            .request_handler("quick", sleep_for(Duration::from_millis(10)))
+            .handler_timeout("stuck", Duration::from_millis(50))
+            .handler_timeout("quick", Duration::from_secs(5))
+            .build();
+        let request = |id, method: &str| JsonRpcRequest {
+            id,
+            method: method.to_string(),
+            ..Default::default()
+        };
+
+        let started = Instant::now();
+        let response = protocol.process_request(request(1, "stuck"), started).await;
+        assert!(started.elapsed() < Duration::from_secs(5));
+        let error = response.error.expect("timeout error");
+        assert_eq!(error.code, ErrorCode::RequestTimeout as i32);
+        assert!(error.error_data().unwrap().retriable);
+
+        let response = protocol
+            .process_request(request(2, "quick"), Instant::now())
+            .await;
+        assert!(response.error.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_aborts_in_flight_request() -> Result<()> {
+        struct SetOnDrop(Arc<std::sync::atomic::AtomicBool>);
+        impl Drop for SetOnDrop {
+            fn drop(&mut self) {
+                self.0.store(true, Ordering::SeqCst);
+            }
+        }
+
+        let (sse_tx, _sse_rx) = broadcast::channel(16);
+        let transport = ServerSseTransport::new(sse_tx);
+        let started = Arc::new(tokio::sync::Notify::new());
+        let aborted = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let (handler_started, handler_aborted) = (started.clone(), aborted.clone());
+        let protocol = Protocol::builder(transport.clone())
+            .request_handler("slow", move |_: serde_json::Value| {
+                let started = handler_started.clone();
+                let aborted = SetOnDrop(handler_aborted.clone());
+                Box::pin(async move {
+                    let _aborted = aborted;
+                    started.notify_one();
+                    tokio::time::sleep(Duration::from_secs(60)).await;
+                    Ok(serde_json::json!({}))
+                })
+            })
+            .build();
+        let listen = tokio::spawn(async move { protocol.listen().await });
+
+        transport
+            .send_message(JsonRpcMessage::Request(JsonRpcRequest {
+                id: 1,
+                method: "slow".to_string(),
+                ..Default::default()
+            }))
+            .await?;
+        started.notified().await;
+        assert!(!aborted.load(Ordering::SeqCst));
+
+        transport.disconnect();
+        tokio::time::timeout(Duration::from_secs(5), listen).await???;
+        assert!(aborted.load(Ordering::SeqCst));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_register_handlers_from_handler() -> Result<()> {
+        use crate::types::CancelledParams;
+
+        fn assert_send<F: Future + Send>(future: F) -> F {
+            future
+        }
+
+        let builder = Protocol::builder(ServerInMemoryTransport::default());
+        let handlers = builder.handlers();
+        let cancelled = Arc::new(AtomicU64::new(0));
+        let seen = cancelled.clone();
+        let protocol = builder
+            .request_handler("subscribe", move |_: serde_json::Value| {
+                // Write locks taken while the lookup's read guard was still alive would deadlock
+                let cancelled = cancelled.clone();
+                handlers.set_notification_handler(
+                    "notifications/cancelled",
+                    move |_: CancelledParams| {
+                        cancelled.fetch_add(1, Ordering::SeqCst);
+                        Box::pin(async move { Ok(()) })
+                    },
+                );
+                handlers.set_request_handler("subscribe", |_: serde_json::Value| {
+                    Box::pin(async move { Ok(serde_json::json!({ "subscribed": "again" })) })
+                });
+                Box::pin(async move { Ok(serde_json::json!({ "subscribed": true })) })
+            })
+            .build();
+        let handlers = protocol.handlers();
+        let request = |id| JsonRpcRequest {
+            id,
+            method: "subscribe".to_string(),
+            ..Default::default()
+        };
+        let limit = Duration::from_secs(5);
+
+        assert!(!handlers.has_notification_handler("notifications/cancelled"));
+        let response = timeout(
+            limit,
+            assert_send(protocol.process_request(request(1), Instant::now())),
+        )
+        .await?;
+        assert_eq!(
+            response.result,
+            Some(serde_json::json!({ "subscribed": true }))
+        );
+        assert!(handlers.has_notification_handler("notifications/cancelled"));
+
+        timeout(
+            limit,
+            assert_send(protocol.handle_notification(JsonRpcNotification {
+                method: "notifications/cancelled".to_string(),
+                params: Some(serde_json::json!({ "requestId": 1 })),
+                ..Default::default()
+            })),
+        )
+        .await??;
+        assert_eq!(seen.load(Ordering::SeqCst), 1);
+        let response = timeout(limit, protocol.process_request(request(2), Instant::now())).await?;
+        assert_eq!(
+            response.result,
+            Some(serde_json::json!({ "subscribed": "again" }))
+        );
+
+        assert!(handlers.remove_request_handler("subscribe"));
+        let response = protocol.process_request(request(3), Instant::now()).await;
+        assert_eq!(
+            response.error.map(|e| e.code),
+            Some(ErrorCode::MethodNotFound as i32)
+        );
+        Ok(())
+    }
+}
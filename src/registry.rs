@@ -1,46 +1,1294 @@
-use crate::types::{CallToolRequest, CallToolResponse, Tool};
+use crate::blob::{read_blob, BlobStore, BLOB_SCHEME};
+use crate::fs::file_content;
+use crate::protocol::RequestOptions;
+use crate::transport::{JsonRpcError, JsonRpcResponse};
+use crate::types::{
+    CallToolRequest, CallToolResponse, ClientCapabilities, CompleteRequest, CompletionOptions,
+    CompletionResult, CreateMessageRequest, CreateMessageResult, ErrorCode, GetPromptRequest,
+    GetPromptResult, ListRootsResult, MessageContent, ProgressParams, ProgressToken, Prompt,
+    PromptMessage, ReadResourceRequest, ReadResourceResponse, Resource, ResourceChunk,
+    ResourceContent, ResourceRange, ResourceStreamResult, ResourceTemplate, Root, Tool,
+};
 use anyhow::Result;
-use std::collections::HashMap;
+use base64::Engine;
+use futures::future::{BoxFuture, FutureExt, Shared};
+use futures::{StreamExt, TryStreamExt};
+use serde::de::DeserializeOwned;
+use std::any::{Any, TypeId};
+use std::collections::{BTreeMap, HashMap};
 use std::future::Future;
+use std::net::SocketAddr;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use url::Url;
 
-pub struct Tools {
-    tool_handlers: HashMap<String, ToolHandler>,
+pub(crate) struct Tools {
+    // Ordered by name, so `tools/list` is the same on every run
+    tool_handlers: RwLock<BTreeMap<String, Arc<ToolHandler>>>,
+    generation: AtomicU64,
 }
 
 impl Tools {
     pub(crate) fn new(map: HashMap<String, ToolHandler>) -> Self {
-        Self { tool_handlers: map }
+        Self {
+            tool_handlers: RwLock::new(
+                map.into_iter()
+                    .map(|(name, handler)| (name, Arc::new(handler)))
+                    .collect(),
+            ),
+            generation: AtomicU64::new(0),
+        }
+    }
+
+    /// Bumped whenever the tool set changes, invalidates outstanding list cursors
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
     }
 
     pub fn get_tool(&self, name: &str) -> Option<Tool> {
         self.tool_handlers
+            .read()
+            .ok()?
             .get(name)
             .map(|tool_handler| tool_handler.tool.clone())
     }
 
-    pub async fn call_tool(&self, req: CallToolRequest) -> Result<CallToolResponse> {
+    /// Dispatch a call, missing and `null` arguments reach the handler as an empty object
+    pub async fn call_tool(
+        &self,
+        mut req: CallToolRequest,
+        ctx: ServerContext,
+    ) -> Result<CallToolResponse> {
+        req.arguments.get_or_insert_with(HashMap::new);
         let handler = self
             .tool_handlers
+            .read()
+            .map_err(|_| anyhow::anyhow!("Lock poisoned"))?
             .get(&req.name)
-            .ok_or_else(|| anyhow::anyhow!("Tool not found: {}", req.name))?;
+            .cloned()
+            .ok_or_else(|| JsonRpcError::tool_not_found(&req.name))?;
 
-        (handler.f)(req).await
+        match (handler.f)(req, ctx).await {
+            // The tool can't do its job with this client, tell the model rather than fail the call
+            Err(e) => match e
+                .chain()
+                .find_map(|e| e.downcast_ref::<CapabilityUnsupported>())
+            {
+                Some(unsupported) => Ok(CallToolResponse::error(unsupported.to_string())),
+                None => Err(e),
+            },
+            response => response,
+        }
     }
 
+    /// Sorted by name
     pub fn list_tools(&self) -> Vec<Tool> {
         self.tool_handlers
-            .values()
-            .map(|tool_handler| tool_handler.tool.clone())
-            .collect()
+            .read()
+            .map(|handlers| {
+                handlers
+                    .values()
+                    .map(|tool_handler| tool_handler.tool.clone())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Add or replace a tool, calls already in flight finish on the previous handler
+    pub(crate) fn insert(&self, handler: ToolHandler) -> Result<()> {
+        self.tool_handlers
+            .write()
+            .map_err(|_| anyhow::anyhow!("Lock poisoned"))?
+            .insert(handler.tool.name.clone(), Arc::new(handler));
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Remove a tool, returns whether it was registered
+    pub(crate) fn remove(&self, name: &str) -> Result<bool> {
+        let removed = self
+            .tool_handlers
+            .write()
+            .map_err(|_| anyhow::anyhow!("Lock poisoned"))?
+            .remove(name)
+            .is_some();
+        if removed {
+            self.generation.fetch_add(1, Ordering::SeqCst);
+        }
+        Ok(removed)
     }
 }
 
+pub(crate) type ToolHandlerFn = Box<
+    dyn Fn(CallToolRequest) -> Pin<Box<dyn Future<Output = Result<CallToolResponse>> + Send>>
+        + Send
+        + Sync,
+>;
+
+pub(crate) type ContextToolHandlerFn = Box<
+    dyn Fn(
+            CallToolRequest,
+            ServerContext,
+        ) -> Pin<Box<dyn Future<Output = Result<CallToolResponse>> + Send>>
+        + Send
+        + Sync,
+>;
+
 pub(crate) struct ToolHandler {
     pub tool: Tool,
-    pub f: Box<
-        dyn Fn(CallToolRequest) -> Pin<Box<dyn Future<Output = Result<CallToolResponse>> + Send>>
-            + Send
-            + Sync,
-    >,
+    pub f: ContextToolHandlerFn,
+}
+
+impl ToolHandler {
+    /// A handler that doesn't need the server context
+    pub(crate) fn new(tool: Tool, f: ToolHandlerFn) -> Self {
+        Self {
+            tool,
+            f: Box::new(move |req, _ctx| f(req)),
+        }
+    }
+}
+
+/// Application state added with [`crate::server::ServerBuilder::with_state`], shared by every
+/// handler registered with it
+pub struct State<S>(pub Arc<S>);
+
+impl<S> Clone for State<S> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<S> std::ops::Deref for State<S> {
+    type Target = S;
+
+    fn deref(&self) -> &S {
+        &self.0
+    }
+}
+
+/// One value per type, what [`State`] is taken from
+#[derive(Clone, Default)]
+pub(crate) struct StateMap {
+    values: HashMap<TypeId, Arc<dyn Any + Send + Sync>>,
+}
+
+impl StateMap {
+    /// Replaces a previous value of the same type
+    pub(crate) fn insert<S: Send + Sync + 'static>(&mut self, value: S) {
+        self.values.insert(TypeId::of::<S>(), Arc::new(value));
+    }
+
+    pub(crate) fn get<S: Send + Sync + 'static>(&self) -> Option<State<S>> {
+        let value = self.values.get(&TypeId::of::<S>())?.clone();
+        value.downcast().ok().map(State)
+    }
+}
+
+/// Session state passed to tool handlers, captured when the call arrives
+#[derive(Debug, Clone, Default)]
+pub struct ServerContext {
+    /// Capabilities the client declared in `initialize`, `None` before it did
+    pub client_capabilities: Option<ClientCapabilities>,
+    /// Set with [`crate::server::ServerBuilder::session_metadata`], over HTTP the claims
+    /// of the token the session connected with
+    pub session_metadata: Option<serde_json::Value>,
+    /// Set with [`crate::server::ServerBuilder::connection`], who the session is connected to
+    pub connection: Option<ConnectionMetadata>,
+    /// Bound to the progress token of the tool call being handled
+    pub(crate) progress: Option<ProgressReporter>,
+    /// Requests to the client, through [`ServerContext::client_features`]
+    pub(crate) client_requests: Option<ClientRequester>,
+}
+
+/// The peer of an HTTP session as seen when it connected, passed to `build_server`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConnectionMetadata {
+    /// `sse` or `ws`
+    pub transport: &'static str,
+    /// The forwarded client's address with `EndpointConfig::trust_proxy`, port 0 if none was sent
+    pub remote_addr: Option<SocketAddr>,
+    pub user_agent: Option<String>,
+    /// Whether the connection to this server is TLS, the negotiated cipher isn't available
+    /// from actix. False behind a proxy terminating TLS
+    pub tls: bool,
+    /// Claims of the bearer token the session connected with
+    pub claims: Option<serde_json::Value>,
+}
+
+impl ConnectionMetadata {
+    /// `sub` claim of the bearer token, who authenticated
+    pub fn auth_subject(&self) -> Option<&str> {
+        self.claims.as_ref()?.get("sub")?.as_str()
+    }
+}
+
+/// `progress` within `[0, max]`, NaN counts as no progress
+fn clamp_progress(progress: f64, max: f64) -> f64 {
+    if progress.is_nan() {
+        0.0
+    } else {
+        progress.clamp(0.0, max)
+    }
+}
+
+/// Sends `notifications/progress` to the client
+pub(crate) type ProgressSink =
+    Arc<dyn Fn(ProgressParams) -> Pin<Box<dyn Future<Output = Result<()>> + Send>> + Send + Sync>;
+
+#[derive(Clone)]
+pub(crate) struct ProgressReporter {
+    pub token: ProgressToken,
+    pub sink: ProgressSink,
+}
+
+impl std::fmt::Debug for ProgressReporter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProgressReporter")
+            .field("token", &self.token)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ServerContext {
+    /// Token the client attached to the tool call in `_meta.progressToken`
+    pub fn progress_token(&self) -> Option<&ProgressToken> {
+        self.progress.as_ref().map(|progress| &progress.token)
+    }
+
+    /// Send `notifications/progress` with the client's token for the tool call being handled,
+    /// does nothing when the client didn't ask for progress
+    /// `progress` is a fraction, clamped to `[0, 1]` so the client never sees e.g. 1.5
+    pub async fn report_progress(&self, progress: f64, message: impl Into<String>) -> Result<()> {
+        self.send_progress(clamp_progress(progress, 1.0), None, message.into())
+            .await
+    }
+
+    /// Like [`report_progress`](Self::report_progress) with `progress` counting up to
+    /// `total`, e.g. files processed, clamped to `[0, total]`
+    pub async fn report_progress_of(
+        &self,
+        progress: f64,
+        total: f64,
+        message: impl Into<String>,
+    ) -> Result<()> {
+        let total = total.max(0.0);
+        self.send_progress(clamp_progress(progress, total), Some(total), message.into())
+            .await
+    }
+
+    async fn send_progress(
+        &self,
+        progress: f64,
+        total: Option<f64>,
+        message: String,
+    ) -> Result<()> {
+        let Some(reporter) = &self.progress else {
+            return Ok(());
+        };
+        (reporter.sink)(ProgressParams {
+            progress_token: reporter.token.clone(),
+            progress,
+            total,
+            message: Some(message),
+        })
+        .await
+    }
+
+    /// Whether the client accepts server-initiated `sampling/createMessage` requests
+    pub fn client_supports_sampling(&self) -> bool {
+        self.client_capabilities
+            .as_ref()
+            .is_some_and(|capabilities| capabilities.sampling.is_some())
+    }
+
+    /// Whether the client exposes `roots/list`
+    pub fn client_supports_roots(&self) -> bool {
+        self.client_capabilities
+            .as_ref()
+            .is_some_and(|capabilities| capabilities.roots.is_some())
+    }
+
+    /// Handles for the client features this session may use
+    pub fn client_features(&self) -> ClientFeatures {
+        ClientFeatures {
+            capabilities: self.client_capabilities.clone().unwrap_or_default(),
+            requests: self.client_requests.clone(),
+        }
+    }
+}
+
+/// The sampling handle, failing with [`CapabilityUnsupported`] when the client didn't advertise
+/// sampling. Returned from a tool handler with `?`, the call answers with an `isError` result
+/// explaining what is missing
+pub fn require_sampling(ctx: &ServerContext) -> Result<SamplingHandle> {
+    ctx.client_features()
+        .sampling()
+        .ok_or_else(|| CapabilityUnsupported::new("sampling").into())
+}
+
+/// The roots handle, failing with [`CapabilityUnsupported`] like [`require_sampling`]
+pub fn require_roots(ctx: &ServerContext) -> Result<RootsHandle> {
+    ctx.client_features()
+        .roots()
+        .ok_or_else(|| CapabilityUnsupported::new("roots").into())
+}
+
+/// Sends a request to the client through the server's protocol
+pub(crate) type ClientRequestFn = Arc<
+    dyn Fn(
+            String,
+            Option<serde_json::Value>,
+            RequestOptions,
+        ) -> BoxFuture<'static, Result<JsonRpcResponse>>
+        + Send
+        + Sync,
+>;
+
+#[derive(Clone)]
+pub(crate) struct ClientRequester {
+    pub send: ClientRequestFn,
+    /// Set with [`crate::server::ServerBuilder::client_request_timeout`]
+    pub timeout: Option<Duration>,
+}
+
+impl std::fmt::Debug for ClientRequester {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ClientRequester")
+            .field("timeout", &self.timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ClientRequester {
+    /// `MethodNotFound` from a client that advertised `capability` anyway, e.g. an older
+    /// client, becomes [`CapabilityUnsupported`] as well
+    async fn request<R: DeserializeOwned>(
+        &self,
+        capability: &'static str,
+        method: &str,
+        params: Option<serde_json::Value>,
+    ) -> Result<R> {
+        let options = match self.timeout {
+            Some(timeout) => RequestOptions::default().timeout(timeout),
+            None => RequestOptions::default(),
+        };
+        let response = (self.send)(method.to_string(), params, options).await?;
+        if let Some(error) = response.error {
+            if error.code == ErrorCode::MethodNotFound as i32 {
+                return Err(CapabilityUnsupported::new(capability).into());
+            }
+            return Err(error.into());
+        }
+        Ok(serde_json::from_value(response.result.unwrap_or_default())?)
+    }
+}
+
+/// What the client can do for the server, from the capabilities it sent in `initialize`.
+/// Each handle is only given out when the client advertised the capability
+#[derive(Debug, Clone, Default)]
+pub struct ClientFeatures {
+    capabilities: ClientCapabilities,
+    requests: Option<ClientRequester>,
+}
+
+impl ClientFeatures {
+    pub fn capabilities(&self) -> &ClientCapabilities {
+        &self.capabilities
+    }
+
+    /// `sampling/createMessage`, asking the client's model for a completion
+    pub fn sampling(&self) -> Option<SamplingHandle> {
+        self.capabilities.sampling.as_ref()?;
+        Some(SamplingHandle {
+            requests: self.requests.clone()?,
+        })
+    }
+
+    /// `roots/list`, the directories and files the client lets the server work in
+    pub fn roots(&self) -> Option<RootsHandle> {
+        self.capabilities.roots.as_ref()?;
+        Some(RootsHandle {
+            requests: self.requests.clone()?,
+        })
+    }
+}
+
+/// Sends `sampling/createMessage` to a client that advertised sampling
+#[derive(Debug, Clone)]
+pub struct SamplingHandle {
+    requests: ClientRequester,
+}
+
+impl SamplingHandle {
+    pub async fn create_message(
+        &self,
+        request: CreateMessageRequest,
+    ) -> Result<CreateMessageResult> {
+        self.requests
+            .request(
+                "sampling",
+                "sampling/createMessage",
+                Some(serde_json::to_value(request)?),
+            )
+            .await
+    }
+}
+
+/// Sends `roots/list` to a client that advertised roots
+#[derive(Debug, Clone)]
+pub struct RootsHandle {
+    requests: ClientRequester,
+}
+
+impl RootsHandle {
+    pub async fn list(&self) -> Result<Vec<Root>> {
+        let result: ListRootsResult = self.requests.request("roots", "roots/list", None).await?;
+        Ok(result.roots)
+    }
+}
+
+/// The client lacks a feature the server wanted to use
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapabilityUnsupported {
+    /// Name of the client capability, e.g. `sampling`
+    pub capability: &'static str,
+}
+
+impl CapabilityUnsupported {
+    pub fn new(capability: &'static str) -> Self {
+        Self { capability }
+    }
+}
+
+impl std::fmt::Display for CapabilityUnsupported {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "This tool needs the client's `{}` capability, which the connected client doesn't support",
+            self.capability
+        )
+    }
+}
+
+impl std::error::Error for CapabilityUnsupported {}
+
+/// Template list callbacks run at most this many at a time during `resources/list`
+const MAX_CONCURRENT_TEMPLATE_LISTS: usize = 8;
+
+pub(crate) struct Resources {
+    resource_handlers: HashMap<String, ResourceHandler>,
+    templates: Vec<ResourceTemplateHandler>,
+    blob_store: Option<Arc<dyn BlobStore>>,
+}
+
+impl Resources {
+    pub(crate) fn new(
+        map: HashMap<String, ResourceHandler>,
+        templates: Vec<ResourceTemplateHandler>,
+        blob_store: Option<Arc<dyn BlobStore>>,
+    ) -> Self {
+        Self {
+            resource_handlers: map,
+            templates,
+            blob_store,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.resource_handlers.is_empty() && self.templates.is_empty() && self.blob_store.is_none()
+    }
+
+    /// Read a registered resource, then the first template matching the URI,
+    /// `blob://` URIs are served by the blob store
+    pub async fn read_resource(&self, req: ReadResourceRequest) -> Result<ReadResourceResponse> {
+        self.read_resource_streaming(req, None).await
+    }
+
+    /// Like [`Resources::read_resource`], streamed resources are sent to `sink` in chunks
+    /// when the request asked for it
+    pub(crate) async fn read_resource_streaming(
+        &self,
+        req: ReadResourceRequest,
+        sink: Option<&ChunkSink>,
+    ) -> Result<ReadResourceResponse> {
+        let mut ctx = ReadResourceContext {
+            range: req.requested_range(),
+            variables: HashMap::new(),
+        };
+        if let Some(handler) = self.resource_handlers.get(req.uri.as_str()) {
+            if let (Some(open), Some(sink), Some(token)) =
+                (&handler.stream, sink, req.stream_token())
+            {
+                let uri = req.uri.clone();
+                let stream = open(req, ctx).await?;
+                return send_chunks(stream, uri, token, sink).await;
+            }
+            return (handler.f)(req, ctx).await;
+        }
+        for handler in &self.templates {
+            if let Some(variables) =
+                template_variables(&handler.template.uri_template, req.uri.as_str())
+            {
+                ctx.variables = variables;
+                return (handler.f)(req, ctx).await;
+            }
+        }
+        match &self.blob_store {
+            Some(store) if req.uri.scheme() == BLOB_SCHEME => {
+                read_blob(store.as_ref(), req.uri).await
+            }
+            _ => Err(anyhow::anyhow!("Resource not found: {}", req.uri)),
+        }
+    }
+
+    /// Registered resources sorted by URI
+    pub fn list_resources(&self) -> Vec<Resource> {
+        let mut resources: Vec<Resource> = self
+            .resource_handlers
+            .values()
+            .map(|resource_handler| resource_handler.resource.clone())
+            .collect();
+        resources.sort_by(|a, b| a.uri.as_str().cmp(b.uri.as_str()));
+        resources
+    }
+
+    /// Registered templates in registration order
+    pub fn list_templates(&self) -> Vec<ResourceTemplate> {
+        self.templates
+            .iter()
+            .map(|handler| handler.template.clone())
+            .collect()
+    }
+
+    /// Registered resources merged with the listings of every template, sorted by URI
+    /// a URI listed more than once keeps the registered resource or the earliest template's entry
+    pub async fn list_resources_with_templates(&self) -> Result<Vec<Resource>> {
+        let lists: Vec<_> = self
+            .templates
+            .iter()
+            .map(|handler| (handler.list)())
+            .collect();
+        let listings: Vec<Vec<Resource>> = futures::stream::iter(lists)
+            .buffered(MAX_CONCURRENT_TEMPLATE_LISTS)
+            .try_collect()
+            .await?;
+
+        let mut merged = BTreeMap::new();
+        for resource in self
+            .list_resources()
+            .into_iter()
+            .chain(listings.into_iter().flatten())
+        {
+            merged.entry(resource.uri.to_string()).or_insert(resource);
+        }
+        Ok(merged.into_values().collect())
+    }
+}
+
+/// Variables of `uri` if it expands from the level 1 URI `template`, each `{var}` matching
+/// a non-empty value without `/`
+fn template_variables(template: &str, uri: &str) -> Option<HashMap<String, String>> {
+    let mut parts = template.split('{');
+    let mut rest = uri.strip_prefix(parts.next()?)?;
+    let mut variables = HashMap::new();
+    for part in parts {
+        let (name, literal) = part.split_once('}')?;
+        let end = match literal {
+            "" => rest.len(),
+            literal => rest.find(literal)?,
+        };
+        let value = &rest[..end];
+        if value.is_empty() || value.contains('/') {
+            return None;
+        }
+        variables.insert(name.to_string(), value.to_string());
+        rest = &rest[end + literal.len()..];
+    }
+    rest.is_empty().then_some(variables)
+}
+
+/// Extra information about a `resources/read` call passed to resource handlers
+#[derive(Debug, Clone, Default)]
+pub struct ReadResourceContext {
+    /// Byte range requested by the client, `None` reads the whole resource
+    pub range: Option<ResourceRange>,
+    /// Values of the URI template variables when a resource template serves the read
+    pub variables: HashMap<String, String>,
+}
+
+pub(crate) type ResourceHandlerFn = Box<
+    dyn Fn(
+            ReadResourceRequest,
+            ReadResourceContext,
+        ) -> Pin<Box<dyn Future<Output = Result<ReadResourceResponse>> + Send>>
+        + Send
+        + Sync,
+>;
+
+/// Size of the chunks a streamed read is sent in, before base64
+pub const RESOURCE_CHUNK_BYTES: usize = 256 * 1024;
+
+/// Contents of a resource read incrementally, see
+/// [`crate::server::ServerBuilder::register_resource_stream`]
+pub struct ResourceStream {
+    pub reader: Pin<Box<dyn AsyncRead + Send>>,
+    pub mime_type: Option<String>,
+}
+
+impl ResourceStream {
+    pub fn new(reader: impl AsyncRead + Send + 'static) -> Self {
+        Self {
+            reader: Box::pin(reader),
+            mime_type: None,
+        }
+    }
+
+    pub fn mime_type(mut self, mime_type: impl Into<String>) -> Self {
+        self.mime_type = Some(mime_type.into());
+        self
+    }
+
+    /// The whole contents as a regular response, for reads that didn't ask to stream
+    pub(crate) async fn into_response(mut self, uri: Url) -> Result<ReadResourceResponse> {
+        let mut bytes = Vec::new();
+        self.reader.read_to_end(&mut bytes).await?;
+        let content = match file_content(uri, bytes) {
+            ResourceContent::Text(mut text) => {
+                text.mime_type = self.mime_type;
+                ResourceContent::Text(text)
+            }
+            ResourceContent::Blob(mut blob) => {
+                blob.mime_type = self.mime_type;
+                ResourceContent::Blob(blob)
+            }
+        };
+        Ok(ReadResourceResponse::new(vec![content]))
+    }
+}
+
+pub(crate) type ResourceStreamFn = Box<
+    dyn Fn(
+            ReadResourceRequest,
+            ReadResourceContext,
+        ) -> Pin<Box<dyn Future<Output = Result<ResourceStream>> + Send>>
+        + Send
+        + Sync,
+>;
+
+/// Sends a chunk of a streamed read to the client
+pub(crate) type ChunkSink =
+    Arc<dyn Fn(ResourceChunk) -> Pin<Box<dyn Future<Output = Result<()>> + Send>> + Send + Sync>;
+
+pub(crate) struct ResourceHandler {
+    pub resource: Resource,
+    pub f: ResourceHandlerFn,
+    /// Opens the contents for streamed reads, `f` serves the others
+    pub stream: Option<ResourceStreamFn>,
+}
+
+/// Send the stream as chunks of [`RESOURCE_CHUNK_BYTES`], only one chunk is held at a time
+async fn send_chunks(
+    mut stream: ResourceStream,
+    uri: Url,
+    token: ProgressToken,
+    sink: &ChunkSink,
+) -> Result<ReadResourceResponse> {
+    let mut buffer = vec![0; RESOURCE_CHUNK_BYTES];
+    let (mut length, mut chunks) = (0u64, 0u64);
+    loop {
+        // Fill the chunk, readers may return a few bytes at a time
+        let mut filled = 0;
+        while filled < buffer.len() {
+            match stream.reader.read(&mut buffer[filled..]).await? {
+                0 => break,
+                read => filled += read,
+            }
+        }
+        if filled == 0 {
+            break;
+        }
+        sink(ResourceChunk {
+            progress_token: token.clone(),
+            uri: uri.clone(),
+            offset: length,
+            blob: base64::engine::general_purpose::STANDARD.encode(&buffer[..filled]),
+        })
+        .await?;
+        length += filled as u64;
+        chunks += 1;
+    }
+    Ok(ReadResourceResponse {
+        contents: vec![],
+        meta: Some(serde_json::to_value(ResourceStreamResult {
+            length,
+            chunks,
+            mime_type: stream.mime_type,
+        })?),
+    })
+}
+
+pub(crate) type ResourceListFn =
+    Box<dyn Fn() -> Pin<Box<dyn Future<Output = Result<Vec<Resource>>> + Send>> + Send + Sync>;
+
+pub(crate) struct ResourceTemplateHandler {
+    pub template: ResourceTemplate,
+    pub list: ResourceListFn,
+    pub f: ResourceHandlerFn,
+}
+
+pub(crate) struct Prompts {
+    // Ordered by name like the tools
+    prompt_handlers: BTreeMap<String, PromptHandler>,
+}
+
+impl Prompts {
+    pub(crate) fn new(map: HashMap<String, PromptHandler>) -> Self {
+        Self {
+            prompt_handlers: map.into_iter().collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.prompt_handlers.is_empty()
+    }
+
+    /// Render a prompt, embedding the contents of referenced resources
+    pub async fn get_prompt(
+        &self,
+        req: GetPromptRequest,
+        resources: &Resources,
+    ) -> Result<GetPromptResult> {
+        let handler = self
+            .prompt_handlers
+            .get(&req.name)
+            .ok_or_else(|| JsonRpcError::prompt_not_found(&req.name))?;
+        let missing: Vec<&str> = handler
+            .prompt
+            .arguments
+            .iter()
+            .flatten()
+            .filter(|argument| argument.required == Some(true))
+            .map(|argument| argument.name.as_str())
+            .filter(|name| {
+                !req.arguments
+                    .as_ref()
+                    .is_some_and(|arguments| arguments.contains_key(*name))
+            })
+            .collect();
+        if !missing.is_empty() {
+            return Err(JsonRpcError::missing_arguments(&req.name, &missing).into());
+        }
+
+        let mut result = (handler.f)(req).await?;
+        let mut messages = Vec::with_capacity(result.messages.len());
+        for message in result.messages {
+            match message.content {
+                MessageContent::ResourceRef { uri } => {
+                    let read = resources
+                        .read_resource(ReadResourceRequest::new(uri))
+                        .await?;
+                    messages.extend(read.contents.into_iter().map(|resource| PromptMessage {
+                        role: message.role,
+                        content: MessageContent::Resource { resource },
+                    }));
+                }
+                _ => messages.push(message),
+            }
+        }
+        result.messages = messages;
+        Ok(result)
+    }
+
+    /// Sorted by name
+    pub fn list_prompts(&self) -> Vec<Prompt> {
+        self.prompt_handlers
+            .values()
+            .map(|prompt_handler| prompt_handler.prompt.clone())
+            .collect()
+    }
+}
+
+pub(crate) type PromptHandlerFn = Box<
+    dyn Fn(GetPromptRequest) -> Pin<Box<dyn Future<Output = Result<GetPromptResult>> + Send>>
+        + Send
+        + Sync,
+>;
+
+pub(crate) struct PromptHandler {
+    pub prompt: Prompt,
+    pub f: PromptHandlerFn,
+}
+
+/// Options of a registered completion handler
+/// interactive clients complete on every keystroke, handlers doing real I/O should
+/// set a minimum input length and dedup so bursts of identical requests share one call
+#[derive(Debug, Clone, Default)]
+pub struct CompletionHandlerOptions {
+    /// Inputs shorter than this get an empty completion without calling the handler
+    pub min_input_length: usize,
+    /// Requests for the same argument value arriving while one is in flight share its result
+    pub dedup_in_flight: bool,
+}
+
+impl CompletionHandlerOptions {
+    pub fn min_input_length(mut self, min_input_length: usize) -> Self {
+        self.min_input_length = min_input_length;
+        self
+    }
+
+    pub fn dedup_in_flight(mut self, dedup_in_flight: bool) -> Self {
+        self.dedup_in_flight = dedup_in_flight;
+        self
+    }
+}
+
+/// Completion candidates that can be replaced at runtime, e.g. a periodically refreshed list of
+/// branches. Clones share the candidates, register one with [`RefreshableCompletions::handler`]
+/// and keep another to call [`RefreshableCompletions::set`] on
+#[derive(Debug, Clone, Default)]
+pub struct RefreshableCompletions {
+    values: Arc<RwLock<Vec<String>>>,
+}
+
+impl RefreshableCompletions {
+    pub fn new(values: Vec<String>) -> Self {
+        Self {
+            values: Arc::new(RwLock::new(values)),
+        }
+    }
+
+    /// Replace the candidates, completions already in progress keep the previous ones
+    pub fn set(&self, values: Vec<String>) {
+        if let Ok(mut current) = self.values.write() {
+            *current = values;
+        }
+    }
+
+    pub fn values(&self) -> Vec<String> {
+        self.values
+            .read()
+            .map(|values| values.clone())
+            .unwrap_or_default()
+    }
+
+    /// Current candidates starting with the argument value, in their original order
+    pub fn complete(&self, req: &CompleteRequest) -> CompletionResult {
+        let matches = self
+            .values
+            .read()
+            .map(|values| {
+                values
+                    .iter()
+                    .filter(|value| value.starts_with(&req.argument.value))
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default();
+        CompletionOptions::new(matches).into()
+    }
+
+    /// Handler for [`crate::server::ServerBuilder::register_completion`]
+    pub fn handler(
+        &self,
+    ) -> impl Fn(CompleteRequest) -> Pin<Box<dyn Future<Output = Result<CompletionResult>> + Send>>
+           + Send
+           + Sync
+           + 'static {
+        let completions = self.clone();
+        move |req| {
+            let result = completions.complete(&req);
+            Box::pin(async move { Ok(result) })
+        }
+    }
+}
+
+pub(crate) struct Completions {
+    completion_handlers: HashMap<String, CompletionHandler>,
+}
+
+impl Completions {
+    pub(crate) fn new(map: HashMap<String, CompletionHandler>) -> Self {
+        Self {
+            completion_handlers: map,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.completion_handlers.is_empty()
+    }
+
+    pub async fn complete(&self, req: CompleteRequest) -> Result<CompletionResult> {
+        let key = req.reference.key();
+        let handler = self
+            .completion_handlers
+            .get(&key)
+            .ok_or_else(|| anyhow::anyhow!("Completion not found: {}", key))?;
+
+        if req.argument.value.chars().count() < handler.options.min_input_length {
+            return Ok(CompletionOptions::new(vec![]).into());
+        }
+        if !handler.options.dedup_in_flight {
+            return (handler.f)(req).await;
+        }
+
+        let in_flight_key = (req.argument.name.clone(), req.argument.value.clone());
+        let shared = {
+            let mut in_flight = handler
+                .in_flight
+                .lock()
+                .map_err(|_| anyhow::anyhow!("Lock poisoned"))?;
+            in_flight
+                .entry(in_flight_key.clone())
+                .or_insert_with(|| {
+                    (handler.f)(req)
+                        .map(|r| r.map_err(Arc::new))
+                        .boxed()
+                        .shared()
+                })
+                .clone()
+        };
+        let result = shared.clone().await;
+        if let Ok(mut in_flight) = handler.in_flight.lock() {
+            // A late waiter of an earlier call must not remove the one started after it
+            if in_flight
+                .get(&in_flight_key)
+                .is_some_and(|current| current.ptr_eq(&shared))
+            {
+                in_flight.remove(&in_flight_key);
+            }
+        }
+        result.map_err(|e| anyhow::anyhow!("{:#}", e))
+    }
+}
+
+pub(crate) type CompletionHandlerFn = Box<
+    dyn Fn(CompleteRequest) -> Pin<Box<dyn Future<Output = Result<CompletionResult>> + Send>>
+        + Send
+        + Sync,
+>;
+
+type SharedCompletion = Shared<BoxFuture<'static, Result<CompletionResult, Arc<anyhow::Error>>>>;
+
+pub(crate) struct CompletionHandler {
+    pub options: CompletionHandlerOptions,
+    pub f: CompletionHandlerFn,
+    pub in_flight: Mutex<HashMap<(String, String), SharedCompletion>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{ResourceContent, Role, TextResourceContents};
+    use url::Url;
+
+    #[tokio::test]
+    async fn test_prompt_embeds_resource() -> Result<()> {
+        let uri = Url::parse("file:///review.diff")?;
+        let resource_uri = uri.clone();
+        let resources = Resources::new(
+            HashMap::from([(
+                uri.to_string(),
+                ResourceHandler {
+                    resource: Resource {
+                        uri: uri.clone(),
+                        name: "review.diff".to_string(),
+                        description: None,
+                        mime_type: Some("text/x-diff".to_string()),
+                    },
+                    stream: None,
+                    f: Box::new(|req: ReadResourceRequest, _ctx| {
+                        Box::pin(async move {
+                            Ok(ReadResourceResponse {
+                                contents: vec![ResourceContent::Text(TextResourceContents {
+                                    uri: req.uri,
+                                    mime_type: Some("text/x-diff".to_string()),
+                                    text: "+ added line".to_string(),
+                                })],
+                                meta: None,
+                            })
+                        })
+                    }),
+                },
+            )]),
+            Vec::new(),
+            None,
+        );
+        let prompts = Prompts::new(HashMap::from([(
+            "code_review".to_string(),
+            PromptHandler {
+                prompt: Prompt {
+                    name: "code_review".to_string(),
+                    description: None,
+                    arguments: None,
+                },
+                f: Box::new(move |_req: GetPromptRequest| {
+                    let uri = resource_uri.clone();
+                    Box::pin(async move {
+                        Ok(GetPromptResult {
+                            description: None,
+                            messages: vec![
+                                PromptMessage {
+                                    role: Role::User,
+                                    content: MessageContent::Text {
+                                        text: "Review this change".to_string(),
+                                    },
+                                },
+                                PromptMessage {
+                                    role: Role::User,
+                                    content: MessageContent::ResourceRef { uri },
+                                },
+                            ],
+                            meta: None,
+                        })
+                    })
+                }),
+            },
+        )]));
+
+        let result = prompts
+            .get_prompt(
+                GetPromptRequest {
+                    name: "code_review".to_string(),
+                    arguments: None,
+                },
+                &resources,
+            )
+            .await?;
+        let json = serde_json::to_value(&result)?;
+        assert_eq!(
+            json["messages"][1],
+            serde_json::json!({
+                "role": "user",
+                "content": {
+                    "type": "resource",
+                    "resource": {"uri": "file:///review.diff", "mimeType": "text/x-diff", "text": "+ added line"}
+                }
+            })
+        );
+
+        let missing = prompts
+            .get_prompt(
+                GetPromptRequest {
+                    name: "code_review".to_string(),
+                    arguments: None,
+                },
+                &Resources::new(HashMap::new(), Vec::new(), None),
+            )
+            .await;
+        assert!(missing.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_completion_min_length_and_dedup() -> Result<()> {
+        use crate::types::{CompletionArgument, Reference};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let handler_calls = calls.clone();
+        let reference = Reference::Prompt {
+            name: "lookup".to_string(),
+        };
+        let completions = Completions::new(HashMap::from([(
+            reference.key(),
+            CompletionHandler {
+                options: CompletionHandlerOptions::default()
+                    .min_input_length(2)
+                    .dedup_in_flight(true),
+                f: Box::new(move |req: CompleteRequest| {
+                    let calls = handler_calls.clone();
+                    Box::pin(async move {
+                        calls.fetch_add(1, Ordering::SeqCst);
+                        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+                        Ok(
+                            CompletionOptions::new(vec![format!("{}-result", req.argument.value)])
+                                .into(),
+                        )
+                    })
+                }),
+                in_flight: Mutex::new(HashMap::new()),
+            },
+        )]));
+        let request = |value: &str| CompleteRequest {
+            reference: reference.clone(),
+            argument: CompletionArgument {
+                name: "query".to_string(),
+                value: value.to_string(),
+            },
+        };
+
+        let short = completions.complete(request("a")).await?;
+        assert!(short.completion.values.is_empty());
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+        let results =
+            futures::future::join_all((0..5).map(|_| completions.complete(request("ab")))).await;
+        for result in results {
+            assert_eq!(result?.completion.values, vec!["ab-result".to_string()]);
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        completions.complete(request("ab")).await?;
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_completion_dedup_across_rounds() -> Result<()> {
+        use crate::types::{CompletionArgument, Reference};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::task::Poll;
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let gate = Arc::new(tokio::sync::Semaphore::new(0));
+        let (handler_calls, handler_gate) = (calls.clone(), gate.clone());
+        let reference = Reference::Prompt {
+            name: "lookup".to_string(),
+        };
+        let completions = Completions::new(HashMap::from([(
+            reference.key(),
+            CompletionHandler {
+                options: CompletionHandlerOptions::default().dedup_in_flight(true),
+                f: Box::new(move |_| {
+                    let calls = handler_calls.clone();
+                    let gate = handler_gate.clone();
+                    Box::pin(async move {
+                        let round = calls.fetch_add(1, Ordering::SeqCst) + 1;
+                        gate.acquire().await?.forget();
+                        Ok(CompletionOptions::new(vec![round.to_string()]).into())
+                    })
+                }),
+                in_flight: Mutex::new(HashMap::new()),
+            },
+        )]));
+        let request = || CompleteRequest {
+            reference: reference.clone(),
+            argument: CompletionArgument {
+                name: "query".to_string(),
+                value: "ab".to_string(),
+            },
+        };
+        let values = |result: Result<CompletionResult>| result.map(|r| r.completion.values);
+
+        // Two waiters share the first call, the second one is only polled again late
+        let mut first = Box::pin(completions.complete(request()));
+        let mut late = Box::pin(completions.complete(request()));
+        assert!(matches!(futures::poll!(&mut first), Poll::Pending));
+        assert!(matches!(futures::poll!(&mut late), Poll::Pending));
+        gate.add_permits(1);
+        assert_eq!(values(first.await)?, vec!["1"]);
+
+        // A new round starts before the late waiter of the first one finishes
+        let mut second = Box::pin(completions.complete(request()));
+        assert!(matches!(futures::poll!(&mut second), Poll::Pending));
+        assert_eq!(values(late.await)?, vec!["1"]);
+
+        // Still shares the second round's call
+        let mut joined = Box::pin(completions.complete(request()));
+        assert!(matches!(futures::poll!(&mut joined), Poll::Pending));
+        gate.add_permits(1);
+        // A third call would wait for a permit forever
+        let (second, joined) = tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            futures::join!(second, joined)
+        })
+        .await?;
+        assert_eq!(values(second)?, vec!["2"]);
+        assert_eq!(values(joined)?, vec!["2"]);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_refreshable_completions() -> Result<()> {
+        use crate::types::{CompletionArgument, Reference};
+
+        let branches = RefreshableCompletions::new(vec!["main".to_string(), "fix/a".to_string()]);
+        let reference = Reference::Prompt {
+            name: "checkout".to_string(),
+        };
+        let completions = Completions::new(HashMap::from([(
+            reference.key(),
+            CompletionHandler {
+                options: CompletionHandlerOptions::default(),
+                f: Box::new(branches.handler()),
+                in_flight: Mutex::new(HashMap::new()),
+            },
+        )]));
+        let request = CompleteRequest {
+            reference,
+            argument: CompletionArgument {
+                name: "branch".to_string(),
+                value: "fix/".to_string(),
+            },
+        };
+
+        let before = completions.complete(request.clone()).await?;
+        assert_eq!(before.completion.values, vec!["fix/a"]);
+
+        branches.set(vec![
+            "fix/b".to_string(),
+            "fix/c".to_string(),
+            "dev".to_string(),
+        ]);
+        let after = completions.complete(request).await?;
+        assert_eq!(after.completion.values, vec!["fix/b", "fix/c"]);
+        assert_eq!(after.completion.total, Some(2));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_tool_arguments_normalized() -> Result<()> {
+        let tools = Tools::new(HashMap::from([(
+            "echo".to_string(),
+            ToolHandler::new(
+                Tool {
+                    name: "echo".to_string(),
+                    description: None,
+                    input_schema: serde_json::json!({"type": "object"}),
+                    output_schema: None,
+                },
+                Box::new(|req: CallToolRequest| {
+                    Box::pin(async move {
+                        let arguments = req
+                            .arguments
+                            .ok_or_else(|| anyhow::anyhow!("arguments missing"))?;
+                        Ok(CallToolResponse::json(arguments))
+                    })
+                }),
+            ),
+        )]));
+
+        for params in [
+            serde_json::json!({"name": "echo"}),
+            serde_json::json!({"name": "echo", "arguments": null}),
+            serde_json::json!({"name": "echo", "arguments": {}}),
+        ] {
+            let req: CallToolRequest = serde_json::from_value(params.clone())?;
+            let response = tools.call_tool(req, ServerContext::default()).await?;
+            assert_eq!(
+                serde_json::to_value(&response.content)?,
+                serde_json::json!([{"type": "text", "text": "{}"}]),
+                "{}",
+                params
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_template_variables() {
+        let variables = template_variables("sqlite://{table}/{id}", "sqlite://users/42").unwrap();
+        assert_eq!(variables["table"], "users");
+        assert_eq!(variables["id"], "42");
+        assert!(template_variables("sqlite://{table}/{id}", "sqlite://users/").is_none());
+        assert!(template_variables("sqlite://{table}/{id}", "sqlite://users/4/2").is_none());
+        assert!(template_variables("file:///{name}.txt", "file:///a.md").is_none());
+        assert!(template_variables("file:///a.txt", "file:///a.txt")
+            .unwrap()
+            .is_empty());
+    }
 }
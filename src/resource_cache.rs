@@ -0,0 +1,197 @@
+//! Cache for expensive `resources/read` handlers
+//! a cached read returns the previous response until its TTL runs out or the resource is
+//! invalidated, e.g. by [`Server::notify_resource_updated`](crate::server::Server::notify_resource_updated)
+use crate::registry::ResourceHandlerFn;
+use crate::types::ReadResourceResponse;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[derive(Default)]
+struct Entry {
+    // Bumped on every invalidation, a read that started before keeps its result to itself
+    generation: u64,
+    cached: Option<(ReadResourceResponse, Instant)>,
+}
+
+/// Cached reads keyed by URI, share one between sessions with
+/// [`ServerBuilder::resource_cache`](crate::server::ServerBuilder::resource_cache) so an
+/// invalidation from any session reaches all of them. A shared cache hands one session's read
+/// to every session, only share it for resources that read the same whoever asks
+#[derive(Clone, Default)]
+pub struct ResourceCache {
+    entries: Arc<Mutex<HashMap<String, Entry>>>,
+}
+
+/// The URI as the registry keys it, `FILE:///a%2a` and `file:///a%2a` are one resource
+pub(crate) fn normalize(uri: &str) -> String {
+    url::Url::parse(uri).map_or_else(|_| uri.to_string(), String::from)
+}
+
+impl ResourceCache {
+    /// Drop the cached read of `uri`, the next read calls the handler again
+    pub fn invalidate(&self, uri: &str) {
+        let mut entries = self.entries.lock();
+        let entry = entries.entry(normalize(uri)).or_default();
+        entry.generation += 1;
+        entry.cached = None;
+    }
+
+    /// Whether a fresh read of `uri` is cached
+    pub fn is_cached(&self, uri: &str) -> bool {
+        self.entries
+            .lock()
+            .get(&normalize(uri))
+            .and_then(|entry| entry.cached.as_ref())
+            .is_some_and(|(_, expires)| *expires > Instant::now())
+    }
+
+    fn get(&self, uri: &str) -> Result<ReadResourceResponse, u64> {
+        let entries = self.entries.lock();
+        let Some(entry) = entries.get(uri) else {
+            return Err(0);
+        };
+        match &entry.cached {
+            Some((response, expires)) if *expires > Instant::now() => Ok(response.clone()),
+            _ => Err(entry.generation),
+        }
+    }
+
+    fn store(&self, uri: &str, generation: u64, response: &ReadResourceResponse, ttl: Duration) {
+        let mut entries = self.entries.lock();
+        let entry = entries.entry(uri.to_string()).or_default();
+        // Invalidated while reading, the response may already be stale
+        if entry.generation == generation {
+            entry.cached = Some((response.clone(), Instant::now() + ttl));
+        }
+    }
+
+    /// Serve whole reads of `uri` from the cache, ranged reads always reach `f`
+    /// `uri` is normalized already
+    pub(crate) fn wrap(
+        &self,
+        uri: String,
+        ttl: Duration,
+        f: ResourceHandlerFn,
+    ) -> ResourceHandlerFn {
+        let cache = self.clone();
+        let f = Arc::new(f);
+        Box::new(move |req, ctx| {
+            let cache = cache.clone();
+            let f = f.clone();
+            let uri = uri.clone();
+            Box::pin(async move {
+                if ctx.range.is_some() {
+                    return f(req, ctx).await;
+                }
+                let generation = match cache.get(&uri) {
+                    Ok(response) => return Ok(response),
+                    Err(generation) => generation,
+                };
+                let response = f(req, ctx).await?;
+                cache.store(&uri, generation, &response, ttl);
+                Ok(response)
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::ClientBuilder;
+    use crate::server::Server;
+    use crate::transport::{ClientInMemoryTransport, ServerInMemoryTransport, Transport};
+    use crate::types::{ReadResourceRequest, Resource, ResourceContent};
+    use anyhow::Result;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn test_cached_reads_until_invalidated() -> Result<()> {
+        let reads = Arc::new(AtomicUsize::new(0));
+        let handler_reads = reads.clone();
+        let (server_tx, mut server_rx) = mpsc::unbounded_channel();
+        let transport = ClientInMemoryTransport::new(move |t: ServerInMemoryTransport| {
+            let reads = handler_reads.clone();
+            // The same URI as the registered resource once normalized
+            let mut builder =
+                Server::builder(t).cache_resource("FILE:///report.csv", Duration::from_millis(200));
+            builder.register_resource(
+                Resource {
+                    uri: "file:///report.csv".parse().unwrap(),
+                    name: "report".to_string(),
+                    description: None,
+                    mime_type: None,
+                },
+                move |req| {
+                    let read = reads.fetch_add(1, Ordering::SeqCst) + 1;
+                    Box::pin(async move {
+                        Ok(ReadResourceResponse::new(vec![ResourceContent::text(
+                            req.uri,
+                            "text/plain",
+                            format!("read {}", read),
+                        )]))
+                    })
+                },
+            );
+            let server = builder.build();
+            let _ = server_tx.send(server.clone());
+            tokio::spawn(async move { server.listen().await.unwrap() })
+        });
+        transport.open().await?;
+        let client = ClientBuilder::new(transport.clone()).build();
+        let client_clone = client.clone();
+        tokio::spawn(async move { client_clone.start().await });
+        let server = server_rx.recv().await.unwrap();
+
+        let uri: url::Url = "file:///report.csv".parse()?;
+        let read = |request: ReadResourceRequest| {
+            let client = client.clone();
+            async move {
+                let response: ReadResourceResponse = client
+                    .request_typed("resources/read", request, Default::default())
+                    .await?;
+                match &response.contents[0] {
+                    ResourceContent::Text(contents) => Ok(contents.text.clone()),
+                    other => Err(anyhow::anyhow!("Expected text, got {:?}", other)),
+                }
+            }
+        };
+        assert_eq!(read(ReadResourceRequest::new(uri.clone())).await?, "read 1");
+        assert_eq!(read(ReadResourceRequest::new(uri.clone())).await?, "read 1");
+        // Ranged reads bypass the cache
+        assert_eq!(
+            read(ReadResourceRequest::new(uri.clone()).range(0, 4)).await?,
+            "read 2"
+        );
+
+        server.notify_resource_updated(&uri).await;
+        assert_eq!(read(ReadResourceRequest::new(uri.clone())).await?, "read 3");
+        assert_eq!(read(ReadResourceRequest::new(uri.clone())).await?, "read 3");
+
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        assert_eq!(read(ReadResourceRequest::new(uri.clone())).await?, "read 4");
+        assert_eq!(reads.load(Ordering::SeqCst), 4);
+
+        drop(server);
+        transport.close().await?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_keys_are_normalized() {
+        let cache = ResourceCache::default();
+        let response = ReadResourceResponse::new(vec![]);
+        cache.store(
+            &normalize("file:///a%2a"),
+            0,
+            &response,
+            Duration::from_secs(60),
+        );
+        assert!(cache.is_cached("FILE:///a%2a"));
+        cache.invalidate("File:///a%2a");
+        assert!(!cache.is_cached("file:///a%2a"));
+    }
+}
@@ -0,0 +1,280 @@
+//! Size limit for tool results
+//! hosts truncate or reject oversized results, so the serialized content of every `tools/call`
+//! response is measured after the handler returns and the overflow policy is applied
+use crate::blob::BlobStore;
+use crate::transport::JsonRpcError;
+use crate::types::{CallToolResponse, ErrorCode, ErrorData, ToolResponseContent};
+use anyhow::Result;
+use std::sync::Arc;
+
+/// What to do with a tool result whose content exceeds the limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Cut the content to the limit, `_meta` reports `truncated` and `originalBytes`
+    Truncate,
+    /// Fail the call with a `result_too_large` error asking to narrow the query
+    Error,
+    /// Store the full content in the blob store, return a summary and a link to it
+    SpillToResource,
+}
+
+pub(crate) const TRUNCATED_MARKER: &str = "\n[truncated]";
+
+#[derive(Clone)]
+pub(crate) struct ResultLimit {
+    pub max_bytes: usize,
+    pub policy: OverflowPolicy,
+    pub blob_store: Option<Arc<dyn BlobStore>>,
+}
+
+impl ResultLimit {
+    pub(crate) async fn apply(&self, response: CallToolResponse) -> Result<CallToolResponse> {
+        let size = content_size(&response.content);
+        if size <= self.max_bytes {
+            return Ok(response);
+        }
+        match self.policy {
+            OverflowPolicy::Truncate => Ok(self.truncate(response, size)),
+            OverflowPolicy::Error => Err(JsonRpcError::with_error_data(
+                ErrorCode::InvalidParams,
+                format!(
+                    "Tool result of {} bytes exceeds the limit of {} bytes, narrow the query",
+                    size, self.max_bytes
+                ),
+                ErrorData::new(ErrorData::RESULT_TOO_LARGE, false)
+                    .details(serde_json::json!({ "bytes": size, "limit": self.max_bytes })),
+            )
+            .into()),
+            OverflowPolicy::SpillToResource => self.spill(response, size).await,
+        }
+    }
+
+    fn truncate(&self, mut response: CallToolResponse, size: usize) -> CallToolResponse {
+        let mut content = Vec::new();
+        // Account for the surrounding `[]`
+        let mut remaining = self.max_bytes.saturating_sub(2);
+        for item in response.content {
+            // Items after the first are preceded by a comma
+            if !content.is_empty() {
+                remaining = remaining.saturating_sub(1);
+            }
+            let item_size = item_size(&item);
+            if item_size <= remaining {
+                remaining -= item_size;
+                content.push(item);
+                continue;
+            }
+            // Text is cut to what's left, anything else that doesn't fit is dropped
+            if let ToolResponseContent::Text { text } = item {
+                if let Some(item) = truncate_text(&text, remaining) {
+                    content.push(item);
+                }
+            }
+            break;
+        }
+        response.content = content;
+        response.meta = Some(merge_meta(
+            response.meta,
+            serde_json::json!({ "truncated": true, "originalBytes": size }),
+        ));
+        response
+    }
+
+    async fn spill(&self, response: CallToolResponse, size: usize) -> Result<CallToolResponse> {
+        let store = self
+            .blob_store
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("SpillToResource requires a blob store"))?;
+        // Plain text stays readable as is, mixed content is kept as its JSON
+        let texts: Option<Vec<&str>> = response
+            .content
+            .iter()
+            .map(|item| match item {
+                ToolResponseContent::Text { text } => Some(text.as_str()),
+                _ => None,
+            })
+            .collect();
+        let (bytes, mime_type) = match texts {
+            Some(texts) => (texts.concat().into_bytes(), "text/plain"),
+            None => (serde_json::to_vec(&response.content)?, "application/json"),
+        };
+        let link = store.store_blob(bytes, mime_type).await?;
+        let uri = match &link {
+            ToolResponseContent::Resource { resource } => resource.uri.to_string(),
+            _ => String::new(),
+        };
+        Ok(CallToolResponse {
+            content: vec![
+                ToolResponseContent::Text {
+                    text: format!(
+                        "Result of {} bytes stored as {}, read it with resources/read",
+                        size, uri
+                    ),
+                },
+                link,
+            ],
+            is_error: response.is_error,
+            meta: Some(merge_meta(
+                response.meta,
+                serde_json::json!({ "spilled": true, "originalBytes": size }),
+            )),
+        })
+    }
+}
+
+fn content_size(content: &[ToolResponseContent]) -> usize {
+    serde_json::to_vec(content)
+        .map(|bytes| bytes.len())
+        .unwrap_or(usize::MAX)
+}
+
+fn item_size(item: &ToolResponseContent) -> usize {
+    serde_json::to_vec(item)
+        .map(|bytes| bytes.len())
+        .unwrap_or(usize::MAX)
+}
+
+/// The longest prefix of `text` that, with the marker, serializes within `budget` bytes
+fn truncate_text(text: &str, budget: usize) -> Option<ToolResponseContent> {
+    let item = |len: usize| ToolResponseContent::Text {
+        text: format!("{}{}", &text[..len], TRUNCATED_MARKER),
+    };
+    let mut len = text.len();
+    loop {
+        while !text.is_char_boundary(len) {
+            len -= 1;
+        }
+        let candidate = item(len);
+        let size = item_size(&candidate);
+        if size <= budget {
+            return Some(candidate);
+        }
+        if len == 0 {
+            return None;
+        }
+        // Escaping makes the serialized size larger than the text, shrink until it fits
+        len = len.saturating_sub((size - budget).max(len / 8).max(1));
+    }
+}
+
+fn merge_meta(meta: Option<serde_json::Value>, extra: serde_json::Value) -> serde_json::Value {
+    match (meta, extra) {
+        (Some(serde_json::Value::Object(mut meta)), serde_json::Value::Object(extra)) => {
+            meta.extend(extra);
+            serde_json::Value::Object(meta)
+        }
+        (_, extra) => extra,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{Client, ClientBuilder};
+    use crate::protocol::RequestOptions;
+    use crate::server::Server;
+    use crate::transport::{ClientInMemoryTransport, ServerInMemoryTransport, Transport};
+    use crate::types::{ReadResourceResponse, ResourceContent, Tool};
+    use base64::Engine;
+
+    async fn serve(
+        policy: OverflowPolicy,
+        text: String,
+    ) -> Result<(ClientInMemoryTransport, Client<ClientInMemoryTransport>)> {
+        let transport = ClientInMemoryTransport::new(move |t: ServerInMemoryTransport| {
+            let text = text.clone();
+            tokio::spawn(async move {
+                let mut builder = Server::builder(t).max_tool_result_bytes(1024, policy);
+                builder.register_tool(
+                    Tool {
+                        name: "dump".to_string(),
+                        description: None,
+                        input_schema: serde_json::json!({"type": "object"}),
+                        output_schema: None,
+                    },
+                    move |_| {
+                        let text = text.clone();
+                        Box::pin(async move { Ok(CallToolResponse::text(text)) })
+                    },
+                );
+                builder.build().listen().await.unwrap();
+            })
+        });
+        transport.open().await?;
+        let client = ClientBuilder::new(transport.clone()).build();
+        let client_clone = client.clone();
+        tokio::spawn(async move { client_clone.start().await });
+        Ok((transport, client))
+    }
+
+    async fn call(client: &Client<ClientInMemoryTransport>) -> Result<CallToolResponse> {
+        client
+            .request_typed(
+                "tools/call",
+                serde_json::json!({"name": "dump"}),
+                RequestOptions::default(),
+            )
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_overflow_policies() -> Result<()> {
+        // Quotes escape to two bytes, so the truncated text must account for escaping
+        let text = "line \"quoted\"\n".repeat(300);
+
+        let (transport, client) = serve(OverflowPolicy::Truncate, text.clone()).await?;
+        let response = call(&client).await?;
+        assert!(content_size(&response.content) <= 1024);
+        let ToolResponseContent::Text { text: truncated } = &response.content[0] else {
+            panic!("Expected text content");
+        };
+        assert!(truncated.ends_with(TRUNCATED_MARKER));
+        assert!(text.starts_with(truncated.trim_end_matches(TRUNCATED_MARKER)));
+        let meta = response.meta.unwrap();
+        assert_eq!(meta["truncated"], true);
+        assert_eq!(
+            meta["originalBytes"],
+            content_size(&[ToolResponseContent::Text { text: text.clone() }])
+        );
+        transport.close().await?;
+
+        let (transport, client) = serve(OverflowPolicy::Error, text.clone()).await?;
+        let err = call(&client).await.unwrap_err();
+        let err = err.downcast_ref::<JsonRpcError>().unwrap();
+        assert_eq!(err.code, ErrorCode::InvalidParams as i32);
+        let data = err.error_data().unwrap();
+        assert_eq!(data.kind, ErrorData::RESULT_TOO_LARGE);
+        assert_eq!(data.details.unwrap()["limit"], 1024);
+        transport.close().await?;
+
+        // Small results are untouched by any policy
+        let (transport, client) = serve(OverflowPolicy::Error, "ok".to_string()).await?;
+        let response = call(&client).await?;
+        assert!(response.meta.is_none());
+        transport.close().await?;
+
+        let (transport, client) = serve(OverflowPolicy::SpillToResource, text.clone()).await?;
+        let response = call(&client).await?;
+        assert_eq!(response.meta.unwrap()["spilled"], true);
+        let ToolResponseContent::Resource { resource } = &response.content[1] else {
+            panic!("Expected resource link");
+        };
+        let read: ReadResourceResponse = client
+            .request_typed(
+                "resources/read",
+                serde_json::json!({ "uri": resource.uri }),
+                RequestOptions::default(),
+            )
+            .await?;
+        match &read.contents[0] {
+            ResourceContent::Blob(blob) => {
+                let bytes = base64::engine::general_purpose::STANDARD.decode(&blob.blob)?;
+                assert_eq!(String::from_utf8(bytes)?, text);
+                assert_eq!(blob.mime_type.as_deref(), Some("text/plain"));
+            }
+            other => panic!("Expected blob contents, got {:?}", other),
+        }
+        transport.close().await?;
+        Ok(())
+    }
+}
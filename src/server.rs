@@ -1,44 +1,165 @@
 use std::{
-    collections::HashMap,
-    sync::{Arc, RwLock},
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex, OnceLock, RwLock},
 };
 
 use crate::{
-    registry::{ToolHandler, Tools},
-    types::{CallToolRequest, CallToolResponse, ListRequest, Tool, ToolsListResponse},
+    blob::{BlobStore, LocalBlobStore},
+    fs::{directory_resources, open_file, read_file_cached},
+    log_limit::{LogThrottle, Verdict},
+    pagination::{listing_generation, paginate},
+    registry::{
+        ChunkSink, ClientRequester, CompletionHandler, CompletionHandlerOptions, Completions,
+        ConnectionMetadata, ProgressReporter, ProgressSink, PromptHandler, Prompts,
+        ReadResourceContext, ResourceHandler, ResourceStream, ResourceStreamFn,
+        ResourceTemplateHandler, Resources, ServerContext, State, StateMap, ToolHandler, Tools,
+    },
+    resource_cache::{self, ResourceCache},
+    result_limit::{OverflowPolicy, ResultLimit},
+    subscriptions::{Subscriptions, UpdateSink},
+    tool_source::{DynamicToolSource, ToolEvent},
+    types::{
+        CallToolRequest, CallToolResponse, CompleteRequest, CompletionResult, GetPromptRequest,
+        GetPromptResult, ListRequest, LoggingLevel, LoggingMessageParams, Prompt,
+        PromptCapabilities, PromptsListResponse, ReadResourceRequest, ReadResourceResponse,
+        Reference, Resource, ResourceCapabilities, ResourceTemplate, ResourceTemplatesListResponse,
+        ResourcesListResponse, SetLevelRequest, SubscribeRequest, Tool, ToolsListResponse,
+        RESOURCE_CHUNK_METHOD, RESOURCE_UPDATED_METHOD,
+    },
+    validation::{BuildError, BuildIssue, IssueCode, Severity},
 };
 
 use super::{
-    protocol::{Protocol, ProtocolBuilder},
-    transport::Transport,
+    protocol::{Protocol, ProtocolBuilder, RequestOptions},
+    transport::{BoxedTransport, JsonRpcError, JsonRpcMessage, JsonRpcResponse, Transport},
     types::{
-        ClientCapabilities, Implementation, InitializeRequest, InitializeResponse,
+        ClientCapabilities, ErrorCode, Implementation, InitializeRequest, InitializeResponse,
         ServerCapabilities, LATEST_PROTOCOL_VERSION,
     },
 };
 use anyhow::Result;
 use serde::{de::DeserializeOwned, Serialize};
 use std::future::Future;
+use std::path::Path;
 use std::pin::Pin;
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::{info, warn};
+use url::Url;
 
-#[derive(Clone)]
+/// What the client sent in `initialize` and `logging/setLevel`, replaced as a whole on
+/// re-initialization
+#[derive(Clone, Default)]
 pub struct ServerState {
     client_capabilities: Option<ClientCapabilities>,
     client_info: Option<Implementation>,
+    protocol_version: Option<String>,
     initialized: bool,
+    log_level: Option<LoggingLevel>,
 }
 
 #[derive(Clone)]
 pub struct Server<T: Transport> {
     protocol: Protocol<T>,
+    // The same protocol, for requests tool handlers send to the client
+    _client_protocol: Arc<OnceLock<Protocol<T>>>,
     state: Arc<RwLock<ServerState>>,
+    initialized: Arc<watch::Sender<bool>>,
+    tools: Arc<Tools>,
+    prompts: Arc<Prompts>,
+    resources: Arc<Resources>,
+    server_info: Implementation,
+    capabilities: ServerCapabilities,
+    tool_sources: Arc<Mutex<Vec<Box<dyn DynamicToolSource>>>>,
+    manage_transport: bool,
+    subscriptions: Subscriptions,
+    session_id: String,
+    resource_cache: ResourceCache,
+    default_log_level: LoggingLevel,
+    log_throttle: Option<Arc<LogThrottle>>,
+}
+
+/// Server over a transport chosen at runtime
+pub type DynServer = Server<BoxedTransport>;
+
+/// Sees each `initialize` request with the response the server would send, may change the
+/// response or reject the client. Session state is only recorded for accepted clients
+pub type InitializeHook = Arc<
+    dyn Fn(&InitializeRequest, &mut InitializeResponse) -> std::result::Result<(), JsonRpcError>
+        + Send
+        + Sync,
+>;
+
+/// Authorizes a request from its method and params before any handler runs
+pub type RequestInterceptor = Arc<
+    dyn Fn(
+            &str,
+            Option<&serde_json::Value>,
+            &ServerContext,
+        ) -> std::result::Result<(), JsonRpcError>
+        + Send
+        + Sync,
+>;
+
+/// Decides whether a client sees a tool in `tools/list`, from the capabilities it sent in
+/// `initialize`, `None` before it initialized
+pub type ToolFilter = Arc<dyn Fn(&Tool, Option<&ClientCapabilities>) -> bool + Send + Sync>;
+
+/// Whether requests must match the advertised capabilities, see
+/// [`ServerBuilder::enforce_capabilities`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CapabilityEnforcement {
+    /// Any registered handler answers and missing handlers aren't reported, the default
+    #[default]
+    Off,
+    /// Reject requests of unadvertised capabilities, warn at build about missing handlers
+    Enforce,
+    /// Like `Enforce`, and `build` panics on missing handlers
+    Strict,
 }
 
+/// Requests a server advertising the capability is expected to answer
+const CAPABILITY_HANDLERS: &[(&str, &[&str])] = &[
+    ("tools", &["tools/list", "tools/call"]),
+    ("prompts", &["prompts/list", "prompts/get"]),
+    ("resources", &["resources/list", "resources/read"]),
+    ("logging", &["logging/setLevel"]),
+    ("completions", &["completion/complete"]),
+];
+
 pub struct ServerBuilder<T: Transport> {
     protocol: ProtocolBuilder<T>,
     server_info: Implementation,
     capabilities: ServerCapabilities,
     tools: HashMap<String, ToolHandler>,
+    prompts: HashMap<String, PromptHandler>,
+    resources: HashMap<String, ResourceHandler>,
+    resource_templates: Vec<ResourceTemplateHandler>,
+    list_template_resources: bool,
+    completions: HashMap<String, CompletionHandler>,
+    blob_store: Option<Arc<dyn BlobStore>>,
+    tool_sources: Vec<Box<dyn DynamicToolSource>>,
+    manage_transport: bool,
+    allow_reinitialize: bool,
+    list_page_size: Option<usize>,
+    result_limit: Option<(usize, OverflowPolicy)>,
+    session_metadata: Option<serde_json::Value>,
+    connection: Option<ConnectionMetadata>,
+    request_interceptor: Option<RequestInterceptor>,
+    on_initialize: Option<InitializeHook>,
+    state: StateMap,
+    client_request_timeout: Option<Duration>,
+    capability_enforcement: CapabilityEnforcement,
+    capability_exempt: HashSet<String>,
+    tool_filter: Option<ToolFilter>,
+    subscriptions: Option<(Subscriptions, String)>,
+    resource_cache: ResourceCache,
+    cached_resources: HashMap<String, Duration>,
+    default_log_level: LoggingLevel,
+    log_rate_limit: Option<(u32, f64)>,
+    // Found while registering, e.g. duplicate names
+    issues: Vec<BuildIssue>,
+    strict_validation: bool,
 }
 
 impl<T: Transport> ServerBuilder<T> {
@@ -52,13 +173,38 @@ impl<T: Transport> ServerBuilder<T> {
         self
     }
 
+    /// Replaces the capabilities set so far, see [`merge_capabilities`](Self::merge_capabilities)
+    /// to add to them
     pub fn capabilities(mut self, capabilities: ServerCapabilities) -> Self {
         self.capabilities = capabilities;
         self
     }
 
+    /// Merged into the capabilities set so far, see [`ServerCapabilities::merge`]
+    pub fn merge_capabilities(mut self, capabilities: ServerCapabilities) -> Self {
+        self.capabilities = std::mem::take(&mut self.capabilities).merge(capabilities);
+        self
+    }
+
+    /// Report server processing time in `result._meta.durationMs` / `queuedMs`
+    pub fn emit_timing_meta(mut self, enabled: bool) -> Self {
+        self.protocol = self.protocol.emit_timing_meta(enabled);
+        self
+    }
+
+    /// Fail requests for `method` with a `RequestTimeout` error once handling exceeds `limit`
+    /// e.g. a short limit for `ping` and a long one for `tools/call`, unlimited by default
+    pub fn handler_timeout(mut self, method: &str, limit: Duration) -> Self {
+        self.protocol = self.protocol.handler_timeout(method, limit);
+        self
+    }
+
     /// Register a typed request handler
     /// for higher-level api use add tool
+    ///
+    /// # Panics
+    /// For `initialize`, which the server handles itself, customize it with
+    /// [`ServerBuilder::on_initialize`]
     pub fn request_handler<Req, Resp>(
         mut self,
         method: &str,
@@ -71,6 +217,10 @@ impl<T: Transport> ServerBuilder<T> {
         Req: DeserializeOwned + Send + Sync + 'static,
         Resp: Serialize + Send + Sync + 'static,
     {
+        assert!(
+            method != "initialize",
+            "`initialize` is handled by the server, use ServerBuilder::on_initialize to customize it"
+        );
         self.protocol = self.protocol.request_handler(method, handler);
         self
     }
@@ -90,6 +240,46 @@ impl<T: Transport> ServerBuilder<T> {
         self
     }
 
+    /// Handle a notification next to the handlers `method` already has, all of them run
+    pub fn add_notification_handler<N>(
+        mut self,
+        method: &str,
+        handler: impl Fn(N) -> Pin<Box<dyn std::future::Future<Output = Result<()>> + Send>>
+            + Send
+            + Sync
+            + 'static,
+    ) -> Self
+    where
+        N: DeserializeOwned + Send + Sync + 'static,
+    {
+        self.protocol = self.protocol.add_notification_handler(method, handler);
+        self
+    }
+
+    /// Called with the error of a failing notification handler, the other handlers still run
+    pub fn on_notification_error(
+        mut self,
+        callback: impl Fn(&str, &anyhow::Error) + Send + Sync + 'static,
+    ) -> Self {
+        self.protocol = self.protocol.on_notification_error(callback);
+        self
+    }
+
+    /// Run the handlers of a notification concurrently instead of in registration order
+    pub fn concurrent_notification_handlers(mut self, enabled: bool) -> Self {
+        self.protocol = self.protocol.concurrent_notification_handlers(enabled);
+        self
+    }
+
+    /// Called instead of a notification handler when the notification's params don't parse
+    pub fn on_malformed_notification(
+        mut self,
+        callback: impl Fn(&str, &serde_json::Value, &serde_json::Error) + Send + Sync + 'static,
+    ) -> Self {
+        self.protocol = self.protocol.on_malformed_notification(callback);
+        self
+    }
+
     pub fn register_tool(
         &mut self,
         tool: Tool,
@@ -98,17 +288,464 @@ impl<T: Transport> ServerBuilder<T> {
             + Sync
             + 'static,
     ) {
-        self.tools.insert(
-            tool.name.clone(),
-            ToolHandler {
-                tool,
+        let name = tool.name.clone();
+        if self
+            .tools
+            .insert(name.clone(), ToolHandler::new(tool, Box::new(f)))
+            .is_some()
+        {
+            self.duplicate(IssueCode::DuplicateTool, "Tool", &name);
+        }
+    }
+
+    /// Register a tool whose handler also receives the server context
+    /// e.g. to check the client's capabilities before a server-initiated request
+    pub fn register_tool_with_context(
+        &mut self,
+        tool: Tool,
+        f: impl Fn(
+                CallToolRequest,
+                ServerContext,
+            ) -> Pin<Box<dyn Future<Output = Result<CallToolResponse>> + Send>>
+            + Send
+            + Sync
+            + 'static,
+    ) {
+        let name = tool.name.clone();
+        if self
+            .tools
+            .insert(
+                name.clone(),
+                ToolHandler {
+                    tool,
+                    f: Box::new(f),
+                },
+            )
+            .is_some()
+        {
+            self.duplicate(IssueCode::DuplicateTool, "Tool", &name);
+        }
+    }
+
+    /// Register a tool whose handler receives the state of type `S` added with
+    /// [`with_state`](Self::with_state)
+    ///
+    /// # Panics
+    /// When no state of type `S` was added before
+    pub fn register_tool_with_state<S: Send + Sync + 'static>(
+        &mut self,
+        tool: Tool,
+        f: impl Fn(
+                CallToolRequest,
+                State<S>,
+            ) -> Pin<Box<dyn Future<Output = Result<CallToolResponse>> + Send>>
+            + Send
+            + Sync
+            + 'static,
+    ) {
+        let Some(state) = self.state.get::<S>() else {
+            panic!(
+                "Tool `{}` needs state of type `{}`, add it with ServerBuilder::with_state before registering",
+                tool.name,
+                std::any::type_name::<S>()
+            );
+        };
+        self.register_tool(tool, move |req| f(req, state.clone()));
+    }
+
+    /// Register a prompt served by `prompts/list` and `prompts/get`
+    /// messages may contain `MessageContent::ResourceRef`, resolved through the registered resources.
+    /// `prompts/get` without an argument the prompt declares as required fails before `f` runs.
+    /// Advertises the `prompts` capability unless prompt capabilities were set explicitly
+    pub fn register_prompt(
+        &mut self,
+        prompt: Prompt,
+        f: impl Fn(GetPromptRequest) -> Pin<Box<dyn Future<Output = Result<GetPromptResult>> + Send>>
+            + Send
+            + Sync
+            + 'static,
+    ) {
+        let name = prompt.name.clone();
+        if self
+            .prompts
+            .insert(
+                name.clone(),
+                PromptHandler {
+                    prompt,
+                    f: Box::new(f),
+                },
+            )
+            .is_some()
+        {
+            self.duplicate(IssueCode::DuplicatePrompt, "Prompt", &name);
+        }
+    }
+
+    /// Register a resource served by `resources/list` and `resources/read`
+    /// advertises the `resources` capability unless resource capabilities were set explicitly
+    pub fn register_resource(
+        &mut self,
+        resource: Resource,
+        f: impl Fn(
+                ReadResourceRequest,
+            ) -> Pin<Box<dyn Future<Output = Result<ReadResourceResponse>> + Send>>
+            + Send
+            + Sync
+            + 'static,
+    ) {
+        self.register_resource_with_context(resource, move |req, _ctx| f(req));
+    }
+
+    /// Register a resource whose handler also receives the read context, e.g. a requested range
+    pub fn register_resource_with_context(
+        &mut self,
+        resource: Resource,
+        f: impl Fn(
+                ReadResourceRequest,
+                ReadResourceContext,
+            ) -> Pin<Box<dyn Future<Output = Result<ReadResourceResponse>> + Send>>
+            + Send
+            + Sync
+            + 'static,
+    ) {
+        self.insert_resource(ResourceHandler {
+            resource,
+            f: Box::new(f),
+            stream: None,
+        });
+    }
+
+    /// Register a resource whose contents are read from an [`AsyncRead`](tokio::io::AsyncRead)
+    /// reads that ask to stream get them as `notifications/resources/chunk` without buffering
+    /// the whole resource, other reads get them inline. `open` should honour `ctx.range`
+    pub fn register_resource_stream(
+        &mut self,
+        resource: Resource,
+        open: impl Fn(
+                ReadResourceRequest,
+                ReadResourceContext,
+            ) -> Pin<Box<dyn Future<Output = Result<ResourceStream>> + Send>>
+            + Send
+            + Sync
+            + 'static,
+    ) {
+        let open = Arc::new(open);
+        let read = open.clone();
+        let f = move |req: ReadResourceRequest, ctx| {
+            let read = read.clone();
+            Box::pin(async move {
+                let uri = req.uri.clone();
+                read(req, ctx).await?.into_response(uri).await
+            }) as Pin<Box<dyn Future<Output = Result<ReadResourceResponse>> + Send>>
+        };
+        self.insert_resource(ResourceHandler {
+            resource,
+            f: Box::new(f),
+            stream: Some(Box::new(move |req, ctx| open(req, ctx))),
+        });
+    }
+
+    /// Register a resource template served by `resources/templates/list`
+    /// `resources/read` of a URI matching the template goes to `f`, `list` enumerates the
+    /// concrete resources it currently expands to for [`ServerBuilder::list_template_resources`]
+    pub fn register_resource_template(
+        &mut self,
+        template: ResourceTemplate,
+        list: impl Fn() -> Pin<Box<dyn Future<Output = Result<Vec<Resource>>> + Send>>
+            + Send
+            + Sync
+            + 'static,
+        f: impl Fn(
+                ReadResourceRequest,
+                ReadResourceContext,
+            ) -> Pin<Box<dyn Future<Output = Result<ReadResourceResponse>> + Send>>
+            + Send
+            + Sync
+            + 'static,
+    ) {
+        self.resource_templates.push(ResourceTemplateHandler {
+            template,
+            list: Box::new(list),
+            f: Box::new(f),
+        });
+    }
+
+    /// Also list the resources of every template in `resources/list`, merged with the
+    /// registered ones by URI. Off by default since the spec lists templates separately
+    pub fn list_template_resources(mut self, enabled: bool) -> Self {
+        self.list_template_resources = enabled;
+        self
+    }
+
+    /// Register every file below `dir` as a `file://` resource
+    /// ranged reads seek into the file instead of loading it whole, streamed reads are sent in
+    /// chunks
+    pub fn serve_directory(&mut self, dir: impl AsRef<Path>) -> Result<()> {
+        for (resource, path) in directory_resources(dir.as_ref())? {
+            let path = Arc::new(path);
+            // Decided on the first read, so every range of the file has the same kind
+            let is_text = Arc::new(tokio::sync::OnceCell::new());
+            let stream_path = path.clone();
+            let stream: ResourceStreamFn = Box::new(move |_req, ctx| {
+                let path = stream_path.clone();
+                Box::pin(async move { open_file(&path, ctx.range).await })
+            });
+            self.insert_resource(ResourceHandler {
+                resource,
+                f: Box::new(move |req, ctx| {
+                    let (path, is_text) = (path.clone(), is_text.clone());
+                    Box::pin(
+                        async move { read_file_cached(&path, req.uri, ctx.range, &is_text).await },
+                    )
+                }),
+                stream: Some(stream),
+            });
+        }
+        Ok(())
+    }
+
+    fn insert_resource(&mut self, handler: ResourceHandler) {
+        let uri = handler.resource.uri.to_string();
+        if self.resources.insert(uri.clone(), handler).is_some() {
+            self.duplicate(IssueCode::DuplicateResource, "Resource", &uri);
+        }
+    }
+
+    fn duplicate(&mut self, code: IssueCode, kind: &str, name: &str) {
+        self.issues.push(BuildIssue::warning(
+            code,
+            format!(
+                "{} `{}` is registered twice, the last registration wins",
+                kind, name
+            ),
+            format!("register each {} once", kind.to_lowercase()),
+        ));
+    }
+
+    /// Register a `completion/complete` handler for the arguments of a prompt or resource
+    pub fn register_completion(
+        &mut self,
+        reference: Reference,
+        options: CompletionHandlerOptions,
+        f: impl Fn(CompleteRequest) -> Pin<Box<dyn Future<Output = Result<CompletionResult>> + Send>>
+            + Send
+            + Sync
+            + 'static,
+    ) {
+        self.completions.insert(
+            reference.key(),
+            CompletionHandler {
+                options,
                 f: Box::new(f),
+                in_flight: Default::default(),
             },
         );
     }
 
+    /// Cache whole reads of the resource at `uri` for `ttl`, until invalidated with
+    /// [`Server::notify_resource_updated`] or [`Server::invalidate_resource`].
+    /// Ranged and streamed reads aren't cached
+    pub fn cache_resource(mut self, uri: impl Into<String>, ttl: Duration) -> Self {
+        self.cached_resources.insert(uri.into(), ttl);
+        self
+    }
+
+    /// Keep cached reads in `cache`, shared by the sessions built with it, each server gets
+    /// its own cache by default. Sessions then see each other's reads, so don't share a cache
+    /// for resources whose handlers answer per session or per client
+    pub fn resource_cache(mut self, cache: ResourceCache) -> Self {
+        self.resource_cache = cache;
+        self
+    }
+
+    /// Whether `listen` opens the transport before receiving and closes it once the peer is gone,
+    /// on by default, opening a transport that is already open is a no-op
+    pub fn with_transport_open(mut self, enabled: bool) -> Self {
+        self.manage_transport = enabled;
+        self
+    }
+
+    /// Accept a second `initialize`, replacing the client's capabilities and info
+    /// and waiting for `notifications/initialized` again, rejected with `InvalidRequest` by default
+    pub fn allow_reinitialize(mut self, enabled: bool) -> Self {
+        self.allow_reinitialize = enabled;
+        self
+    }
+
+    /// Level of the log records a session receives until its client sends `logging/setLevel`,
+    /// `Debug` by default so every record is sent
+    pub fn default_log_level(mut self, level: LoggingLevel) -> Self {
+        self.default_log_level = level;
+        self
+    }
+
+    /// Send at most `burst` log records at once and `per_second` on average, the rest are
+    /// dropped and a record with their count follows once sending resumes, unlimited by default
+    ///
+    /// # Panics
+    /// When `burst` is 0 or `per_second` isn't positive
+    pub fn log_rate_limit(mut self, burst: u32, per_second: f64) -> Self {
+        assert!(
+            burst > 0 && per_second > 0.0,
+            "The log rate limit needs a burst of at least 1 and a positive rate"
+        );
+        self.log_rate_limit = Some((burst, per_second));
+        self
+    }
+
+    /// Answer `tools/*`, `prompts/*`, `resources/*`, `logging/*` and `completion/*` requests
+    /// only when their capability is advertised, other requests are never checked
+    pub fn enforce_capabilities(mut self, enforcement: CapabilityEnforcement) -> Self {
+        self.capability_enforcement = enforcement;
+        self
+    }
+
+    /// Let a vendor method under one of the standard prefixes through whatever is advertised
+    pub fn capability_exempt(mut self, method: impl Into<String>) -> Self {
+        self.capability_exempt.insert(method.into());
+        self
+    }
+
+    /// Application state for handlers registered with
+    /// [`register_tool_with_state`](Self::register_tool_with_state), one value per type,
+    /// a second value of the same type replaces the first for tools registered afterwards
+    pub fn with_state<S: Send + Sync + 'static>(mut self, state: S) -> Self {
+        self.state.insert(state);
+        self
+    }
+
+    /// Timeout of requests tool handlers send through [`ServerContext::client_features`],
+    /// sampling can take a while, the protocol's default request timeout otherwise
+    pub fn client_request_timeout(mut self, timeout: Duration) -> Self {
+        self.client_request_timeout = Some(timeout);
+        self
+    }
+
+    /// Passed to tool handlers and the request interceptor in [`ServerContext::session_metadata`]
+    /// e.g. the token claims in the connection the HTTP server's `build_server` callback receives
+    pub fn session_metadata(mut self, metadata: Option<serde_json::Value>) -> Self {
+        self.session_metadata = metadata;
+        self
+    }
+
+    /// Passed to tool handlers and the request interceptor in [`ServerContext::connection`]
+    /// e.g. to record who called which tool from where
+    pub fn connection(mut self, connection: ConnectionMetadata) -> Self {
+        self.connection = Some(connection);
+        self
+    }
+
+    /// Check every incoming request before it is dispatched, e.g. which tools a user may call.
+    /// An error is sent back as the response and the handler never runs
+    pub fn request_interceptor(
+        mut self,
+        interceptor: impl Fn(
+                &str,
+                Option<&serde_json::Value>,
+                &ServerContext,
+            ) -> std::result::Result<(), JsonRpcError>
+            + Send
+            + Sync
+            + 'static,
+    ) -> Self {
+        self.request_interceptor = Some(Arc::new(interceptor));
+        self
+    }
+
+    /// Customize the `initialize` response or reject clients, e.g. by name
+    /// runs after the re-initialization check, replaces a previously set hook
+    pub fn on_initialize(
+        mut self,
+        hook: impl Fn(&InitializeRequest, &mut InitializeResponse) -> std::result::Result<(), JsonRpcError>
+            + Send
+            + Sync
+            + 'static,
+    ) -> Self {
+        self.on_initialize = Some(Arc::new(hook));
+        self
+    }
+
+    /// Hide tools from `tools/list` that the client can't use, e.g. image-only tools for a
+    /// client without image support, hidden tools can still be called, all are listed by default
+    pub fn tool_filter(
+        mut self,
+        filter: impl Fn(&Tool, Option<&ClientCapabilities>) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.tool_filter = Some(Arc::new(filter));
+        self
+    }
+
+    /// Record this session's `resources/subscribe` requests in a registry shared by the
+    /// server's sessions, each session gets its own registry by default. Subscribing is
+    /// answered when `resources.subscribe` is advertised
+    pub fn subscriptions(
+        mut self,
+        subscriptions: Subscriptions,
+        session_id: impl Into<String>,
+    ) -> Self {
+        self.subscriptions = Some((subscriptions, session_id.into()));
+        self
+    }
+
+    /// Paginate `tools/list`, `resources/list` and `prompts/list` with signed cursors
+    /// lists are returned whole by default
+    pub fn list_page_size(mut self, page_size: usize) -> Self {
+        self.list_page_size = Some(page_size);
+        self
+    }
+
+    /// Apply `policy` to `tools/call` results whose serialized content exceeds `max_bytes`
+    /// spilling uses the blob store, a local one with a one hour TTL is created if none is set
+    pub fn max_tool_result_bytes(mut self, max_bytes: usize, policy: OverflowPolicy) -> Self {
+        self.result_limit = Some((max_bytes, policy));
+        self
+    }
+
+    /// Keep the tool set in sync with a dynamic source while the server listens
+    /// advertises the `tools.listChanged` capability unless tool capabilities were set explicitly
+    pub fn with_tool_source(mut self, source: impl DynamicToolSource) -> Self {
+        self.tool_sources.push(Box::new(source));
+        self
+    }
+
+    /// Serve `blob://` resources from the given store through `resources/read`
+    /// a custom `resources/read` handler takes precedence, advertises the `resources` capability
+    /// unless resource capabilities were set explicitly
+    pub fn blob_store(mut self, store: Arc<dyn BlobStore>) -> Self {
+        self.blob_store = Some(store);
+        self
+    }
+
+    /// Turn every configuration warning of [`try_build`](Self::try_build) into an error,
+    /// e.g. a tool registered twice, off by default
+    pub fn strict_validation(mut self, strict: bool) -> Self {
+        self.strict_validation = strict;
+        self
+    }
+
+    /// Like [`build`](Self::build), failing with every configuration problem found
+    /// warnings are logged when there is no error
+    pub fn try_build(mut self) -> std::result::Result<Server<T>, BuildError> {
+        let strict = self.strict_validation;
+        let mut issues = std::mem::take(&mut self.issues);
+        if self.list_page_size == Some(0) {
+            issues.push(BuildIssue::warning(
+                IssueCode::ZeroPageSize,
+                "List page size is 0, pages hold one item",
+                "use a page size of at least 1, or don't paginate",
+            ));
+        }
+        let (server, missing) = Server::new(self);
+        issues.extend(missing);
+        BuildError::check(issues, strict)?;
+        Ok(server)
+    }
+
+    /// # Panics
+    /// On the errors [`try_build`](Self::try_build) returns, e.g. advertised capabilities
+    /// without handlers under [`CapabilityEnforcement::Strict`]
     pub fn build(self) -> Server<T> {
-        Server::new(self)
+        self.try_build().unwrap_or_else(|e| panic!("{}", e))
     }
 }
 
@@ -122,99 +759,556 @@ impl<T: Transport> Server<T> {
             },
             capabilities: Default::default(),
             tools: HashMap::new(),
+            prompts: HashMap::new(),
+            resources: HashMap::new(),
+            resource_templates: Vec::new(),
+            list_template_resources: false,
+            completions: HashMap::new(),
+            blob_store: None,
+            tool_sources: Vec::new(),
+            manage_transport: true,
+            allow_reinitialize: false,
+            list_page_size: None,
+            result_limit: None,
+            session_metadata: None,
+            connection: None,
+            request_interceptor: None,
+            on_initialize: None,
+            state: StateMap::default(),
+            client_request_timeout: None,
+            capability_enforcement: CapabilityEnforcement::Off,
+            capability_exempt: HashSet::new(),
+            tool_filter: None,
+            subscriptions: None,
+            resource_cache: ResourceCache::default(),
+            cached_resources: HashMap::new(),
+            default_log_level: LoggingLevel::Debug,
+            log_rate_limit: None,
+            issues: Vec::new(),
+            strict_validation: false,
         }
     }
 
-    fn new(builder: ServerBuilder<T>) -> Self {
-        let state = Arc::new(RwLock::new(ServerState {
-            client_capabilities: None,
-            client_info: None,
-            initialized: false,
-        }));
+    // Also returns the problems only known once the handlers are in place
+    fn new(mut builder: ServerBuilder<T>) -> (Self, Vec<BuildIssue>) {
+        let state = Arc::new(RwLock::new(ServerState::default()));
+        let initialized = Arc::new(watch::Sender::new(false));
+        if !builder.tool_sources.is_empty() && builder.capabilities.tools.is_none() {
+            builder.capabilities.tools = Some(serde_json::json!({ "listChanged": true }));
+        }
+        if let Some((_, OverflowPolicy::SpillToResource)) = builder.result_limit {
+            builder
+                .blob_store
+                .get_or_insert_with(|| Arc::new(LocalBlobStore::new(Duration::from_secs(3600))));
+        }
+        // Registered resources and prompts are advertised unless their capabilities were set
+        let has_resources = !builder.resources.is_empty()
+            || !builder.resource_templates.is_empty()
+            || builder.blob_store.is_some();
+        if has_resources && builder.capabilities.resources.is_none() {
+            builder.capabilities.resources = Some(ResourceCapabilities::default());
+        }
+        if !builder.prompts.is_empty() && builder.capabilities.prompts.is_none() {
+            builder.capabilities.prompts = Some(PromptCapabilities::default());
+        }
+        let server_info = builder.server_info.clone();
+        let capabilities = builder.capabilities.clone();
+        let (subscriptions, session_id) = builder
+            .subscriptions
+            .take()
+            .unwrap_or_else(|| (Subscriptions::default(), uuid::Uuid::new_v4().to_string()));
 
         // Initialize protocol with handlers
         let mut protocol = builder
             .protocol
             .request_handler(
                 "initialize",
-                Self::handle_init(state.clone(), builder.server_info, builder.capabilities),
+                Self::handle_init(
+                    state.clone(),
+                    initialized.clone(),
+                    builder.server_info,
+                    builder.capabilities,
+                    builder.allow_reinitialize,
+                    builder.on_initialize.take(),
+                    (subscriptions.clone(), session_id.clone()),
+                ),
             )
-            .notification_handler(
+            // Added, a handler the application registered for it keeps running
+            .add_notification_handler(
                 "notifications/initialized",
-                Self::handle_initialized(state.clone()),
+                Self::handle_initialized(state.clone(), initialized.clone()),
             );
 
+        // Handlers reach the protocol weakly, it holds them, the server keeps it alive
+        let client_protocol: Arc<OnceLock<Protocol<T>>> = Arc::new(OnceLock::new());
+        let client_requests = {
+            let protocol = Arc::downgrade(&client_protocol);
+            ClientRequester {
+                send: Arc::new(move |method, params, options| {
+                    let protocol = protocol
+                        .upgrade()
+                        .and_then(|protocol| protocol.get().cloned());
+                    Box::pin(async move {
+                        let protocol = protocol.ok_or_else(|| anyhow::anyhow!("Server is gone"))?;
+                        protocol.request(&method, params, options).await
+                    })
+                }),
+                timeout: builder.client_request_timeout,
+            }
+        };
+
+        // Captured when a request arrives
+        let context = {
+            let state = state.clone();
+            let session_metadata = builder.session_metadata.clone();
+            let connection = builder.connection.clone();
+            move || ServerContext {
+                client_capabilities: state
+                    .read()
+                    .ok()
+                    .and_then(|state| state.client_capabilities.clone()),
+                session_metadata: session_metadata.clone(),
+                connection: connection.clone(),
+                progress: None,
+                client_requests: Some(client_requests.clone()),
+            }
+        };
+        let gate = (builder.capability_enforcement != CapabilityEnforcement::Off).then(|| {
+            (
+                capabilities.clone(),
+                std::mem::take(&mut builder.capability_exempt),
+            )
+        });
+        let intercept = builder.request_interceptor.take();
+        if gate.is_some() || intercept.is_some() {
+            let context = context.clone();
+            protocol = protocol.request_interceptor(move |request| {
+                if let Some((capabilities, exempt)) = &gate {
+                    check_capability(capabilities, exempt, &request.method)?;
+                }
+                match &intercept {
+                    Some(intercept) => {
+                        intercept(&request.method, request.params.as_ref(), &context())
+                    }
+                    None => Ok(()),
+                }
+            });
+        }
+
+        if !protocol.has_request_handler("ping") {
+            protocol = protocol.request_handler("ping", |_: serde_json::Value| {
+                Box::pin(async move { Ok(serde_json::json!({})) })
+            });
+        }
+
+        let page_size = builder.list_page_size;
+        let result_limit = builder.result_limit.map(|(max_bytes, policy)| ResultLimit {
+            max_bytes,
+            policy,
+            blob_store: builder.blob_store.clone(),
+        });
+
+        let mut issues = Vec::new();
+        if capabilities.tools.is_some()
+            && builder.tools.is_empty()
+            && builder.tool_sources.is_empty()
+            && !protocol.has_request_handler("tools/list")
+        {
+            issues.push(BuildIssue::warning(
+                IssueCode::NoTools,
+                "The tools capability is advertised but no tool is registered",
+                "register a tool or a tool source, or don't advertise tools",
+            ));
+        }
+
         // Add tools handlers if not already present
+        let tools = Arc::new(Tools::new(builder.tools));
         if !protocol.has_request_handler("tools/list") {
-            let tools = Arc::new(Tools::new(builder.tools));
-            let tools_clone = tools.clone();
+            let notifier = protocol.notifier();
+            let progress_sink: ProgressSink = Arc::new(move |params| {
+                let notifier = notifier.clone();
+                Box::pin(async move {
+                    notifier
+                        .notify(
+                            "notifications/progress",
+                            Some(serde_json::to_value(params)?),
+                        )
+                        .await
+                })
+            });
             let tools_list = tools.clone();
-            let tools_call = tools_clone.clone();
+            let tools_call = tools.clone();
+            let tool_filter = builder.tool_filter.take();
+            let list_state = state.clone();
 
             protocol = protocol
-                .request_handler("tools/list", move |_req: ListRequest| {
+                .request_handler("tools/list", move |req: ListRequest| {
                     let tools = tools_list.clone();
+                    let mut listed = tools.list_tools();
+                    if let Some(filter) = &tool_filter {
+                        let client_capabilities = list_state
+                            .read()
+                            .ok()
+                            .and_then(|state| state.client_capabilities.clone());
+                        listed.retain(|tool| filter(tool, client_capabilities.as_ref()));
+                    }
                     Box::pin(async move {
+                        let (tools, next_cursor) = paginate(
+                            "tools",
+                            listed,
+                            req.cursor.as_deref(),
+                            tools.generation(),
+                            page_size,
+                        )?;
                         Ok(ToolsListResponse {
-                            tools: tools.list_tools(),
-                            next_cursor: None,
+                            tools,
+                            next_cursor,
                             meta: None,
                         })
                     })
                 })
                 .request_handler("tools/call", move |req: CallToolRequest| {
                     let tools = tools_call.clone();
-                    Box::pin(async move { tools.call_tool(req).await })
+                    let result_limit = result_limit.clone();
+                    let mut ctx = context();
+                    ctx.progress = req
+                        .meta
+                        .as_ref()
+                        .and_then(|meta| meta.get("progressToken"))
+                        .and_then(|token| serde_json::from_value(token.clone()).ok())
+                        .map(|token| ProgressReporter {
+                            token,
+                            sink: progress_sink.clone(),
+                        });
+                    Box::pin(async move {
+                        let response = tools.call_tool(req, ctx).await?;
+                        match result_limit {
+                            Some(limit) => limit.apply(response).await,
+                            None => Ok(response),
+                        }
+                    })
                 });
         }
 
-        Server {
-            protocol: protocol.build(),
-            state,
+        for (uri, ttl) in std::mem::take(&mut builder.cached_resources) {
+            let uri = resource_cache::normalize(&uri);
+            match builder.resources.remove(&uri) {
+                Some(mut handler) => {
+                    handler.f = builder.resource_cache.wrap(uri.clone(), ttl, handler.f);
+                    builder.resources.insert(uri, handler);
+                }
+                None => issues.push(BuildIssue::warning(
+                    IssueCode::UnknownResource,
+                    format!(
+                        "Caching is configured for `{}`, no such resource is registered",
+                        uri
+                    ),
+                    "register the resource, or check the URI",
+                )),
+            }
         }
-    }
 
-    // Helper function for initialize handler
-    fn handle_init(
-        state: Arc<RwLock<ServerState>>,
-        server_info: Implementation,
-        capabilities: ServerCapabilities,
-    ) -> impl Fn(
-        InitializeRequest,
-    )
-        -> Pin<Box<dyn std::future::Future<Output = Result<InitializeResponse>> + Send>> {
-        move |req| {
-            let state = state.clone();
-            let server_info = server_info.clone();
-            let capabilities = capabilities.clone();
-
-            Box::pin(async move {
-                let mut state = state
-                    .write()
-                    .map_err(|_| anyhow::anyhow!("Lock poisoned"))?;
-                state.client_capabilities = Some(req.capabilities);
-                state.client_info = Some(req.client_info);
-
-                Ok(InitializeResponse {
-                    protocol_version: LATEST_PROTOCOL_VERSION.to_string(),
-                    capabilities,
-                    server_info,
+        // Add resources and prompts handlers when any were registered and not already present
+        let resources = Arc::new(Resources::new(
+            builder.resources,
+            builder.resource_templates,
+            builder.blob_store,
+        ));
+        let has_templates = !resources.list_templates().is_empty();
+        let list_template_resources = builder.list_template_resources && has_templates;
+        if (!resources.list_resources().is_empty() || list_template_resources)
+            && !protocol.has_request_handler("resources/list")
+        {
+            let resources = resources.clone();
+            protocol = protocol.request_handler("resources/list", move |req: ListRequest| {
+                let resources = resources.clone();
+                Box::pin(async move {
+                    // Template listings can change between pages, key the cursor to the URIs
+                    let all = if list_template_resources {
+                        resources.list_resources_with_templates().await?
+                    } else {
+                        resources.list_resources()
+                    };
+                    let generation =
+                        listing_generation(all.iter().map(|resource| resource.uri.as_str()));
+                    let (resources, next_cursor) = paginate(
+                        "resources",
+                        all,
+                        req.cursor.as_deref(),
+                        generation,
+                        page_size,
+                    )?;
+                    Ok(ResourcesListResponse {
+                        resources,
+                        next_cursor,
+                        meta: None,
+                    })
                 })
-            })
+            });
         }
-    }
-
-    // Helper function for initialized handler
-    fn handle_initialized(
-        state: Arc<RwLock<ServerState>>,
-    ) -> impl Fn(()) -> Pin<Box<dyn std::future::Future<Output = Result<()>> + Send>> {
-        move |_| {
-            let state = state.clone();
-            Box::pin(async move {
-                let mut state = state
+        if has_templates && !protocol.has_request_handler("resources/templates/list") {
+            let resources = resources.clone();
+            protocol =
+                protocol.request_handler("resources/templates/list", move |req: ListRequest| {
+                    let resources = resources.clone();
+                    Box::pin(async move {
+                        let templates = resources.list_templates();
+                        let generation = listing_generation(
+                            templates
+                                .iter()
+                                .map(|template| template.uri_template.as_str()),
+                        );
+                        let (resource_templates, next_cursor) = paginate(
+                            "resources/templates",
+                            templates,
+                            req.cursor.as_deref(),
+                            generation,
+                            page_size,
+                        )?;
+                        Ok(ResourceTemplatesListResponse {
+                            resource_templates,
+                            next_cursor,
+                            meta: None,
+                        })
+                    })
+                });
+        }
+        if !resources.is_empty() && !protocol.has_request_handler("resources/read") {
+            let resources = resources.clone();
+            let notifier = protocol.notifier();
+            let sink: ChunkSink = Arc::new(move |chunk| {
+                let notifier = notifier.clone();
+                Box::pin(async move {
+                    notifier
+                        .notify(RESOURCE_CHUNK_METHOD, Some(serde_json::to_value(chunk)?))
+                        .await
+                })
+            });
+            protocol =
+                protocol.request_handler("resources/read", move |req: ReadResourceRequest| {
+                    let resources = resources.clone();
+                    let sink = sink.clone();
+                    Box::pin(
+                        async move { resources.read_resource_streaming(req, Some(&sink)).await },
+                    )
+                });
+        }
+
+        let subscribe = capabilities
+            .resources
+            .as_ref()
+            .and_then(|resources| resources.subscribe)
+            == Some(true);
+        if subscribe && !protocol.has_request_handler("resources/subscribe") {
+            let notifier = protocol.notifier();
+            let sink: UpdateSink = Arc::new(move |params| {
+                let notifier = notifier.clone();
+                Box::pin(async move {
+                    notifier
+                        .notify(RESOURCE_UPDATED_METHOD, Some(serde_json::to_value(params)?))
+                        .await
+                })
+            });
+            let subscribe = (subscriptions.clone(), session_id.clone());
+            let unsubscribe = subscribe.clone();
+            protocol = protocol
+                .request_handler("resources/subscribe", move |req: SubscribeRequest| {
+                    let (subscriptions, session_id) = &subscribe;
+                    subscriptions.subscribe(session_id, req.uri, &sink);
+                    Box::pin(async move { Ok(serde_json::json!({})) })
+                })
+                .request_handler("resources/unsubscribe", move |req: SubscribeRequest| {
+                    let (subscriptions, session_id) = &unsubscribe;
+                    subscriptions.unsubscribe(session_id, &req.uri);
+                    Box::pin(async move { Ok(serde_json::json!({})) })
+                });
+        }
+
+        let prompts = Arc::new(Prompts::new(builder.prompts));
+        if !prompts.is_empty() && !protocol.has_request_handler("prompts/list") {
+            let prompts_list = prompts.clone();
+            let prompts_get = prompts.clone();
+            let resources = resources.clone();
+            protocol = protocol
+                .request_handler("prompts/list", move |req: ListRequest| {
+                    let prompts = prompts_list.clone();
+                    Box::pin(async move {
+                        // Keyed to the names, a server built with other prompts rejects the cursor
+                        let all = prompts.list_prompts();
+                        let generation =
+                            listing_generation(all.iter().map(|prompt| prompt.name.as_str()));
+                        let (prompts, next_cursor) =
+                            paginate("prompts", all, req.cursor.as_deref(), generation, page_size)?;
+                        Ok(PromptsListResponse {
+                            prompts,
+                            next_cursor,
+                            meta: None,
+                        })
+                    })
+                })
+                .request_handler("prompts/get", move |req: GetPromptRequest| {
+                    let prompts = prompts_get.clone();
+                    let resources = resources.clone();
+                    Box::pin(async move { prompts.get_prompt(req, &resources).await })
+                });
+        }
+
+        let completions = Arc::new(Completions::new(builder.completions));
+        if !completions.is_empty() && !protocol.has_request_handler("completion/complete") {
+            protocol =
+                protocol.request_handler("completion/complete", move |req: CompleteRequest| {
+                    let completions = completions.clone();
+                    Box::pin(async move { completions.complete(req).await })
+                });
+        }
+
+        if capabilities.logging.is_some() && !protocol.has_request_handler("logging/setLevel") {
+            let state = state.clone();
+            protocol = protocol.request_handler("logging/setLevel", move |req: SetLevelRequest| {
+                let state = state.clone();
+                Box::pin(async move {
+                    state
+                        .write()
+                        .map_err(|_| anyhow::anyhow!("Lock poisoned"))?
+                        .log_level = Some(req.level);
+                    Ok(serde_json::json!({}))
+                })
+            });
+        }
+
+        let severity = match builder.capability_enforcement {
+            CapabilityEnforcement::Strict => Severity::Error,
+            _ => Severity::Warning,
+        };
+        // Only checked when enforcing, they would be logged for every session otherwise
+        let missing = match builder.capability_enforcement {
+            CapabilityEnforcement::Off => Vec::new(),
+            _ => missing_handlers(&capabilities, |method| protocol.has_request_handler(method)),
+        };
+        for (capability, method) in missing {
+            issues.push(BuildIssue {
+                code: IssueCode::MissingHandler,
+                severity,
+                message: format!(
+                    "Capability {} is advertised without a {} handler",
+                    capability, method
+                ),
+                suggestion: format!(
+                    "register a {} handler, or don't advertise {}",
+                    method, capability
+                ),
+            });
+        }
+
+        let protocol = protocol.build();
+        let _ = client_protocol.set(protocol.clone());
+        let server = Server {
+            protocol,
+            _client_protocol: client_protocol,
+            state,
+            initialized,
+            tools,
+            prompts,
+            resources,
+            server_info,
+            capabilities,
+            tool_sources: Arc::new(Mutex::new(builder.tool_sources)),
+            manage_transport: builder.manage_transport,
+            subscriptions,
+            session_id,
+            resource_cache: builder.resource_cache,
+            default_log_level: builder.default_log_level,
+            log_throttle: builder
+                .log_rate_limit
+                .map(|(burst, per_second)| Arc::new(LogThrottle::new(burst, per_second))),
+        };
+        (server, issues)
+    }
+
+    // Helper function for initialize handler
+    fn handle_init(
+        state: Arc<RwLock<ServerState>>,
+        initialized: Arc<watch::Sender<bool>>,
+        server_info: Implementation,
+        capabilities: ServerCapabilities,
+        allow_reinitialize: bool,
+        on_initialize: Option<InitializeHook>,
+        subscriptions: (Subscriptions, String),
+    ) -> impl Fn(
+        InitializeRequest,
+    )
+        -> Pin<Box<dyn std::future::Future<Output = Result<InitializeResponse>> + Send>> {
+        move |req| {
+            let state = state.clone();
+            let initialized = initialized.clone();
+            let server_info = server_info.clone();
+            let capabilities = capabilities.clone();
+            let on_initialize = on_initialize.clone();
+            let subscriptions = subscriptions.clone();
+
+            Box::pin(async move {
+                let mut state = state
                     .write()
                     .map_err(|_| anyhow::anyhow!("Lock poisoned"))?;
-                state.initialized = true;
+                if state.client_info.is_some() && !allow_reinitialize {
+                    return Err(JsonRpcError::new(
+                        ErrorCode::InvalidRequest,
+                        "Server is already initialized",
+                    )
+                    .into());
+                }
+                let mut response = InitializeResponse {
+                    protocol_version: LATEST_PROTOCOL_VERSION.to_string(),
+                    capabilities,
+                    server_info,
+                    instructions: None,
+                };
+                if let Some(hook) = &on_initialize {
+                    hook(&req, &mut response)?;
+                }
+                // Only once the hook accepted, a rejected re-initialize keeps the session
+                if let Some(previous) = &state.client_info {
+                    info!(
+                        previous_client = %previous.name,
+                        previous_version = ?state.protocol_version,
+                        client = %req.client_info.name,
+                        version = %req.protocol_version,
+                        "Client re-initialized, session state reset"
+                    );
+                    // The new client didn't ask for the old client's updates
+                    let (subscriptions, session_id) = &subscriptions;
+                    subscriptions.remove_session(session_id);
+                }
+                *state = ServerState {
+                    client_capabilities: Some(req.capabilities),
+                    client_info: Some(req.client_info),
+                    protocol_version: Some(response.protocol_version.clone()),
+                    initialized: false,
+                    log_level: None,
+                };
+                initialized.send_replace(false);
+
+                Ok(response)
+            })
+        }
+    }
+
+    // Helper function for initialized handler, params are optional and may carry `_meta`
+    fn handle_initialized(
+        state: Arc<RwLock<ServerState>>,
+        initialized: Arc<watch::Sender<bool>>,
+    ) -> impl Fn(
+        Option<serde_json::Value>,
+    ) -> Pin<Box<dyn std::future::Future<Output = Result<()>> + Send>> {
+        move |_| {
+            let state = state.clone();
+            let initialized = initialized.clone();
+            Box::pin(async move {
+                state
+                    .write()
+                    .map_err(|_| anyhow::anyhow!("Lock poisoned"))?
+                    .initialized = true;
+                initialized.send_replace(true);
                 Ok(())
             })
         }
@@ -228,6 +1322,11 @@ impl<T: Transport> Server<T> {
         self.state.read().ok()?.client_info.clone()
     }
 
+    /// Protocol version agreed on in the last `initialize`
+    pub fn protocol_version(&self) -> Option<String> {
+        self.state.read().ok()?.protocol_version.clone()
+    }
+
     pub fn is_initialized(&self) -> bool {
         self.state
             .read()
@@ -236,7 +1335,2158 @@ impl<T: Transport> Server<T> {
             .unwrap_or(false)
     }
 
+    /// Resolves once the client sent `notifications/initialized`
+    pub async fn wait_initialized(&self) {
+        let mut rx = self.initialized.subscribe();
+        // The sender lives as long as the server, so this can't fail
+        let _ = rx.wait_for(|initialized| *initialized).await;
+    }
+
+    /// Like [`Server::wait_initialized`], failing after `timeout`
+    pub async fn wait_initialized_timeout(&self, timeout: Duration) -> Result<()> {
+        tokio::time::timeout(timeout, self.wait_initialized())
+            .await
+            .map_err(|_| anyhow::anyhow!("Timed out waiting for initialization"))
+    }
+
+    /// Tools currently registered, including ones added while running
+    /// reads the registry behind `tools/list`, a custom `tools/list` handler isn't reflected
+    pub fn tools(&self) -> Vec<Tool> {
+        self.tools.list_tools()
+    }
+
+    pub fn has_tool(&self, name: &str) -> bool {
+        self.tools.get_tool(name).is_some()
+    }
+
+    pub fn prompts(&self) -> Vec<Prompt> {
+        self.prompts.list_prompts()
+    }
+
+    pub fn resources(&self) -> Vec<Resource> {
+        self.resources.list_resources()
+    }
+
+    pub fn resource_templates(&self) -> Vec<ResourceTemplate> {
+        self.resources.list_templates()
+    }
+
+    /// Sessions subscribed to `uri` in this server's subscription registry
+    pub fn subscription_count(&self, uri: &Url) -> usize {
+        self.subscriptions.count(uri)
+    }
+
+    /// Send `notifications/resources/updated` to every session subscribed to `uri`, not only
+    /// this one, returns how many were notified. Drops the cached read of `uri` first
+    pub async fn notify_resource_updated(&self, uri: &Url) -> usize {
+        self.invalidate_resource(uri);
+        self.subscriptions.notify_updated(uri).await
+    }
+
+    /// Drop the cached read of `uri` without notifying anyone
+    pub fn invalidate_resource(&self, uri: &Url) {
+        self.resource_cache.invalidate(uri.as_str());
+    }
+
+    pub fn server_info(&self) -> &Implementation {
+        &self.server_info
+    }
+
+    /// Capabilities advertised in the `initialize` response
+    pub fn capabilities(&self) -> &ServerCapabilities {
+        &self.capabilities
+    }
+
+    /// Advanced: send a notification or response as is, e.g. a vendor notification the typed
+    /// API doesn't cover. Requests are rejected, use [`Server::request_raw`]
+    pub async fn send_raw(&self, message: &JsonRpcMessage) -> Result<()> {
+        self.protocol.send_raw(message).await
+    }
+
+    /// Advanced: send a request to the client and return its response as received
+    /// the response is only picked up while [`Server::listen`] runs
+    pub async fn request_raw(
+        &self,
+        method: &str,
+        params: Option<serde_json::Value>,
+        options: RequestOptions,
+    ) -> Result<JsonRpcResponse> {
+        self.protocol.request(method, params, options).await
+    }
+
+    /// Least severe level the client receives, from its `logging/setLevel` or
+    /// [`ServerBuilder::default_log_level`]
+    pub fn log_level(&self) -> LoggingLevel {
+        current_log_level(&self.state, self.default_log_level)
+    }
+
+    /// Send a log record to the client as `notifications/message`, unless it is below the
+    /// client's level or over the rate limit
+    pub async fn log(&self, params: LoggingMessageParams) -> Result<()> {
+        self.log_with(params.level, || params).await
+    }
+
+    /// Like [`log`](Self::log), `record` is only called when a record of `level` would be sent,
+    /// so filtered records cost nothing to format. The record is sent with `level`
+    pub async fn log_with(
+        &self,
+        level: LoggingLevel,
+        record: impl FnOnce() -> LoggingMessageParams,
+    ) -> Result<()> {
+        if level < self.log_level() {
+            return Ok(());
+        }
+        if let Some(throttle) = &self.log_throttle {
+            match throttle.admit(level) {
+                Verdict::Send => {}
+                Verdict::Dropped => return Ok(()),
+                Verdict::Throttled(delay) => {
+                    let (throttle, protocol) = (throttle.clone(), self.protocol.clone());
+                    let (state, default_level) = (self.state.clone(), self.default_log_level);
+                    tokio::spawn(async move {
+                        tokio::time::sleep(delay).await;
+                        let Some(summary) = throttle.summary() else {
+                            return;
+                        };
+                        // The client may have raised its level in the meantime
+                        if summary.level < current_log_level(&state, default_level) {
+                            return;
+                        }
+                        let params = serde_json::to_value(summary).ok();
+                        let _ = protocol.notify("notifications/message", params).await;
+                    });
+                    return Ok(());
+                }
+            }
+        }
+        let params = LoggingMessageParams { level, ..record() };
+        self.protocol
+            .notify("notifications/message", Some(serde_json::to_value(params)?))
+            .await
+    }
+
+    /// Add or replace a tool on the running server and notify the client
+    pub async fn add_tool(
+        &self,
+        tool: Tool,
+        f: impl Fn(CallToolRequest) -> Pin<Box<dyn Future<Output = Result<CallToolResponse>> + Send>>
+            + Send
+            + Sync
+            + 'static,
+    ) -> Result<()> {
+        self.tools.insert(ToolHandler::new(tool, Box::new(f)))?;
+        self.notify_tools_changed().await
+    }
+
+    /// Remove a tool from the running server, returns whether it was registered
+    pub async fn remove_tool(&self, name: &str) -> Result<bool> {
+        let removed = self.tools.remove(name)?;
+        if removed {
+            self.notify_tools_changed().await?;
+        }
+        Ok(removed)
+    }
+
+    async fn notify_tools_changed(&self) -> Result<()> {
+        // Clients only expect list_changed once the session is initialized
+        if !self.is_initialized() {
+            return Ok(());
+        }
+        self.protocol
+            .notify("notifications/tools/list_changed", None)
+            .await
+    }
+
+    async fn run_tool_source(&self, mut source: Box<dyn DynamicToolSource>) {
+        loop {
+            let events = match source.next_events().await {
+                Ok(Some(events)) => events,
+                Ok(None) => return,
+                Err(e) => {
+                    warn!("Tool source error: {:#}", e);
+                    continue;
+                }
+            };
+            for event in events {
+                let result = match event {
+                    ToolEvent::Added(tool, f) | ToolEvent::Updated(tool, f) => {
+                        self.tools.insert(ToolHandler::new(tool, f))
+                    }
+                    ToolEvent::Removed(name) => self.tools.remove(&name).map(|_| ()),
+                };
+                if let Err(e) = result {
+                    warn!("Failed to apply tool event: {:#}", e);
+                }
+            }
+            if let Err(e) = self.notify_tools_changed().await {
+                warn!("Failed to send tools/list_changed: {:#}", e);
+            }
+        }
+    }
+
     pub async fn listen(&self) -> Result<()> {
-        self.protocol.listen().await
+        if self.manage_transport {
+            self.protocol.transport().open().await?;
+        }
+        let result = self.listen_opened().await;
+        // The session is over, updates have nowhere to go
+        self.subscriptions.remove_session(&self.session_id);
+        if self.manage_transport {
+            let closed = self.protocol.transport().close().await;
+            if let (Err(_), Err(e)) = (&result, &closed) {
+                warn!("Failed to close the transport: {:#}", e);
+            }
+            return result.and(closed);
+        }
+        result
+    }
+
+    async fn listen_opened(&self) -> Result<()> {
+        let sources = std::mem::take(
+            &mut *self
+                .tool_sources
+                .lock()
+                .map_err(|_| anyhow::anyhow!("Lock poisoned"))?,
+        );
+        let run_sources = async {
+            futures::future::join_all(
+                sources
+                    .into_iter()
+                    .map(|source| self.run_tool_source(source)),
+            )
+            .await;
+            // Sources that finished leave the tool set as is, keep serving requests
+            std::future::pending::<Result<()>>().await
+        };
+        tokio::select! {
+            result = self.protocol.listen() => result,
+            result = run_sources => result,
+        }
+    }
+}
+
+/// The client's `logging/setLevel` level, `default` until it sends one
+fn current_log_level(state: &RwLock<ServerState>, default: LoggingLevel) -> LoggingLevel {
+    state
+        .read()
+        .ok()
+        .and_then(|state| state.log_level)
+        .unwrap_or(default)
+}
+
+/// Rejects `method` when it belongs to a capability that isn't advertised
+fn check_capability(
+    capabilities: &ServerCapabilities,
+    exempt: &HashSet<String>,
+    method: &str,
+) -> std::result::Result<(), JsonRpcError> {
+    match ServerCapabilities::capability_for(method) {
+        Some(capability) if !capabilities.advertises(capability) && !exempt.contains(method) => {
+            Err(JsonRpcError::capability_not_advertised(method, capability))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Advertised capabilities and the requests they should answer but can't
+fn missing_handlers(
+    capabilities: &ServerCapabilities,
+    has_handler: impl Fn(&str) -> bool,
+) -> Vec<(&'static str, &'static str)> {
+    CAPABILITY_HANDLERS
+        .iter()
+        .filter(|(capability, _)| capabilities.advertises(capability))
+        .flat_map(|(capability, methods)| methods.iter().map(move |method| (*capability, *method)))
+        .filter(|(_, method)| !has_handler(method))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::ClientBuilder;
+    use crate::transport::{ClientInMemoryTransport, ServerInMemoryTransport};
+    use crate::types::ToolBuilder;
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn test_wait_initialized() -> Result<()> {
+        let (server_tx, mut server_rx) = mpsc::channel(1);
+        let transport = ClientInMemoryTransport::new(move |t: ServerInMemoryTransport| {
+            let server = Server::builder(t).build();
+            let _ = server_tx.try_send(server.clone());
+            tokio::spawn(async move { server.listen().await.unwrap() })
+        });
+        transport.open().await?;
+        let server = server_rx.recv().await.unwrap();
+
+        assert!(server
+            .wait_initialized_timeout(Duration::from_millis(50))
+            .await
+            .is_err());
+
+        let client = ClientBuilder::new(transport.clone()).build();
+        let client_clone = client.clone();
+        tokio::spawn(async move { client_clone.start().await });
+        client.initialize(Implementation::default()).await?;
+
+        server
+            .wait_initialized_timeout(Duration::from_secs(5))
+            .await?;
+        assert!(server.is_initialized());
+        // Resolves immediately once initialized
+        server.wait_initialized().await;
+
+        drop(server);
+        transport.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_initialized_with_params() -> Result<()> {
+        use crate::protocol::RequestOptions;
+        use crate::types::InitializeRequest;
+
+        let (server_tx, mut server_rx) = mpsc::channel(1);
+        let transport = ClientInMemoryTransport::new(move |t: ServerInMemoryTransport| {
+            let server = Server::builder(t).build();
+            let _ = server_tx.try_send(server.clone());
+            tokio::spawn(async move { server.listen().await.unwrap() })
+        });
+        transport.open().await?;
+        let server = server_rx.recv().await.unwrap();
+        let client = ClientBuilder::new(transport.clone()).build();
+        let client_clone = client.clone();
+        tokio::spawn(async move { client_clone.start().await });
+
+        let _: InitializeResponse = client
+            .request_typed(
+                "initialize",
+                InitializeRequest {
+                    protocol_version: LATEST_PROTOCOL_VERSION.to_string(),
+                    capabilities: Default::default(),
+                    client_info: Implementation::default(),
+                },
+                RequestOptions::default(),
+            )
+            .await?;
+        transport
+            .send(&JsonRpcMessage::Notification(
+                crate::transport::JsonRpcNotification {
+                    method: "notifications/initialized".to_string(),
+                    params: Some(serde_json::json!({})),
+                    ..Default::default()
+                },
+            ))
+            .await?;
+        server
+            .wait_initialized_timeout(Duration::from_secs(5))
+            .await?;
+        assert_eq!(server.protocol.malformed_notification_count(), 0);
+
+        drop(server);
+        transport.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_registry_accessors() -> Result<()> {
+        let tool = |name: &str| Tool {
+            name: name.to_string(),
+            description: None,
+            input_schema: serde_json::json!({"type": "object"}),
+            output_schema: None,
+        };
+        let respond = |_| -> Pin<Box<dyn Future<Output = Result<CallToolResponse>> + Send>> {
+            Box::pin(async move {
+                Ok(CallToolResponse {
+                    content: vec![],
+                    is_error: None,
+                    meta: None,
+                })
+            })
+        };
+        let (server_tx, mut server_rx) = mpsc::channel(1);
+        let transport = ClientInMemoryTransport::new(move |t: ServerInMemoryTransport| {
+            let mut builder = Server::builder(t).name("accessors");
+            builder.register_tool(tool("a"), respond);
+            builder.register_tool(tool("b"), respond);
+            builder.register_prompt(
+                Prompt {
+                    name: "greet".to_string(),
+                    description: None,
+                    arguments: None,
+                },
+                |_| Box::pin(async move { Err(anyhow::anyhow!("unused")) }),
+            );
+            let server = builder.build();
+            let _ = server_tx.try_send(server.clone());
+            tokio::spawn(async move { server.listen().await.unwrap() })
+        });
+        transport.open().await?;
+        let server = server_rx.recv().await.unwrap();
+        let client = ClientBuilder::new(transport.clone()).build();
+        let client_clone = client.clone();
+        tokio::spawn(async move { client_clone.start().await });
+
+        let listed_names = |client: crate::client::Client<ClientInMemoryTransport>| async move {
+            let listed: ToolsListResponse = client
+                .request_typed(
+                    "tools/list",
+                    serde_json::json!({}),
+                    crate::protocol::RequestOptions::default(),
+                )
+                .await?;
+            let mut names: Vec<_> = listed.tools.into_iter().map(|t| t.name).collect();
+            names.sort();
+            Ok::<_, anyhow::Error>(names)
+        };
+        let names = |tools: Vec<Tool>| {
+            let mut names: Vec<_> = tools.into_iter().map(|t| t.name).collect();
+            names.sort();
+            names
+        };
+
+        assert_eq!(names(server.tools()), listed_names(client.clone()).await?);
+        assert!(server.has_tool("a") && !server.has_tool("c"));
+        assert_eq!(server.prompts()[0].name, "greet");
+        assert!(server.resources().is_empty());
+        assert_eq!(server.server_info().name, "accessors");
+        assert!(server.capabilities().tools.is_none());
+
+        // Dynamic changes are reflected
+        server.add_tool(tool("c"), respond).await?;
+        server.remove_tool("a").await?;
+        assert_eq!(names(server.tools()), vec!["b", "c"]);
+        assert_eq!(names(server.tools()), listed_names(client.clone()).await?);
+
+        drop(server);
+        transport.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_lists_are_sorted() -> Result<()> {
+        use crate::protocol::RequestOptions;
+
+        let names = ["zeta", "alpha", "mid", "beta"];
+        let transport = ClientInMemoryTransport::new(move |t: ServerInMemoryTransport| {
+            let mut builder = Server::builder(t);
+            for name in names {
+                builder.register_tool(ToolBuilder::new(name).build(), |_| {
+                    Box::pin(async move { Ok(CallToolResponse::text("")) })
+                });
+                builder.register_prompt(
+                    Prompt {
+                        name: name.to_string(),
+                        description: None,
+                        arguments: None,
+                    },
+                    |_| Box::pin(async move { Err(anyhow::anyhow!("unused")) }),
+                );
+                builder.register_resource(
+                    Resource {
+                        uri: format!("file:///{}", name).parse().unwrap(),
+                        name: name.to_string(),
+                        description: None,
+                        mime_type: None,
+                    },
+                    |_| Box::pin(async move { Ok(ReadResourceResponse::new(vec![])) }),
+                );
+            }
+            tokio::spawn(async move { builder.build().listen().await.unwrap() })
+        });
+        transport.open().await?;
+        let client = ClientBuilder::new(transport.clone()).build();
+        let client_clone = client.clone();
+        tokio::spawn(async move { client_clone.start().await });
+
+        let sorted = vec!["alpha", "beta", "mid", "zeta"];
+        let tools: ToolsListResponse = client
+            .request_typed(
+                "tools/list",
+                serde_json::json!({}),
+                RequestOptions::default(),
+            )
+            .await?;
+        assert_eq!(
+            tools.tools.iter().map(|t| &t.name).collect::<Vec<_>>(),
+            sorted
+        );
+        let prompts: PromptsListResponse = client
+            .request_typed(
+                "prompts/list",
+                serde_json::json!({}),
+                RequestOptions::default(),
+            )
+            .await?;
+        assert_eq!(
+            prompts.prompts.iter().map(|p| &p.name).collect::<Vec<_>>(),
+            sorted
+        );
+        let resources: ResourcesListResponse = client
+            .request_typed(
+                "resources/list",
+                serde_json::json!({}),
+                RequestOptions::default(),
+            )
+            .await?;
+        assert_eq!(
+            resources
+                .resources
+                .iter()
+                .map(|r| &r.name)
+                .collect::<Vec<_>>(),
+            sorted
+        );
+
+        transport.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_multiple_contents() -> Result<()> {
+        use crate::protocol::RequestOptions;
+        use crate::types::ResourceContent;
+
+        let uri = url::Url::parse("mem://chart").unwrap();
+        let read_uri = uri.clone();
+        let transport = ClientInMemoryTransport::new(move |t: ServerInMemoryTransport| {
+            let mut builder = Server::builder(t);
+            builder.register_resource(
+                Resource {
+                    uri: read_uri.clone(),
+                    name: "chart".to_string(),
+                    description: None,
+                    mime_type: None,
+                },
+                |req| {
+                    Box::pin(async move {
+                        Ok(ReadResourceResponse::new(vec![
+                            ResourceContent::text(req.uri.clone(), "text/csv", "x,y\n1,2"),
+                            ResourceContent::blob(req.uri, "image/png", b"png"),
+                        ]))
+                    })
+                },
+            );
+            tokio::spawn(async move { builder.build().listen().await.unwrap() })
+        });
+        transport.open().await?;
+        let client = ClientBuilder::new(transport.clone()).build();
+        let client_clone = client.clone();
+        tokio::spawn(async move { client_clone.start().await });
+
+        let response: serde_json::Value = client
+            .request_typed(
+                "resources/read",
+                ReadResourceRequest::new(uri),
+                RequestOptions::default(),
+            )
+            .await?;
+        assert_eq!(
+            response,
+            serde_json::json!({"contents": [
+                {"uri": "mem://chart", "mimeType": "text/csv", "text": "x,y\n1,2"},
+                {"uri": "mem://chart", "mimeType": "image/png", "blob": "cG5n"},
+            ]})
+        );
+
+        transport.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_resource_stream() -> Result<()> {
+        use crate::protocol::RequestOptions;
+        use crate::types::ResourceContent;
+
+        let uri = url::Url::parse("mem://report").unwrap();
+        let read_uri = uri.clone();
+        let transport = ClientInMemoryTransport::new(move |t: ServerInMemoryTransport| {
+            let mut builder = Server::builder(t);
+            builder.register_resource_stream(
+                Resource {
+                    uri: read_uri.clone(),
+                    name: "report".to_string(),
+                    description: None,
+                    mime_type: None,
+                },
+                |_req, _ctx| {
+                    Box::pin(async move {
+                        Ok(ResourceStream::new(&b"col\n1\n2\n"[..]).mime_type("text/csv"))
+                    })
+                },
+            );
+            tokio::spawn(async move { builder.build().listen().await.unwrap() })
+        });
+        transport.open().await?;
+        let client = ClientBuilder::new(transport.clone()).build();
+        let client_clone = client.clone();
+        tokio::spawn(async move { client_clone.start().await });
+
+        // Reads that don't ask to stream get the contents inline
+        let response: ReadResourceResponse = client
+            .request_typed(
+                "resources/read",
+                ReadResourceRequest::new(uri.clone()),
+                RequestOptions::default(),
+            )
+            .await?;
+        let ResourceContent::Text(text) = &response.contents[0] else {
+            panic!("expected text contents");
+        };
+        assert_eq!(
+            (text.text.as_str(), text.mime_type.as_deref()),
+            ("col\n1\n2\n", Some("text/csv"))
+        );
+
+        let mut streamed = Vec::new();
+        let written = client
+            .read_resource_to(uri, &mut streamed, RequestOptions::default())
+            .await?;
+        assert_eq!((written, streamed.as_slice()), (8, &b"col\n1\n2\n"[..]));
+
+        transport.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_list_resources_with_templates() -> Result<()> {
+        use crate::types::{ResourceContent, TextResourceContents};
+
+        let resource = |uri: &str, name: &str| Resource {
+            uri: url::Url::parse(uri).unwrap(),
+            name: name.to_string(),
+            description: None,
+            mime_type: None,
+        };
+        let transport = ClientInMemoryTransport::new(move |t: ServerInMemoryTransport| {
+            let mut builder = Server::builder(t)
+                .list_page_size(4)
+                .list_template_resources(true);
+            for uri in ["mem://fixed/1", "mem://a/2"] {
+                builder.register_resource(resource(uri, "fixed"), |_| {
+                    Box::pin(async move { Err(anyhow::anyhow!("unused")) })
+                });
+            }
+            // Registered b before a, the listing is still sorted by URI
+            for prefix in ["b", "a"] {
+                builder.register_resource_template(
+                    ResourceTemplate {
+                        uri_template: format!("mem://{prefix}/{{id}}"),
+                        name: prefix.to_string(),
+                        description: None,
+                        mime_type: None,
+                    },
+                    move || {
+                        Box::pin(async move {
+                            // Slow lists must not reorder the merged result
+                            if prefix == "b" {
+                                tokio::time::sleep(Duration::from_millis(20)).await;
+                            }
+                            Ok((1..=3)
+                                .map(|id| resource(&format!("mem://{prefix}/{id}"), prefix))
+                                .collect())
+                        })
+                    },
+                    move |req, _ctx| {
+                        Box::pin(async move {
+                            Ok(ReadResourceResponse {
+                                contents: vec![ResourceContent::Text(TextResourceContents {
+                                    text: format!("from {prefix}"),
+                                    uri: req.uri,
+                                    mime_type: None,
+                                })],
+                                meta: None,
+                            })
+                        })
+                    },
+                );
+            }
+            tokio::spawn(async move { builder.build().listen().await.unwrap() })
+        });
+        transport.open().await?;
+        let client = ClientBuilder::new(transport.clone()).build();
+        let client_clone = client.clone();
+        tokio::spawn(async move { client_clone.start().await });
+
+        let list_all = || async {
+            let mut pages = Vec::new();
+            let mut cursor = None;
+            loop {
+                let page: ResourcesListResponse = client
+                    .request_typed(
+                        "resources/list",
+                        ListRequest { cursor, meta: None },
+                        crate::protocol::RequestOptions::default(),
+                    )
+                    .await?;
+                pages.push(
+                    page.resources
+                        .into_iter()
+                        .map(|r| format!("{} {}", r.uri, r.name))
+                        .collect::<Vec<_>>(),
+                );
+                cursor = page.next_cursor;
+                if cursor.is_none() {
+                    return Ok::<_, anyhow::Error>(pages);
+                }
+            }
+        };
+        let pages = list_all().await?;
+        assert_eq!(
+            pages,
+            vec![
+                vec![
+                    "mem://a/1 a",
+                    "mem://a/2 fixed",
+                    "mem://a/3 a",
+                    "mem://b/1 b"
+                ],
+                vec!["mem://b/2 b", "mem://b/3 b", "mem://fixed/1 fixed"],
+            ]
+        );
+        assert_eq!(list_all().await?, pages);
+
+        let templates: ResourceTemplatesListResponse = client
+            .request_typed(
+                "resources/templates/list",
+                serde_json::json!({}),
+                crate::protocol::RequestOptions::default(),
+            )
+            .await?;
+        let templates: Vec<_> = templates
+            .resource_templates
+            .into_iter()
+            .map(|t| t.uri_template)
+            .collect();
+        assert_eq!(templates, vec!["mem://b/{id}", "mem://a/{id}"]);
+
+        let read: ReadResourceResponse = client
+            .request_typed(
+                "resources/read",
+                ReadResourceRequest::new(url::Url::parse("mem://b/7")?),
+                crate::protocol::RequestOptions::default(),
+            )
+            .await?;
+        assert!(matches!(
+            &read.contents[0],
+            ResourceContent::Text(TextResourceContents { text, .. }) if text == "from b"
+        ));
+
+        transport.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unknown_tool_error() -> Result<()> {
+        let transport = ClientInMemoryTransport::new(|t: ServerInMemoryTransport| {
+            tokio::spawn(async move { Server::builder(t).build().listen().await.unwrap() })
+        });
+        transport.open().await?;
+        let client = ClientBuilder::new(transport.clone()).build();
+        let client_clone = client.clone();
+        tokio::spawn(async move { client_clone.start().await });
+
+        let err = client
+            .request_typed::<_, CallToolResponse>(
+                "tools/call",
+                serde_json::json!({"name": "missing"}),
+                crate::protocol::RequestOptions::default(),
+            )
+            .await
+            .unwrap_err();
+        let err = err
+            .downcast_ref::<crate::transport::JsonRpcError>()
+            .unwrap();
+        assert!(err.is_tool_not_found());
+        assert_eq!(err.code, crate::types::ErrorCode::InvalidParams as i32);
+        assert_eq!(
+            err.error_data().and_then(|data| data.details),
+            Some(serde_json::json!({"name": "missing"}))
+        );
+
+        transport.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_request_interceptor() -> Result<()> {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let handler_calls = calls.clone();
+        let transport = ClientInMemoryTransport::new(move |t: ServerInMemoryTransport| {
+            let calls = handler_calls.clone();
+            let mut builder = Server::builder(t)
+                .session_metadata(Some(serde_json::json!({"sub": "ada", "role": "viewer"})))
+                .request_interceptor(|method, params, ctx| {
+                    let admin_only = method == "tools/call"
+                        && params
+                            .and_then(|p| p.get("name"))
+                            .is_some_and(|n| n == "drop");
+                    let role = ctx
+                        .session_metadata
+                        .as_ref()
+                        .and_then(|claims| claims.get("role"));
+                    if admin_only && role.is_none_or(|role| role != "admin") {
+                        return Err(JsonRpcError::forbidden("drop requires the admin role"));
+                    }
+                    Ok(())
+                });
+            for name in ["drop", "echo"] {
+                let calls = calls.clone();
+                builder.register_tool(
+                    Tool {
+                        name: name.to_string(),
+                        description: None,
+                        input_schema: serde_json::json!({"type": "object"}),
+                        output_schema: None,
+                    },
+                    move |_| {
+                        calls.fetch_add(1, Ordering::SeqCst);
+                        Box::pin(async move { Ok(CallToolResponse::text("ran")) })
+                    },
+                );
+            }
+            tokio::spawn(async move { builder.build().listen().await.unwrap() })
+        });
+        transport.open().await?;
+        let client = ClientBuilder::new(transport.clone()).build();
+        let client_clone = client.clone();
+        tokio::spawn(async move { client_clone.start().await });
+        let call = |name: &str| {
+            client.request_typed::<_, CallToolResponse>(
+                "tools/call",
+                serde_json::json!({"name": name}),
+                crate::protocol::RequestOptions::default(),
+            )
+        };
+
+        let err = call("drop").await.unwrap_err();
+        let err = err
+            .downcast_ref::<crate::transport::JsonRpcError>()
+            .unwrap();
+        assert_eq!(err.code, ErrorCode::InvalidRequest as i32);
+        assert_eq!(
+            err.error_data().unwrap().kind,
+            crate::types::ErrorData::FORBIDDEN
+        );
+        // Rejected before the handler ran
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+        call("echo").await?;
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        transport.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reinitialize() -> Result<()> {
+        use crate::protocol::RequestOptions;
+        use crate::types::RootCapabilities;
+
+        for allow in [false, true] {
+            let (server_tx, mut server_rx) = mpsc::channel(1);
+            let transport = ClientInMemoryTransport::new(move |t: ServerInMemoryTransport| {
+                let server = Server::builder(t).allow_reinitialize(allow).build();
+                let _ = server_tx.try_send(server.clone());
+                tokio::spawn(async move { server.listen().await.unwrap() })
+            });
+            transport.open().await?;
+            let server = server_rx.recv().await.unwrap();
+            let client = ClientBuilder::new(transport.clone()).build();
+            let client_clone = client.clone();
+            tokio::spawn(async move { client_clone.start().await });
+
+            client
+                .initialize(Implementation {
+                    name: "first".to_string(),
+                    version: "1".to_string(),
+                })
+                .await?;
+            server
+                .wait_initialized_timeout(Duration::from_secs(5))
+                .await?;
+
+            let second = InitializeRequest {
+                protocol_version: LATEST_PROTOCOL_VERSION.to_string(),
+                capabilities: ClientCapabilities {
+                    experimental: None,
+                    sampling: None,
+                    roots: Some(RootCapabilities {
+                        list_changed: Some(true),
+                    }),
+                },
+                client_info: Implementation {
+                    name: "second".to_string(),
+                    version: "2".to_string(),
+                },
+            };
+            let result = client
+                .request_typed::<_, InitializeResponse>(
+                    "initialize",
+                    second,
+                    RequestOptions::default(),
+                )
+                .await;
+
+            if !allow {
+                let err = result.unwrap_err();
+                let err = err
+                    .downcast_ref::<crate::transport::JsonRpcError>()
+                    .unwrap();
+                assert_eq!(err.code, ErrorCode::InvalidRequest as i32);
+                // The first initialization is left untouched
+                assert_eq!(server.get_client_info().unwrap().name, "first");
+                assert!(server.get_client_capabilities().unwrap().roots.is_none());
+                assert!(server.is_initialized());
+            } else {
+                result?;
+                // Replaced as a whole, nothing is kept from the first initialization
+                assert_eq!(server.get_client_info().unwrap().name, "second");
+                let capabilities = server.get_client_capabilities().unwrap();
+                assert!(capabilities.sampling.is_none());
+                assert!(capabilities.experimental.is_none());
+                assert_eq!(
+                    server.protocol_version().as_deref(),
+                    Some(LATEST_PROTOCOL_VERSION)
+                );
+                // Waiting for the client to confirm again
+                assert!(!server.is_initialized());
+            }
+
+            drop(server);
+            transport.close().await?;
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_server_raw_messages() -> Result<()> {
+        use crate::transport::JsonRpcNotification;
+
+        let (server_tx, mut server_rx) = mpsc::channel(1);
+        let transport = ClientInMemoryTransport::new(move |t: ServerInMemoryTransport| {
+            let server = Server::builder(t).build();
+            let _ = server_tx.try_send(server.clone());
+            tokio::spawn(async move { server.listen().await.unwrap() })
+        });
+        transport.open().await?;
+        let server = server_rx.recv().await.unwrap();
+
+        let notification = JsonRpcMessage::Notification(JsonRpcNotification {
+            method: "vendor/status".to_string(),
+            params: Some(serde_json::json!({"busy": true})),
+            ..Default::default()
+        });
+        server.send_raw(&notification).await?;
+        assert_eq!(transport.receive().await?, Some(notification));
+
+        // The client answers by hand, the response is matched by the id the server picked
+        let request = tokio::spawn({
+            let server = server.clone();
+            async move {
+                server
+                    .request_raw("vendor/query", None, RequestOptions::default())
+                    .await
+            }
+        });
+        let Some(JsonRpcMessage::Request(sent)) = transport.receive().await? else {
+            panic!("expected a request");
+        };
+        assert_eq!(sent.method, "vendor/query");
+        let answer = JsonRpcResponse {
+            id: sent.id,
+            result: Some(serde_json::json!({"answer": 42})),
+            ..Default::default()
+        };
+        transport
+            .send(&JsonRpcMessage::Response(answer.clone()))
+            .await?;
+        assert_eq!(request.await??, answer);
+
+        drop(server);
+        transport.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_on_initialize_hook() -> Result<()> {
+        let (server_tx, mut server_rx) = mpsc::channel(1);
+        let transport = ClientInMemoryTransport::new(move |t: ServerInMemoryTransport| {
+            let server = Server::builder(t)
+                .on_initialize(|req, response| {
+                    if req.client_info.name == "blocked" {
+                        return Err(JsonRpcError::forbidden("Client not allowed"));
+                    }
+                    let instructions = response.instructions.get_or_insert_with(String::new);
+                    instructions.push_str("Call list_files before reading.");
+                    Ok(())
+                })
+                .build();
+            let _ = server_tx.try_send(server.clone());
+            tokio::spawn(async move { server.listen().await.unwrap() })
+        });
+        transport.open().await?;
+        let server = server_rx.recv().await.unwrap();
+        let client = ClientBuilder::new(transport.clone()).build();
+        let client_clone = client.clone();
+        tokio::spawn(async move { client_clone.start().await });
+
+        let client_info = |name: &str| Implementation {
+            name: name.to_string(),
+            version: "1".to_string(),
+        };
+        let err = client.initialize(client_info("blocked")).await.unwrap_err();
+        let err = err
+            .downcast_ref::<crate::transport::JsonRpcError>()
+            .unwrap();
+        assert_eq!(err.code, ErrorCode::InvalidRequest as i32);
+        // Nothing recorded for a rejected client, it may initialize again
+        assert!(server.get_client_info().is_none());
+
+        let response = client.initialize(client_info("allowed")).await?;
+        assert_eq!(
+            response.instructions.as_deref(),
+            Some("Call list_files before reading.")
+        );
+        server
+            .wait_initialized_timeout(Duration::from_secs(5))
+            .await?;
+        assert_eq!(server.get_client_info().unwrap().name, "allowed");
+
+        drop(server);
+        transport.close().await?;
+        Ok(())
+    }
+
+    #[test]
+    #[should_panic(expected = "on_initialize")]
+    fn test_raw_initialize_handler_rejected() {
+        let _ = Server::builder(ServerInMemoryTransport::default()).request_handler(
+            "initialize",
+            |_: InitializeRequest| -> Pin<Box<dyn Future<Output = Result<InitializeResponse>> + Send>> {
+                Box::pin(async { Ok(InitializeResponse::default()) })
+            },
+        );
+    }
+
+    #[tokio::test]
+    async fn test_initialize_twice_in_one_batch() -> Result<()> {
+        // A reconnecting client replaying its handshake, before `notifications/initialized`
+        let (server_tx, mut server_rx) = mpsc::channel(1);
+        let transport = ClientInMemoryTransport::new(move |t: ServerInMemoryTransport| {
+            let server = Server::builder(t).build();
+            let _ = server_tx.try_send(server.clone());
+            tokio::spawn(async move { server.listen().await.unwrap() })
+        });
+        transport.open().await?;
+        let server = server_rx.recv().await.unwrap();
+        let client = ClientBuilder::new(transport.clone()).build();
+        let client_clone = client.clone();
+        tokio::spawn(async move { client_clone.start().await });
+
+        let initialize = |name: &str| {
+            serde_json::to_value(InitializeRequest {
+                client_info: Implementation {
+                    name: name.to_string(),
+                    version: "1".to_string(),
+                },
+                ..Default::default()
+            })
+        };
+        let results = client
+            .request_batch(
+                vec![
+                    ("initialize", Some(initialize("first")?)),
+                    ("initialize", Some(initialize("second")?)),
+                ],
+                crate::protocol::RequestOptions::default(),
+            )
+            .await?;
+
+        // The batch runs concurrently, whichever gets the state first wins
+        let (accepted, rejected): (Vec<_>, Vec<_>) = results.into_iter().partition(Result::is_ok);
+        assert_eq!((accepted.len(), rejected.len()), (1, 1));
+        let err = rejected.into_iter().next().unwrap().unwrap_err();
+        let err = err
+            .downcast_ref::<crate::transport::JsonRpcError>()
+            .unwrap();
+        assert_eq!(err.code, ErrorCode::InvalidRequest as i32);
+        let winner: InitializeResponse =
+            serde_json::from_value(accepted.into_iter().next().unwrap()?)?;
+        assert_eq!(winner.protocol_version, LATEST_PROTOCOL_VERSION);
+        assert!(server.get_client_info().is_some());
+        assert!(!server.is_initialized());
+
+        drop(server);
+        transport.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_tool_sees_client_capabilities() -> Result<()> {
+        let transport = ClientInMemoryTransport::new(|t: ServerInMemoryTransport| {
+            let mut builder = Server::builder(t);
+            builder.register_tool_with_context(
+                Tool {
+                    name: "sample".to_string(),
+                    description: None,
+                    input_schema: serde_json::json!({"type": "object"}),
+                    output_schema: None,
+                },
+                |_, ctx| {
+                    Box::pin(async move {
+                        Ok(CallToolResponse::text(
+                            ctx.client_supports_sampling().to_string(),
+                        ))
+                    })
+                },
+            );
+            tokio::spawn(async move { builder.build().listen().await.unwrap() })
+        });
+        transport.open().await?;
+        let client = ClientBuilder::new(transport.clone())
+            .sampling_handler(|_| Box::pin(async move { Err(anyhow::anyhow!("unused")) }))
+            .build();
+        let client_clone = client.clone();
+        tokio::spawn(async move { client_clone.start().await });
+
+        let call = || async {
+            let response: CallToolResponse = client
+                .request_typed(
+                    "tools/call",
+                    serde_json::json!({"name": "sample"}),
+                    crate::protocol::RequestOptions::default(),
+                )
+                .await?;
+            anyhow::Ok(serde_json::to_value(&response.content)?)
+        };
+        // Nothing was negotiated yet
+        assert_eq!(call().await?[0]["text"], "false");
+        client.initialize(Implementation::default()).await?;
+        assert_eq!(call().await?[0]["text"], "true");
+
+        transport.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reinitialize_drops_subscriptions() -> Result<()> {
+        use crate::protocol::RequestOptions;
+        use crate::types::ResourceCapabilities;
+
+        let (server_tx, mut server_rx) = mpsc::channel(1);
+        let transport = ClientInMemoryTransport::new(move |t: ServerInMemoryTransport| {
+            let server = Server::builder(t)
+                .allow_reinitialize(true)
+                .capabilities(ServerCapabilities {
+                    resources: Some(ResourceCapabilities {
+                        subscribe: Some(true),
+                        list_changed: None,
+                    }),
+                    ..Default::default()
+                })
+                .build();
+            let _ = server_tx.try_send(server.clone());
+            tokio::spawn(async move { server.listen().await.unwrap() })
+        });
+        transport.open().await?;
+        let server = server_rx.recv().await.unwrap();
+        let client = ClientBuilder::new(transport.clone()).build();
+        let client_clone = client.clone();
+        tokio::spawn(async move { client_clone.start().await });
+
+        client.initialize(Implementation::default()).await?;
+        let uri = Url::parse("file:///notes.txt")?;
+        client
+            .request(
+                "resources/subscribe",
+                Some(serde_json::json!({ "uri": uri })),
+                RequestOptions::default(),
+            )
+            .await?;
+        assert_eq!(server.subscription_count(&uri), 1);
+
+        client.initialize(Implementation::default()).await?;
+        assert_eq!(server.subscription_count(&uri), 0);
+
+        drop(server);
+        transport.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_tool_filter() -> Result<()> {
+        use crate::protocol::RequestOptions;
+        use crate::types::ClientCapabilities;
+
+        let transport = ClientInMemoryTransport::new(|t: ServerInMemoryTransport| {
+            let mut builder =
+                Server::builder(t)
+                    .allow_reinitialize(true)
+                    .tool_filter(|tool, capabilities| {
+                        let images = capabilities
+                            .and_then(|capabilities| capabilities.experimental.as_ref())
+                            .is_some_and(|experimental| experimental.get("images").is_some());
+                        images || tool.name != "screenshot"
+                    });
+            for name in ["screenshot", "echo"] {
+                builder.register_tool(ToolBuilder::new(name).build(), |_| {
+                    Box::pin(async move { Ok(CallToolResponse::text("ran")) })
+                });
+            }
+            tokio::spawn(async move { builder.build().listen().await.unwrap() })
+        });
+        transport.open().await?;
+        let client = ClientBuilder::new(transport.clone()).build();
+        let client_clone = client.clone();
+        tokio::spawn(async move { client_clone.start().await });
+
+        let list = || async {
+            let response: ToolsListResponse = client
+                .request_typed(
+                    "tools/list",
+                    serde_json::json!({}),
+                    RequestOptions::default(),
+                )
+                .await?;
+            let mut names: Vec<_> = response.tools.into_iter().map(|tool| tool.name).collect();
+            names.sort();
+            anyhow::Ok(names)
+        };
+        assert_eq!(list().await?, vec!["echo"]);
+        client.initialize(Implementation::default()).await?;
+        assert_eq!(list().await?, vec!["echo"]);
+        client
+            .request_typed::<_, InitializeResponse>(
+                "initialize",
+                InitializeRequest {
+                    protocol_version: LATEST_PROTOCOL_VERSION.to_string(),
+                    capabilities: ClientCapabilities {
+                        experimental: Some(serde_json::json!({"images": {}})),
+                        ..Default::default()
+                    },
+                    client_info: Implementation::default(),
+                },
+                RequestOptions::default(),
+            )
+            .await?;
+        assert_eq!(list().await?, vec!["echo", "screenshot"]);
+
+        transport.close().await?;
+        Ok(())
+    }
+
+    // Initializes with `capabilities`, answers sampling when `answer_sampling`, and calls
+    // a tool that needs sampling
+    async fn call_sampling_tool(
+        capabilities: ClientCapabilities,
+        answer_sampling: bool,
+    ) -> Result<CallToolResponse> {
+        use crate::registry::require_sampling;
+        use crate::types::{CreateMessageRequest, CreateMessageResult, Role, SamplingContent};
+
+        let transport = ClientInMemoryTransport::new(|t: ServerInMemoryTransport| {
+            let mut builder = Server::builder(t).client_request_timeout(Duration::from_secs(5));
+            builder.register_tool_with_context(ToolBuilder::new("summarize").build(), |_, ctx| {
+                Box::pin(async move {
+                    let sampling = require_sampling(&ctx)?;
+                    let request = CreateMessageRequest::builder()
+                        .user_message("Summarize the notes")
+                        .max_tokens(100)
+                        .build()?;
+                    let result = sampling.create_message(request).await?;
+                    Ok(CallToolResponse::text(result.content.to_string()))
+                })
+            });
+            tokio::spawn(async move { builder.build().listen().await.unwrap() })
+        });
+        transport.open().await?;
+        let mut client = Protocol::builder(transport.clone());
+        if answer_sampling {
+            client =
+                client.request_handler("sampling/createMessage", |req: CreateMessageRequest| {
+                    Box::pin(async move {
+                        Ok(CreateMessageResult {
+                            role: Role::Assistant,
+                            content: SamplingContent::Text {
+                                text: format!("{} messages summarized", req.messages.len()),
+                            },
+                            model: "test-model".to_string(),
+                            stop_reason: None,
+                        })
+                    })
+                });
+        }
+        let client = client.build();
+        let listener = client.clone();
+        tokio::spawn(async move { listener.listen().await });
+
+        let initialize = InitializeRequest {
+            protocol_version: LATEST_PROTOCOL_VERSION.to_string(),
+            capabilities,
+            client_info: Implementation::default(),
+        };
+        let options = || crate::protocol::RequestOptions::default().timeout(Duration::from_secs(5));
+        client
+            .request(
+                "initialize",
+                Some(serde_json::to_value(initialize)?),
+                options(),
+            )
+            .await?;
+        let response = client
+            .request(
+                "tools/call",
+                Some(serde_json::json!({"name": "summarize"})),
+                options(),
+            )
+            .await?;
+        transport.close().await?;
+        assert!(response.error.is_none(), "{:?}", response.error);
+        Ok(serde_json::from_value(response.result.unwrap())?)
+    }
+
+    #[tokio::test]
+    async fn test_tool_degrades_without_sampling() -> Result<()> {
+        let sampling = ClientCapabilities {
+            sampling: Some(serde_json::json!({})),
+            ..Default::default()
+        };
+        let text = |response: &CallToolResponse| {
+            serde_json::to_value(&response.content).unwrap()[0]["text"].clone()
+        };
+
+        let response = call_sampling_tool(sampling.clone(), true).await?;
+        assert!(response.is_error.is_none());
+        assert_eq!(text(&response), "1 messages summarized");
+
+        // Same handler, a client without sampling gets a tool error naming the capability
+        let response = call_sampling_tool(ClientCapabilities::default(), true).await?;
+        assert_eq!(response.is_error, Some(true));
+        assert!(text(&response).as_str().unwrap().contains("`sampling`"));
+
+        // Advertised but not implemented, like an older client answering MethodNotFound
+        let response = call_sampling_tool(sampling, false).await?;
+        assert_eq!(response.is_error, Some(true));
+        assert!(text(&response).as_str().unwrap().contains("`sampling`"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_tool_sees_connection() -> Result<()> {
+        let transport = ClientInMemoryTransport::new(|t: ServerInMemoryTransport| {
+            let mut builder = Server::builder(t).connection(ConnectionMetadata {
+                transport: "sse",
+                remote_addr: Some("10.0.0.7:52100".parse().unwrap()),
+                user_agent: Some("inspector/1.0".to_string()),
+                tls: false,
+                claims: Some(serde_json::json!({"sub": "ada"})),
+            });
+            builder.register_tool_with_context(
+                Tool {
+                    name: "whoami".to_string(),
+                    description: None,
+                    input_schema: serde_json::json!({"type": "object"}),
+                    output_schema: None,
+                },
+                |_, ctx| {
+                    Box::pin(async move {
+                        let connection = ctx.connection.unwrap_or_default();
+                        Ok(CallToolResponse::text(format!(
+                            "{} from {}",
+                            connection.auth_subject().unwrap_or("anonymous"),
+                            connection.remote_addr.unwrap().ip()
+                        )))
+                    })
+                },
+            );
+            tokio::spawn(async move { builder.build().listen().await.unwrap() })
+        });
+        transport.open().await?;
+        let client = ClientBuilder::new(transport.clone()).build();
+        let client_clone = client.clone();
+        tokio::spawn(async move { client_clone.start().await });
+
+        let response: CallToolResponse = client
+            .request_typed(
+                "tools/call",
+                serde_json::json!({"name": "whoami"}),
+                crate::protocol::RequestOptions::default(),
+            )
+            .await?;
+        assert_eq!(
+            serde_json::to_value(&response.content)?[0]["text"],
+            "ada from 10.0.0.7"
+        );
+
+        transport.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_prompt_round_trip() -> Result<()> {
+        use crate::types::{MessageContent, PromptArgument, PromptMessage, Role};
+
+        let transport = ClientInMemoryTransport::new(|t: ServerInMemoryTransport| {
+            let mut builder = Server::builder(t);
+            builder.register_prompt(
+                Prompt {
+                    name: "review".to_string(),
+                    description: Some("Review code".to_string()),
+                    arguments: Some(vec![
+                        PromptArgument {
+                            name: "code".to_string(),
+                            description: None,
+                            required: Some(true),
+                        },
+                        PromptArgument {
+                            name: "focus".to_string(),
+                            description: None,
+                            required: None,
+                        },
+                    ]),
+                },
+                |req| {
+                    Box::pin(async move {
+                        let arguments = req.arguments.unwrap_or_default();
+                        Ok(GetPromptResult {
+                            description: Some("Code review".to_string()),
+                            messages: vec![PromptMessage {
+                                role: Role::User,
+                                content: MessageContent::Text {
+                                    text: format!("Review: {}", arguments["code"]),
+                                },
+                            }],
+                            meta: None,
+                        })
+                    })
+                },
+            );
+            tokio::spawn(async move { builder.build().listen().await.unwrap() })
+        });
+        transport.open().await?;
+        let client = ClientBuilder::new(transport.clone()).build();
+        let client_clone = client.clone();
+        tokio::spawn(async move { client_clone.start().await });
+
+        let get = |params: serde_json::Value| {
+            client.request_raw(
+                "prompts/get",
+                Some(params),
+                crate::protocol::RequestOptions::default(),
+            )
+        };
+        let response =
+            get(serde_json::json!({"name": "review", "arguments": {"code": "x = 1"}})).await?;
+        assert_eq!(
+            response.result,
+            Some(serde_json::json!({
+                "description": "Code review",
+                "messages": [{"role": "user", "content": {"type": "text", "text": "Review: x = 1"}}]
+            }))
+        );
+        let result: GetPromptResult = serde_json::from_value(response.result.unwrap())?;
+        assert_eq!(result.messages[0].content.to_string(), "Review: x = 1");
+
+        let missing = get(serde_json::json!({"name": "review", "arguments": {"focus": "style"}}))
+            .await?
+            .error
+            .unwrap();
+        assert_eq!(missing.code, ErrorCode::InvalidParams as i32);
+        assert_eq!(missing.error_data().unwrap().kind, "missing_arguments");
+        let unknown = get(serde_json::json!({"name": "nope"}))
+            .await?
+            .error
+            .unwrap();
+        assert_eq!(unknown.error_data().unwrap().kind, "prompt_not_found");
+
+        transport.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_prompt_cursor_tied_to_registrations() -> Result<()> {
+        let list = |names: &'static [&'static str], cursor: Option<String>| async move {
+            let transport = ClientInMemoryTransport::new(move |t: ServerInMemoryTransport| {
+                let mut builder = Server::builder(t).list_page_size(1);
+                for name in names {
+                    let prompt = Prompt {
+                        name: name.to_string(),
+                        description: None,
+                        arguments: None,
+                    };
+                    builder.register_prompt(prompt, |_| {
+                        Box::pin(async move { Err(anyhow::anyhow!("unused")) })
+                    });
+                }
+                tokio::spawn(async move { builder.build().listen().await.unwrap() })
+            });
+            transport.open().await?;
+            let client = ClientBuilder::new(transport.clone()).build();
+            let client_clone = client.clone();
+            tokio::spawn(async move { client_clone.start().await });
+            let response = client
+                .request_raw(
+                    "prompts/list",
+                    Some(serde_json::json!({ "cursor": cursor })),
+                    crate::protocol::RequestOptions::default(),
+                )
+                .await?;
+            transport.close().await?;
+            Ok::<_, anyhow::Error>(response)
+        };
+
+        let first = list(&["a", "b"], None).await?.result.unwrap();
+        let cursor = first["nextCursor"].as_str().unwrap().to_string();
+        // Same registrations, e.g. the next session of the same server
+        let second = list(&["a", "b"], Some(cursor.clone()))
+            .await?
+            .result
+            .unwrap();
+        assert_eq!(second["prompts"][0]["name"], "b");
+        let changed = list(&["a", "c"], Some(cursor)).await?.error.unwrap();
+        assert_eq!(
+            changed.error_data().unwrap().kind,
+            crate::pagination::CURSOR_EXPIRED
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_capabilities_are_merged() {
+        use crate::types::ResourceCapabilities;
+
+        let mut builder =
+            Server::builder(ServerInMemoryTransport::default()).capabilities(ServerCapabilities {
+                tools: Some(serde_json::json!({})),
+                ..Default::default()
+            });
+        builder.register_tool(ToolBuilder::new("echo").build(), |_| {
+            Box::pin(async move { Ok(CallToolResponse::text("echo")) })
+        });
+        let mut builder = builder.merge_capabilities(ServerCapabilities {
+            resources: Some(ResourceCapabilities::default()),
+            ..Default::default()
+        });
+        builder.register_resource(
+            Resource {
+                uri: "memory://notes".parse().unwrap(),
+                name: "notes".to_string(),
+                description: None,
+                mime_type: None,
+            },
+            |_| Box::pin(async move { Ok(ReadResourceResponse::new(vec![])) }),
+        );
+        let server = builder.build();
+        assert!(server.capabilities().tools.is_some());
+        assert!(server.capabilities().resources.is_some());
+
+        // Setting them again replaces them
+        let server = Server::builder(ServerInMemoryTransport::default())
+            .capabilities(ServerCapabilities {
+                tools: Some(serde_json::json!({})),
+                ..Default::default()
+            })
+            .capabilities(ServerCapabilities {
+                logging: Some(serde_json::json!({})),
+                ..Default::default()
+            })
+            .build();
+        assert!(server.capabilities().tools.is_none());
+        assert!(server.capabilities().logging.is_some());
+    }
+
+    #[test]
+    fn test_registrations_are_advertised() {
+        use crate::types::ResourceCapabilities;
+
+        let mut builder = Server::builder(ServerInMemoryTransport::default());
+        builder.register_prompt(
+            Prompt {
+                name: "review".to_string(),
+                description: None,
+                arguments: None,
+            },
+            |_| Box::pin(async move { Err(anyhow::anyhow!("unused")) }),
+        );
+        let server = builder.build();
+        assert!(server.capabilities().prompts.is_some());
+        assert!(server.capabilities().resources.is_none());
+
+        let store = Arc::new(crate::blob::LocalBlobStore::new(Duration::from_secs(60)));
+        let server = Server::builder(ServerInMemoryTransport::default())
+            .blob_store(store)
+            .build();
+        assert!(server.capabilities().resources.is_some());
+
+        // Set explicitly, kept as they are
+        let server = Server::builder(ServerInMemoryTransport::default())
+            .capabilities(ServerCapabilities {
+                resources: Some(ResourceCapabilities {
+                    subscribe: Some(true),
+                    list_changed: None,
+                }),
+                ..Default::default()
+            })
+            .max_tool_result_bytes(1024, OverflowPolicy::SpillToResource)
+            .build();
+        let resources = server.capabilities().resources.as_ref().unwrap();
+        assert_eq!(resources.subscribe, Some(true));
+        assert!(server.capabilities().prompts.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_tools_share_state() -> Result<()> {
+        struct Counter {
+            calls: std::sync::atomic::AtomicU64,
+        }
+        let tool = |name: &str| ToolBuilder::new(name).build();
+        let transport = ClientInMemoryTransport::new(move |t: ServerInMemoryTransport| {
+            let mut builder = Server::builder(t)
+                .with_state(Counter {
+                    calls: std::sync::atomic::AtomicU64::new(0),
+                })
+                .with_state("calls".to_string());
+            builder.register_tool_with_state(tool("bump"), |_, counter: State<Counter>| {
+                Box::pin(async move {
+                    counter
+                        .calls
+                        .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    Ok(CallToolResponse::text("bumped"))
+                })
+            });
+            builder.register_tool_with_state(tool("count"), |_, counter: State<Counter>| {
+                Box::pin(async move {
+                    let count = counter.calls.load(std::sync::atomic::Ordering::SeqCst);
+                    Ok(CallToolResponse::text(count.to_string()))
+                })
+            });
+            builder.register_tool_with_state(tool("unit"), |_, unit: State<String>| {
+                Box::pin(async move { Ok(CallToolResponse::text(unit.as_str())) })
+            });
+            tokio::spawn(async move { builder.build().listen().await.unwrap() })
+        });
+        transport.open().await?;
+        let client = ClientBuilder::new(transport.clone()).build();
+        let client_clone = client.clone();
+        tokio::spawn(async move { client_clone.start().await });
+
+        let call = |name: &str| {
+            let client = client.clone();
+            let name = name.to_string();
+            async move {
+                let response: CallToolResponse = client
+                    .request_typed(
+                        "tools/call",
+                        serde_json::json!({ "name": name }),
+                        crate::protocol::RequestOptions::default(),
+                    )
+                    .await?;
+                Ok::<_, anyhow::Error>(serde_json::to_value(&response.content)?[0]["text"].clone())
+            }
+        };
+        call("bump").await?;
+        call("bump").await?;
+        assert_eq!(call("count").await?, "2");
+        assert_eq!(call("unit").await?, "calls");
+
+        transport.close().await?;
+        Ok(())
+    }
+
+    #[test]
+    #[should_panic(expected = "Tool `lookup` needs state of type `u32`")]
+    fn test_missing_state_rejected_at_registration() {
+        let mut builder = Server::builder(ServerInMemoryTransport::default()).with_state(1u64);
+        builder.register_tool_with_state(ToolBuilder::new("lookup").build(), |_, _: State<u32>| {
+            Box::pin(async move { Ok(CallToolResponse::text("unreachable")) })
+        });
+    }
+
+    #[derive(Clone)]
+    struct TrackedTransport {
+        inner: ServerInMemoryTransport,
+        opens: Arc<std::sync::atomic::AtomicUsize>,
+        closes: Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    #[async_trait::async_trait]
+    impl Transport for TrackedTransport {
+        async fn send(&self, message: &crate::transport::Message) -> Result<()> {
+            self.inner.send(message).await
+        }
+        async fn receive(&self) -> Result<Option<crate::transport::Message>> {
+            self.inner.receive().await
+        }
+        async fn open(&self) -> Result<()> {
+            self.opens.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            self.inner.open().await
+        }
+        async fn close(&self) -> Result<()> {
+            self.closes
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            self.inner.close().await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_listen_error_wins_over_close_error() -> Result<()> {
+        struct BrokenTransport;
+
+        #[async_trait::async_trait]
+        impl Transport for BrokenTransport {
+            async fn send(&self, _: &crate::transport::Message) -> Result<()> {
+                Ok(())
+            }
+            async fn receive(&self) -> Result<Option<crate::transport::Message>> {
+                Err(JsonRpcError::new(ErrorCode::ConnectionClosed, "receive failed").into())
+            }
+            async fn open(&self) -> Result<()> {
+                Ok(())
+            }
+            async fn close(&self) -> Result<()> {
+                anyhow::bail!("close failed")
+            }
+        }
+
+        let error = Server::builder(BrokenTransport)
+            .build()
+            .listen()
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("receive failed"), "{:#}", error);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_report_progress() -> Result<()> {
+        use crate::protocol::RequestOptions;
+
+        let transport = ClientInMemoryTransport::new(|t: ServerInMemoryTransport| {
+            let mut builder = Server::builder(t);
+            builder.register_tool_with_context(
+                Tool {
+                    name: "work".to_string(),
+                    description: None,
+                    input_schema: serde_json::json!({"type": "object"}),
+                    output_schema: None,
+                },
+                |_, ctx| {
+                    Box::pin(async move {
+                        ctx.report_progress(0.5, "halfway").await?;
+                        ctx.report_progress(1.5, "overshoot").await?;
+                        ctx.report_progress(f64::NAN, "broken").await?;
+                        ctx.report_progress_of(12.0, 10.0, "files").await?;
+                        ctx.report_progress(1.0, "done").await?;
+                        Ok(CallToolResponse::text(format!(
+                            "{:?}",
+                            ctx.progress_token()
+                        )))
+                    })
+                },
+            );
+            tokio::spawn(async move { builder.build().listen().await.unwrap() })
+        });
+        transport.open().await?;
+        let client = ClientBuilder::new(transport.clone()).build();
+        let client_clone = client.clone();
+        tokio::spawn(async move { client_clone.start().await });
+
+        let progress = Arc::new(Mutex::new(Vec::new()));
+        let handle = progress.clone();
+        let options = RequestOptions::default().on_progress(move |p| {
+            handle
+                .lock()
+                .unwrap()
+                .push((p.progress, p.total, p.message.unwrap()));
+        });
+        let call = serde_json::json!({"name": "work"});
+        let response: CallToolResponse = client
+            .request_typed("tools/call", call.clone(), options)
+            .await?;
+        assert_eq!(
+            *progress.lock().unwrap(),
+            vec![
+                (0.5, None, "halfway".to_string()),
+                (1.0, None, "overshoot".to_string()),
+                (0.0, None, "broken".to_string()),
+                (10.0, Some(10.0), "files".to_string()),
+                (1.0, None, "done".to_string())
+            ]
+        );
+        assert_eq!(
+            serde_json::to_value(&response.content)?[0]["text"],
+            "Some(Number(0))"
+        );
+
+        // Without a token the reports are skipped
+        let response: CallToolResponse = client
+            .request_typed("tools/call", call, RequestOptions::default())
+            .await?;
+        assert_eq!(serde_json::to_value(&response.content)?[0]["text"], "None");
+
+        transport.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_listen_manages_transport() -> Result<()> {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let opens = Arc::new(AtomicUsize::new(0));
+        let closes = Arc::new(AtomicUsize::new(0));
+        let (server_opens, server_closes) = (opens.clone(), closes.clone());
+        let transport = ClientInMemoryTransport::new(move |t: ServerInMemoryTransport| {
+            let transport = TrackedTransport {
+                inner: t,
+                opens: server_opens.clone(),
+                closes: server_closes.clone(),
+            };
+            tokio::spawn(async move {
+                let server = Server::builder(transport).build();
+                server.listen().await.unwrap();
+            })
+        });
+        transport.open().await?;
+        let client = ClientBuilder::new(transport.clone()).build();
+        let client_clone = client.clone();
+        tokio::spawn(async move { client_clone.start().await });
+        client.initialize(Implementation::default()).await?;
+        assert_eq!(opens.load(Ordering::SeqCst), 1);
+        assert_eq!(closes.load(Ordering::SeqCst), 0);
+
+        // close waits for the server task, so listen has returned afterwards
+        transport.close().await?;
+        assert_eq!(closes.load(Ordering::SeqCst), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unadvertised_capability_rejected() -> Result<()> {
+        use crate::protocol::RequestOptions;
+        use crate::types::PromptCapabilities;
+
+        let transport = ClientInMemoryTransport::new(|t: ServerInMemoryTransport| {
+            let mut builder = Server::builder(t)
+                .capabilities(ServerCapabilities {
+                    prompts: Some(PromptCapabilities::default()),
+                    ..Default::default()
+                })
+                .enforce_capabilities(CapabilityEnforcement::Enforce)
+                .capability_exempt("tools/acme.batch")
+                .request_handler("tools/acme.batch", |_: serde_json::Value| {
+                    Box::pin(async move { Ok(serde_json::json!({"ran": "batch"})) })
+                })
+                .request_handler("acme/echo", |params: serde_json::Value| {
+                    Box::pin(async move { Ok(params) })
+                });
+            builder.register_tool(ToolBuilder::new("echo").build(), |_| {
+                Box::pin(async move { Ok(CallToolResponse::text("ran")) })
+            });
+            tokio::spawn(async move { builder.build().listen().await.unwrap() })
+        });
+        transport.open().await?;
+        let client = ClientBuilder::new(transport.clone()).build();
+        let client_clone = client.clone();
+        tokio::spawn(async move { client_clone.start().await });
+
+        // Handled but never advertised
+        for method in ["tools/list", "tools/call", "completion/complete"] {
+            let err = client
+                .request(method, None, RequestOptions::default())
+                .await
+                .unwrap_err();
+            let err = err.downcast_ref::<JsonRpcError>().unwrap();
+            assert_eq!(err.code, ErrorCode::MethodNotFound as i32);
+            assert_eq!(
+                err.error_data().unwrap().kind,
+                crate::types::ErrorData::CAPABILITY_NOT_ADVERTISED
+            );
+        }
+        // Advertised but unhandled, the usual MethodNotFound
+        let err = client
+            .request("prompts/list", None, RequestOptions::default())
+            .await
+            .unwrap_err();
+        let err = err.downcast_ref::<JsonRpcError>().unwrap();
+        assert_eq!(
+            err.error_data().unwrap().kind,
+            crate::types::ErrorData::METHOD_NOT_FOUND
+        );
+
+        let batch = client
+            .request("tools/acme.batch", None, RequestOptions::default())
+            .await?;
+        assert_eq!(batch["ran"], "batch");
+        let echo = client
+            .request(
+                "acme/echo",
+                Some(serde_json::json!({"x": 1})),
+                RequestOptions::default(),
+            )
+            .await?;
+        assert_eq!(echo["x"], 1);
+        client
+            .request("ping", None, RequestOptions::default())
+            .await?;
+
+        transport.close().await?;
+        Ok(())
+    }
+
+    #[test]
+    #[should_panic(expected = "Capability prompts is advertised without a prompts/list handler")]
+    fn test_strict_capabilities_need_handlers() {
+        use crate::types::PromptCapabilities;
+
+        let builder = Server::builder(ServerInMemoryTransport::default())
+            .capabilities(ServerCapabilities {
+                tools: Some(serde_json::json!({})),
+                prompts: Some(PromptCapabilities::default()),
+                ..Default::default()
+            })
+            .enforce_capabilities(CapabilityEnforcement::Strict);
+        assert_eq!(
+            missing_handlers(&builder.capabilities, |method| method.starts_with("tools/")),
+            vec![("prompts", "prompts/list"), ("prompts", "prompts/get")]
+        );
+        builder.build();
+    }
+
+    // A server advertising logging and a client collecting `notifications/message`
+    async fn logging_session(
+        configure: impl Fn(ServerBuilder<ServerInMemoryTransport>) -> ServerBuilder<ServerInMemoryTransport>
+            + Send
+            + Sync
+            + 'static,
+    ) -> Result<(
+        ClientInMemoryTransport,
+        crate::client::Client<ClientInMemoryTransport>,
+        Server<ServerInMemoryTransport>,
+        mpsc::UnboundedReceiver<LoggingMessageParams>,
+    )> {
+        let (server_tx, mut server_rx) = mpsc::channel(1);
+        let transport = ClientInMemoryTransport::new(move |t: ServerInMemoryTransport| {
+            let server = configure(Server::builder(t).capabilities(ServerCapabilities {
+                logging: Some(serde_json::json!({})),
+                ..Default::default()
+            }))
+            .build();
+            let _ = server_tx.try_send(server.clone());
+            tokio::spawn(async move { server.listen().await.unwrap() })
+        });
+        transport.open().await?;
+        let (records_tx, records) = mpsc::unbounded_channel();
+        let client = ClientBuilder::new(transport.clone())
+            .notification_handler("notifications/message", move |p: LoggingMessageParams| {
+                let _ = records_tx.send(p);
+                Box::pin(async move { Ok(()) })
+            })
+            .build();
+        let client_clone = client.clone();
+        tokio::spawn(async move { client_clone.start().await });
+        let server = server_rx.recv().await.unwrap();
+        Ok((transport, client, server, records))
+    }
+
+    #[tokio::test]
+    async fn test_log_level_per_session() -> Result<()> {
+        use crate::protocol::RequestOptions;
+
+        let configure = |builder: ServerBuilder<_>| builder.default_log_level(LoggingLevel::Info);
+        let (quiet_transport, quiet, quiet_server, mut quiet_records) =
+            logging_session(configure).await?;
+        let (chatty_transport, chatty, chatty_server, mut chatty_records) =
+            logging_session(configure).await?;
+
+        // Only one session raises its level, the other keeps the default
+        let _: serde_json::Value = quiet
+            .request_typed(
+                "logging/setLevel",
+                SetLevelRequest {
+                    level: LoggingLevel::Error,
+                },
+                RequestOptions::default(),
+            )
+            .await?;
+        assert_eq!(quiet_server.log_level(), LoggingLevel::Error);
+        assert_eq!(chatty_server.log_level(), LoggingLevel::Info);
+
+        let formatted = Arc::new(Mutex::new(Vec::new()));
+        for server in [&quiet_server, &chatty_server] {
+            for level in [
+                LoggingLevel::Debug,
+                LoggingLevel::Warning,
+                LoggingLevel::Error,
+            ] {
+                let formatted = formatted.clone();
+                server
+                    .log_with(level, move || {
+                        formatted.lock().unwrap().push(level);
+                        LoggingMessageParams::new(level, format!("{:?}", level))
+                    })
+                    .await?;
+            }
+        }
+        // Filtered records are never formatted
+        assert_eq!(
+            *formatted.lock().unwrap(),
+            vec![
+                LoggingLevel::Error,
+                LoggingLevel::Warning,
+                LoggingLevel::Error
+            ]
+        );
+
+        // A response after the records means they were all handled
+        for client in [&quiet, &chatty] {
+            let _: serde_json::Value = client
+                .request_typed("ping", serde_json::json!({}), RequestOptions::default())
+                .await?;
+        }
+        let drain = |records: &mut mpsc::UnboundedReceiver<LoggingMessageParams>| {
+            std::iter::from_fn(|| records.try_recv().ok())
+                .map(|record| record.level)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(drain(&mut quiet_records), vec![LoggingLevel::Error]);
+        assert_eq!(
+            drain(&mut chatty_records),
+            vec![LoggingLevel::Warning, LoggingLevel::Error]
+        );
+
+        drop((quiet_server, chatty_server));
+        quiet_transport.close().await?;
+        chatty_transport.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_log_flood_is_throttled() -> Result<()> {
+        let (transport, _client, server, mut records) =
+            logging_session(|builder| builder.log_rate_limit(3, 5.0)).await?;
+
+        for i in 0..10 {
+            server
+                .log(LoggingMessageParams::new(LoggingLevel::Info, i))
+                .await?;
+        }
+        server
+            .log(LoggingMessageParams::new(LoggingLevel::Error, "disk full"))
+            .await?;
+
+        let mut received = Vec::new();
+        while received.len() < 4 {
+            let record = tokio::time::timeout(Duration::from_secs(5), records.recv())
+                .await?
+                .unwrap();
+            received.push(record);
+        }
+        let data: Vec<_> = received.iter().map(|r| r.data.clone().unwrap()).collect();
+        assert_eq!(data[..3], [serde_json::json!(0), 1.into(), 2.into()]);
+        // The summary stands for the most severe record it replaces
+        assert_eq!(received[3].level, LoggingLevel::Error);
+        assert_eq!(data[3]["dropped"], 8);
+
+        drop(server);
+        transport.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_log_summary_respects_level() -> Result<()> {
+        use crate::protocol::RequestOptions;
+
+        let (transport, client, server, mut records) =
+            logging_session(|builder| builder.log_rate_limit(1, 5.0)).await?;
+        for i in 0..3 {
+            server
+                .log(LoggingMessageParams::new(LoggingLevel::Info, i))
+                .await?;
+        }
+        let _: serde_json::Value = client
+            .request_typed(
+                "logging/setLevel",
+                SetLevelRequest {
+                    level: LoggingLevel::Error,
+                },
+                RequestOptions::default(),
+            )
+            .await?;
+
+        assert_eq!(records.recv().await.unwrap().data, Some(0.into()));
+        // The summary of the dropped info records is below the new level
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        assert!(records.try_recv().is_err());
+
+        drop(server);
+        transport.close().await?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_try_build_reports_every_issue() {
+        use crate::validation::IssueCode;
+
+        let broken = |strict: bool| {
+            let mut builder = Server::builder(ServerInMemoryTransport::default())
+                .capabilities(ServerCapabilities {
+                    tools: Some(serde_json::json!({})),
+                    completions: Some(serde_json::json!({})),
+                    ..Default::default()
+                })
+                .list_page_size(0)
+                .enforce_capabilities(CapabilityEnforcement::Enforce)
+                .strict_validation(strict);
+            for _ in 0..2 {
+                builder.register_prompt(
+                    Prompt {
+                        name: "greet".to_string(),
+                        description: None,
+                        arguments: None,
+                    },
+                    |_| Box::pin(async move { Err(anyhow::anyhow!("unused")) }),
+                );
+                builder.register_resource(
+                    Resource {
+                        uri: "file:///notes.md".parse().unwrap(),
+                        name: "notes".to_string(),
+                        description: None,
+                        mime_type: None,
+                    },
+                    |_| Box::pin(async move { Ok(ReadResourceResponse::new(vec![])) }),
+                );
+            }
+            builder
+        };
+
+        // Warnings only, the server is built
+        assert!(broken(false).try_build().is_ok());
+
+        let error = broken(true).try_build().err().unwrap();
+        assert_eq!(
+            error.codes(),
+            vec![
+                IssueCode::DuplicatePrompt,
+                IssueCode::DuplicateResource,
+                IssueCode::ZeroPageSize,
+                IssueCode::NoTools,
+                IssueCode::MissingHandler,
+            ]
+        );
+        assert!(error.issues[4].message.contains("completion/complete"));
+        assert!(error.to_string().contains("error[duplicate_prompt]"));
+
+        // Missing handlers only matter when capabilities are enforced
+        let error = broken(true)
+            .enforce_capabilities(CapabilityEnforcement::Off)
+            .try_build()
+            .err()
+            .unwrap();
+        assert!(!error.codes().contains(&IssueCode::MissingHandler));
     }
 }
@@ -0,0 +1,256 @@
+//! Serve the rows of SQLite tables as resources
+//! each table gets a `sqlite://{table}/{id}` template whose reads return the row as a JSON object
+use crate::server::ServerBuilder;
+use crate::transport::Transport;
+use crate::types::{ReadResourceResponse, Resource, ResourceContent, ResourceTemplate};
+use anyhow::Result;
+use base64::Engine;
+use parking_lot::Mutex;
+use rusqlite::{types::ValueRef, Connection, OptionalExtension};
+use std::sync::Arc;
+use url::Url;
+
+pub const SQLITE_SCHEME: &str = "sqlite";
+
+const DEFAULT_LIST_LIMIT: usize = 1000;
+
+#[derive(Debug, Clone)]
+struct SqlTable {
+    name: String,
+    key_column: String,
+}
+
+/// Exposes rows of SQLite tables through `resources/templates/list` and `resources/read`
+/// queries run on the blocking thread pool, one at a time on the shared connection
+pub struct SqlResourceProvider {
+    conn: Arc<Mutex<Connection>>,
+    tables: Vec<SqlTable>,
+    list_limit: usize,
+}
+
+impl SqlResourceProvider {
+    pub fn new(conn: Connection) -> Self {
+        Self {
+            conn: Arc::new(Mutex::new(conn)),
+            tables: Vec::new(),
+            list_limit: DEFAULT_LIST_LIMIT,
+        }
+    }
+
+    /// Serve the rows of `table` keyed by `key_column`
+    /// both must be plain identifiers since they are spliced into the queries
+    pub fn table(mut self, table: &str, key_column: &str) -> Result<Self> {
+        for name in [table, key_column] {
+            if !is_identifier(name) {
+                anyhow::bail!("Invalid SQL identifier: {:?}", name);
+            }
+        }
+        self.tables.push(SqlTable {
+            name: table.to_string(),
+            key_column: key_column.to_string(),
+        });
+        Ok(self)
+    }
+
+    /// Most rows listed per table when template resources are listed, reads aren't limited
+    pub fn list_limit(mut self, limit: usize) -> Self {
+        self.list_limit = limit;
+        self
+    }
+
+    /// Register a resource template per table
+    /// enable [`ServerBuilder::list_template_resources`] to also list the rows in `resources/list`
+    pub fn register<T: Transport>(&self, builder: &mut ServerBuilder<T>) {
+        for table in &self.tables {
+            let template = ResourceTemplate {
+                uri_template: format!("{}://{}/{{id}}", SQLITE_SCHEME, table.name),
+                name: table.name.clone(),
+                description: Some(format!(
+                    "Row of {} by {}, as a JSON object",
+                    table.name, table.key_column
+                )),
+                mime_type: Some("application/json".to_string()),
+            };
+            let (list_conn, list_table) = (self.conn.clone(), table.clone());
+            let (read_conn, read_table) = (self.conn.clone(), table.clone());
+            let list_limit = self.list_limit;
+            builder.register_resource_template(
+                template,
+                move || {
+                    let conn = list_conn.clone();
+                    let table = list_table.clone();
+                    Box::pin(async move {
+                        tokio::task::spawn_blocking(move || {
+                            list_rows(&conn.lock(), &table, list_limit)
+                        })
+                        .await?
+                    })
+                },
+                move |req, ctx| {
+                    let conn = read_conn.clone();
+                    let table = read_table.clone();
+                    Box::pin(async move {
+                        let id =
+                            ctx.variables.get("id").cloned().ok_or_else(|| {
+                                anyhow::anyhow!("Resource not found: {}", req.uri)
+                            })?;
+                        let row = tokio::task::spawn_blocking(move || {
+                            read_row(&conn.lock(), &table, &id)
+                        })
+                        .await??
+                        .ok_or_else(|| anyhow::anyhow!("Resource not found: {}", req.uri))?;
+                        Ok(ReadResourceResponse::new(vec![ResourceContent::text(
+                            req.uri,
+                            "application/json",
+                            row.to_string(),
+                        )]))
+                    })
+                },
+            );
+        }
+    }
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Rows ordered by key, keys that can't appear verbatim in a URI are skipped
+fn list_rows(conn: &Connection, table: &SqlTable, limit: usize) -> Result<Vec<Resource>> {
+    let sql = format!(
+        "SELECT \"{key}\" FROM \"{table}\" ORDER BY \"{key}\" LIMIT ?1",
+        key = table.key_column,
+        table = table.name
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let keys = stmt.query_map([limit as i64], |row| {
+        Ok(match row.get_ref(0)? {
+            ValueRef::Integer(key) => Some(key.to_string()),
+            ValueRef::Text(key) => Some(String::from_utf8_lossy(key).into_owned()),
+            _ => None,
+        })
+    })?;
+
+    let mut resources = Vec::new();
+    for key in keys {
+        let Some(key) = key? else { continue };
+        let uri = format!("{}://{}/{}", SQLITE_SCHEME, table.name, key);
+        match Url::parse(&uri) {
+            Ok(url) if !key.contains('/') && url.as_str() == uri => resources.push(Resource {
+                uri: url,
+                name: format!("{} {}", table.name, key),
+                description: None,
+                mime_type: Some("application/json".to_string()),
+            }),
+            _ => continue,
+        }
+    }
+    Ok(resources)
+}
+
+fn read_row(conn: &Connection, table: &SqlTable, id: &str) -> Result<Option<serde_json::Value>> {
+    let sql = format!(
+        "SELECT * FROM \"{}\" WHERE \"{}\" = ?1",
+        table.name, table.key_column
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let columns: Vec<String> = stmt.column_names().into_iter().map(String::from).collect();
+    let row = stmt
+        .query_row([id], |row| {
+            let mut object = serde_json::Map::new();
+            for (i, column) in columns.iter().enumerate() {
+                object.insert(column.clone(), json_value(row.get_ref(i)?));
+            }
+            Ok(serde_json::Value::Object(object))
+        })
+        .optional()?;
+    Ok(row)
+}
+
+/// Blobs become base64 strings
+fn json_value(value: ValueRef<'_>) -> serde_json::Value {
+    match value {
+        ValueRef::Null => serde_json::Value::Null,
+        ValueRef::Integer(value) => value.into(),
+        ValueRef::Real(value) => value.into(),
+        ValueRef::Text(value) => String::from_utf8_lossy(value).into(),
+        ValueRef::Blob(value) => base64::engine::general_purpose::STANDARD
+            .encode(value)
+            .into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::ClientBuilder;
+    use crate::protocol::RequestOptions;
+    use crate::server::Server;
+    use crate::transport::{ClientInMemoryTransport, ServerInMemoryTransport};
+    use crate::types::{ListRequest, ReadResourceRequest, ResourcesListResponse};
+
+    fn seeded() -> Result<Connection> {
+        let conn = Connection::open_in_memory()?;
+        conn.execute_batch(
+            "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT, score REAL, avatar BLOB);
+             INSERT INTO users VALUES (1, 'ada', 9.5, x'6869');
+             INSERT INTO users VALUES (2, 'grace', NULL, NULL);",
+        )?;
+        Ok(conn)
+    }
+
+    #[tokio::test]
+    async fn test_sql_resources() -> Result<()> {
+        assert!(SqlResourceProvider::new(seeded()?)
+            .table("users; DROP TABLE users", "id")
+            .is_err());
+
+        let provider = Arc::new(SqlResourceProvider::new(seeded()?).table("users", "id")?);
+        let transport = ClientInMemoryTransport::new(move |t: ServerInMemoryTransport| {
+            let mut builder = Server::builder(t).list_template_resources(true);
+            provider.register(&mut builder);
+            tokio::spawn(async move { builder.build().listen().await.unwrap() })
+        });
+        transport.open().await?;
+        let client = ClientBuilder::new(transport.clone()).build();
+        let client_clone = client.clone();
+        tokio::spawn(async move { client_clone.start().await });
+
+        let listed: ResourcesListResponse = client
+            .request_typed(
+                "resources/list",
+                ListRequest {
+                    cursor: None,
+                    meta: None,
+                },
+                RequestOptions::default(),
+            )
+            .await?;
+        let uris: Vec<_> = listed.resources.iter().map(|r| r.uri.as_str()).collect();
+        assert_eq!(uris, vec!["sqlite://users/1", "sqlite://users/2"]);
+
+        let read = |uri: &str| {
+            client.request_typed::<_, ReadResourceResponse>(
+                "resources/read",
+                ReadResourceRequest::new(Url::parse(uri).unwrap()),
+                RequestOptions::default(),
+            )
+        };
+        let response = read("sqlite://users/1").await?;
+        let ResourceContent::Text(contents) = &response.contents[0] else {
+            panic!("expected text contents");
+        };
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&contents.text)?,
+            serde_json::json!({"id": 1, "name": "ada", "score": 9.5, "avatar": "aGk="})
+        );
+        assert!(read("sqlite://users/3").await.is_err());
+
+        transport.close().await?;
+        Ok(())
+    }
+}
@@ -1,4 +1,4 @@
-use actix_web::middleware::Logger;
+use actix_web::middleware::{Condition, Logger};
 use actix_web::web::Payload;
 use actix_web::web::Query;
 use actix_web::HttpMessage;
@@ -7,16 +7,26 @@ use anyhow::Result;
 use futures::StreamExt;
 use uuid::Uuid;
 
+use crate::registry::ConnectionMetadata;
 use crate::server::Server;
-use crate::sse::middleware::{AuthConfig, JwtAuth};
-use crate::transport::ServerHttpTransport;
-use crate::transport::{handle_ws_connection, Message, ServerSseTransport, ServerWsTransport};
+use crate::sse::limits::{Admission, ConnectionLimits, Refusal, Rejection, Rejections};
+use crate::sse::middleware::{AuthConfig, JwtAuth, SharedAuth, VerifiedToken};
+use crate::subscriptions::Subscriptions;
+use crate::transport::{Clock, DecodeLimits, ServerSseTransport, ServerWsTransport, SystemClock};
+use crate::transport::{
+    JsonRpcError, JsonRpcMessage, JsonRpcNotification, ServerHttpTransport, Transport,
+};
+use crate::transport::{SlowConsumerPolicy, SlowConsumerStats};
+use crate::types::ErrorCode;
+use parking_lot::RwLock;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fmt::Debug;
-use std::sync::{Arc, Mutex};
-use tokio::sync::broadcast;
-use tracing::{debug, error, info};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::{broadcast, mpsc};
+use tracing::{debug, error, info, warn};
 
 /// Server-side SSE transport that handles HTTP POST requests for incoming messages
 /// and sends responses via SSE
@@ -35,43 +45,461 @@ pub struct MessageQuery {
     session_id: Option<String>,
 }
 
+/// Factory invoked for every new HTTP session to build its server instance
+pub type BuildServerFn = Arc<
+    dyn Fn(
+            ServerHttpTransport,
+            ConnectionMetadata,
+            String,
+        ) -> futures::future::BoxFuture<'static, Result<Server<ServerHttpTransport>>>
+        + Send
+        + Sync,
+>;
+
+/// Transport of an HTTP session
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionKind {
+    Sse,
+    Ws,
+}
+
+/// A connected HTTP session, times are milliseconds since the Unix epoch when serialized
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionInfo {
+    pub id: String,
+    pub kind: SessionKind,
+    #[serde(serialize_with = "unix_ms")]
+    pub connected_at: SystemTime,
+    #[serde(serialize_with = "unix_ms")]
+    pub last_activity: SystemTime,
+}
+
+fn unix_ms<S: serde::Serializer>(time: &SystemTime, serializer: S) -> Result<S::Ok, S::Error> {
+    let ms = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    serializer.serialize_u64(ms)
+}
+
+/// How the `endpoint` event tells SSE clients where to POST their messages
+/// by default the path is sent relative to the SSE route, the client resolves it against the
+/// URL of its event stream, so prefixes added by a proxy or [`McpOptions::prefix`] are kept
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EndpointConfig {
+    /// Base of the advertised URL, e.g. `https://mcp.example.com/api`, takes precedence
+    pub public_base_url: Option<String>,
+    /// Derive the base from `Forwarded` or `X-Forwarded-Proto`/`X-Forwarded-Host`, and
+    /// [`ConnectionMetadata::remote_addr`] from `Forwarded` or `X-Forwarded-For`,
+    /// only enable behind a proxy that overwrites these headers
+    pub trust_proxy: bool,
+}
+
+impl EndpointConfig {
+    pub fn public_base_url(mut self, url: impl Into<String>) -> Self {
+        self.public_base_url = Some(url.into());
+        self
+    }
+
+    pub fn trust_proxy(mut self, trust_proxy: bool) -> Self {
+        self.trust_proxy = trust_proxy;
+        self
+    }
+
+    /// Base the prefixed message path is appended to, empty for a relative endpoint
+    fn base(&self, req: &actix_web::HttpRequest) -> String {
+        if let Some(url) = &self.public_base_url {
+            return url.trim_end_matches('/').to_string();
+        }
+        let forwarded = ["forwarded", "x-forwarded-host", "x-forwarded-proto"]
+            .iter()
+            .any(|header| req.headers().contains_key(*header));
+        if self.trust_proxy && forwarded {
+            // Parses the forwarded headers, falling back to Host for what they leave out
+            let info = req.connection_info();
+            return format!("{}://{}", info.scheme(), info.host());
+        }
+        String::new()
+    }
+}
+
+/// Where [`configure_mcp`] mounts the SSE, message and WebSocket routes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct McpOptions {
+    /// Path the routes are mounted under, e.g. `/mcp` for `/mcp/sse`, empty for the root
+    pub prefix: String,
+    /// Check JWTs with the session state's auth config on these routes only
+    pub jwt_auth: bool,
+    /// Mount the `/ws` route next to `/sse` and `/message`
+    pub websocket: bool,
+}
+
+impl Default for McpOptions {
+    fn default() -> Self {
+        Self {
+            prefix: String::new(),
+            jwt_auth: true,
+            websocket: true,
+        }
+    }
+}
+
+impl McpOptions {
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// Leave authentication to the application, e.g. its own middleware on the whole `App`
+    pub fn jwt_auth(mut self, jwt_auth: bool) -> Self {
+        self.jwt_auth = jwt_auth;
+        self
+    }
+
+    /// Serve SSE clients only
+    pub fn websocket(mut self, websocket: bool) -> Self {
+        self.websocket = websocket;
+        self
+    }
+
+    /// `/`-led without a trailing `/`, empty for the root
+    fn normalized_prefix(&self) -> String {
+        let prefix = self.prefix.trim_matches('/');
+        if prefix.is_empty() {
+            String::new()
+        } else {
+            format!("/{}", prefix)
+        }
+    }
+}
+
+/// Path of the message route advertised in the `endpoint` event, `/message` when not set
+#[derive(Clone)]
+struct MessagePath(String);
+
+/// Source of session ids, injectable so tests get deterministic ids
+/// an id already in use is rejected with 409 Conflict, derived ids need a unique part
+pub trait IdGenerator: Send + Sync {
+    fn next_id(&self) -> String;
+
+    /// Id of a session opened over `connection`, e.g. from its token's `sub` claim
+    fn id_for(&self, _connection: &ConnectionMetadata) -> String {
+        self.next_id()
+    }
+}
+
+/// Session ids derived from the connection, e.g. a user id and a nonce for tracing sessions
+/// back to users
+pub struct SessionIdGenerator<F>(pub F);
+
+impl<F> IdGenerator for SessionIdGenerator<F>
+where
+    F: Fn(&ConnectionMetadata) -> String + Send + Sync,
+{
+    fn next_id(&self) -> String {
+        (self.0)(&ConnectionMetadata::default())
+    }
+
+    fn id_for(&self, connection: &ConnectionMetadata) -> String {
+        (self.0)(connection)
+    }
+}
+
+/// Random v4 UUIDs, the default
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UuidGenerator;
+
+impl IdGenerator for UuidGenerator {
+    fn next_id(&self) -> String {
+        Uuid::new_v4().to_string()
+    }
+}
+
+/// Settings of a running HTTP server that can be changed without dropping its sessions
+#[derive(Clone)]
+pub struct ReloadHandle {
+    auth: SharedAuth,
+    admission: Arc<Admission>,
+}
+
+impl ReloadHandle {
+    pub fn new(auth_config: Option<AuthConfig>, limits: ConnectionLimits) -> Self {
+        Self {
+            auth: SharedAuth::new(auth_config),
+            admission: Arc::new(Admission::new(limits)),
+        }
+    }
+
+    /// Rotate the JWT secrets, new connections must use the new ones
+    /// while established sessions keep posting with the token they connected with
+    pub fn set_auth(&self, auth_config: Option<AuthConfig>) {
+        self.auth.set(auth_config);
+    }
+
+    /// Applies to new sessions and messages, existing sessions are never evicted
+    pub fn set_connection_limits(&self, limits: ConnectionLimits) {
+        self.admission.set_limits(limits);
+    }
+
+    /// Requests rejected by the connection limits so far
+    pub fn rejections(&self) -> Rejections {
+        self.admission.rejections()
+    }
+}
+
 #[derive(Clone)]
 pub struct SessionState {
-    sessions: Arc<Mutex<HashMap<String, ServerHttpTransport>>>,
-    build_server: Arc<
-        dyn Fn(
-                ServerHttpTransport,
-                Option<serde_json::Value>,
-                String,
-            )
-                -> futures::future::BoxFuture<'static, Result<Server<ServerHttpTransport>>>
-            + Send
-            + Sync,
-    >,
-    endpoint: String,
+    sessions: Arc<RwLock<HashMap<String, ServerHttpTransport>>>,
+    build_server: BuildServerFn,
+    endpoint: EndpointConfig,
+    limits: DecodeLimits,
+    admission: Arc<Admission>,
+    auth: SharedAuth,
+    ids: Arc<dyn IdGenerator>,
+    clock: Arc<dyn Clock>,
+    idle_timeout: Option<Duration>,
+    slow_consumer: SlowConsumerPolicy,
+    slow_consumer_stats: Arc<SlowConsumerStats>,
+    subscriptions: Subscriptions,
 }
 
 impl SessionState {
+    /// Sessions built by `build_server`, starting without any
+    pub fn from_fn<F, Fut>(build_server: F) -> Self
+    where
+        F: Fn(ServerHttpTransport, ConnectionMetadata, String) -> Fut + Send + Sync + 'static,
+        Fut: futures::Future<Output = Result<Server<ServerHttpTransport>>> + Send + 'static,
+    {
+        let build_server = Arc::new(move |t, o, session_id| {
+            Box::pin(build_server(t, o, session_id)) as futures::future::BoxFuture<_>
+        });
+        Self::new(build_server, Arc::new(RwLock::new(HashMap::new())))
+    }
+
     /// Create a new SessionState instance with configurable parameters
     pub fn new(
-        endpoint: String,
-        build_server: Arc<
-            dyn Fn(
-                    ServerHttpTransport,
-                    Option<serde_json::Value>,
-                    String,
-                )
-                    -> futures::future::BoxFuture<'static, Result<Server<ServerHttpTransport>>>
-                + Send
-                + Sync,
-        >,
-        sessions: Arc<Mutex<HashMap<String, ServerHttpTransport>>>,
+        build_server: BuildServerFn,
+        sessions: Arc<RwLock<HashMap<String, ServerHttpTransport>>>,
     ) -> Self {
         Self {
             sessions,
             build_server,
-            endpoint,
+            endpoint: EndpointConfig::default(),
+            limits: DecodeLimits::default(),
+            admission: Arc::new(Admission::default()),
+            auth: SharedAuth::default(),
+            ids: Arc::new(UuidGenerator),
+            clock: Arc::new(SystemClock),
+            idle_timeout: None,
+            slow_consumer: SlowConsumerPolicy::Unbounded,
+            slow_consumer_stats: Arc::new(SlowConsumerStats::default()),
+            subscriptions: Subscriptions::default(),
+        }
+    }
+
+    pub fn id_generator(mut self, ids: impl IdGenerator + 'static) -> Self {
+        self.ids = Arc::new(ids);
+        self
+    }
+
+    /// Clock of the sessions' activity times and of the idle timeout
+    pub fn clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Disconnect sessions that sent nothing for `timeout`, checked periodically by [`http_server`]
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// How to treat SSE clients that stop reading their event stream
+    /// `Unbounded` by default, nothing is dropped or disconnected
+    pub fn slow_consumer_policy(mut self, policy: SlowConsumerPolicy) -> Self {
+        self.slow_consumer = policy;
+        self
+    }
+
+    /// Sessions disconnected and notifications dropped by the slow consumer policy so far
+    pub fn slow_consumer_stats(&self) -> Arc<SlowConsumerStats> {
+        self.slow_consumer_stats.clone()
+    }
+
+    /// Drop a session's resource subscriptions when it is removed, pass the same registry
+    /// to [`ServerBuilder::subscriptions`](crate::server::ServerBuilder::subscriptions) when
+    /// building the sessions' servers
+    pub fn subscriptions(mut self, subscriptions: Subscriptions) -> Self {
+        self.subscriptions = subscriptions;
+        self
+    }
+
+    /// Share the auth config and connection limits of `handle`
+    pub fn reload_handle(mut self, handle: ReloadHandle) -> Self {
+        self.auth = handle.auth;
+        self.admission = handle.admission;
+        self
+    }
+
+    /// Session caps and message rate limit, requests over the limits get a 429
+    pub fn connection_limits(mut self, limits: ConnectionLimits) -> Self {
+        self.admission = Arc::new(Admission::new(limits));
+        self
+    }
+
+    /// How the message endpoint is advertised to SSE clients
+    pub fn endpoint(mut self, endpoint: EndpointConfig) -> Self {
+        self.endpoint = endpoint;
+        self
+    }
+
+    /// Requests rejected by the connection limits so far
+    pub fn rejections(&self) -> Rejections {
+        self.admission.rejections()
+    }
+
+    /// Limits for messages received from clients over SSE and WebSocket
+    pub fn decode_limits(mut self, limits: DecodeLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Sessions whose server is still running, oldest first
+    /// only takes the read side of the sessions lock
+    pub fn active_sessions(&self) -> Vec<SessionInfo> {
+        let mut sessions: Vec<_> = self
+            .sessions
+            .read()
+            .iter()
+            .map(|(id, transport)| SessionInfo {
+                id: id.clone(),
+                kind: match transport {
+                    ServerHttpTransport::Sse(_) => SessionKind::Sse,
+                    ServerHttpTransport::Ws(_) => SessionKind::Ws,
+                },
+                connected_at: transport.activity().connected_at(),
+                last_activity: transport.activity().last_activity(),
+            })
+            .collect();
+        sessions.sort_by(|a, b| (a.connected_at, &a.id).cmp(&(b.connected_at, &b.id)));
+        sessions
+    }
+
+    /// Send `notification` to every session, e.g. `notifications/resources/list_changed` or a
+    /// shutdown warning, returns how many sessions it reached. Sessions failing the send are
+    /// logged and skipped, the sessions lock isn't held while sending
+    pub async fn broadcast_notification(&self, notification: JsonRpcNotification) -> usize {
+        let sessions: Vec<_> = self
+            .sessions
+            .read()
+            .iter()
+            .map(|(id, transport)| (id.clone(), transport.clone()))
+            .collect();
+        let message = JsonRpcMessage::Notification(notification);
+        let sends = sessions.iter().map(|(session_id, transport)| {
+            let message = &message;
+            async move {
+                match transport.send(message).await {
+                    Ok(()) => true,
+                    Err(e) => {
+                        warn!("Failed to notify session {}: {}", session_id, e);
+                        false
+                    }
+                }
+            }
+        });
+        futures::future::join_all(sends)
+            .await
+            .into_iter()
+            .filter(|sent| *sent)
+            .count()
+    }
+
+    /// Disconnect and remove the sessions idle for longer than the idle timeout
+    /// returns their ids, sorted
+    pub fn reap_idle(&self) -> Vec<String> {
+        let Some(timeout) = self.idle_timeout else {
+            return Vec::new();
+        };
+        let idle: Vec<_> = self
+            .sessions
+            .read()
+            .iter()
+            .filter(|(_, transport)| transport.activity().idle_for() >= timeout)
+            .map(|(id, transport)| (id.clone(), transport.clone()))
+            .collect();
+        let mut reaped = Vec::with_capacity(idle.len());
+        for (session_id, transport) in idle {
+            debug!("Disconnecting idle session {}", session_id);
+            match transport {
+                ServerHttpTransport::Sse(sse) => sse.disconnect(),
+                ServerHttpTransport::Ws(ws) => {
+                    tokio::spawn(async move { ws.close_with(1001, "Session idle").await });
+                }
+            }
+            self.remove(&session_id);
+            reaped.push(session_id);
         }
+        reaped.sort();
+        reaped
+    }
+
+    /// Periodically disconnect idle sessions, `None` without an idle timeout
+    /// abort the task when the server stops
+    pub fn spawn_idle_reaper(&self) -> Option<tokio::task::JoinHandle<()>> {
+        let timeout = self.idle_timeout?;
+        let state = self.clone();
+        Some(tokio::spawn(async move {
+            let mut interval = tokio::time::interval(reap_interval(timeout));
+            loop {
+                interval.tick().await;
+                state.reap_idle();
+            }
+        }))
+    }
+
+    fn get(&self, session_id: &str) -> Option<ServerHttpTransport> {
+        self.sessions.read().get(session_id).cloned()
+    }
+
+    fn insert(
+        &self,
+        session_id: String,
+        transport: ServerHttpTransport,
+        token: Option<VerifiedToken>,
+    ) {
+        self.auth.pin(&session_id, token);
+        self.sessions.write().insert(session_id, transport);
+    }
+
+    fn remove(&self, session_id: &str) {
+        self.sessions.write().remove(session_id);
+        self.admission.release(session_id);
+        self.auth.unpin(session_id);
+        self.subscriptions.remove_session(session_id);
+    }
+}
+
+/// Twice per idle timeout, short timeouts are checked at most every 10ms
+fn reap_interval(timeout: Duration) -> Duration {
+    (timeout / 2).max(Duration::from_millis(10))
+}
+
+/// Removes a session once its SSE event stream or its server task is dropped
+/// also when the server panics, so the session's slot isn't leaked
+struct SessionGuard {
+    state: SessionState,
+    session_id: String,
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        if let Some(ServerHttpTransport::Sse(sse)) = self.state.get(&self.session_id) {
+            sse.disconnect();
+        }
+        self.state.remove(&self.session_id);
     }
 }
 
@@ -82,62 +510,258 @@ pub async fn run_http_server<F, Fut>(
     build_server: F,
 ) -> Result<()>
 where
-    F: Fn(ServerHttpTransport, Option<serde_json::Value>, String) -> Fut + Send + Sync + 'static,
+    F: Fn(ServerHttpTransport, ConnectionMetadata, String) -> Fut + Send + Sync + 'static,
     Fut: futures::Future<Output = Result<Server<ServerHttpTransport>>> + Send + 'static,
 {
-    info!("Starting server on http://0.0.0.0:{}", port);
-    info!("WebSocket endpoint: ws://0.0.0.0:{}/ws", port);
-    info!("SSE endpoint: http://0.0.0.0:{}/sse", port);
-
-    let sessions = Arc::new(Mutex::new(HashMap::new()));
+    let handle = ReloadHandle::new(jwt_secret.map(AuthConfig::new), ConnectionLimits::default());
+    run_http_server_with_reload(port, handle, build_server).await
+}
 
-    // Box the future when creating the Arc
-    let build_server = Arc::new(move |t, o, session_id| {
-        Box::pin(build_server(t, o, session_id)) as futures::future::BoxFuture<_>
-    });
+/// Like [`run_http_server`] with session ids from `ids`, e.g. a [`SessionIdGenerator`]
+pub async fn run_http_server_with_ids<F, Fut>(
+    port: u16,
+    jwt_secret: Option<String>,
+    ids: impl IdGenerator + 'static,
+    build_server: F,
+) -> Result<()>
+where
+    F: Fn(ServerHttpTransport, ConnectionMetadata, String) -> Fut + Send + Sync + 'static,
+    Fut: futures::Future<Output = Result<Server<ServerHttpTransport>>> + Send + 'static,
+{
+    let handle = ReloadHandle::new(jwt_secret.map(AuthConfig::new), ConnectionLimits::default());
+    let session_state = SessionState::from_fn(build_server)
+        .reload_handle(handle)
+        .id_generator(ids);
+    spawn_http_server(HttpServerOptions::new(port), session_state)?
+        .wait()
+        .await?;
+    Ok(())
+}
 
-    let auth_config = jwt_secret.map(|jwt_secret| AuthConfig { jwt_secret });
-    let http_server = http_server(port, sessions, auth_config, build_server);
+/// Like [`run_http_server`] without the WebSocket route, for servers that only need the transport
+pub async fn run_sse_server<F, Fut>(
+    port: u16,
+    jwt_secret: Option<String>,
+    build_server: F,
+) -> Result<()>
+where
+    F: Fn(ServerHttpTransport) -> Fut + Send + Sync + 'static,
+    Fut: futures::Future<Output = Result<Server<ServerHttpTransport>>> + Send + 'static,
+{
+    let handle = ReloadHandle::new(jwt_secret.map(AuthConfig::new), ConnectionLimits::default());
+    let options = HttpServerOptions::new(port).mcp(McpOptions::default().websocket(false));
+    start_http_server(options, handle, move |transport, _, _| {
+        build_server(transport)
+    })?
+    .wait()
+    .await?;
+    Ok(())
+}
 
-    http_server.await?;
+/// Like [`run_http_server`], keep a clone of `handle` to rotate secrets or change limits while it runs
+pub async fn run_http_server_with_reload<F, Fut>(
+    port: u16,
+    handle: ReloadHandle,
+    build_server: F,
+) -> Result<()>
+where
+    F: Fn(ServerHttpTransport, ConnectionMetadata, String) -> Fut + Send + Sync + 'static,
+    Fut: futures::Future<Output = Result<Server<ServerHttpTransport>>> + Send + 'static,
+{
+    start_http_server(HttpServerOptions::new(port), handle, build_server)?
+        .wait()
+        .await?;
     Ok(())
 }
 
+/// Like [`run_http_server_with_reload`] on the addresses of `options`, returns once they are bound
+pub fn start_http_server<F, Fut>(
+    options: HttpServerOptions,
+    handle: ReloadHandle,
+    build_server: F,
+) -> Result<HttpServerHandle>
+where
+    F: Fn(ServerHttpTransport, ConnectionMetadata, String) -> Fut + Send + Sync + 'static,
+    Fut: futures::Future<Output = Result<Server<ServerHttpTransport>>> + Send + 'static,
+{
+    let session_state = SessionState::from_fn(build_server).reload_handle(handle);
+    let server = spawn_http_server(options, session_state)?;
+    for addr in server.addrs() {
+        info!("Starting server on http://{}", addr);
+        info!("WebSocket endpoint: ws://{}/ws", addr);
+        info!("SSE endpoint: http://{}/sse", addr);
+    }
+    Ok(server)
+}
+
+/// Where [`spawn_http_server`] listens
+#[derive(Debug, Clone)]
+pub struct HttpServerOptions {
+    /// TCP addresses, port 0 picks a free port, see [`HttpServerHandle::addrs`]
+    pub addrs: Vec<SocketAddr>,
+    uds: Vec<std::path::PathBuf>,
+    pub mcp: McpOptions,
+}
+
+impl HttpServerOptions {
+    /// All IPv4 interfaces on `port`
+    pub fn new(port: u16) -> Self {
+        Self {
+            addrs: vec![SocketAddr::from(([0, 0, 0, 0], port))],
+            uds: Vec::new(),
+            mcp: McpOptions::default(),
+        }
+    }
+
+    /// Listen on `addrs` instead, e.g. `127.0.0.1:0` for a local-only server or `[::]:port` for IPv6
+    pub fn bind(mut self, addrs: impl IntoIterator<Item = SocketAddr>) -> Self {
+        self.addrs = addrs.into_iter().collect();
+        self
+    }
+
+    /// Also listen on a unix socket at `path`, for servers only reachable from the local machine
+    /// a socket file left behind by a stopped server is replaced, binding fails if one still listens
+    #[cfg(unix)]
+    pub fn bind_uds(mut self, path: impl Into<std::path::PathBuf>) -> Self {
+        self.uds.push(path.into());
+        self
+    }
+
+    pub fn mcp(mut self, mcp: McpOptions) -> Self {
+        self.mcp = mcp;
+        self
+    }
+}
+
+/// A server started with [`spawn_http_server`], it keeps running when the handle is dropped
+pub struct HttpServerHandle {
+    addrs: Vec<SocketAddr>,
+    server: actix_web::dev::ServerHandle,
+    task: tokio::task::JoinHandle<std::io::Result<()>>,
+}
+
+impl HttpServerHandle {
+    /// The bound TCP addresses, with the assigned port where port 0 was requested
+    pub fn addrs(&self) -> &[SocketAddr] {
+        &self.addrs
+    }
+
+    /// Stop accepting connections, `graceful` waits for in-flight requests
+    pub async fn stop(self, graceful: bool) -> std::io::Result<()> {
+        self.server.stop(graceful).await;
+        self.wait().await
+    }
+
+    /// Wait for the server to stop
+    pub async fn wait(self) -> std::io::Result<()> {
+        self.task.await.map_err(std::io::Error::other)?
+    }
+}
+
+/// Serve `session_state` on all interfaces, reaping idle sessions if it has an idle timeout
 pub async fn http_server(
     port: u16,
-    sessions: Arc<Mutex<HashMap<String, ServerHttpTransport>>>,
-    auth_config: Option<AuthConfig>,
-    build_server: Arc<
-        dyn Fn(
-                ServerHttpTransport,
-                Option<serde_json::Value>,
-                String,
-            )
-                -> futures::future::BoxFuture<'static, Result<Server<ServerHttpTransport>>>
-            + Send
-            + Sync,
-    >,
+    session_state: SessionState,
 ) -> std::result::Result<(), std::io::Error> {
-    let session_state = SessionState {
-        sessions,
-        build_server,
-        endpoint: format!("http://0.0.0.0:{}", port),
-    };
+    spawn_http_server(HttpServerOptions::new(port), session_state)?
+        .wait()
+        .await
+}
 
-    let server = HttpServer::new(move || {
+/// Bind the addresses of `options` and serve `session_state` on them in the background
+pub fn spawn_http_server(
+    options: HttpServerOptions,
+    session_state: SessionState,
+) -> std::io::Result<HttpServerHandle> {
+    if options.addrs.is_empty() && options.uds.is_empty() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "no address to bind",
+        ));
+    }
+    let reaper_state = session_state.clone();
+    let mcp = options.mcp.clone();
+    let mut server = HttpServer::new(move || {
         let session_state = session_state.clone();
         App::new()
             .wrap(Logger::default())
-            .wrap(JwtAuth::new(auth_config.clone()))
-            .app_data(web::Data::new(session_state))
-            .route("/sse", web::get().to(sse_handler))
-            .route("/message", web::post().to(message_handler))
-            .route("/ws", web::get().to(ws_handler))
+            .configure(|cfg| configure_mcp(cfg, &mcp, session_state))
+    });
+    if !options.addrs.is_empty() {
+        server = server.bind(&options.addrs[..])?;
+    }
+    // actix lists a placeholder address for unix sockets, take the TCP ones first
+    let addrs = server.addrs();
+    #[cfg(unix)]
+    for path in &options.uds {
+        remove_stale_socket(path)?;
+        server = server.bind_uds(path)?;
+    }
+    let server = server.run();
+    let handle = server.handle();
+
+    let reaper = reaper_state.spawn_idle_reaper();
+    let task = tokio::spawn(async move {
+        let result = server.await;
+        if let Some(reaper) = reaper {
+            reaper.abort();
+        }
+        result
+    });
+    Ok(HttpServerHandle {
+        addrs,
+        server: handle,
+        task,
     })
-    .bind(("0.0.0.0", port))?
-    .run();
+}
+
+/// Remove a socket file at `path` nobody listens on, fail if a server still does
+/// actix removes the file unconditionally, which would take the socket over from a live server
+#[cfg(unix)]
+fn remove_stale_socket(path: &std::path::Path) -> std::io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
 
-    server.await
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => {
+            match std::os::unix::net::UnixStream::connect(path) {
+                Ok(_) => Err(std::io::Error::new(
+                    std::io::ErrorKind::AddrInUse,
+                    format!("{} is in use", path.display()),
+                )),
+                Err(_) => std::fs::remove_file(path),
+            }
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Register the SSE, message and WebSocket routes under `options.prefix` on an existing `App`
+/// create `session_state` once outside the `HttpServer` factory and clone it into each worker,
+/// a message is posted to whichever worker accepts the request and must find its session there.
+/// An empty prefix matches every path, configure it after the application's own routes then.
+/// Idle sessions are only reaped with [`SessionState::spawn_idle_reaper`]
+pub fn configure_mcp(
+    cfg: &mut web::ServiceConfig,
+    options: &McpOptions,
+    session_state: SessionState,
+) {
+    let prefix = options.normalized_prefix();
+    let limits = session_state.limits;
+    let mut scope = web::scope(&prefix)
+        .wrap(Condition::new(
+            options.jwt_auth,
+            JwtAuth::shared(session_state.auth.clone()),
+        ))
+        // Let oversized bodies through to the decoder so they get a proper error
+        .app_data(web::PayloadConfig::new(limits.max_bytes.saturating_add(1)))
+        .app_data(MessagePath(format!("{}/message", prefix)))
+        .app_data(web::Data::new(session_state))
+        .route("/sse", web::get().to(sse_handler))
+        .route("/message", web::post().to(message_handler));
+    if options.websocket {
+        scope = scope.route("/ws", web::get().to(ws_handler));
+    }
+    cfg.service(scope);
 }
 
 pub async fn sse_handler(
@@ -145,7 +769,7 @@ pub async fn sse_handler(
     session_state: web::Data<SessionState>,
 ) -> HttpResponse {
     let endpoint = req.extensions().get::<Endpoint>().cloned();
-    let session_metadata = req.extensions().get::<serde_json::Value>().cloned();
+    let connection = connection_metadata(&req, "sse", session_state.endpoint.trust_proxy);
     let client_ip = req
         .peer_addr()
         .map(|addr| addr.ip().to_string())
@@ -154,65 +778,136 @@ pub async fn sse_handler(
     debug!("New SSE connection request from {}", client_ip);
 
     // Create new session
-    let session_id = Uuid::new_v4().to_string();
+    let session_id = session_state.ids.id_for(&connection);
+    let peer_ip = req.peer_addr().map(|addr| addr.ip());
+    match session_state.admission.admit(&session_id, peer_ip) {
+        Ok(()) => {}
+        Err(Refusal::InUse) => return session_id_in_use(&session_id),
+        Err(Refusal::Limited(rejection)) => {
+            debug!("Rejecting SSE session from {}: {:?}", client_ip, rejection);
+            return too_many_requests(rejection);
+        }
+    }
 
     // Create channel for SSE messages
-    let (sse_tx, sse_rx) = broadcast::channel(100);
+    let (sse_tx, mut sse_rx) = broadcast::channel(100);
 
     // Create new transport for this session
-    let transport = ServerHttpTransport::Sse(ServerSseTransport::new(sse_tx.clone()));
+    let sse = ServerSseTransport::new(sse_tx.clone())
+        .with_clock(session_state.clock.clone())
+        .slow_consumer_policy(
+            session_state.slow_consumer,
+            session_state.slow_consumer_stats.clone(),
+        );
+    let transport = ServerHttpTransport::Sse(sse.clone());
+
+    // Queue messages until the client reads them, the slow consumer policy bounds the queue
+    // instead of the broadcast channel lagging
+    let (queue_tx, queue_rx) = mpsc::unbounded_channel::<Arc<str>>();
+    let forwarder = sse.clone();
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = forwarder.closed() => break,
+                json = sse_rx.recv() => match json {
+                    Ok(json) => {
+                        if queue_tx.send(json).is_err() {
+                            break;
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        error!("SSE forwarder skipped {} messages", skipped);
+                        forwarder.skipped(skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+            }
+        }
+    });
 
     // Store transport in sessions map
-    session_state
-        .sessions
-        .lock()
-        .unwrap()
-        .insert(session_id.clone(), transport.clone());
+    let token = req.extensions().get::<VerifiedToken>().cloned();
+    session_state.insert(session_id.clone(), transport.clone(), token);
 
     debug!(
         "SSE connection established for {} with session_id {}",
         client_ip, session_id
     );
-    let endpoint = endpoint.map_or(session_state.endpoint.clone(), |e| e.0);
+    let endpoint = endpoint.map_or_else(|| session_state.endpoint.base(&req), |e| e.0);
+    let message_path = if endpoint.is_empty() {
+        // The message route sits next to the SSE route
+        "message"
+    } else {
+        req.app_data::<MessagePath>()
+            .map_or("/message", |path| path.0.as_str())
+    };
+    // Ids from a generator may hold anything, encoded they can't break the query or the event
+    let encoded_id: String = url::form_urlencoded::byte_serialize(session_id.as_bytes()).collect();
     // Create initial endpoint info event
     let endpoint_info =
-        format!("event: endpoint\ndata: {endpoint}/message?sessionId={session_id}\n\n",);
+        format!("event: endpoint\ndata: {endpoint}{message_path}?sessionId={encoded_id}\n\n",);
 
+    let guard = SessionGuard {
+        state: session_state.get_ref().clone(),
+        session_id: session_id.clone(),
+    };
     let stream = futures::stream::once(async move {
         Ok::<_, std::convert::Infallible>(web::Bytes::from(endpoint_info))
     })
-    .chain(futures::stream::unfold(sse_rx, move |mut rx| {
-        let client_ip = client_ip.clone();
-        async move {
-            match rx.recv().await {
-                Ok(msg) => {
-                    // Show first and last 500 characters for debugging
-                    let json = serde_json::to_string(&msg).unwrap();
-                    if json.len() > 1000 {
-                        let first = &json[..500];
-                        let last = &json[json.len() - 500..];
-                        debug!("Sending SSE message to {}: {}...{}", client_ip, first, last);
-                    } else {
-                        debug!("Sending SSE message to {}: {}", client_ip, json);
+    .chain(futures::stream::unfold(
+        (queue_rx, sse.clone()),
+        move |(mut rx, sse)| {
+            // Dropped with the stream when the client disconnects
+            let _guard = &guard;
+            let client_ip = client_ip.clone();
+            async move {
+                let json = tokio::select! {
+                    biased;
+                    _ = sse.closed() => None,
+                    json = rx.recv() => json,
+                };
+                match json {
+                    Some(json) => {
+                        sse.delivered();
+                        // Show first and last 500 characters for debugging
+                        if json.len() > 1000 {
+                            let first = &json[..500];
+                            let last = &json[json.len() - 500..];
+                            debug!("Sending SSE message to {}: {}...{}", client_ip, first, last);
+                        } else {
+                            debug!("Sending SSE message to {}: {}", client_ip, json);
+                        }
+                        let sse_data = format!("data: {}\n\n", json);
+                        Some((
+                            Ok::<_, std::convert::Infallible>(web::Bytes::from(sse_data)),
+                            (rx, sse),
+                        ))
                     }
-                    let sse_data = format!("data: {}\n\n", json);
-                    Some((
-                        Ok::<_, std::convert::Infallible>(web::Bytes::from(sse_data)),
-                        rx,
-                    ))
+                    None => None,
                 }
-                _ => None,
             }
-        }
-    }));
+        },
+    ));
+
+    if let SlowConsumerPolicy::DisconnectAfter(timeout) = session_state.slow_consumer {
+        spawn_slow_consumer_watchdog(
+            session_state.get_ref().clone(),
+            session_id.clone(),
+            sse,
+            timeout,
+        );
+    }
 
     // Create and start server instance for this session
     let transport_clone = transport.clone();
-    let build_server = session_state.build_server.clone();
-    let session_metadata = session_metadata.clone();
+    let state = session_state.clone();
     let ses_id = session_id.clone();
     tokio::spawn(async move {
-        match build_server(transport_clone, session_metadata, ses_id.clone()).await {
+        let _guard = SessionGuard {
+            state: state.get_ref().clone(),
+            session_id: ses_id.clone(),
+        };
+        match (state.build_server)(transport_clone, connection, ses_id.clone()).await {
             Ok(server) => {
                 if let Err(e) = server.listen().await {
                     error!("Server error: {:?}", e);
@@ -225,29 +920,69 @@ pub async fn sse_handler(
     });
 
     HttpResponse::Ok()
-        .append_header(("X-Session-Id", session_id))
+        .append_header(("X-Session-Id", encoded_id))
         .content_type("text/event-stream")
         .streaming(stream)
 }
 
+/// Disconnects the session once a message sent on its event stream went unread for `timeout`
+fn spawn_slow_consumer_watchdog(
+    state: SessionState,
+    session_id: String,
+    sse: ServerSseTransport,
+    timeout: Duration,
+) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(
+            (timeout / 4).clamp(Duration::from_millis(10), Duration::from_secs(1)),
+        );
+        loop {
+            tokio::select! {
+                _ = sse.closed() => break,
+                _ = interval.tick() => {}
+            }
+            if sse.stalled_for() >= timeout {
+                debug!("Disconnecting slow SSE session {}", session_id);
+                state.slow_consumer_stats.record_disconnect();
+                sse.disconnect();
+                state.remove(&session_id);
+                break;
+            }
+        }
+    });
+}
+
+/// Takes a single message or a JSON-RPC batch array, both decoded with the session's limits
+/// the responses to a batch go back on the event stream as one batch
 pub async fn message_handler(
     query: Query<MessageQuery>,
-    message: web::Json<Message>,
+    body: web::Bytes,
     session_state: web::Data<SessionState>,
 ) -> HttpResponse {
     if let Some(session_id) = &query.session_id {
-        let sessions = session_state.sessions.lock().unwrap();
-        if let Some(transport) = sessions.get(session_id) {
+        if let Some(transport) = session_state.get(session_id) {
+            if let Err(rejection) = session_state.admission.check_message(session_id) {
+                return too_many_requests(rejection);
+            }
+            let message = match std::str::from_utf8(&body)
+                .map_err(anyhow::Error::from)
+                .and_then(|text| session_state.limits.decode(text))
+            {
+                Ok(message) => message,
+                Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+            };
             match transport {
-                ServerHttpTransport::Sse(sse) => match sse.send_message(message.into_inner()).await
-                {
+                ServerHttpTransport::Sse(sse) => match sse.send_message(message).await {
                     Ok(_) => {
                         debug!("Successfully sent message to session {}", session_id);
                         HttpResponse::Accepted().finish()
                     }
                     Err(e) => {
                         error!("Failed to send message to session {}: {:?}", session_id, e);
-                        HttpResponse::InternalServerError().finish()
+                        HttpResponse::InternalServerError().json(JsonRpcError::new(
+                            ErrorCode::InternalError,
+                            format!("Session {} is not accepting messages", session_id),
+                        ))
                     }
                 },
                 ServerHttpTransport::Ws(_) => HttpResponse::BadRequest()
@@ -266,9 +1001,24 @@ pub async fn ws_handler(
     body: Payload,
     session_state: web::Data<SessionState>,
 ) -> Result<HttpResponse, actix_web::Error> {
-    let session_metadata = req.extensions().get::<serde_json::Value>().cloned();
+    let connection = connection_metadata(&req, "ws", session_state.endpoint.trust_proxy);
 
-    let (response, session, msg_stream) = actix_ws::handle(&req, body)?;
+    let session_id = session_state.ids.id_for(&connection);
+    match session_state
+        .admission
+        .admit(&session_id, req.peer_addr().map(|addr| addr.ip()))
+    {
+        Ok(()) => {}
+        Err(Refusal::InUse) => return Ok(session_id_in_use(&session_id)),
+        Err(Refusal::Limited(rejection)) => return Ok(too_many_requests(rejection)),
+    }
+    let (response, session, msg_stream) = match actix_ws::handle(&req, body) {
+        Ok(handshake) => handshake,
+        Err(e) => {
+            session_state.admission.release(&session_id);
+            return Err(e);
+        }
+    };
 
     let client_ip = req
         .peer_addr()
@@ -277,32 +1027,1313 @@ pub async fn ws_handler(
 
     info!("New WebSocket connection from {}", client_ip);
 
-    // Create channels for message passing
-    let (tx, rx) = broadcast::channel(100);
-    let transport =
-        ServerHttpTransport::Ws(ServerWsTransport::new(session.clone(), rx.resubscribe()));
+    let transport = ServerHttpTransport::Ws(ServerWsTransport::spawn(
+        session,
+        msg_stream,
+        session_state.limits,
+        session_state.clock.clone(),
+    ));
 
     // Store transport in sessions map
-    let session_id = Uuid::new_v4().to_string();
-    session_state
-        .sessions
-        .lock()
-        .unwrap()
-        .insert(session_id.clone(), transport.clone());
-
-    // Start WebSocket handling in the background
-    actix_web::rt::spawn(async move {
-        let _ = handle_ws_connection(session, msg_stream, tx.clone(), rx.resubscribe()).await;
-    });
+    let token = req.extensions().get::<VerifiedToken>().cloned();
+    session_state.insert(session_id.clone(), transport.clone(), token);
 
     // Spawn server instance
-    let build_server = session_state.build_server.clone();
-    let session_metadata = session_metadata.clone();
+    let state = session_state.get_ref().clone();
     actix_web::rt::spawn(async move {
-        if let Ok(server) = build_server(transport, session_metadata, session_id.clone()).await {
+        let _guard = SessionGuard {
+            state: state.clone(),
+            session_id: session_id.clone(),
+        };
+        if let Ok(server) = (state.build_server)(transport, connection, session_id.clone()).await {
             let _ = server.listen().await;
         }
     });
 
     Ok(response)
 }
+
+/// Peer of the request, with the claims the auth middleware verified
+fn connection_metadata(
+    req: &actix_web::HttpRequest,
+    transport: &'static str,
+    trust_proxy: bool,
+) -> ConnectionMetadata {
+    ConnectionMetadata {
+        transport,
+        remote_addr: remote_addr(req, trust_proxy),
+        user_agent: req
+            .headers()
+            .get(actix_web::http::header::USER_AGENT)
+            .and_then(|agent| agent.to_str().ok())
+            .map(String::from),
+        tls: req.app_config().secure(),
+        claims: req.extensions().get::<serde_json::Value>().cloned(),
+    }
+}
+
+/// The client a trusted proxy forwarded for, with port 0 when it only sent the address,
+/// otherwise and for obfuscated identifiers the peer
+fn remote_addr(req: &actix_web::HttpRequest, trust_proxy: bool) -> Option<SocketAddr> {
+    let forwarded = trust_proxy
+        .then(|| req.connection_info().realip_remote_addr().map(String::from))
+        .flatten();
+    let parsed = forwarded.and_then(|addr| {
+        addr.parse::<SocketAddr>().ok().or_else(|| {
+            let ip = addr.trim_start_matches('[').trim_end_matches(']');
+            ip.parse::<IpAddr>().ok().map(|ip| SocketAddr::new(ip, 0))
+        })
+    });
+    parsed.or_else(|| req.peer_addr())
+}
+
+fn session_id_in_use(session_id: &str) -> HttpResponse {
+    warn!("Session id {} is already in use", session_id);
+    HttpResponse::Conflict().body("Session id already in use")
+}
+
+/// 429 with `Retry-After` in whole seconds, at least one
+fn too_many_requests(rejection: Rejection) -> HttpResponse {
+    let retry_after = rejection.retry_after().as_secs_f64().ceil().max(1.0) as u64;
+    let message = match rejection {
+        Rejection::TooManySessions(_) => "Too many sessions",
+        Rejection::RateLimited(_) => "Too many messages",
+    };
+    HttpResponse::TooManyRequests()
+        .append_header(("Retry-After", retry_after.to_string()))
+        .body(message)
+}
+
+/// Lists the active sessions as JSON, not mounted by default
+/// session ids let a client post to the session, only mount it behind authentication
+pub async fn sessions_handler(session_state: web::Data<SessionState>) -> HttpResponse {
+    HttpResponse::Ok().json(session_state.active_sessions())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::{JsonRpcMessage, JsonRpcNotification};
+
+    #[tokio::test]
+    async fn test_active_sessions() -> Result<()> {
+        let build_server: BuildServerFn =
+            Arc::new(|_, _, _| Box::pin(async { Err(anyhow::anyhow!("unused")) }));
+        let state = SessionState::new(build_server, Arc::new(RwLock::new(HashMap::new())));
+
+        let sse = ServerSseTransport::new(broadcast::channel(1).0);
+        state.insert(
+            "sse".to_string(),
+            ServerHttpTransport::Sse(sse.clone()),
+            None,
+        );
+        let (_, ws_rx) = broadcast::channel(1);
+        let (ws_tx, _ws_outbound) = tokio::sync::mpsc::channel(1);
+        let ws = ServerWsTransport::new(ws_rx, ws_tx);
+        state.insert("ws".to_string(), ServerHttpTransport::Ws(ws), None);
+
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        sse.send_message(JsonRpcMessage::Notification(JsonRpcNotification::default()))
+            .await?;
+
+        let sessions = state.active_sessions();
+        assert_eq!(
+            sessions
+                .iter()
+                .map(|s| (s.id.as_str(), s.kind))
+                .collect::<Vec<_>>(),
+            vec![("sse", SessionKind::Sse), ("ws", SessionKind::Ws)]
+        );
+        assert!(sessions[0].last_activity > sessions[0].connected_at);
+        assert_eq!(sessions[1].last_activity, sessions[1].connected_at);
+        let json = serde_json::to_value(&sessions[1])?;
+        assert_eq!(json["kind"], "ws");
+        assert!(json["connectedAt"].is_u64());
+
+        state.remove("sse");
+        assert_eq!(state.active_sessions().len(), 1);
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_connection_limits() {
+        use actix_web::test;
+
+        let build_server: BuildServerFn =
+            Arc::new(|t, _, _| Box::pin(async move { Ok(Server::builder(t).build()) }));
+        let state = SessionState::new(build_server, Arc::new(RwLock::new(HashMap::new())))
+            .connection_limits(
+                ConnectionLimits::default()
+                    .max_sessions(3)
+                    .max_sessions_per_ip(2)
+                    .message_rate(1.0, 2)
+                    .session_retry_after(std::time::Duration::from_secs(5)),
+            );
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(state.clone()))
+                .route("/sse", web::get().to(sse_handler))
+                .route("/message", web::post().to(message_handler)),
+        )
+        .await;
+        let connect = |ip: &str| {
+            test::TestRequest::get()
+                .uri("/sse")
+                .peer_addr(ip.parse().unwrap())
+                .to_request()
+        };
+        let post = |session: &str| {
+            test::TestRequest::post()
+                .uri(&format!("/message?sessionId={}", session))
+                .set_payload(r#"{"jsonrpc":"2.0","method":"notifications/initialized"}"#)
+                .to_request()
+        };
+        let session_id = |response: &actix_web::dev::ServiceResponse| {
+            response
+                .headers()
+                .get("X-Session-Id")
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_string()
+        };
+
+        let first = test::call_service(&app, connect("10.0.0.1:1000")).await;
+        assert_eq!(first.status(), 200);
+        let second = test::call_service(&app, connect("10.0.0.1:1001")).await;
+        assert_eq!(second.status(), 200);
+        // Per-IP cap
+        let rejected = test::call_service(&app, connect("10.0.0.1:1002")).await;
+        assert_eq!(rejected.status(), 429);
+        assert_eq!(rejected.headers().get("Retry-After").unwrap(), "5");
+        let other = test::call_service(&app, connect("10.0.0.2:1000")).await;
+        assert_eq!(other.status(), 200);
+        // Global cap
+        let rejected = test::call_service(&app, connect("10.0.0.3:1000")).await;
+        assert_eq!(rejected.status(), 429);
+
+        // Message rate, the burst goes through, then one per second
+        let first_id = session_id(&first);
+        assert_eq!(
+            test::call_service(&app, post(&first_id)).await.status(),
+            202
+        );
+        assert_eq!(
+            test::call_service(&app, post(&first_id)).await.status(),
+            202
+        );
+        let limited = test::call_service(&app, post(&first_id)).await;
+        assert_eq!(limited.status(), 429);
+        assert_eq!(limited.headers().get("Retry-After").unwrap(), "1");
+        // Other sessions are unaffected
+        let other_id = session_id(&other);
+        assert_eq!(
+            test::call_service(&app, post(&other_id)).await.status(),
+            202
+        );
+
+        assert_eq!(
+            state.rejections(),
+            Rejections {
+                sessions: 2,
+                messages: 1
+            }
+        );
+
+        // A disconnected client frees its slot
+        drop(first);
+        assert_eq!(state.active_sessions().len(), 2);
+        let reconnected = test::call_service(&app, connect("10.0.0.1:1003")).await;
+        assert_eq!(reconnected.status(), 200);
+    }
+
+    #[actix_web::test]
+    async fn test_advertised_endpoint() {
+        use actix_web::body::MessageBody;
+        use actix_web::test;
+
+        async fn advertised(endpoint: EndpointConfig, headers: &[(&str, &str)]) -> String {
+            let build_server: BuildServerFn =
+                Arc::new(|t, _, _| Box::pin(async move { Ok(Server::builder(t).build()) }));
+            let state = SessionState::new(build_server, Arc::new(RwLock::new(HashMap::new())))
+                .endpoint(endpoint);
+            let app = test::init_service(
+                App::new()
+                    .app_data(web::Data::new(state))
+                    .route("/sse", web::get().to(sse_handler)),
+            )
+            .await;
+            let mut request = test::TestRequest::get()
+                .uri("/sse")
+                .insert_header(("Host", "10.0.0.5:3004"));
+            for header in headers {
+                request = request.insert_header(*header);
+            }
+            let response = test::call_service(&app, request.to_request()).await;
+            let session_id = response.headers().get("X-Session-Id").unwrap().to_owned();
+            let mut body = std::pin::pin!(response.into_body());
+            let chunk = std::future::poll_fn(|cx| body.as_mut().poll_next(cx))
+                .await
+                .unwrap()
+                .unwrap();
+            let event = std::str::from_utf8(&chunk).unwrap();
+            let data = event
+                .strip_prefix("event: endpoint\ndata: ")
+                .and_then(|data| data.strip_suffix("\n\n"))
+                .unwrap();
+            data.replace(session_id.to_str().unwrap(), "{id}")
+        }
+
+        let proxied = [
+            ("X-Forwarded-Proto", "https"),
+            ("X-Forwarded-Host", "mcp.example.com"),
+        ];
+        // Relative unless configured, forwarded headers are ignored by default
+        assert_eq!(
+            advertised(EndpointConfig::default(), &proxied).await,
+            "message?sessionId={id}"
+        );
+        assert_eq!(
+            advertised(EndpointConfig::default().trust_proxy(true), &proxied).await,
+            "https://mcp.example.com/message?sessionId={id}"
+        );
+        assert_eq!(
+            advertised(
+                EndpointConfig::default().trust_proxy(true),
+                &[("Forwarded", "for=1.2.3.4;proto=https;host=edge.example.com")]
+            )
+            .await,
+            "https://edge.example.com/message?sessionId={id}"
+        );
+        assert_eq!(
+            advertised(EndpointConfig::default().trust_proxy(true), &[]).await,
+            "message?sessionId={id}"
+        );
+        assert_eq!(
+            advertised(
+                EndpointConfig::default()
+                    .trust_proxy(true)
+                    .public_base_url("https://api.example.com/mcp/"),
+                &proxied
+            )
+            .await,
+            "https://api.example.com/mcp/message?sessionId={id}"
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_batch_over_message_endpoint() {
+        use actix_web::body::MessageBody;
+        use actix_web::test;
+
+        let build_server: BuildServerFn =
+            Arc::new(|t, _, _| Box::pin(async move { Ok(Server::builder(t).build()) }));
+        let state = SessionState::new(build_server, Arc::new(RwLock::new(HashMap::new())));
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(state))
+                .route("/sse", web::get().to(sse_handler))
+                .route("/message", web::post().to(message_handler)),
+        )
+        .await;
+        let sse = test::call_service(&app, test::TestRequest::get().uri("/sse").to_request()).await;
+        let session_id = sse
+            .headers()
+            .get("X-Session-Id")
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        let batch = r#"[
+            {"jsonrpc":"2.0","id":1,"method":"ping"},
+            {"jsonrpc":"2.0","method":"notifications/initialized"},
+            {"jsonrpc":"2.0","id":2,"method":"tools/list","params":{}}
+        ]"#;
+        let post = test::TestRequest::post()
+            .uri(&format!("/message?sessionId={}", session_id))
+            .set_payload(batch)
+            .to_request();
+        assert_eq!(test::call_service(&app, post).await.status(), 202);
+
+        let mut body = std::pin::pin!(sse.into_body());
+        let mut events = String::new();
+        let response = loop {
+            let chunk = std::future::poll_fn(|cx| body.as_mut().poll_next(cx))
+                .await
+                .unwrap()
+                .unwrap();
+            events.push_str(std::str::from_utf8(&chunk).unwrap());
+            if let Some(data) = events
+                .split("\n\n")
+                .filter_map(|event| event.strip_prefix("data: "))
+                .find(|data| data.starts_with('['))
+            {
+                break serde_json::from_str::<serde_json::Value>(data).unwrap();
+            }
+        };
+        let ids: Vec<_> = response
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|message| message["id"].clone())
+            .collect();
+        assert_eq!(ids, vec![serde_json::json!(1), serde_json::json!(2)]);
+    }
+
+    #[actix_web::test]
+    async fn test_secret_rotation() {
+        use actix_web::test;
+        use jsonwebtoken::{encode, EncodingKey, Header};
+
+        let token = |secret: &str| {
+            let now = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_secs() as usize;
+            let claims = Claims {
+                iat: now,
+                exp: now + 3600,
+            };
+            let key = EncodingKey::from_secret(secret.as_bytes());
+            format!(
+                "Bearer {}",
+                encode(&Header::default(), &claims, &key).unwrap()
+            )
+        };
+        let build_server: BuildServerFn =
+            Arc::new(|t, _, _| Box::pin(async move { Ok(Server::builder(t).build()) }));
+        let handle = ReloadHandle::new(Some(AuthConfig::new("old")), ConnectionLimits::default());
+        let state = SessionState::new(build_server, Arc::new(RwLock::new(HashMap::new())))
+            .reload_handle(handle.clone());
+        let app = test::init_service(
+            App::new()
+                .wrap(JwtAuth::shared(state.auth.clone()))
+                .app_data(web::Data::new(state.clone()))
+                .route("/sse", web::get().to(sse_handler))
+                .route("/message", web::post().to(message_handler)),
+        )
+        .await;
+        let connect = |secret: &str| {
+            test::TestRequest::get()
+                .uri("/sse")
+                .insert_header(("Authorization", token(secret)))
+                .to_request()
+        };
+        let post = |session_id: &str, secret: &str| {
+            test::TestRequest::post()
+                .uri(&format!("/message?sessionId={}", session_id))
+                .insert_header(("Authorization", token(secret)))
+                .set_payload(r#"{"jsonrpc":"2.0","method":"notifications/initialized"}"#)
+                .to_request()
+        };
+
+        let session_id = |response: &actix_web::dev::ServiceResponse<_>| {
+            response
+                .headers()
+                .get("X-Session-Id")
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_string()
+        };
+
+        let old = token("old");
+        let established = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri("/sse")
+                .insert_header(("Authorization", old.clone()))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(established.status(), 200);
+        let established = session_id(&established);
+        let post_old = |session_id: &str, token: &str| {
+            test::TestRequest::post()
+                .uri(&format!("/message?sessionId={}", session_id))
+                .insert_header(("Authorization", token.to_string()))
+                .set_payload(r#"{"jsonrpc":"2.0","method":"notifications/initialized"}"#)
+                .to_request()
+        };
+
+        handle.set_auth(Some(AuthConfig::new("new")));
+        assert_eq!(test::call_service(&app, connect("old")).await.status(), 401);
+        // A live session id doesn't let the old token open a session
+        let reconnect = test::TestRequest::get()
+            .uri(&format!("/sse?sessionId={}", established))
+            .insert_header(("Authorization", old.clone()))
+            .to_request();
+        assert_eq!(test::call_service(&app, reconnect).await.status(), 401);
+        let rotated = test::call_service(&app, connect("new")).await;
+        assert_eq!(rotated.status(), 200);
+        let rotated = session_id(&rotated);
+        // The established session still accepts its token, newer sessions don't
+        assert_eq!(
+            test::call_service(&app, post_old(&established, &old))
+                .await
+                .status(),
+            202
+        );
+        // Nor does it accept other tokens signed with the retired secret
+        let forged = {
+            let now = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_secs() as usize;
+            let claims = Claims {
+                iat: now - 10,
+                exp: now + 7200,
+            };
+            let key = jsonwebtoken::EncodingKey::from_secret(b"old");
+            format!(
+                "Bearer {}",
+                encode(&Header::default(), &claims, &key).unwrap()
+            )
+        };
+        assert_eq!(
+            test::call_service(&app, post_old(&established, &forged))
+                .await
+                .status(),
+            401
+        );
+        assert_eq!(
+            test::call_service(&app, post(&rotated, "old"))
+                .await
+                .status(),
+            401
+        );
+
+        // Both secrets are accepted during a rotation window
+        handle.set_auth(Some(AuthConfig::new("newer").additional_secret("new")));
+        assert_eq!(test::call_service(&app, connect("new")).await.status(), 200);
+        assert_eq!(
+            test::call_service(&app, connect("newer")).await.status(),
+            200
+        );
+        assert_eq!(test::call_service(&app, connect("old")).await.status(), 401);
+
+        // Limits swap in place too
+        handle.set_connection_limits(ConnectionLimits::default().max_sessions(1));
+        assert_eq!(
+            test::call_service(&app, connect("newer")).await.status(),
+            429
+        );
+        assert_eq!(handle.rejections().sessions, 1);
+        assert_eq!(
+            test::call_service(&app, post_old(&established, &old))
+                .await
+                .status(),
+            202
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_deterministic_sessions() {
+        use crate::sse::testing::{FixedIdGenerator, ManualClock};
+        use actix_web::body::MessageBody;
+        use actix_web::test;
+
+        let clock = ManualClock::new(SystemTime::UNIX_EPOCH + Duration::from_secs(1_000));
+        let build_server: BuildServerFn =
+            Arc::new(|t, _, _| Box::pin(async move { Ok(Server::builder(t).build()) }));
+        let state = SessionState::new(build_server, Arc::new(RwLock::new(HashMap::new())))
+            .id_generator(FixedIdGenerator::new(vec!["first", "second"]))
+            .clock(clock.clone())
+            .idle_timeout(Duration::from_secs(60));
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(state.clone()))
+                .route("/sse", web::get().to(sse_handler))
+                .route("/message", web::post().to(message_handler)),
+        )
+        .await;
+        let connect = || test::TestRequest::get().uri("/sse").to_request();
+        let post = |session: &str| {
+            test::TestRequest::post()
+                .uri(&format!("/message?sessionId={}", session))
+                .set_payload(r#"{"jsonrpc":"2.0","method":"notifications/initialized"}"#)
+                .to_request()
+        };
+
+        let first = test::call_service(&app, connect()).await;
+        let _second = test::call_service(&app, connect()).await;
+        let mut body = std::pin::pin!(first.into_body());
+        let chunk = std::future::poll_fn(|cx| body.as_mut().poll_next(cx))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            std::str::from_utf8(&chunk).unwrap(),
+            "event: endpoint\ndata: message?sessionId=first\n\n"
+        );
+        let sessions = state.active_sessions();
+        assert_eq!(sessions[0].id, "first");
+        assert_eq!(sessions[1].id, "second");
+        assert_eq!(
+            serde_json::to_value(&sessions[0]).unwrap()["connectedAt"],
+            1_000_000
+        );
+
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(test::call_service(&app, post("second")).await.status(), 202);
+        assert!(state.reap_idle().is_empty());
+
+        // `first` has been idle for 70s, `second` for 40s
+        clock.advance(Duration::from_secs(40));
+        assert_eq!(state.reap_idle(), vec!["first".to_string()]);
+        assert_eq!(
+            state
+                .active_sessions()
+                .iter()
+                .map(|s| s.id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["second"]
+        );
+        assert_eq!(test::call_service(&app, post("first")).await.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_notification() {
+        let (live_tx, mut live_rx) = broadcast::channel(10);
+        // Nobody reads the other session's events, sending to it fails
+        let (gone_tx, _) = broadcast::channel(10);
+        let sessions = HashMap::from([
+            (
+                "live".to_string(),
+                ServerHttpTransport::Sse(ServerSseTransport::new(live_tx)),
+            ),
+            (
+                "gone".to_string(),
+                ServerHttpTransport::Sse(ServerSseTransport::new(gone_tx)),
+            ),
+        ]);
+        let build_server: BuildServerFn =
+            Arc::new(|t, _, _| Box::pin(async move { Ok(Server::builder(t).build()) }));
+        let state = SessionState::new(build_server, Arc::new(RwLock::new(sessions)));
+
+        let sent = state
+            .broadcast_notification(JsonRpcNotification {
+                method: "notifications/resources/list_changed".to_string(),
+                ..Default::default()
+            })
+            .await;
+        assert_eq!(sent, 1);
+        let event: serde_json::Value =
+            serde_json::from_str(&live_rx.recv().await.unwrap()).unwrap();
+        assert_eq!(event["method"], "notifications/resources/list_changed");
+    }
+
+    #[actix_web::test]
+    async fn test_session_ids_from_connection() {
+        use actix_web::body::MessageBody;
+        use actix_web::test;
+
+        let build_server: BuildServerFn =
+            Arc::new(|t, _, _| Box::pin(async move { Ok(Server::builder(t).build()) }));
+        let state = SessionState::new(build_server, Arc::new(RwLock::new(HashMap::new())))
+            .id_generator(SessionIdGenerator(|connection: &ConnectionMetadata| {
+                format!(
+                    "{}-1",
+                    connection.user_agent.as_deref().unwrap_or("anonymous")
+                )
+            }));
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(state.clone()))
+                .route("/sse", web::get().to(sse_handler)),
+        )
+        .await;
+        let connect = || {
+            test::TestRequest::get()
+                .uri("/sse")
+                .insert_header(("User-Agent", "alice"))
+                .to_request()
+        };
+
+        let response = test::call_service(&app, connect()).await;
+        let mut body = std::pin::pin!(response.into_body());
+        let chunk = std::future::poll_fn(|cx| body.as_mut().poll_next(cx))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            std::str::from_utf8(&chunk).unwrap(),
+            "event: endpoint\ndata: message?sessionId=alice-1\n\n"
+        );
+        assert_eq!(state.active_sessions()[0].id, "alice-1");
+
+        // The same id again would take over alice's session
+        assert_eq!(test::call_service(&app, connect()).await.status(), 409);
+        assert_eq!(state.active_sessions().len(), 1);
+
+        // Characters with a meaning in queries or events are encoded
+        let response = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri("/sse")
+                .insert_header(("User-Agent", "bob+co &x#y"))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(
+            response.headers().get("X-Session-Id").unwrap(),
+            "bob%2Bco+%26x%23y-1"
+        );
+        let mut body = std::pin::pin!(response.into_body());
+        let chunk = std::future::poll_fn(|cx| body.as_mut().poll_next(cx))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            std::str::from_utf8(&chunk).unwrap(),
+            "event: endpoint\ndata: message?sessionId=bob%2Bco+%26x%23y-1\n\n"
+        );
+        let query: Query<MessageQuery> =
+            Query::from_query("sessionId=bob%2Bco+%26x%23y-1").unwrap();
+        assert_eq!(query.session_id.as_deref(), Some("bob+co &x#y-1"));
+    }
+
+    #[actix_web::test]
+    async fn test_panicking_session_is_isolated() {
+        use crate::types::{CallToolResponse, Tool};
+        use actix_web::body::MessageBody;
+        use actix_web::test;
+
+        let build_server: BuildServerFn = Arc::new(|t, _, _| {
+            Box::pin(async move {
+                let mut builder = Server::builder(t);
+                builder.register_tool(
+                    Tool {
+                        name: "boom".to_string(),
+                        description: None,
+                        input_schema: serde_json::json!({"type": "object"}),
+                        output_schema: None,
+                    },
+                    |_| -> std::pin::Pin<
+                        Box<dyn futures::Future<Output = Result<CallToolResponse>> + Send>,
+                    > { panic!("tool panicked") },
+                );
+                Ok(builder.build())
+            })
+        });
+        let state = SessionState::new(build_server, Arc::new(RwLock::new(HashMap::new())));
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(state.clone()))
+                .route("/sse", web::get().to(sse_handler))
+                .route("/message", web::post().to(message_handler)),
+        )
+        .await;
+        let connect = || test::TestRequest::get().uri("/sse").to_request();
+        let post = |session: &str, body: &'static str| {
+            test::TestRequest::post()
+                .uri(&format!("/message?sessionId={}", session))
+                .set_payload(body)
+                .to_request()
+        };
+        let session_id = |response: &actix_web::dev::ServiceResponse| {
+            response
+                .headers()
+                .get("X-Session-Id")
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_string()
+        };
+
+        let crashing = test::call_service(&app, connect()).await;
+        let crashing_id = session_id(&crashing);
+        let healthy = test::call_service(&app, connect()).await;
+        let healthy_id = session_id(&healthy);
+
+        let call = r#"{"jsonrpc":"2.0","id":1,"method":"tools/call","params":{"name":"boom"}}"#;
+        assert_eq!(
+            test::call_service(&app, post(&crashing_id, call))
+                .await
+                .status(),
+            202
+        );
+        // The panicking session is cleaned up, the other one is still listed
+        for _ in 0..50 {
+            if state.active_sessions().len() == 1 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(
+            state
+                .active_sessions()
+                .iter()
+                .map(|s| s.id.clone())
+                .collect::<Vec<_>>(),
+            vec![healthy_id.clone()]
+        );
+        assert_eq!(
+            test::call_service(&app, post(&crashing_id, call))
+                .await
+                .status(),
+            404
+        );
+
+        let list = r#"{"jsonrpc":"2.0","id":2,"method":"tools/list","params":{}}"#;
+        assert_eq!(
+            test::call_service(&app, post(&healthy_id, list))
+                .await
+                .status(),
+            202
+        );
+        let mut body = std::pin::pin!(healthy.into_body());
+        let mut events = String::new();
+        while !events.contains(r#""id":2"#) {
+            let chunk = std::future::poll_fn(|cx| body.as_mut().poll_next(cx))
+                .await
+                .unwrap()
+                .unwrap();
+            events.push_str(std::str::from_utf8(&chunk).unwrap());
+        }
+        assert!(events.contains("boom"));
+
+        // New sessions are still accepted
+        assert_eq!(test::call_service(&app, connect()).await.status(), 200);
+    }
+
+    #[actix_web::test]
+    async fn test_sse_only() {
+        use actix_web::test;
+
+        let state = SessionState::from_fn(|t, _, _| async move { Ok(Server::builder(t).build()) });
+        let options = McpOptions::default().websocket(false);
+        let app = test::init_service(
+            App::new().configure(|cfg| configure_mcp(cfg, &options, state.clone())),
+        )
+        .await;
+
+        let ws = test::TestRequest::get()
+            .uri("/ws")
+            .insert_header(("upgrade", "websocket"))
+            .insert_header(("connection", "upgrade"))
+            .insert_header(("sec-websocket-version", "13"))
+            .insert_header(("sec-websocket-key", "dGhlIHNhbXBsZSBub25jZQ=="))
+            .to_request();
+        assert_eq!(test::call_service(&app, ws).await.status(), 404);
+        let sse = test::call_service(&app, test::TestRequest::get().uri("/sse").to_request()).await;
+        assert_eq!(sse.status(), 200);
+        assert_eq!(state.active_sessions().len(), 1);
+    }
+
+    #[actix_web::test]
+    async fn test_mounted_under_prefix() {
+        use crate::types::{CallToolResponse, Tool};
+        use actix_web::body::MessageBody;
+        use actix_web::test;
+        use jsonwebtoken::{encode, EncodingKey, Header};
+
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as usize;
+        let claims = Claims {
+            iat: now,
+            exp: now + 3600,
+        };
+        let token = encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(b"secret"),
+        )
+        .unwrap();
+        let bearer = format!("Bearer {}", token);
+
+        let state = SessionState::from_fn(|t, _, _| async move {
+            let mut builder = Server::builder(t);
+            builder.register_tool(
+                Tool {
+                    name: "echo".to_string(),
+                    description: None,
+                    input_schema: serde_json::json!({"type": "object"}),
+                    output_schema: None,
+                },
+                |req| Box::pin(async move { Ok(CallToolResponse::json(req.arguments)) }),
+            );
+            Ok(builder.build())
+        })
+        .reload_handle(ReloadHandle::new(
+            Some(AuthConfig::new("secret")),
+            ConnectionLimits::default(),
+        ));
+        let options = McpOptions::default().prefix("/mcp/");
+        let app = test::init_service(
+            App::new()
+                .route("/healthz", web::get().to(HttpResponse::Ok))
+                .configure(|cfg| configure_mcp(cfg, &options, state.clone())),
+        )
+        .await;
+
+        // Auth only applies to the mounted routes
+        let healthz = test::TestRequest::get().uri("/healthz").to_request();
+        assert_eq!(test::call_service(&app, healthz).await.status(), 200);
+        let anonymous = test::TestRequest::get().uri("/mcp/sse").to_request();
+        assert_eq!(test::call_service(&app, anonymous).await.status(), 401);
+        let unmounted = test::TestRequest::get()
+            .uri("/sse")
+            .insert_header(("Authorization", bearer.clone()))
+            .to_request();
+        assert_eq!(test::call_service(&app, unmounted).await.status(), 404);
+
+        let sse = test::TestRequest::get()
+            .uri("/mcp/sse")
+            .insert_header(("Authorization", bearer.clone()))
+            .to_request();
+        let sse = test::call_service(&app, sse).await;
+        assert_eq!(sse.status(), 200);
+        let mut body = std::pin::pin!(sse.into_body());
+        let mut events = String::new();
+        while !events.ends_with("\n\n") {
+            let chunk = std::future::poll_fn(|cx| body.as_mut().poll_next(cx))
+                .await
+                .unwrap()
+                .unwrap();
+            events.push_str(std::str::from_utf8(&chunk).unwrap());
+        }
+        // Resolved against the event stream, the advertised endpoint keeps the prefix
+        let endpoint = events
+            .strip_prefix("event: endpoint\ndata: ")
+            .and_then(|event| event.strip_suffix("\n\n"))
+            .unwrap();
+        let endpoint = url::Url::parse("http://localhost/mcp/sse")
+            .unwrap()
+            .join(endpoint)
+            .unwrap();
+        assert_eq!(endpoint.path(), "/mcp/message");
+        let endpoint = format!("{}?{}", endpoint.path(), endpoint.query().unwrap());
+
+        let call = r#"{"jsonrpc":"2.0","id":1,"method":"tools/call","params":{"name":"echo","arguments":{"hello":"world"}}}"#;
+        let post = test::TestRequest::post()
+            .uri(&endpoint)
+            .insert_header(("Authorization", bearer))
+            .set_payload(call)
+            .to_request();
+        assert_eq!(test::call_service(&app, post).await.status(), 202);
+        while !events.contains(r#""id":1"#) {
+            let chunk = std::future::poll_fn(|cx| body.as_mut().poll_next(cx))
+                .await
+                .unwrap()
+                .unwrap();
+            events.push_str(std::str::from_utf8(&chunk).unwrap());
+        }
+        let response: serde_json::Value = serde_json::from_str(
+            events
+                .split("\n\n")
+                .find_map(|event| event.strip_prefix("data: "))
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            response["result"]["content"][0]["text"],
+            r#"{"hello":"world"}"#
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_connection_metadata() {
+        use actix_web::test;
+        use jsonwebtoken::{encode, EncodingKey, Header};
+
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let claims =
+            serde_json::json!({"iat": now, "exp": now + 3600, "sub": "ada", "role": "admin"});
+        let token = encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(b"secret"),
+        )
+        .unwrap();
+
+        let (metadata_tx, mut metadata_rx) = tokio::sync::mpsc::unbounded_channel();
+        let state = SessionState::from_fn(move |t, connection: ConnectionMetadata, _| {
+            let _ = metadata_tx.send(connection.clone());
+            async move {
+                Ok(Server::builder(t)
+                    .session_metadata(connection.claims.clone())
+                    .connection(connection)
+                    .build())
+            }
+        })
+        .reload_handle(ReloadHandle::new(
+            Some(AuthConfig::new("secret")),
+            ConnectionLimits::default(),
+        ));
+        let app = test::init_service(
+            App::new().configure(|cfg| configure_mcp(cfg, &McpOptions::default(), state)),
+        )
+        .await;
+        let sse = test::TestRequest::get()
+            .uri("/sse")
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .insert_header(("User-Agent", "inspector/1.0"))
+            .peer_addr("10.0.0.7:52100".parse().unwrap())
+            .to_request();
+        assert_eq!(test::call_service(&app, sse).await.status(), 200);
+        let connection = metadata_rx.recv().await.unwrap();
+        assert_eq!(
+            connection,
+            ConnectionMetadata {
+                transport: "sse",
+                remote_addr: Some("10.0.0.7:52100".parse().unwrap()),
+                user_agent: Some("inspector/1.0".to_string()),
+                tls: false,
+                claims: Some(claims),
+            }
+        );
+        assert_eq!(connection.auth_subject(), Some("ada"));
+    }
+
+    #[test]
+    fn test_remote_addr_behind_proxy() {
+        use actix_web::test::TestRequest;
+
+        let peer: SocketAddr = "10.0.0.7:52100".parse().unwrap();
+        let request = |header: (&'static str, &'static str)| {
+            TestRequest::get()
+                .peer_addr(peer)
+                .insert_header(header)
+                .to_http_request()
+        };
+        let forwarded_for = request(("X-Forwarded-For", "203.0.113.9, 10.0.0.1"));
+        assert_eq!(remote_addr(&forwarded_for, false), Some(peer));
+        assert_eq!(
+            remote_addr(&forwarded_for, true),
+            Some("203.0.113.9:0".parse().unwrap())
+        );
+        // actix leaves the port out of `Forwarded`
+        let forwarded = request(("Forwarded", r#"for="[2001:db8::1]:4711""#));
+        assert_eq!(
+            remote_addr(&forwarded, true),
+            Some("[2001:db8::1]:0".parse().unwrap())
+        );
+        // Obfuscated identifiers say nothing about the address
+        let hidden = request(("Forwarded", "for=_hidden"));
+        assert_eq!(remote_addr(&hidden, true), Some(peer));
+    }
+
+    #[actix_web::test]
+    async fn test_paused_client_is_disconnected() {
+        use actix_web::body::MessageBody;
+        use actix_web::test;
+
+        let build_server: BuildServerFn =
+            Arc::new(|t, _, _| Box::pin(async move { Ok(Server::builder(t).build()) }));
+        let state = SessionState::new(build_server, Arc::new(RwLock::new(HashMap::new())))
+            .slow_consumer_policy(SlowConsumerPolicy::DisconnectAfter(Duration::from_millis(
+                100,
+            )));
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(state.clone()))
+                .route("/sse", web::get().to(sse_handler))
+                .route("/message", web::post().to(message_handler)),
+        )
+        .await;
+        let session_id = |response: &actix_web::dev::ServiceResponse| {
+            response
+                .headers()
+                .get("X-Session-Id")
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_string()
+        };
+        let ping = |session: &str| {
+            test::TestRequest::post()
+                .uri(&format!("/message?sessionId={}", session))
+                .set_payload(r#"{"jsonrpc":"2.0","id":1,"method":"ping"}"#)
+                .to_request()
+        };
+
+        let paused =
+            test::call_service(&app, test::TestRequest::get().uri("/sse").to_request()).await;
+        let paused_id = session_id(&paused);
+        let reading =
+            test::call_service(&app, test::TestRequest::get().uri("/sse").to_request()).await;
+        let reading_id = session_id(&reading);
+        assert_eq!(
+            test::call_service(&app, ping(&paused_id)).await.status(),
+            202
+        );
+        assert_eq!(
+            test::call_service(&app, ping(&reading_id)).await.status(),
+            202
+        );
+
+        let mut body = std::pin::pin!(reading.into_body());
+        let mut events = String::new();
+        while !events.contains(r#""id":1"#) {
+            let chunk = std::future::poll_fn(|cx| body.as_mut().poll_next(cx))
+                .await
+                .unwrap()
+                .unwrap();
+            events.push_str(std::str::from_utf8(&chunk).unwrap());
+        }
+
+        // Only the session that never read its response is disconnected
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        let active: Vec<_> = state.active_sessions().into_iter().map(|s| s.id).collect();
+        assert_eq!(active, vec![reading_id.clone()]);
+        assert_eq!(state.slow_consumer_stats().disconnected_sessions(), 1);
+        assert_eq!(
+            test::call_service(&app, ping(&paused_id)).await.status(),
+            404
+        );
+        assert_eq!(
+            test::call_service(&app, ping(&reading_id)).await.status(),
+            202
+        );
+
+        // The paused stream ends without the queued response
+        let mut body = std::pin::pin!(paused.into_body());
+        let mut events = String::new();
+        while let Some(chunk) = std::future::poll_fn(|cx| body.as_mut().poll_next(cx)).await {
+            events.push_str(std::str::from_utf8(&chunk.unwrap()).unwrap());
+        }
+        assert!(events.starts_with("event: endpoint") && !events.contains("data: {"));
+    }
+
+    #[actix_web::test]
+    async fn test_notifications_dropped_for_slow_client() {
+        use crate::transport::{JsonRpcResponse, Message, Transport};
+        use actix_web::body::MessageBody;
+        use actix_web::test;
+
+        let build_server: BuildServerFn =
+            Arc::new(|t, _, _| Box::pin(async move { Ok(Server::builder(t).build()) }));
+        let state = SessionState::new(build_server, Arc::new(RwLock::new(HashMap::new())))
+            .slow_consumer_policy(SlowConsumerPolicy::DropNotificationsKeepResponses);
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(state.clone()))
+                .route("/sse", web::get().to(sse_handler)),
+        )
+        .await;
+        let sse = test::call_service(&app, test::TestRequest::get().uri("/sse").to_request()).await;
+        let session_id = sse.headers().get("X-Session-Id").unwrap().to_str().unwrap();
+        let Some(ServerHttpTransport::Sse(transport)) = state.get(session_id) else {
+            panic!("expected an SSE session");
+        };
+
+        for _ in 0..50 {
+            let notification = JsonRpcNotification {
+                method: "notifications/progress".to_string(),
+                ..Default::default()
+            };
+            transport
+                .send(&Message::Notification(notification))
+                .await
+                .unwrap();
+        }
+        let response = JsonRpcResponse {
+            id: 7,
+            result: Some(serde_json::json!({})),
+            ..Default::default()
+        };
+        transport.send(&Message::Response(response)).await.unwrap();
+
+        let mut body = std::pin::pin!(sse.into_body());
+        let mut events = String::new();
+        while !events.contains(r#""id":7"#) {
+            let chunk = std::future::poll_fn(|cx| body.as_mut().poll_next(cx))
+                .await
+                .unwrap()
+                .unwrap();
+            events.push_str(std::str::from_utf8(&chunk).unwrap());
+        }
+        assert_eq!(
+            events.matches("notifications/progress").count(),
+            crate::transport::SLOW_CONSUMER_BACKLOG
+        );
+        assert_eq!(state.slow_consumer_stats().dropped_notifications(), 18);
+        assert_eq!(transport.stalled_for(), Duration::ZERO);
+    }
+
+    #[actix_web::test]
+    async fn test_close_flushes_event_stream() {
+        use crate::transport::{Message, Transport};
+        use actix_web::body::MessageBody;
+        use actix_web::test;
+
+        let build_server: BuildServerFn =
+            Arc::new(|t, _, _| Box::pin(async move { Ok(Server::builder(t).build()) }));
+        let state = SessionState::new(build_server, Arc::new(RwLock::new(HashMap::new())));
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(state.clone()))
+                .route("/sse", web::get().to(sse_handler)),
+        )
+        .await;
+        let sse = test::call_service(&app, test::TestRequest::get().uri("/sse").to_request()).await;
+        let session_id = sse.headers().get("X-Session-Id").unwrap().to_str().unwrap();
+        let Some(ServerHttpTransport::Sse(transport)) = state.get(session_id) else {
+            panic!("expected an SSE session");
+        };
+
+        let notification = JsonRpcNotification {
+            method: "notifications/message".to_string(),
+            ..Default::default()
+        };
+        transport
+            .send(&Message::Notification(notification))
+            .await
+            .unwrap();
+        let closing = transport.clone();
+        let close = tokio::spawn(async move {
+            closing
+                .close_with_timeout(Duration::from_secs(5))
+                .await
+                .unwrap()
+        });
+
+        // The stream ends after the notification instead of dropping it
+        let mut body = std::pin::pin!(sse.into_body());
+        let mut events = String::new();
+        while let Some(chunk) = std::future::poll_fn(|cx| body.as_mut().poll_next(cx)).await {
+            events.push_str(std::str::from_utf8(&chunk.unwrap()).unwrap());
+        }
+        assert!(events.contains("notifications/message"));
+        close.await.unwrap();
+        assert!(transport.is_disconnected());
+    }
+
+    #[actix_web::test]
+    async fn test_bind_ephemeral_port() -> Result<()> {
+        use crate::client::ClientBuilder;
+        use crate::transport::ClientSseTransportBuilder;
+        use crate::types::Implementation;
+
+        let options = HttpServerOptions::new(0).bind(["127.0.0.1:0".parse()?]);
+        let server = start_http_server(
+            options,
+            ReloadHandle::new(None, ConnectionLimits::default()),
+            |t, _, _| async move { Ok(Server::builder(t).build()) },
+        )?;
+        let addr = server.addrs()[0];
+        assert!(addr.ip().is_loopback());
+        assert_ne!(addr.port(), 0);
+
+        let transport = ClientSseTransportBuilder::new(format!("http://{}", addr)).build();
+        transport.open().await?;
+        let client = ClientBuilder::new(transport.clone()).build();
+        let client_clone = client.clone();
+        tokio::spawn(async move { client_clone.start().await });
+        let response = client
+            .initialize(Implementation {
+                name: "test".to_string(),
+                version: "0.1.0".to_string(),
+            })
+            .await?;
+        assert_eq!(
+            response.protocol_version,
+            crate::types::LATEST_PROTOCOL_VERSION
+        );
+
+        transport.close().await?;
+        server.stop(false).await?;
+        assert!(std::net::TcpStream::connect(addr).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_reap_interval() {
+        assert_eq!(
+            reap_interval(Duration::from_secs(60)),
+            Duration::from_secs(30)
+        );
+        // Timeouts below a second used to panic
+        assert_eq!(
+            reap_interval(Duration::from_millis(500)),
+            Duration::from_millis(250)
+        );
+        assert_eq!(reap_interval(Duration::ZERO), Duration::from_millis(10));
+    }
+
+    #[actix_web::test]
+    async fn test_client_through_prefix() -> Result<()> {
+        use crate::client::ClientBuilder;
+        use crate::protocol::RequestOptions;
+        use crate::transport::ClientSseTransportBuilder;
+        use crate::types::Implementation;
+
+        let options = HttpServerOptions::new(0)
+            .bind(["127.0.0.1:0".parse()?])
+            .mcp(McpOptions::default().prefix("/mcp"));
+        let state = SessionState::from_fn(|t, _, _| async move { Ok(Server::builder(t).build()) });
+        let server = spawn_http_server(options, state)?;
+        let addr = server.addrs()[0];
+
+        let transport = ClientSseTransportBuilder::new(format!("http://{}/mcp", addr)).build();
+        transport.open().await?;
+        let client = ClientBuilder::new(transport.clone()).build();
+        let client_clone = client.clone();
+        tokio::spawn(async move { client_clone.start().await });
+        client.initialize(Implementation::default()).await?;
+        let pong = client
+            .request("ping", None, RequestOptions::default())
+            .await?;
+        assert_eq!(pong, serde_json::json!({}));
+
+        transport.close().await?;
+        server.stop(false).await?;
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[actix_web::test]
+    async fn test_bind_unix_socket() -> Result<()> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let path = std::env::temp_dir().join(format!("async-mcp-{}.sock", Uuid::new_v4()));
+        let options = HttpServerOptions::new(0).bind([]).bind_uds(&path);
+        let state = SessionState::from_fn(|t, _, _| async move { Ok(Server::builder(t).build()) });
+        let server = spawn_http_server(options, state)?;
+        assert!(server.addrs().is_empty());
+
+        let mut stream = tokio::net::UnixStream::connect(&path).await?;
+        stream
+            .write_all(b"GET /sse HTTP/1.1\r\nhost: localhost\r\n\r\n")
+            .await?;
+        let mut buf = vec![0; 1024];
+        let n = stream.read(&mut buf).await?;
+        let head = String::from_utf8_lossy(&buf[..n]);
+        assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+
+        drop(stream);
+        server.stop(false).await?;
+        let _ = std::fs::remove_file(&path);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[actix_web::test]
+    async fn test_bind_replaces_stale_unix_socket() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("async-mcp-{}.sock", Uuid::new_v4()));
+        let state = SessionState::from_fn(|t, _, _| async move { Ok(Server::builder(t).build()) });
+        // A listener dropped without cleanup leaves its socket file behind
+        drop(std::os::unix::net::UnixListener::bind(&path)?);
+        assert!(path.exists());
+
+        let options = HttpServerOptions::new(0).bind([]).bind_uds(&path);
+        let server = spawn_http_server(options.clone(), state.clone())?;
+        tokio::net::UnixStream::connect(&path).await?;
+
+        // A socket in use is left alone
+        let err = spawn_http_server(options, state).err().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::AddrInUse);
+        tokio::net::UnixStream::connect(&path).await?;
+
+        server.stop(false).await?;
+        let _ = std::fs::remove_file(&path);
+        Ok(())
+    }
+}
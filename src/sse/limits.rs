@@ -0,0 +1,198 @@
+//! Admission control for the public HTTP endpoints
+//! caps the number of concurrent sessions, globally and per client IP, and rate limits
+//! `POST /message` per session with a token bucket
+use crate::token_bucket::TokenBucket;
+use parking_lot::{Mutex, RwLock};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Token bucket refilled at `per_second`, holding at most `burst` tokens
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    pub per_second: f64,
+    pub burst: u32,
+}
+
+impl RateLimit {
+    pub fn new(per_second: f64, burst: u32) -> Self {
+        Self {
+            per_second,
+            burst: burst.max(1),
+        }
+    }
+}
+
+/// Everything is unlimited by default
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ConnectionLimits {
+    pub max_sessions: Option<usize>,
+    pub max_sessions_per_ip: Option<usize>,
+    pub message_rate: Option<RateLimit>,
+    /// `Retry-After` sent when a session is rejected
+    pub session_retry_after: Duration,
+}
+
+impl ConnectionLimits {
+    pub fn max_sessions(mut self, max_sessions: usize) -> Self {
+        self.max_sessions = Some(max_sessions);
+        self
+    }
+
+    pub fn max_sessions_per_ip(mut self, max_sessions_per_ip: usize) -> Self {
+        self.max_sessions_per_ip = Some(max_sessions_per_ip);
+        self
+    }
+
+    pub fn message_rate(mut self, per_second: f64, burst: u32) -> Self {
+        self.message_rate = Some(RateLimit::new(per_second, burst));
+        self
+    }
+
+    pub fn session_retry_after(mut self, retry_after: Duration) -> Self {
+        self.session_retry_after = retry_after;
+        self
+    }
+}
+
+/// Requests rejected with 429 since the server started
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Rejections {
+    /// New sessions refused by the global or per-IP cap
+    pub sessions: u64,
+    /// Messages refused by the per-session rate limit
+    pub messages: u64,
+}
+
+/// Why a request was refused, with the delay to send as `Retry-After`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejection {
+    TooManySessions(Duration),
+    RateLimited(Duration),
+}
+
+/// Why [`Admission::admit`] turned a new session away
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Refusal {
+    /// Another session has the id
+    InUse,
+    Limited(Rejection),
+}
+
+impl Rejection {
+    pub fn retry_after(&self) -> Duration {
+        match self {
+            Rejection::TooManySessions(delay) | Rejection::RateLimited(delay) => *delay,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Admitted {
+    // Client IP of each session, unknown peers are only subject to the global cap
+    ips: HashMap<String, Option<IpAddr>>,
+    per_ip: HashMap<IpAddr, usize>,
+    buckets: HashMap<String, TokenBucket>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Admission {
+    limits: RwLock<ConnectionLimits>,
+    admitted: Mutex<Admitted>,
+    rejected_sessions: AtomicU64,
+    rejected_messages: AtomicU64,
+}
+
+impl Admission {
+    pub(crate) fn new(limits: ConnectionLimits) -> Self {
+        Self {
+            limits: RwLock::new(limits),
+            ..Default::default()
+        }
+    }
+
+    /// Applies to sessions admitted and messages received from now on,
+    /// sessions over a lowered cap are not disconnected
+    pub(crate) fn set_limits(&self, limits: ConnectionLimits) {
+        *self.limits.write() = limits;
+    }
+
+    /// Admit a new session unless its id is taken or it would exceed a session cap,
+    /// checking and claiming the id is one step so concurrent sessions can't share it
+    pub(crate) fn admit(&self, session_id: &str, ip: Option<IpAddr>) -> Result<(), Refusal> {
+        let limits = *self.limits.read();
+        let mut admitted = self.admitted.lock();
+        if admitted.ips.contains_key(session_id) {
+            return Err(Refusal::InUse);
+        }
+        let over_global = limits
+            .max_sessions
+            .is_some_and(|max| admitted.ips.len() >= max);
+        let over_ip = match (ip, limits.max_sessions_per_ip) {
+            (Some(ip), Some(max)) => admitted.per_ip.get(&ip).copied().unwrap_or(0) >= max,
+            _ => false,
+        };
+        if over_global || over_ip {
+            self.rejected_sessions.fetch_add(1, Ordering::Relaxed);
+            return Err(Refusal::Limited(Rejection::TooManySessions(
+                limits.session_retry_after,
+            )));
+        }
+        admitted.ips.insert(session_id.to_string(), ip);
+        if let Some(ip) = ip {
+            *admitted.per_ip.entry(ip).or_default() += 1;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn release(&self, session_id: &str) {
+        let mut admitted = self.admitted.lock();
+        admitted.buckets.remove(session_id);
+        if let Some(Some(ip)) = admitted.ips.remove(session_id) {
+            if let Some(count) = admitted.per_ip.get_mut(&ip) {
+                *count -= 1;
+                if *count == 0 {
+                    admitted.per_ip.remove(&ip);
+                }
+            }
+        }
+    }
+
+    /// Take a token from the session's bucket for an incoming message
+    pub(crate) fn check_message(&self, session_id: &str) -> Result<(), Rejection> {
+        let Some(limit) = self.limits.read().message_rate else {
+            return Ok(());
+        };
+        let mut admitted = self.admitted.lock();
+        let bucket = admitted
+            .buckets
+            .entry(session_id.to_string())
+            .or_insert_with(|| TokenBucket::new(&limit));
+        bucket.take(&limit).map_err(|delay| {
+            self.rejected_messages.fetch_add(1, Ordering::Relaxed);
+            Rejection::RateLimited(delay)
+        })
+    }
+
+    pub(crate) fn rejections(&self) -> Rejections {
+        Rejections {
+            sessions: self.rejected_sessions.load(Ordering::Relaxed),
+            messages: self.rejected_messages.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_id_claimed_once() {
+        let admission = Admission::default();
+        assert_eq!(admission.admit("alice-1", None), Ok(()));
+        assert_eq!(admission.admit("alice-1", None), Err(Refusal::InUse));
+        admission.release("alice-1");
+        assert_eq!(admission.admit("alice-1", None), Ok(()));
+    }
+}
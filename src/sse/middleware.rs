@@ -1,12 +1,15 @@
 use actix_web::{
     body::EitherBody,
     dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
-    Error, HttpResponse,
+    Error, HttpMessage, HttpResponse,
 };
 use futures::future::LocalBoxFuture;
 use jsonwebtoken::{decode, DecodingKey, Validation};
+use parking_lot::RwLock;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::future::{ready, Ready};
+use std::sync::Arc;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
@@ -16,14 +19,153 @@ pub struct Claims {
 
 #[derive(Clone)]
 pub struct AuthConfig {
+    /// Secret clients sign their tokens with
     pub jwt_secret: String,
+    // Also accepted when verifying, e.g. the previous secret during a rotation
+    additional_secrets: Vec<String>,
 }
 
-pub struct JwtAuth(Option<AuthConfig>);
+impl AuthConfig {
+    pub fn new(jwt_secret: impl Into<String>) -> Self {
+        Self {
+            jwt_secret: jwt_secret.into(),
+            additional_secrets: Vec::new(),
+        }
+    }
+
+    /// Also accept tokens signed with `secret`, e.g. the previous secret during a rotation
+    pub fn additional_secret(mut self, secret: impl Into<String>) -> Self {
+        self.additional_secrets.push(secret.into());
+        self
+    }
+
+    /// Whether `token` is signed with any of the accepted secrets and not expired
+    pub fn verify(&self, token: &str) -> bool {
+        self.claims(token).is_some()
+    }
+
+    /// All claims of `token`, `None` unless [`AuthConfig::verify`] accepts it
+    pub fn claims(&self, token: &str) -> Option<serde_json::Value> {
+        std::iter::once(&self.jwt_secret)
+            .chain(&self.additional_secrets)
+            .find_map(|secret| {
+                decode::<serde_json::Value>(
+                    token,
+                    &DecodingKey::from_secret(secret.as_bytes()),
+                    &Validation::default(),
+                )
+                .ok()
+            })
+            .map(|data| data.claims)
+    }
+}
+
+/// Token a session connected with and its verified claims, stored in the request extensions
+/// by [`JwtAuth`] for the SSE and WebSocket handlers to pin
+#[derive(Clone)]
+pub(crate) struct VerifiedToken {
+    token: String,
+    claims: serde_json::Value,
+}
+
+impl VerifiedToken {
+    fn expired(&self) -> bool {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        self.claims["exp"].as_u64().is_none_or(|exp| exp < now)
+    }
+}
+
+#[derive(Default)]
+struct SharedAuthInner {
+    current: Option<AuthConfig>,
+    // Token each session connected with, its messages keep being accepted after a rotation
+    sessions: HashMap<String, VerifiedToken>,
+}
+
+/// Auth config consulted on every request, swapping it doesn't restart the server
+#[derive(Clone, Default)]
+pub struct SharedAuth(Arc<RwLock<SharedAuthInner>>);
+
+impl SharedAuth {
+    pub fn new(config: Option<AuthConfig>) -> Self {
+        Self(Arc::new(RwLock::new(SharedAuthInner {
+            current: config,
+            sessions: HashMap::new(),
+        })))
+    }
+
+    pub fn get(&self) -> Option<AuthConfig> {
+        self.0.read().current.clone()
+    }
+
+    /// Applies to new connections, established sessions keep posting with the token they
+    /// connected with until it expires
+    pub fn set(&self, config: Option<AuthConfig>) {
+        self.0.write().current = config;
+    }
+
+    pub(crate) fn pin(&self, session_id: &str, token: Option<VerifiedToken>) {
+        if let Some(token) = token {
+            self.0
+                .write()
+                .sessions
+                .insert(session_id.to_string(), token);
+        }
+    }
+
+    pub(crate) fn unpin(&self, session_id: &str) {
+        self.0.write().sessions.remove(session_id);
+    }
+
+    /// Accepted by the current config, or for a message to `session_id`, the unexpired token
+    /// that session connected with
+    fn authorize(&self, token: Option<&str>, message_to: Option<&str>) -> Authorization {
+        let inner = self.0.read();
+        let Some(current) = &inner.current else {
+            return Authorization::Open;
+        };
+        let Some(token) = token else {
+            return Authorization::Denied;
+        };
+        if let Some(claims) = current.claims(token) {
+            return Authorization::Verified(VerifiedToken {
+                token: token.to_string(),
+                claims,
+            });
+        }
+        match message_to.and_then(|id| inner.sessions.get(id)) {
+            Some(pinned) if pinned.token == token && !pinned.expired() => {
+                Authorization::Pinned(pinned.claims.clone())
+            }
+            _ => Authorization::Denied,
+        }
+    }
+}
+
+enum Authorization {
+    /// No auth configured
+    Open,
+    Verified(VerifiedToken),
+    /// A message with the token its session connected with, from before a rotation
+    Pinned(serde_json::Value),
+    Denied,
+}
+
+/// Rejects requests without a valid bearer token with 401, the verified claims are stored
+/// in the request extensions as a `serde_json::Value` and become the session metadata
+pub struct JwtAuth(SharedAuth);
 
 impl JwtAuth {
     pub fn new(config: Option<AuthConfig>) -> Self {
-        JwtAuth(config)
+        JwtAuth(SharedAuth::new(config))
+    }
+
+    /// Reads `auth` per request, so changes apply without rebuilding the app
+    pub fn shared(auth: SharedAuth) -> Self {
+        JwtAuth(auth)
     }
 }
 
@@ -42,14 +184,14 @@ where
     fn new_transform(&self, service: S) -> Self::Future {
         ready(Ok(JwtAuthMiddleware {
             service,
-            auth_config: self.0.clone(),
+            auth: self.0.clone(),
         }))
     }
 }
 
 pub struct JwtAuthMiddleware<S> {
     service: S,
-    auth_config: Option<AuthConfig>,
+    auth: SharedAuth,
 }
 
 impl<S, B> Service<ServiceRequest> for JwtAuthMiddleware<S>
@@ -65,53 +207,47 @@ where
     forward_ready!(service);
 
     fn call(&self, req: ServiceRequest) -> Self::Future {
-        if let Some(config) = &self.auth_config {
-            let auth_header = req
-                .headers()
-                .get("Authorization")
-                .and_then(|h| h.to_str().ok());
-
-            match auth_header {
-                Some(auth) if auth.starts_with("Bearer ") => {
-                    let token = &auth[7..];
-                    match decode::<Claims>(
-                        token,
-                        &DecodingKey::from_secret(config.jwt_secret.as_bytes()),
-                        &Validation::default(),
-                    ) {
-                        Ok(_) => {
-                            let fut = self.service.call(req);
-                            Box::pin(
-                                async move { fut.await.map(ServiceResponse::map_into_left_body) },
-                            )
-                        }
-                        Err(_) => {
-                            let (req, _) = req.into_parts();
-                            Box::pin(async move {
-                                Ok(
-                                    ServiceResponse::new(
-                                        req,
-                                        HttpResponse::Unauthorized().finish(),
-                                    )
-                                    .map_into_right_body(),
-                                )
-                            })
-                        }
-                    }
-                }
-                _ => {
-                    let (req, _) = req.into_parts();
-                    Box::pin(async move {
-                        Ok(
-                            ServiceResponse::new(req, HttpResponse::Unauthorized().finish())
-                                .map_into_right_body(),
-                        )
-                    })
-                }
+        let token = req
+            .headers()
+            .get("Authorization")
+            .and_then(|h| h.to_str().ok())
+            .and_then(|auth| auth.strip_prefix("Bearer "));
+        // Only messages may fall back to their session's pinned token, connecting never does
+        let message_to = (req.method() == actix_web::http::Method::POST
+            && req.path().ends_with("/message"))
+        .then(|| {
+            actix_web::web::Query::<HashMap<String, String>>::from_query(req.query_string())
+                .ok()
+                .and_then(|query| query.get("sessionId").cloned())
+        })
+        .flatten();
+
+        let authorization = self.auth.authorize(token, message_to.as_deref());
+        let claims = match &authorization {
+            Authorization::Verified(verified) => {
+                req.extensions_mut().insert(verified.clone());
+                Some(verified.claims.clone())
             }
-        } else {
+            Authorization::Pinned(claims) => Some(claims.clone()),
+            _ => None,
+        };
+        // Becomes the session metadata passed to `build_server`, unless set by an earlier middleware
+        if let Some(claims) = claims {
+            if req.extensions().get::<serde_json::Value>().is_none() {
+                req.extensions_mut().insert(claims);
+            }
+        }
+        if !matches!(authorization, Authorization::Denied) {
             let fut = self.service.call(req);
             Box::pin(async move { fut.await.map(ServiceResponse::map_into_left_body) })
+        } else {
+            let (req, _) = req.into_parts();
+            Box::pin(async move {
+                Ok(
+                    ServiceResponse::new(req, HttpResponse::Unauthorized().finish())
+                        .map_into_right_body(),
+                )
+            })
         }
     }
 }
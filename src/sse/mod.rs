@@ -1,2 +1,5 @@
 pub mod http_server;
+pub mod limits;
 pub mod middleware;
+#[cfg(any(test, feature = "test-util"))]
+pub mod testing;
@@ -0,0 +1,52 @@
+//! Deterministic session ids and a manual clock for tests against the HTTP server
+use super::http_server::IdGenerator;
+use crate::transport::Clock;
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// Hands out the given ids in order, then falls back to `session-{n}`
+#[derive(Debug)]
+pub struct FixedIdGenerator(Mutex<(VecDeque<String>, usize)>);
+
+impl FixedIdGenerator {
+    pub fn new<S: Into<String>>(ids: Vec<S>) -> Self {
+        Self(Mutex::new((ids.into_iter().map(Into::into).collect(), 0)))
+    }
+}
+
+impl IdGenerator for FixedIdGenerator {
+    fn next_id(&self) -> String {
+        let (ids, issued) = &mut *self.0.lock();
+        *issued += 1;
+        ids.pop_front()
+            .unwrap_or_else(|| format!("session-{}", issued))
+    }
+}
+
+/// Clock that only moves when advanced, clones share the same time
+#[derive(Debug, Clone)]
+pub struct ManualClock(Arc<Mutex<SystemTime>>);
+
+impl ManualClock {
+    pub fn new(start: SystemTime) -> Self {
+        Self(Arc::new(Mutex::new(start)))
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.0.lock() += by;
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new(SystemTime::UNIX_EPOCH)
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> SystemTime {
+        *self.0.lock()
+    }
+}
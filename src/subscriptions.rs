@@ -0,0 +1,175 @@
+//! Resource subscriptions of the sessions of a server, share one registry between sessions
+//! with [`ServerBuilder::subscriptions`](crate::server::ServerBuilder::subscriptions) so an
+//! update reaches the sessions subscribed to the resource and no other
+use crate::types::ResourceUpdatedParams;
+use anyhow::Result;
+use futures::future::BoxFuture;
+use parking_lot::Mutex;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use tracing::warn;
+use url::Url;
+
+/// Sends `notifications/resources/updated` on a session's transport
+pub(crate) type UpdateSink =
+    Arc<dyn Fn(ResourceUpdatedParams) -> BoxFuture<'static, Result<()>> + Send + Sync>;
+
+struct Session {
+    sink: UpdateSink,
+    uris: HashSet<Url>,
+}
+
+/// Which session subscribed to which resource, keyed by session id
+#[derive(Clone, Default)]
+pub struct Subscriptions {
+    sessions: Arc<Mutex<HashMap<String, Session>>>,
+}
+
+impl Subscriptions {
+    pub(crate) fn subscribe(&self, session_id: &str, uri: Url, sink: &UpdateSink) {
+        self.sessions
+            .lock()
+            .entry(session_id.to_string())
+            .or_insert_with(|| Session {
+                sink: sink.clone(),
+                uris: HashSet::new(),
+            })
+            .uris
+            .insert(uri);
+    }
+
+    pub(crate) fn unsubscribe(&self, session_id: &str, uri: &Url) {
+        let mut sessions = self.sessions.lock();
+        if let Some(session) = sessions.get_mut(session_id) {
+            session.uris.remove(uri);
+            if session.uris.is_empty() {
+                sessions.remove(session_id);
+            }
+        }
+    }
+
+    /// Forget the subscriptions of `session_id`, done when its transport closes
+    pub fn remove_session(&self, session_id: &str) {
+        self.sessions.lock().remove(session_id);
+    }
+
+    /// Sessions subscribed to `uri`
+    pub fn count(&self, uri: &Url) -> usize {
+        self.sessions
+            .lock()
+            .values()
+            .filter(|session| session.uris.contains(uri))
+            .count()
+    }
+
+    /// Notify the sessions subscribed to `uri`, returns how many were reached
+    pub async fn notify_updated(&self, uri: &Url) -> usize {
+        // Send outside the lock, a slow session mustn't block subscribing
+        let sinks: Vec<_> = self
+            .sessions
+            .lock()
+            .iter()
+            .filter(|(_, session)| session.uris.contains(uri))
+            .map(|(id, session)| (id.clone(), session.sink.clone()))
+            .collect();
+        let params = ResourceUpdatedParams { uri: uri.clone() };
+        let mut notified = 0;
+        for (session_id, sink) in sinks {
+            match sink(params.clone()).await {
+                Ok(()) => notified += 1,
+                Err(e) => warn!("Failed to notify session {} of {}: {}", session_id, uri, e),
+            }
+        }
+        notified
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::ClientBuilder;
+    use crate::protocol::RequestOptions;
+    use crate::server::Server;
+    use crate::transport::{ClientInMemoryTransport, ServerInMemoryTransport, Transport};
+    use crate::types::{ResourceCapabilities, ServerCapabilities, RESOURCE_UPDATED_METHOD};
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn test_updates_reach_subscribed_sessions() -> Result<()> {
+        let subscriptions = Subscriptions::default();
+        let (server_tx, mut server_rx) = mpsc::unbounded_channel();
+        let connect = |session_id: &'static str| {
+            let subscriptions = subscriptions.clone();
+            let server_tx = server_tx.clone();
+            let transport = ClientInMemoryTransport::new(move |t: ServerInMemoryTransport| {
+                let server = Server::builder(t)
+                    .capabilities(ServerCapabilities {
+                        resources: Some(ResourceCapabilities {
+                            subscribe: Some(true),
+                            list_changed: None,
+                        }),
+                        ..Default::default()
+                    })
+                    .subscriptions(subscriptions.clone(), session_id)
+                    .build();
+                let _ = server_tx.send(server.clone());
+                tokio::spawn(async move { server.listen().await.unwrap() })
+            });
+            let (updates_tx, updates_rx) = mpsc::unbounded_channel();
+            let client = ClientBuilder::new(transport.clone())
+                .notification_handler(
+                    RESOURCE_UPDATED_METHOD,
+                    move |params: ResourceUpdatedParams| {
+                        let _ = updates_tx.send(params.uri);
+                        Box::pin(async move { Ok(()) })
+                    },
+                )
+                .build();
+            (transport, client, updates_rx)
+        };
+
+        let (subscriber, subscriber_client, mut subscriber_updates) = connect("a");
+        let (bystander, bystander_client, mut bystander_updates) = connect("b");
+        for (transport, client) in [
+            (&subscriber, &subscriber_client),
+            (&bystander, &bystander_client),
+        ] {
+            transport.open().await?;
+            let client = client.clone();
+            tokio::spawn(async move { client.start().await });
+        }
+        // Holding a session's server keeps its client from closing, ask the bystander's
+        let _ = server_rx.recv().await;
+        let server = server_rx.recv().await.unwrap();
+
+        let uri = Url::parse("file:///notes.md")?;
+        subscriber_client
+            .request(
+                "resources/subscribe",
+                Some(serde_json::json!({ "uri": uri })),
+                RequestOptions::default(),
+            )
+            .await?;
+        assert_eq!(server.subscription_count(&uri), 1);
+
+        assert_eq!(server.notify_resource_updated(&uri).await, 1);
+        assert_eq!(subscriber_updates.recv().await, Some(uri.clone()));
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(bystander_updates.try_recv().is_err());
+
+        // The subscriber's session ends, its subscriptions go with it
+        subscriber.close().await?;
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while server.subscription_count(&uri) > 0 {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await?;
+        assert_eq!(server.notify_resource_updated(&uri).await, 0);
+
+        drop(server);
+        bystander.close().await?;
+        Ok(())
+    }
+}
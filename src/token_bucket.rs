@@ -0,0 +1,79 @@
+//! Token bucket behind the HTTP message rate limit and the log rate limit
+use crate::sse::limits::RateLimit;
+use std::time::{Duration, Instant};
+
+/// Longest wait reported for a token, a rate close to zero would overflow a `Duration`
+const MAX_DELAY: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+pub(crate) struct TokenBucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    pub(crate) fn new(limit: &RateLimit) -> Self {
+        Self {
+            tokens: limit.burst as f64,
+            refilled_at: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, limit: &RateLimit) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.per_second).min(limit.burst as f64);
+        self.refilled_at = now;
+    }
+
+    /// Take a token, or return how long until one is available
+    pub(crate) fn take(&mut self, limit: &RateLimit) -> Result<(), Duration> {
+        self.refill(limit);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return Ok(());
+        }
+        if limit.per_second <= 0.0 {
+            return Err(MAX_DELAY);
+        }
+        Err(
+            Duration::try_from_secs_f64((1.0 - self.tokens) / limit.per_second)
+                .map_or(MAX_DELAY, |delay| delay.min(MAX_DELAY)),
+        )
+    }
+
+    /// Take a token if there is one, for messages that go out either way
+    pub(crate) fn spend(&mut self, limit: &RateLimit) {
+        self.refill(limit);
+        self.tokens = (self.tokens - 1.0).max(0.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket() {
+        let limit = RateLimit::new(10.0, 2);
+        let mut bucket = TokenBucket::new(&limit);
+        assert!(bucket.take(&limit).is_ok());
+        assert!(bucket.take(&limit).is_ok());
+        let delay = bucket.take(&limit).unwrap_err();
+        assert!(delay > Duration::ZERO && delay <= Duration::from_millis(100));
+
+        // Refills over time, never beyond the burst
+        bucket.refilled_at -= Duration::from_secs(10);
+        assert!(bucket.take(&limit).is_ok());
+        assert!(bucket.take(&limit).is_ok());
+        assert!(bucket.take(&limit).is_err());
+    }
+
+    #[test]
+    fn test_tiny_rate_caps_the_delay() {
+        let limit = RateLimit::new(f64::MIN_POSITIVE, 1);
+        let mut bucket = TokenBucket::new(&limit);
+        assert!(bucket.take(&limit).is_ok());
+        assert_eq!(bucket.take(&limit), Err(MAX_DELAY));
+    }
+}
@@ -0,0 +1,319 @@
+//! Tool sets that change while the server is running
+//! a [`DynamicToolSource`] yields add/update/remove events that are applied to the live registry,
+//! each batch is followed by `notifications/tools/list_changed`
+use crate::registry::ToolHandlerFn;
+use crate::types::{CallToolRequest, CallToolResponse, Tool, ToolResponseContent};
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+pub enum ToolEvent {
+    Added(Tool, ToolHandlerFn),
+    Updated(Tool, ToolHandlerFn),
+    Removed(String),
+}
+
+#[async_trait]
+pub trait DynamicToolSource: Send + 'static {
+    /// Wait for the next batch of changes, `None` once the source won't change anymore
+    async fn next_events(&mut self) -> Result<Option<Vec<ToolEvent>>>;
+}
+
+/// Manifest entry, a tool backed by an external command
+/// the command gets the call arguments as JSON on stdin and its stdout is the text response
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ManifestTool {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default = "default_input_schema")]
+    pub input_schema: serde_json::Value,
+    pub command: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    /// Overrides the source timeout for this tool
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+}
+
+fn default_input_schema() -> serde_json::Value {
+    serde_json::json!({"type": "object"})
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    pub tools: Vec<ManifestTool>,
+}
+
+/// Tools defined by a YAML or JSON manifest file, reloaded when the file changes
+/// the file is polled every `poll_interval`, with the `watch` feature changes are also
+/// picked up as soon as the filesystem reports them
+pub struct ManifestFileSource {
+    path: PathBuf,
+    poll_interval: Duration,
+    timeout: Duration,
+    loaded: Option<HashMap<String, ManifestTool>>,
+    #[cfg(feature = "watch")]
+    watcher: Option<(
+        notify::RecommendedWatcher,
+        tokio::sync::mpsc::UnboundedReceiver<()>,
+    )>,
+}
+
+impl ManifestFileSource {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            poll_interval: Duration::from_secs(2),
+            timeout: Duration::from_secs(30),
+            loaded: None,
+            #[cfg(feature = "watch")]
+            watcher: None,
+        }
+    }
+
+    pub fn poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Default time a command may run before the call fails
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Reload as soon as the manifest directory changes, polling stays as a fallback
+    #[cfg(feature = "watch")]
+    pub fn watch(mut self) -> Result<Self> {
+        use notify::Watcher;
+
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut watcher = notify::recommended_watcher(move |_| {
+            let _ = tx.send(());
+        })?;
+        // Watch the directory, editors often replace the file instead of writing it in place
+        let dir = match self.path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        watcher.watch(&dir, notify::RecursiveMode::NonRecursive)?;
+        self.watcher = Some((watcher, rx));
+        Ok(self)
+    }
+
+    async fn wait_for_change(&mut self) {
+        #[cfg(feature = "watch")]
+        if let Some((_, rx)) = &mut self.watcher {
+            tokio::select! {
+                _ = tokio::time::sleep(self.poll_interval) => {}
+                _ = rx.recv() => {}
+            }
+            return;
+        }
+        tokio::time::sleep(self.poll_interval).await;
+    }
+
+    async fn load(&self) -> Result<HashMap<String, ManifestTool>> {
+        let content = tokio::fs::read_to_string(&self.path).await?;
+        // JSON is valid YAML, so one parser handles both formats
+        let manifest: Manifest = serde_yaml::from_str(&content)?;
+        Ok(manifest
+            .tools
+            .into_iter()
+            .map(|tool| (tool.name.clone(), tool))
+            .collect())
+    }
+
+    fn diff(&self, manifest: &HashMap<String, ManifestTool>) -> Vec<ToolEvent> {
+        let empty = HashMap::new();
+        let previous = self.loaded.as_ref().unwrap_or(&empty);
+        let mut events = Vec::new();
+        for (name, entry) in manifest {
+            match previous.get(name) {
+                None => events.push(ToolEvent::Added(entry.tool(), entry.handler(self.timeout))),
+                Some(old) if old != entry => events.push(ToolEvent::Updated(
+                    entry.tool(),
+                    entry.handler(self.timeout),
+                )),
+                Some(_) => {}
+            }
+        }
+        for name in previous.keys() {
+            if !manifest.contains_key(name) {
+                events.push(ToolEvent::Removed(name.clone()));
+            }
+        }
+        events
+    }
+}
+
+#[async_trait]
+impl DynamicToolSource for ManifestFileSource {
+    async fn next_events(&mut self) -> Result<Option<Vec<ToolEvent>>> {
+        loop {
+            if self.loaded.is_some() {
+                self.wait_for_change().await;
+            }
+            let manifest = match self.load().await {
+                Ok(manifest) => manifest,
+                Err(e) => {
+                    // Keep serving the last good manifest until the file is fixed
+                    self.loaded.get_or_insert_with(HashMap::new);
+                    return Err(
+                        e.context(format!("Failed to load manifest {}", self.path.display()))
+                    );
+                }
+            };
+            let events = self.diff(&manifest);
+            self.loaded = Some(manifest);
+            if !events.is_empty() {
+                return Ok(Some(events));
+            }
+        }
+    }
+}
+
+impl ManifestTool {
+    fn tool(&self) -> Tool {
+        Tool {
+            name: self.name.clone(),
+            description: self.description.clone(),
+            input_schema: self.input_schema.clone(),
+            output_schema: None,
+        }
+    }
+
+    fn handler(&self, default_timeout: Duration) -> ToolHandlerFn {
+        let entry = Arc::new(self.clone());
+        let timeout = self
+            .timeout_ms
+            .map(Duration::from_millis)
+            .unwrap_or(default_timeout);
+        Box::new(move |req: CallToolRequest| {
+            let entry = entry.clone();
+            Box::pin(async move { entry.run(req, timeout).await })
+        })
+    }
+
+    async fn run(&self, req: CallToolRequest, timeout: Duration) -> Result<CallToolResponse> {
+        let input = serde_json::to_vec(&req.arguments.unwrap_or_default())?;
+        let mut child = Command::new(&self.command)
+            .args(&self.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+        let mut stdin = child
+            .stdin
+            .take()
+            .ok_or_else(|| anyhow::anyhow!("Child stdin unavailable"))?;
+
+        let output = tokio::time::timeout(timeout, async move {
+            // The command may exit without reading its input
+            let _ = stdin.write_all(&input).await;
+            drop(stdin);
+            child.wait_with_output().await
+        })
+        .await
+        .map_err(|_| anyhow::anyhow!("Tool {} timed out after {:?}", self.name, timeout))??;
+
+        let mut content = vec![ToolResponseContent::Text {
+            text: String::from_utf8_lossy(&output.stdout).into_owned(),
+        }];
+        if !output.status.success() && !output.stderr.is_empty() {
+            content.push(ToolResponseContent::Text {
+                text: String::from_utf8_lossy(&output.stderr).into_owned(),
+            });
+        }
+        Ok(CallToolResponse {
+            content,
+            is_error: (!output.status.success()).then_some(true),
+            meta: None,
+        })
+    }
+}
+
+// The manifests run `cat` and `sh`
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::client::ClientBuilder;
+    use crate::protocol::RequestOptions;
+    use crate::server::Server;
+    use crate::transport::{ClientInMemoryTransport, ServerInMemoryTransport, Transport};
+
+    const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+    #[tokio::test]
+    async fn test_manifest_reload() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("async-mcp-manifest-{}", uuid::Uuid::new_v4()));
+        tokio::fs::create_dir_all(&dir).await?;
+        let manifest = dir.join("tools.yaml");
+        tokio::fs::write(&manifest, "tools:\n  - name: echo\n    command: cat\n").await?;
+
+        let server_manifest = manifest.clone();
+        let transport = ClientInMemoryTransport::new(move |t: ServerInMemoryTransport| {
+            let source =
+                ManifestFileSource::new(server_manifest.clone()).poll_interval(POLL_INTERVAL);
+            tokio::spawn(async move {
+                let server = Server::builder(t).with_tool_source(source).build();
+                server.listen().await.unwrap();
+            })
+        });
+        transport.open().await?;
+        let client = ClientBuilder::new(transport.clone()).build();
+        let client_clone = client.clone();
+        tokio::spawn(async move { client_clone.start().await });
+
+        let call = |name: &'static str| {
+            let client = client.clone();
+            async move {
+                client
+                    .request_typed::<_, CallToolResponse>(
+                        "tools/call",
+                        serde_json::json!({"name": name, "arguments": {"text": "hi"}}),
+                        RequestOptions::default(),
+                    )
+                    .await
+            }
+        };
+        let text = |response: &CallToolResponse| match &response.content[0] {
+            ToolResponseContent::Text { text } => text.clone(),
+            other => panic!("Expected text, got {:?}", other),
+        };
+
+        // The initial manifest is loaded as soon as the server listens
+        let mut response = call("echo").await;
+        for _ in 0..10 {
+            if response.is_ok() {
+                break;
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+            response = call("echo").await;
+        }
+        assert_eq!(text(&response?), r#"{"text":"hi"}"#);
+
+        tokio::fs::write(
+            &manifest,
+            r#"{"tools": [{"name": "shout", "command": "sh", "args": ["-c", "tr a-z A-Z"]}]}"#,
+        )
+        .await?;
+        tokio::time::sleep(POLL_INTERVAL * 3).await;
+        assert_eq!(text(&call("shout").await?), r#"{"TEXT":"HI"}"#);
+        assert!(call("echo").await.is_err());
+
+        transport.close().await?;
+        tokio::fs::remove_dir_all(&dir).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,131 @@
+use super::{
+    ClientHttpTransport, ClientInMemoryTransport, ClientSseTransport, ClientStdioTransport,
+    ClientWsTransport, Message, ServerHttpTransport, ServerInMemoryTransport, ServerSseTransport,
+    ServerStdioTransport, ServerWsTransport, Transport,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Type-erased transport for selecting the concrete transport at runtime
+/// e.g. `Server<BoxedTransport>` can be fed by stdio, SSE or WS depending on a CLI flag
+#[derive(Clone)]
+pub struct BoxedTransport(Arc<dyn Transport>);
+
+impl BoxedTransport {
+    pub fn new<T: Transport>(transport: T) -> Self {
+        Self(Arc::new(transport))
+    }
+}
+
+#[async_trait]
+impl Transport for BoxedTransport {
+    async fn send(&self, message: &Message) -> Result<()> {
+        self.0.send(message).await
+    }
+
+    async fn receive(&self) -> Result<Option<Message>> {
+        self.0.receive().await
+    }
+
+    async fn open(&self) -> Result<()> {
+        self.0.open().await
+    }
+
+    async fn close(&self) -> Result<()> {
+        self.0.close().await
+    }
+
+    async fn close_with_timeout(&self, timeout: Duration) -> Result<()> {
+        self.0.close_with_timeout(timeout).await
+    }
+
+    async fn closed(&self) {
+        self.0.closed().await
+    }
+}
+
+impl From<Arc<dyn Transport>> for BoxedTransport {
+    fn from(transport: Arc<dyn Transport>) -> Self {
+        Self(transport)
+    }
+}
+
+macro_rules! impl_from_transport {
+    ($($transport:ty),* $(,)?) => {
+        $(
+            impl From<$transport> for BoxedTransport {
+                fn from(transport: $transport) -> Self {
+                    Self::new(transport)
+                }
+            }
+        )*
+    };
+}
+
+impl_from_transport!(
+    ServerStdioTransport,
+    ClientStdioTransport,
+    ServerInMemoryTransport,
+    ClientInMemoryTransport,
+    ServerSseTransport,
+    ClientSseTransport,
+    ServerWsTransport,
+    ClientWsTransport,
+    ServerHttpTransport,
+    ClientHttpTransport,
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{ClientBuilder, DynClient};
+    use crate::protocol::RequestOptions;
+    use crate::server::{DynServer, Server};
+    use crate::types::ToolsListResponse;
+
+    // Compile-time check that the trait stays object safe
+    fn _assert_object_safe(_: &dyn Transport) {}
+
+    fn build_server(use_stdio: bool, transport: ServerInMemoryTransport) -> DynServer {
+        let transport: BoxedTransport = if use_stdio {
+            ServerStdioTransport::default().into()
+        } else {
+            transport.into()
+        };
+        Server::builder(transport).build()
+    }
+
+    #[tokio::test]
+    async fn test_runtime_selected_transport() -> Result<()> {
+        // stdio is only built here, never listened on, as it would read the test's stdin
+        let _stdio_server = build_server(true, ServerInMemoryTransport::default());
+
+        let transport: BoxedTransport = ClientInMemoryTransport::new(|t| {
+            tokio::spawn(async move {
+                let server = build_server(false, t);
+                server.listen().await.unwrap();
+            })
+        })
+        .into();
+        transport.open().await?;
+
+        let client: DynClient = ClientBuilder::new(transport.clone()).build();
+        let client_clone = client.clone();
+        tokio::spawn(async move { client_clone.start().await });
+
+        let response = client
+            .request(
+                "tools/list",
+                Some(serde_json::json!({})),
+                RequestOptions::default(),
+            )
+            .await?;
+        let response: ToolsListResponse = serde_json::from_value(response)?;
+        assert!(response.tools.is_empty());
+
+        transport.close().await?;
+        Ok(())
+    }
+}
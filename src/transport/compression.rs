@@ -0,0 +1,213 @@
+//! Optional gzip framing for the line-delimited stdio transports
+//! a compressed message is one line of `gz:` followed by the base64 of the gzipped JSON,
+//! JSON lines never start with it so both kinds can be mixed on the same stream.
+//! Each end advertises support under `experimental.stdioCompression` in `initialize`
+//! and only compresses once the peer did, decoding is always supported
+use super::{invalid_message, Message, RequestId};
+use anyhow::Result;
+use base64::Engine;
+use flate2::{read::GzDecoder, write::GzEncoder};
+use parking_lot::Mutex;
+use std::borrow::Cow;
+use std::io::{Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Key of the experimental capability advertised in `initialize`
+pub const COMPRESSION_CAPABILITY: &str = "stdioCompression";
+
+const GZIP_PREFIX: &str = "gz:";
+
+/// Gzip `json` into a single framed line, without the newline
+pub fn encode_frame(json: &str) -> Result<String> {
+    let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::fast());
+    encoder.write_all(json.as_bytes())?;
+    let compressed = encoder.finish()?;
+    Ok(format!(
+        "{}{}",
+        GZIP_PREFIX,
+        base64::engine::general_purpose::STANDARD.encode(compressed)
+    ))
+}
+
+/// The JSON of a line, decompressed if it is a gzip frame
+/// decompression stops past `max_bytes` so a small frame can't expand without bound
+pub fn decode_frame(line: &str, max_bytes: usize) -> Result<Cow<'_, str>> {
+    let Some(encoded) = line.trim_end().strip_prefix(GZIP_PREFIX) else {
+        return Ok(Cow::Borrowed(line));
+    };
+    let compressed = base64::engine::general_purpose::STANDARD
+        .decode(encoded)
+        .map_err(|e| invalid_message(format!("invalid gzip frame: {}", e)))?;
+    let mut json = String::new();
+    GzDecoder::new(compressed.as_slice())
+        .take(max_bytes as u64 + 1)
+        .read_to_string(&mut json)
+        .map_err(|e| invalid_message(format!("invalid gzip frame: {}", e)))?;
+    if json.len() > max_bytes {
+        return Err(invalid_message(format!(
+            "gzip frame expands beyond the limit of {} bytes",
+            max_bytes
+        ))
+        .into());
+    }
+    Ok(Cow::Owned(json))
+}
+
+/// Negotiation state of one transport, shared by its clones
+#[derive(Debug)]
+pub(crate) struct Compression {
+    threshold: usize,
+    negotiated: AtomicBool,
+    // Our `initialize` request, answered by the peer's capabilities
+    sent_initialize: Mutex<Option<RequestId>>,
+    // The peer's `initialize` request, our response advertises support
+    received_initialize: Mutex<Option<RequestId>>,
+}
+
+impl Compression {
+    pub(crate) fn new(threshold: usize) -> Self {
+        Self {
+            threshold,
+            negotiated: AtomicBool::new(false),
+            sent_initialize: Mutex::new(None),
+            received_initialize: Mutex::new(None),
+        }
+    }
+
+    pub(crate) fn is_negotiated(&self) -> bool {
+        self.negotiated.load(Ordering::Relaxed)
+    }
+
+    /// Advertise support in our side of the `initialize` exchange
+    pub(crate) fn outgoing<'a>(&self, message: &'a Message) -> Cow<'a, Message> {
+        match message {
+            Message::Request(request) if request.method == "initialize" => {
+                *self.sent_initialize.lock() = Some(request.id);
+                let mut request = request.clone();
+                if let Some(params) = request.params.as_mut() {
+                    advertise(params);
+                }
+                Cow::Owned(Message::Request(request))
+            }
+            Message::Response(response)
+                if *self.received_initialize.lock() == Some(response.id) =>
+            {
+                let mut response = response.clone();
+                if let Some(result) = response.result.as_mut() {
+                    advertise(result);
+                }
+                Cow::Owned(Message::Response(response))
+            }
+            _ => Cow::Borrowed(message),
+        }
+    }
+
+    /// Pick up the peer's side of the `initialize` exchange
+    pub(crate) fn incoming(&self, message: &Message) {
+        let capabilities = match message {
+            Message::Request(request) if request.method == "initialize" => {
+                *self.received_initialize.lock() = Some(request.id);
+                request.params.as_ref()
+            }
+            Message::Response(response) if *self.sent_initialize.lock() == Some(response.id) => {
+                response.result.as_ref()
+            }
+            _ => return,
+        };
+        let supported = capabilities
+            .and_then(|value| {
+                value.pointer(&format!(
+                    "/capabilities/experimental/{}",
+                    COMPRESSION_CAPABILITY
+                ))
+            })
+            .and_then(|capability| capability.get("encodings"))
+            .and_then(|encodings| encodings.as_array())
+            .is_some_and(|encodings| encodings.iter().any(|e| e == "gzip"));
+        self.negotiated.store(supported, Ordering::Relaxed);
+    }
+
+    /// The line to write for `json`, compressed once negotiated and above the threshold
+    pub(crate) fn encode(&self, json: String) -> Result<String> {
+        if self.is_negotiated() && json.len() > self.threshold {
+            encode_frame(&json)
+        } else {
+            Ok(json)
+        }
+    }
+}
+
+fn advertise(params: &mut serde_json::Value) {
+    let Some(capabilities) = params
+        .get_mut("capabilities")
+        .and_then(|capabilities| capabilities.as_object_mut())
+    else {
+        return;
+    };
+    let experimental = capabilities
+        .entry("experimental")
+        .or_insert_with(|| serde_json::json!({}));
+    if experimental.is_null() {
+        *experimental = serde_json::json!({});
+    }
+    if let Some(experimental) = experimental.as_object_mut() {
+        experimental.insert(
+            COMPRESSION_CAPABILITY.to_string(),
+            serde_json::json!({ "encodings": ["gzip"] }),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::{JsonRpcRequest, JsonRpcResponse};
+
+    #[test]
+    fn test_gzip_frames() -> Result<()> {
+        let json = serde_json::json!({"text": "abc".repeat(1000)}).to_string();
+        let frame = encode_frame(&json)?;
+        assert!(frame.starts_with("gz:") && frame.len() < json.len() / 10);
+        assert_eq!(decode_frame(&frame, json.len())?, json);
+        assert!(matches!(decode_frame(&json, 10)?, Cow::Borrowed(_)));
+        // Expanding past the limit fails instead of allocating the whole payload
+        assert!(decode_frame(&frame, 100).is_err());
+        assert!(decode_frame("gz:not base64!", 100).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_negotiation() -> Result<()> {
+        let client = Compression::new(100);
+        let server = Compression::new(100);
+        let large = "x".repeat(200);
+
+        let initialize = Message::Request(JsonRpcRequest {
+            id: 1,
+            method: "initialize".to_string(),
+            params: Some(serde_json::json!({"capabilities": {"experimental": null}})),
+            ..Default::default()
+        });
+        let sent = client.outgoing(&initialize).into_owned();
+        assert!(!client.is_negotiated());
+        server.incoming(&sent);
+        assert!(server.is_negotiated());
+
+        let response = Message::Response(JsonRpcResponse {
+            id: 1,
+            result: Some(serde_json::json!({"capabilities": {}})),
+            ..Default::default()
+        });
+        client.incoming(&server.outgoing(&response));
+        assert!(client.is_negotiated());
+        assert!(client.encode(large.clone())?.starts_with("gz:"));
+        assert_eq!(client.encode("small".to_string())?, "small");
+
+        // A peer that doesn't advertise it gets plain JSON
+        let plain = Compression::new(100);
+        plain.incoming(&initialize);
+        assert!(!plain.is_negotiated());
+        assert_eq!(plain.encode(large.clone())?, large);
+        Ok(())
+    }
+}
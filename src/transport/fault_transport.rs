@@ -0,0 +1,570 @@
+use super::{Message, Transport};
+use anyhow::Result;
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::debug;
+
+/// Held messages are released once no message was sent for this long
+const REORDER_QUIET_PERIOD: Duration = Duration::from_millis(50);
+
+/// Kind of corruption applied to a message on the wire
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// Cut the JSON in half
+    Truncate,
+    /// Flip a bit in the first byte
+    FlipByte,
+    /// Insert a newline in the middle, splitting a line-delimited frame
+    ExtraNewline,
+}
+
+impl Fault {
+    /// Apply the fault to a serialized message
+    pub fn corrupt(&self, json: &str) -> String {
+        let mid = json.len() / 2;
+        let mid = (0..=mid)
+            .rev()
+            .find(|i| json.is_char_boundary(*i))
+            .unwrap_or(0);
+        match self {
+            Fault::Truncate => json[..mid].to_string(),
+            Fault::FlipByte => {
+                let mut bytes = json.as_bytes().to_vec();
+                if let Some(first) = bytes.first_mut() {
+                    *first ^= 0x20;
+                }
+                String::from_utf8_lossy(&bytes).to_string()
+            }
+            Fault::ExtraNewline => format!("{}\n{}", &json[..mid], &json[mid..]),
+        }
+    }
+}
+
+/// Seedable policy for chaos testing, the same seed always yields the same faults
+#[derive(Debug, Clone, Default)]
+pub struct FaultPolicy {
+    pub seed: u64,
+    /// Probability of silently dropping a message
+    pub drop_probability: f64,
+    /// Probability of delivering a message twice
+    pub duplicate_probability: f64,
+    /// Outbound messages are held and released shuffled within this window, 0 disables reordering
+    /// a partly filled window is released after a quiet period and on close
+    pub max_reorder_window: usize,
+    /// Artificial latency, drawn uniformly from the range
+    pub latency: Option<Range<Duration>>,
+    /// Disconnect once this many messages went through (in either direction)
+    pub disconnect_after_n_messages: Option<usize>,
+}
+
+impl FaultPolicy {
+    /// Drops and duplicates a few messages
+    pub fn lossy(seed: u64) -> Self {
+        Self {
+            seed,
+            drop_probability: 0.1,
+            duplicate_probability: 0.1,
+            ..Default::default()
+        }
+    }
+
+    /// Slow network with reordering
+    pub fn laggy(seed: u64) -> Self {
+        Self {
+            seed,
+            max_reorder_window: 3,
+            latency: Some(Duration::from_millis(1)..Duration::from_millis(20)),
+            ..Default::default()
+        }
+    }
+
+    /// Every fault at once
+    pub fn heavy(seed: u64) -> Self {
+        Self {
+            seed,
+            drop_probability: 0.3,
+            duplicate_probability: 0.2,
+            max_reorder_window: 4,
+            latency: Some(Duration::ZERO..Duration::from_millis(10)),
+            disconnect_after_n_messages: None,
+        }
+    }
+
+    pub fn disconnect_after(mut self, n: usize) -> Self {
+        self.disconnect_after_n_messages = Some(n);
+        self
+    }
+}
+
+// splitmix64, good enough for reproducible fault decisions
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        probability > 0.0 && ((self.next_u64() >> 11) as f64) < probability * (1u64 << 53) as f64
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n.max(1) as u64) as usize
+    }
+
+    fn duration(&mut self, range: &Range<Duration>) -> Duration {
+        let span = range.end.saturating_sub(range.start).as_micros() as u64;
+        range.start + Duration::from_micros(self.next_u64() % span.max(1))
+    }
+}
+
+struct ChaosState {
+    policy: FaultPolicy,
+    rng: Rng,
+    messages: usize,
+    disconnected: bool,
+    held: Vec<Message>,
+    // Bumped on every send, a quiet period flush only goes ahead if nothing was sent since
+    sends: u64,
+    duplicates: VecDeque<Message>,
+}
+
+impl ChaosState {
+    // Count a message, returns false once the disconnect threshold is reached
+    fn admit(&mut self) -> bool {
+        if self.disconnected {
+            return false;
+        }
+        self.messages += 1;
+        if let Some(n) = self.policy.disconnect_after_n_messages {
+            if self.messages > n {
+                debug!("Injecting disconnect after {} messages", n);
+                self.disconnected = true;
+                self.held.clear();
+                return false;
+            }
+        }
+        true
+    }
+
+    fn latency(&mut self) -> Option<Duration> {
+        let range = self.policy.latency.clone()?;
+        Some(self.rng.duration(&range))
+    }
+
+    // Empty the reorder window in a random order
+    fn release_held(&mut self) -> Vec<Message> {
+        let mut outgoing = Vec::new();
+        while !self.held.is_empty() {
+            let i = self.rng.below(self.held.len());
+            outgoing.push(self.held.swap_remove(i));
+        }
+        outgoing
+    }
+}
+
+/// Test transport wrapping an inner transport and injecting faults
+/// `corrupt_next` corrupts received messages, which surface as parse errors from `receive`
+/// like a misbehaving peer, a [`FaultPolicy`] drops, duplicates, delays and reorders messages
+#[derive(Clone)]
+pub struct FaultInjectingTransport<T: Transport> {
+    inner: Arc<T>,
+    faults: Arc<Mutex<VecDeque<Fault>>>,
+    chaos: Option<Arc<Mutex<ChaosState>>>,
+}
+
+impl<T: Transport> FaultInjectingTransport<T> {
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner: Arc::new(inner),
+            faults: Arc::new(Mutex::new(VecDeque::new())),
+            chaos: None,
+        }
+    }
+
+    pub fn with_policy(inner: T, policy: FaultPolicy) -> Self {
+        let chaos = ChaosState {
+            rng: Rng(policy.seed),
+            policy,
+            messages: 0,
+            disconnected: false,
+            held: Vec::new(),
+            sends: 0,
+            duplicates: VecDeque::new(),
+        };
+        Self {
+            chaos: Some(Arc::new(Mutex::new(chaos))),
+            ..Self::new(inner)
+        }
+    }
+
+    /// Corrupt the next `count` received messages with `fault`
+    pub fn corrupt_next(&self, fault: Fault, count: usize) {
+        let mut faults = self.faults.lock();
+        faults.extend(std::iter::repeat_n(fault, count));
+    }
+
+    /// Number of faults still to be injected
+    pub fn pending_faults(&self) -> usize {
+        self.faults.lock().len()
+    }
+
+    fn corrupt(&self, message: Message) -> Result<Message> {
+        let Some(fault) = self.faults.lock().pop_front() else {
+            return Ok(message);
+        };
+
+        let corrupted = fault.corrupt(&serde_json::to_string(&message)?);
+        debug!("Injecting {:?}: {}", fault, corrupted);
+        // Line-delimited peers would read the first line only
+        let line = corrupted.lines().next().unwrap_or_default();
+        Ok(serde_json::from_str(line)?)
+    }
+
+    async fn chaos_receive(&self, chaos: &Mutex<ChaosState>) -> Result<Option<Message>> {
+        loop {
+            let duplicate = chaos.lock().duplicates.pop_front();
+            let message = match duplicate {
+                Some(message) => message,
+                None => match self.inner.receive().await? {
+                    Some(message) => message,
+                    None => return Ok(None),
+                },
+            };
+
+            let latency = {
+                let mut guard = chaos.lock();
+                let state = &mut *guard;
+                if !state.admit() {
+                    return Ok(None);
+                }
+                if state.rng.chance(state.policy.drop_probability) {
+                    debug!("Dropping received message: {:?}", message);
+                    continue;
+                }
+                if state.rng.chance(state.policy.duplicate_probability) {
+                    state.duplicates.push_back(message.clone());
+                }
+                state.latency()
+            };
+            if let Some(latency) = latency {
+                tokio::time::sleep(latency).await;
+            }
+            return Ok(Some(message));
+        }
+    }
+
+    // Release what is held if nothing else is sent for a while, a request
+    // waiting in the window would otherwise never reach the peer
+    fn flush_when_quiet(&self, chaos: Arc<Mutex<ChaosState>>, sends: u64) {
+        let inner = self.inner.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(REORDER_QUIET_PERIOD).await;
+            let outgoing = {
+                let mut state = chaos.lock();
+                if state.sends != sends || state.disconnected {
+                    return;
+                }
+                state.release_held()
+            };
+            for message in outgoing {
+                if inner.send(&message).await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    async fn flush(&self) -> Result<()> {
+        let Some(chaos) = &self.chaos else {
+            return Ok(());
+        };
+        let outgoing = chaos.lock().release_held();
+        for message in outgoing {
+            self.inner.send(&message).await?;
+        }
+        Ok(())
+    }
+
+    async fn chaos_send(&self, chaos: &Arc<Mutex<ChaosState>>, message: &Message) -> Result<()> {
+        let (latency, outgoing) = {
+            let mut guard = chaos.lock();
+            let state = &mut *guard;
+            if !state.admit() {
+                return Err(anyhow::anyhow!("Transport disconnected"));
+            }
+            if state.rng.chance(state.policy.drop_probability) {
+                debug!("Dropping sent message: {:?}", message);
+                return Ok(());
+            }
+            let copies = if state.rng.chance(state.policy.duplicate_probability) {
+                2
+            } else {
+                1
+            };
+            state
+                .held
+                .extend(std::iter::repeat_n(message.clone(), copies));
+
+            state.sends += 1;
+
+            // Release held messages shuffled once the window is full
+            let outgoing = if state.held.len() > state.policy.max_reorder_window {
+                state.release_held()
+            } else {
+                self.flush_when_quiet(chaos.clone(), state.sends);
+                Vec::new()
+            };
+            (state.latency(), outgoing)
+        };
+        if let Some(latency) = latency {
+            tokio::time::sleep(latency).await;
+        }
+        for message in outgoing {
+            self.inner.send(&message).await?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<T: Transport> Transport for FaultInjectingTransport<T> {
+    async fn receive(&self) -> Result<Option<Message>> {
+        let message = match &self.chaos {
+            Some(chaos) => self.chaos_receive(chaos).await?,
+            None => self.inner.receive().await?,
+        };
+        message.map(|message| self.corrupt(message)).transpose()
+    }
+
+    async fn send(&self, message: &Message) -> Result<()> {
+        match &self.chaos {
+            Some(chaos) => self.chaos_send(chaos, message).await,
+            None => self.inner.send(message).await,
+        }
+    }
+
+    async fn open(&self) -> Result<()> {
+        self.inner.open().await
+    }
+
+    async fn close(&self) -> Result<()> {
+        self.flush().await?;
+        self.inner.close().await
+    }
+
+    async fn close_with_timeout(&self, timeout: Duration) -> Result<()> {
+        self.flush().await?;
+        self.inner.close_with_timeout(timeout).await
+    }
+
+    async fn closed(&self) {
+        self.inner.closed().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::Protocol;
+    use crate::transport::{
+        ClientInMemoryTransport, JsonRpcMessage, JsonRpcRequest, ServerInMemoryTransport,
+    };
+
+    fn ping(id: u64) -> JsonRpcMessage {
+        JsonRpcMessage::Request(JsonRpcRequest {
+            id,
+            method: "ping".to_string(),
+            params: None,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_faults_break_json() {
+        let json = serde_json::to_string(&ping(1)).unwrap();
+        for fault in [Fault::Truncate, Fault::FlipByte, Fault::ExtraNewline] {
+            let corrupted = fault.corrupt(&json);
+            let line = corrupted.lines().next().unwrap();
+            assert!(
+                serde_json::from_str::<Message>(line).is_err(),
+                "{:?} produced valid JSON",
+                fault
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_listen_recovers_from_malformed_messages() -> Result<()> {
+        let transport = ClientInMemoryTransport::new(|t: ServerInMemoryTransport| {
+            tokio::spawn(async move {
+                let transport = FaultInjectingTransport::new(t);
+                transport.corrupt_next(Fault::Truncate, 1);
+                transport.corrupt_next(Fault::FlipByte, 1);
+                transport.corrupt_next(Fault::ExtraNewline, 1);
+                let protocol = Protocol::builder(transport)
+                    .request_handler("ping", |_: serde_json::Value| {
+                        Box::pin(async move { Ok(serde_json::json!({})) })
+                    })
+                    .build();
+                protocol.listen().await.unwrap();
+            })
+        });
+        transport.open().await?;
+
+        for id in 0..4 {
+            transport.send(&ping(id)).await?;
+        }
+
+        // Only the last request survives, the listener keeps going after bad input
+        match transport.receive().await? {
+            Some(JsonRpcMessage::Response(response)) => {
+                assert_eq!(response.id, 3);
+                assert!(response.error.is_none());
+            }
+            other => panic!("Unexpected message: {:?}", other),
+        }
+
+        transport.close().await?;
+        Ok(())
+    }
+
+    // Inner transport recording what was sent
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<Message>>>);
+
+    #[async_trait]
+    impl Transport for Recorder {
+        async fn send(&self, message: &Message) -> Result<()> {
+            self.0.lock().push(message.clone());
+            Ok(())
+        }
+        async fn receive(&self) -> Result<Option<Message>> {
+            Ok(None)
+        }
+        async fn open(&self) -> Result<()> {
+            Ok(())
+        }
+        async fn close(&self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    async fn record(policy: FaultPolicy) -> Vec<Message> {
+        let recorder = Recorder::default();
+        let transport = FaultInjectingTransport::with_policy(recorder.clone(), policy);
+        for id in 0..50 {
+            let _ = transport.send(&ping(id)).await;
+        }
+        let sent = recorder.0.lock().clone();
+        sent
+    }
+
+    #[tokio::test]
+    async fn test_policy_is_deterministic() {
+        let policy = FaultPolicy {
+            latency: None,
+            ..FaultPolicy::heavy(7)
+        };
+        let first = record(policy.clone()).await;
+        assert_eq!(first, record(policy).await);
+        assert_ne!(first.len(), 50);
+        assert_ne!(
+            first,
+            record(FaultPolicy {
+                latency: None,
+                ..FaultPolicy::heavy(8)
+            })
+            .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_held_messages_are_released() -> Result<()> {
+        let policy = FaultPolicy {
+            max_reorder_window: 4,
+            ..Default::default()
+        };
+        let recorder = Recorder::default();
+        let transport = FaultInjectingTransport::with_policy(recorder.clone(), policy.clone());
+        transport.send(&ping(1)).await?;
+        transport.send(&ping(2)).await?;
+        assert!(recorder.0.lock().is_empty());
+        // Nothing else comes to fill the window
+        tokio::time::sleep(REORDER_QUIET_PERIOD * 4).await;
+        assert_eq!(recorder.0.lock().len(), 2);
+
+        let recorder = Recorder::default();
+        let transport = FaultInjectingTransport::with_policy(recorder.clone(), policy);
+        transport.send(&ping(1)).await?;
+        transport.close().await?;
+        assert_eq!(*recorder.0.lock(), vec![ping(1)]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_after_n_messages() {
+        let policy = FaultPolicy::default().disconnect_after(3);
+        let sent = record(policy).await;
+        assert_eq!(sent.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_pingpong_under_heavy_faults() -> Result<()> {
+        use crate::protocol::RequestOptions;
+
+        let transport = ClientInMemoryTransport::new(|t: ServerInMemoryTransport| {
+            tokio::spawn(async move {
+                let protocol = Protocol::builder(t)
+                    .request_handler("ping", |_: serde_json::Value| {
+                        Box::pin(async move { Ok(serde_json::json!("pong")) })
+                    })
+                    .build();
+                protocol.listen().await.unwrap();
+            })
+        });
+        let chaos = FaultInjectingTransport::with_policy(transport.clone(), FaultPolicy::heavy(42));
+        chaos.open().await?;
+        let protocol = Protocol::builder(chaos).build();
+        let listener = protocol.clone();
+        tokio::spawn(async move { listener.listen().await });
+
+        let exchange = async {
+            let (mut ok, mut failed) = (0, 0);
+            for _ in 0..10 {
+                // Retry each ping a few times before giving up
+                let mut result = Err(anyhow::anyhow!("not attempted"));
+                for _ in 0..5 {
+                    let options = RequestOptions::default().timeout(Duration::from_millis(100));
+                    result = protocol.request("ping", None, options).await;
+                    if result.is_ok() {
+                        break;
+                    }
+                }
+                match result {
+                    Ok(response) => {
+                        assert_eq!(response.result, Some(serde_json::json!("pong")));
+                        ok += 1;
+                    }
+                    Err(e) => {
+                        assert!(e.to_string().contains("timed out"), "{e}");
+                        failed += 1;
+                    }
+                }
+            }
+            (ok, failed)
+        };
+        let (ok, failed) = tokio::time::timeout(Duration::from_secs(30), exchange).await?;
+        assert_eq!(ok + failed, 10);
+        assert!(ok > 0);
+        Ok(())
+    }
+}
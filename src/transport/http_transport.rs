@@ -1,8 +1,9 @@
 use super::{
-    ClientSseTransport, ClientWsTransport, Message, ServerSseTransport, ServerWsTransport,
-    Transport,
+    ClientSseTransport, ClientWsTransport, ConnectionActivity, Message, ServerSseTransport,
+    ServerWsTransport, Transport,
 };
 use anyhow::Result;
+use std::time::Duration;
 pub enum ServerHttpTransport {
     Sse(ServerSseTransport),
     Ws(ServerWsTransport),
@@ -12,6 +13,15 @@ pub enum ClientHttpTransport {
     Ws(ClientWsTransport),
 }
 
+impl ServerHttpTransport {
+    pub fn activity(&self) -> &ConnectionActivity {
+        match self {
+            ServerHttpTransport::Sse(sse) => sse.activity(),
+            ServerHttpTransport::Ws(ws) => ws.activity(),
+        }
+    }
+}
+
 impl Clone for ServerHttpTransport {
     fn clone(&self) -> Self {
         match self {
@@ -50,6 +60,20 @@ impl Transport for ServerHttpTransport {
             ServerHttpTransport::Ws(ws) => ws.close().await,
         }
     }
+
+    async fn close_with_timeout(&self, timeout: Duration) -> Result<()> {
+        match self {
+            ServerHttpTransport::Sse(sse) => sse.close_with_timeout(timeout).await,
+            ServerHttpTransport::Ws(ws) => ws.close_with_timeout(timeout).await,
+        }
+    }
+
+    async fn closed(&self) {
+        match self {
+            ServerHttpTransport::Sse(sse) => sse.closed().await,
+            ServerHttpTransport::Ws(ws) => ws.closed().await,
+        }
+    }
 }
 
 impl Clone for ClientHttpTransport {
@@ -90,4 +114,11 @@ impl Transport for ClientHttpTransport {
             ClientHttpTransport::Ws(ws) => ws.close().await,
         }
     }
+
+    async fn close_with_timeout(&self, timeout: Duration) -> Result<()> {
+        match self {
+            ClientHttpTransport::Sse(sse) => sse.close_with_timeout(timeout).await,
+            ClientHttpTransport::Ws(ws) => ws.close_with_timeout(timeout).await,
+        }
+    }
 }
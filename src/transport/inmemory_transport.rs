@@ -1,9 +1,10 @@
-use super::{Message, Transport};
+use super::{Message, Transport, DEFAULT_DRAIN_TIMEOUT};
 use anyhow::Result;
 use async_trait::async_trait;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::mpsc::{self, Receiver, Sender};
-use tokio::sync::Mutex;
+use tokio::sync::{watch, Mutex};
 use tokio::task::JoinHandle;
 use tracing::debug;
 
@@ -12,6 +13,7 @@ use tracing::debug;
 pub struct ServerInMemoryTransport {
     rx: Arc<Mutex<Option<Receiver<Message>>>>,
     tx: Sender<Message>,
+    crashed: Crashed,
 }
 
 impl Default for ServerInMemoryTransport {
@@ -20,6 +22,35 @@ impl Default for ServerInMemoryTransport {
         Self {
             rx: Arc::new(Mutex::new(Some(rx))),
             tx,
+            crashed: Crashed::default(),
+        }
+    }
+}
+
+/// Set by [`ClientInMemoryTransport::crash_server`], shared by both ends of a connection
+/// so tasks the server spawned itself see the disconnect too
+#[derive(Clone, Default)]
+struct Crashed(Arc<watch::Sender<bool>>);
+
+impl Crashed {
+    fn crash(&self) {
+        self.0.send_replace(true);
+    }
+
+    fn check(&self) -> Result<()> {
+        if *self.0.borrow() {
+            return Err(anyhow::anyhow!("In-memory server crashed"));
+        }
+        Ok(())
+    }
+
+    /// Receive from `rx`, `None` once crashed
+    async fn recv(&self, rx: &mut Receiver<Message>) -> Option<Message> {
+        let mut crashed = self.0.subscribe();
+        tokio::select! {
+            biased;
+            _ = crashed.wait_for(|crashed| *crashed) => None,
+            message = rx.recv() => message,
         }
     }
 }
@@ -32,7 +63,7 @@ impl Transport for ServerInMemoryTransport {
             .as_mut()
             .ok_or_else(|| anyhow::anyhow!("Transport not opened"))?;
 
-        match rx.recv().await {
+        match self.crashed.recv(rx).await {
             Some(message) => {
                 debug!("Server received: {:?}", message);
                 Ok(Some(message))
@@ -46,6 +77,7 @@ impl Transport for ServerInMemoryTransport {
 
     async fn send(&self, message: &Message) -> Result<()> {
         debug!("Server sending: {:?}", message);
+        self.crashed.check()?;
         self.tx
             .send(message.clone())
             .await
@@ -58,9 +90,28 @@ impl Transport for ServerInMemoryTransport {
     }
 
     async fn close(&self) -> Result<()> {
+        self.close_with_timeout(DEFAULT_DRAIN_TIMEOUT).await
+    }
+
+    async fn close_with_timeout(&self, timeout: Duration) -> Result<()> {
+        if tokio::time::timeout(timeout, drained(&self.tx))
+            .await
+            .is_err()
+        {
+            debug!("Client left messages unread on close");
+        }
         *self.rx.lock().await = None;
         Ok(())
     }
+
+    // The client drops its receiver on close
+    async fn closed(&self) {
+        let mut crashed = self.crashed.0.subscribe();
+        tokio::select! {
+            _ = self.tx.closed() => {}
+            _ = crashed.wait_for(|crashed| *crashed) => {}
+        }
+    }
 }
 
 /// Client-side transport that communicates with a spawned server task
@@ -68,6 +119,8 @@ impl Transport for ServerInMemoryTransport {
 pub struct ClientInMemoryTransport {
     tx: Arc<Mutex<Option<Sender<Message>>>>,
     rx: Arc<Mutex<Option<Receiver<Message>>>>,
+    // Of the current connection, replaced when the server is spawned again
+    crashed: Arc<parking_lot::Mutex<Crashed>>,
     server_handle: Arc<Mutex<Option<JoinHandle<()>>>>,
     server_factory: Arc<dyn Fn(ServerInMemoryTransport) -> JoinHandle<()> + Send + Sync>,
 }
@@ -80,10 +133,53 @@ impl ClientInMemoryTransport {
         Self {
             tx: Arc::new(Mutex::new(None)),
             rx: Arc::new(Mutex::new(None)),
+            crashed: Arc::new(parking_lot::Mutex::new(Crashed::default())),
             server_handle: Arc::new(Mutex::new(None)),
             server_factory: Arc::new(server_factory),
         }
     }
+
+    /// Abort the server task as if it crashed, pending and later receives return `None`
+    /// and sends fail until [`reopen`](Self::reopen). Tasks the server spawned get `None`
+    /// from its transport too, so a server listening in one of them stops
+    pub async fn crash_server(&self) {
+        self.crashed.lock().crash();
+        if let Some(handle) = self.server_handle.lock().await.take() {
+            handle.abort();
+            let _ = handle.await;
+        }
+    }
+
+    /// Abort the server task if it is still running and start a fresh one from the factory,
+    /// for testing code that reconnects after the server went away
+    pub async fn reopen(&self) -> Result<()> {
+        self.crash_server().await;
+        let handle = self.spawn_server().await;
+        *self.server_handle.lock().await = Some(handle);
+        Ok(())
+    }
+
+    async fn spawn_server(&self) -> JoinHandle<()> {
+        let (client_tx, server_rx) = mpsc::channel(100);
+        let (server_tx, client_rx) = mpsc::channel(100);
+
+        let crashed = Crashed::default();
+        let server_transport = ServerInMemoryTransport {
+            rx: Arc::new(Mutex::new(Some(server_rx))),
+            tx: server_tx,
+            crashed: crashed.clone(),
+        };
+
+        let server_handle = (self.server_factory)(server_transport);
+
+        // Swapped under the receiver's lock, a receive sees the signal of its channel
+        let mut rx_guard = self.rx.lock().await;
+        *self.crashed.lock() = crashed;
+        *rx_guard = Some(client_rx);
+        drop(rx_guard);
+        *self.tx.lock().await = Some(client_tx);
+        server_handle
+    }
 }
 
 #[async_trait]
@@ -94,7 +190,8 @@ impl Transport for ClientInMemoryTransport {
             .as_mut()
             .ok_or_else(|| anyhow::anyhow!("Transport not opened"))?;
 
-        match rx.recv().await {
+        let crashed = self.crashed.lock().clone();
+        match crashed.recv(rx).await {
             Some(message) => {
                 debug!("Client received: {:?}", message);
                 Ok(Some(message))
@@ -113,6 +210,7 @@ impl Transport for ClientInMemoryTransport {
             .ok_or_else(|| anyhow::anyhow!("Transport not opened"))?;
 
         debug!("Client sending: {:?}", message);
+        self.crashed.lock().check()?;
         tx.send(message.clone())
             .await
             .map_err(|e| anyhow::anyhow!("Failed to send message: {}", e))?;
@@ -120,25 +218,29 @@ impl Transport for ClientInMemoryTransport {
     }
 
     async fn open(&self) -> Result<()> {
-        let (client_tx, server_rx) = mpsc::channel(100);
-        let (server_tx, client_rx) = mpsc::channel(100);
-
-        let server_transport = ServerInMemoryTransport {
-            rx: Arc::new(Mutex::new(Some(server_rx))),
-            tx: server_tx,
-        };
-
-        let server_handle = (self.server_factory)(server_transport);
-
-        *self.rx.lock().await = Some(client_rx);
-        *self.tx.lock().await = Some(client_tx);
-        *self.server_handle.lock().await = Some(server_handle);
-
+        // Held until the new server is stored, concurrent opens spawn it once
+        let mut handle_guard = self.server_handle.lock().await;
+        if handle_guard
+            .as_ref()
+            .is_some_and(|handle| !handle.is_finished())
+        {
+            return Ok(());
+        }
+        *handle_guard = Some(self.spawn_server().await);
         Ok(())
     }
 
     async fn close(&self) -> Result<()> {
-        *self.tx.lock().await = None;
+        self.close_with_timeout(DEFAULT_DRAIN_TIMEOUT).await
+    }
+
+    async fn close_with_timeout(&self, timeout: Duration) -> Result<()> {
+        let tx = self.tx.lock().await.take();
+        if let Some(tx) = tx {
+            if tokio::time::timeout(timeout, drained(&tx)).await.is_err() {
+                debug!("Server left messages unread on close");
+            }
+        }
         *self.rx.lock().await = None;
 
         if let Some(handle) = self.server_handle.lock().await.take() {
@@ -149,10 +251,15 @@ impl Transport for ClientInMemoryTransport {
     }
 }
 
+/// Resolves once the receiver took every message queued on `tx` or went away
+async fn drained(tx: &Sender<Message>) {
+    let _ = tx.reserve_many(tx.max_capacity()).await;
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::transport::{JsonRpcMessage, JsonRpcRequest, JsonRpcVersion};
+    use crate::transport::{JsonRpcMessage, JsonRpcNotification, JsonRpcRequest, JsonRpcVersion};
     use std::time::Duration;
 
     async fn echo_server(transport: ServerInMemoryTransport) {
@@ -261,4 +368,116 @@ mod tests {
         transport.close().await?;
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_open_is_idempotent() -> Result<()> {
+        let spawned = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let factory_spawned = spawned.clone();
+        let transport = ClientInMemoryTransport::new(move |t| {
+            factory_spawned.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            tokio::spawn(echo_server(t))
+        });
+
+        let (first, second) = tokio::join!(transport.open(), transport.open());
+        first?;
+        second?;
+        assert_eq!(spawned.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        // Closing ended the server, opening again starts a new one
+        transport.close().await?;
+        transport.open().await?;
+        assert_eq!(spawned.load(std::sync::atomic::Ordering::SeqCst), 2);
+
+        transport.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_crash_and_reopen() -> Result<()> {
+        let spawned = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let factory_spawned = spawned.clone();
+        let (stopped_tx, mut stopped) = mpsc::unbounded_channel();
+        let transport = ClientInMemoryTransport::new(move |t| {
+            factory_spawned.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            // The server runs in a task of its own, the returned one only waits
+            let stopped_tx = stopped_tx.clone();
+            let server = tokio::spawn(async move {
+                echo_server(t).await;
+                let _ = stopped_tx.send(());
+            });
+            tokio::spawn(async move {
+                let _ = server.await;
+            })
+        });
+        let message = |id| {
+            JsonRpcMessage::Request(JsonRpcRequest {
+                id,
+                method: "test".to_string(),
+                params: None,
+                jsonrpc: JsonRpcVersion::default(),
+            })
+        };
+        transport.open().await?;
+        transport.send(&message(1)).await?;
+        assert_eq!(transport.receive().await?, Some(message(1)));
+
+        // A reader blocked on the crashed server sees the disconnect
+        let reader = transport.clone();
+        let pending = tokio::spawn(async move { reader.receive().await });
+        // Blocked once it holds the receiver
+        while transport.rx.try_lock().is_ok() {
+            tokio::task::yield_now().await;
+        }
+        transport.crash_server().await;
+        assert_eq!(
+            tokio::time::timeout(Duration::from_secs(5), pending).await???,
+            None
+        );
+        assert!(transport.send(&message(2)).await.is_err());
+        // The server task stopped although only the waiting task was aborted
+        tokio::time::timeout(Duration::from_secs(5), stopped.recv()).await?;
+
+        transport.reopen().await?;
+        transport.send(&message(3)).await?;
+        assert_eq!(transport.receive().await?, Some(message(3)));
+        assert_eq!(spawned.load(std::sync::atomic::Ordering::SeqCst), 2);
+
+        // Reopening a running server replaces it
+        transport.reopen().await?;
+        transport.send(&message(4)).await?;
+        assert_eq!(transport.receive().await?, Some(message(4)));
+        assert_eq!(spawned.load(std::sync::atomic::Ordering::SeqCst), 3);
+
+        transport.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_close_waits_for_unread_messages() -> Result<()> {
+        let closed = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let server_closed = closed.clone();
+        let notification = JsonRpcMessage::Notification(JsonRpcNotification {
+            method: "notifications/progress".to_string(),
+            ..Default::default()
+        });
+        let sent = notification.clone();
+        let transport = ClientInMemoryTransport::new(move |t| {
+            let closed = server_closed.clone();
+            let sent = sent.clone();
+            tokio::spawn(async move {
+                t.send(&sent).await.unwrap();
+                t.close_with_timeout(Duration::from_secs(5)).await.unwrap();
+                closed.store(true, std::sync::atomic::Ordering::SeqCst);
+            })
+        });
+        transport.open().await?;
+
+        // The server's close waits for the client to read the notification
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!closed.load(std::sync::atomic::Ordering::SeqCst));
+        assert_eq!(transport.receive().await?, Some(notification));
+        transport.close().await?;
+        assert!(closed.load(std::sync::atomic::Ordering::SeqCst));
+        Ok(())
+    }
 }
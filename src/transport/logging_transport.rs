@@ -0,0 +1,336 @@
+use super::{Message, RequestId, Transport};
+use anyhow::Result;
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{warn, Level};
+
+const DEFAULT_MAX_BODY_LEN: usize = 1024;
+
+// tracing needs the level at compile time
+macro_rules! log_at {
+    ($level:expr, $($arg:tt)+) => {
+        match $level {
+            Level::ERROR => tracing::error!($($arg)+),
+            Level::WARN => tracing::warn!($($arg)+),
+            Level::INFO => tracing::info!($($arg)+),
+            Level::DEBUG => tracing::debug!($($arg)+),
+            _ => tracing::trace!($($arg)+),
+        }
+    };
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Direction {
+    Sent,
+    Received,
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Direction::Sent => write!(f, "sent"),
+            Direction::Received => write!(f, "received"),
+        }
+    }
+}
+
+struct Pending {
+    correlation: u64,
+    method: String,
+    at: Instant,
+}
+
+#[derive(Default)]
+struct LogState {
+    next_correlation: AtomicU64,
+    // Requests we sent, answered by responses we receive
+    outbound: Mutex<HashMap<RequestId, Pending>>,
+    // Requests we received, answered by responses we send
+    inbound: Mutex<HashMap<RequestId, Pending>>,
+    unmatched: AtomicU64,
+}
+
+impl LogState {
+    /// Requests travelling in `direction`
+    fn requests(&self, direction: Direction) -> &Mutex<HashMap<RequestId, Pending>> {
+        match direction {
+            Direction::Sent => &self.outbound,
+            Direction::Received => &self.inbound,
+        }
+    }
+}
+
+/// Logs every message going through `T`, pairing each request with its response
+/// a request gets a correlation id that is repeated on the line of its response along
+/// with the elapsed time, responses to unknown requests are logged as warnings
+pub struct LoggingTransport<T> {
+    inner: T,
+    level: Level,
+    max_body_len: usize,
+    state: Arc<LogState>,
+}
+
+impl<T: Clone> Clone for LoggingTransport<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            level: self.level,
+            max_body_len: self.max_body_len,
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl<T: Transport> LoggingTransport<T> {
+    /// Logs at `info`, bodies are cut after 1024 bytes
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            level: Level::INFO,
+            max_body_len: DEFAULT_MAX_BODY_LEN,
+            state: Arc::new(LogState::default()),
+        }
+    }
+
+    /// Level of the request and response lines, unmatched responses are always warnings
+    pub fn level(mut self, level: Level) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Bytes of JSON logged per message, the rest is replaced by the total size
+    pub fn max_body_len(mut self, max_body_len: usize) -> Self {
+        self.max_body_len = max_body_len;
+        self
+    }
+
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    /// Requests in either direction still waiting for their response
+    pub fn pending_requests(&self) -> usize {
+        self.state.outbound.lock().len() + self.state.inbound.lock().len()
+    }
+
+    /// Responses seen without a matching request so far
+    pub fn unmatched_responses(&self) -> u64 {
+        self.state.unmatched.load(Ordering::Relaxed)
+    }
+
+    fn body(&self, message: &Message) -> String {
+        let json = serde_json::to_string(message).unwrap_or_default();
+        truncate(&json, self.max_body_len).into_owned()
+    }
+
+    fn observe(&self, message: &Message, direction: Direction) {
+        match message {
+            Message::Batch(messages) => {
+                for message in messages {
+                    self.observe(message, direction);
+                }
+            }
+            Message::Request(request) => {
+                let correlation = self.state.next_correlation.fetch_add(1, Ordering::Relaxed) + 1;
+                self.state.requests(direction).lock().insert(
+                    request.id,
+                    Pending {
+                        correlation,
+                        method: request.method.clone(),
+                        at: Instant::now(),
+                    },
+                );
+                log_at!(
+                    self.level,
+                    correlation,
+                    id = request.id,
+                    method = %request.method,
+                    body = %self.body(message),
+                    "{} request",
+                    direction
+                );
+            }
+            Message::Response(response) => {
+                let answered = match direction {
+                    Direction::Sent => Direction::Received,
+                    Direction::Received => Direction::Sent,
+                };
+                let pending = self.state.requests(answered).lock().remove(&response.id);
+                match pending {
+                    Some(pending) => log_at!(
+                        self.level,
+                        correlation = pending.correlation,
+                        id = response.id,
+                        method = %pending.method,
+                        elapsed_ms = pending.at.elapsed().as_millis() as u64,
+                        body = %self.body(message),
+                        "{} response",
+                        direction
+                    ),
+                    None => {
+                        self.state.unmatched.fetch_add(1, Ordering::Relaxed);
+                        warn!(
+                            id = response.id,
+                            body = %self.body(message),
+                            "{} response with no matching request",
+                            direction
+                        );
+                    }
+                }
+            }
+            Message::Notification(notification) => log_at!(
+                self.level,
+                method = %notification.method,
+                body = %self.body(message),
+                "{} notification",
+                direction
+            ),
+            Message::UnidentifiedError(_) => warn!(
+                body = %self.body(message),
+                "{} error without id",
+                direction
+            ),
+        }
+    }
+}
+
+/// Cut `json` at a char boundary, noting the full size
+fn truncate(json: &str, max_len: usize) -> Cow<'_, str> {
+    if json.len() <= max_len {
+        return Cow::Borrowed(json);
+    }
+    let end = (0..=max_len)
+        .rev()
+        .find(|i| json.is_char_boundary(*i))
+        .unwrap_or(0);
+    Cow::Owned(format!("{}... ({} bytes)", &json[..end], json.len()))
+}
+
+#[async_trait]
+impl<T: Transport> Transport for LoggingTransport<T> {
+    async fn send(&self, message: &Message) -> Result<()> {
+        // Tracked before sending, the response may be received before send returns
+        self.observe(message, Direction::Sent);
+        self.inner.send(message).await
+    }
+
+    async fn receive(&self) -> Result<Option<Message>> {
+        let message = self.inner.receive().await?;
+        if let Some(message) = &message {
+            self.observe(message, Direction::Received);
+        }
+        Ok(message)
+    }
+
+    async fn open(&self) -> Result<()> {
+        self.inner.open().await
+    }
+
+    async fn close(&self) -> Result<()> {
+        self.inner.close().await
+    }
+
+    async fn close_with_timeout(&self, timeout: Duration) -> Result<()> {
+        self.inner.close_with_timeout(timeout).await
+    }
+
+    async fn closed(&self) {
+        self.inner.closed().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::{
+        ClientInMemoryTransport, JsonRpcRequest, JsonRpcResponse, ServerInMemoryTransport,
+    };
+
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // Answers every request, then a response nobody asked for
+    async fn responder(transport: ServerInMemoryTransport) {
+        while let Ok(Some(Message::Request(request))) = transport.receive().await {
+            for id in [request.id, 99] {
+                let response = Message::Response(JsonRpcResponse {
+                    id,
+                    result: Some(serde_json::json!({"echo": "x".repeat(100)})),
+                    ..Default::default()
+                });
+                if transport.send(&response).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_logging_transport() -> Result<()> {
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .with_max_level(Level::DEBUG)
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let transport =
+            LoggingTransport::new(ClientInMemoryTransport::new(|t| tokio::spawn(responder(t))))
+                .level(Level::DEBUG)
+                .max_body_len(40);
+        transport.open().await?;
+
+        transport
+            .send(&Message::Request(JsonRpcRequest {
+                id: 7,
+                method: "tools/list".to_string(),
+                ..Default::default()
+            }))
+            .await?;
+        assert_eq!(transport.pending_requests(), 1);
+        transport.receive().await?;
+        transport.receive().await?;
+        assert_eq!(transport.pending_requests(), 0);
+        assert_eq!(transport.unmatched_responses(), 1);
+        transport.close().await?;
+
+        let logs = String::from_utf8(captured.0.lock().clone())?;
+        let lines: Vec<_> = logs
+            .lines()
+            .filter(|line| line.contains("logging_transport"))
+            .collect();
+        assert!(lines[0].contains("DEBUG") && lines[0].contains("sent request"));
+        assert!(lines[0].contains("correlation=1 id=7 method=tools/list"));
+        assert!(lines[1].contains("received response"));
+        assert!(lines[1].contains("correlation=1 id=7 method=tools/list elapsed_ms="));
+        assert!(lines[1].contains("bytes)"));
+        assert!(lines[2].contains("WARN") && lines[2].contains("no matching request"));
+        assert!(lines[2].contains("id=99"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("short", 10), "short");
+        assert_eq!(truncate("héllo world", 2), "h... (12 bytes)");
+    }
+}
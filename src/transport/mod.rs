@@ -2,9 +2,14 @@
 //! handles the serialization and deserialization of message
 //! handles send and receive of messages
 //! defines transport layer types
+use crate::types::{ErrorCode, ErrorData};
 use anyhow::Result;
 use async_trait::async_trait;
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
 mod stdio_transport;
 pub use stdio_transport::*;
@@ -16,10 +21,37 @@ mod ws_transport;
 pub use ws_transport::*;
 mod http_transport;
 pub use http_transport::*;
+mod boxed_transport;
+pub use boxed_transport::*;
+mod multi_transport;
+pub use multi_transport::*;
+mod logging_transport;
+pub use logging_transport::*;
+mod compression;
+pub use compression::{decode_frame, encode_frame, COMPRESSION_CAPABILITY};
+pub mod validation;
+pub use validation::{message_anomalies, MessageAnomalies, MessageViolation};
+#[cfg(any(test, feature = "test-util"))]
+mod fault_transport;
+#[cfg(any(test, feature = "test-util"))]
+pub use fault_transport::*;
+#[cfg(feature = "quic")]
+mod quic_transport;
+#[cfg(feature = "quic")]
+pub use quic_transport::*;
 /// only JsonRpcMessage is supported for now
 /// https://spec.modelcontextprotocol.io/specification/basic/messages/
 pub type Message = JsonRpcMessage;
 
+tokio::task_local! {
+    /// Per-message headers set through `RequestOptions::header`
+    /// scoped to the send of a single message, transports without headers ignore them
+    pub static MESSAGE_HEADERS: HashMap<String, String>;
+}
+
+/// How long [`Transport::close`] gives queued outbound messages to reach the peer
+pub const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
 #[async_trait]
 pub trait Transport: Send + Sync + 'static {
     /// Send a message to the transport
@@ -34,6 +66,167 @@ pub trait Transport: Send + Sync + 'static {
 
     /// Close the transport
     async fn close(&self) -> Result<()>;
+
+    /// Close the transport once queued outbound messages reached the peer, waiting at most
+    /// `timeout` for them, transports without an outbound queue just close
+    async fn close_with_timeout(&self, _timeout: Duration) -> Result<()> {
+        self.close().await
+    }
+
+    /// Resolves once the peer is gone, in-flight requests are aborted when it does
+    /// transports that can't detect it never resolve
+    async fn closed(&self) {
+        futures::future::pending::<()>().await
+    }
+}
+
+/// Limits applied when decoding a message from a peer
+/// guards against stack exhaustion from deeply nested JSON and against huge payloads,
+/// serde_json refuses nesting beyond 128 levels regardless of `max_depth`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeLimits {
+    pub max_depth: usize,
+    pub max_bytes: usize,
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        Self {
+            max_depth: 128,
+            max_bytes: 4 * 1024 * 1024,
+        }
+    }
+}
+
+impl DecodeLimits {
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    pub fn max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    /// Parse a message, failing with an `invalid_message` error when it exceeds the limits
+    pub fn decode(&self, text: &str) -> Result<Message> {
+        if text.len() > self.max_bytes {
+            return Err(invalid_message(format!(
+                "message of {} bytes exceeds the limit of {} bytes",
+                text.len(),
+                self.max_bytes
+            ))
+            .into());
+        }
+        // Check the nesting before parsing, the parser recurses once per level
+        let mut depth = 0usize;
+        let mut in_string = false;
+        let mut escaped = false;
+        for byte in text.bytes() {
+            if in_string {
+                match byte {
+                    _ if escaped => escaped = false,
+                    b'\\' => escaped = true,
+                    b'"' => in_string = false,
+                    _ => {}
+                }
+                continue;
+            }
+            match byte {
+                b'"' => in_string = true,
+                b'[' | b'{' => {
+                    depth += 1;
+                    if depth > self.max_depth {
+                        return Err(invalid_message(format!(
+                            "message nested deeper than {} levels",
+                            self.max_depth
+                        ))
+                        .into());
+                    }
+                }
+                b']' | b'}' => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+        }
+        serde_json::from_str(text)
+            .map_err(|e| invalid_message(format!("invalid message: {}", e)).into())
+    }
+}
+
+fn invalid_message(message: String) -> JsonRpcError {
+    JsonRpcError::with_error_data(
+        ErrorCode::ParseError,
+        message,
+        ErrorData::new(ErrorData::INVALID_MESSAGE, false),
+    )
+}
+
+/// Source of the current time, injectable so tests can advance time without sleeping
+pub trait Clock: Send + Sync + std::fmt::Debug {
+    fn now(&self) -> SystemTime;
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// Connect time and time of the last message received on a server-side connection
+#[derive(Debug)]
+pub struct ConnectionActivity {
+    clock: Arc<dyn Clock>,
+    connected_at: SystemTime,
+    // Milliseconds after `connected_at`
+    last_activity_ms: AtomicU64,
+}
+
+impl Default for ConnectionActivity {
+    fn default() -> Self {
+        Self::with_clock(Arc::new(SystemClock))
+    }
+}
+
+impl ConnectionActivity {
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            connected_at: clock.now(),
+            clock,
+            last_activity_ms: AtomicU64::new(0),
+        }
+    }
+
+    /// Record a message from the peer
+    pub fn touch(&self) {
+        let elapsed = self
+            .clock
+            .now()
+            .duration_since(self.connected_at)
+            .unwrap_or_default();
+        self.last_activity_ms
+            .fetch_max(elapsed.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    pub fn connected_at(&self) -> SystemTime {
+        self.connected_at
+    }
+
+    /// The connect time until the peer sends something
+    pub fn last_activity(&self) -> SystemTime {
+        self.connected_at + Duration::from_millis(self.last_activity_ms.load(Ordering::Relaxed))
+    }
+
+    /// Time since the last message, or since connecting
+    pub fn idle_for(&self) -> Duration {
+        self.clock
+            .now()
+            .duration_since(self.last_activity())
+            .unwrap_or_default()
+    }
 }
 
 /// Request ID type
@@ -55,13 +248,17 @@ impl JsonRpcVersion {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
-#[serde(deny_unknown_fields)]
+/// Deserialized by the rules of the [`validation`] table, not by trying each variant
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
 #[serde(untagged)]
 pub enum JsonRpcMessage {
     Response(JsonRpcResponse),
     Request(JsonRpcRequest),
     Notification(JsonRpcNotification),
+    /// Several messages sent as one JSON array
+    Batch(Vec<JsonRpcMessage>),
+    /// Error answering a message without a usable id, sent with `id: null`
+    UnidentifiedError(JsonRpcUnidentifiedError),
 }
 
 // json rpc types
@@ -93,8 +290,11 @@ pub struct JsonRpcNotification {
 pub struct JsonRpcResponse {
     /// The request ID this response corresponds to
     pub id: RequestId,
-    /// The result of the request, if successful
-    #[serde(skip_serializing_if = "Option::is_none")]
+    /// The result of the request, if successful, `null` is kept as a result
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "deserialize_result"
+    )]
     pub result: Option<serde_json::Value>,
     /// The error, if the request failed
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -103,6 +303,64 @@ pub struct JsonRpcResponse {
     pub jsonrpc: JsonRpcVersion,
 }
 
+/// Error response with `id: null`, for messages whose id can't be known such as an empty batch
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct JsonRpcUnidentifiedError {
+    pub error: JsonRpcError,
+    pub jsonrpc: JsonRpcVersion,
+}
+
+impl JsonRpcUnidentifiedError {
+    pub fn new(error: JsonRpcError) -> Self {
+        Self {
+            error,
+            jsonrpc: JsonRpcVersion::default(),
+        }
+    }
+}
+
+impl Serialize for JsonRpcUnidentifiedError {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+        let mut state = serializer.serialize_struct("JsonRpcUnidentifiedError", 3)?;
+        state.serialize_field("id", &())?;
+        state.serialize_field("error", &self.error)?;
+        state.serialize_field("jsonrpc", &self.jsonrpc)?;
+        state.end()
+    }
+}
+
+fn deserialize_result<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Option<serde_json::Value>, D::Error> {
+    serde_json::Value::deserialize(deserializer).map(Some)
+}
+
+impl JsonRpcResponse {
+    /// The raw result, or the error response as a [`JsonRpcError`]
+    pub fn error_or_result(self) -> Result<serde_json::Value> {
+        match (self.result, self.error) {
+            (_, Some(error)) => Err(error.into()),
+            (Some(result), None) => Ok(result),
+            (None, None) => Err(anyhow::anyhow!("Request failed: empty response")),
+        }
+    }
+
+    /// Deserialize the result into `T`
+    /// error responses are returned as a [`JsonRpcError`] which can be recovered with `downcast_ref`
+    pub fn result_as<T: DeserializeOwned>(&self) -> Result<T> {
+        if let Some(error) = &self.error {
+            return Err(error.clone().into());
+        }
+        let result = self.result.clone().unwrap_or(serde_json::Value::Null);
+        serde_json::from_value(result)
+            .map_err(|e| anyhow::anyhow!("Failed to parse response: {}", e))
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
 #[serde(rename_all = "camelCase")]
 #[serde(default)]
@@ -116,9 +374,173 @@ pub struct JsonRpcError {
     pub data: Option<serde_json::Value>,
 }
 
+impl JsonRpcError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code: code as i32,
+            message: message.into(),
+            data: None,
+        }
+    }
+
+    pub fn with_data(mut self, data: serde_json::Value) -> Self {
+        self.data = Some(data);
+        self
+    }
+
+    /// Error carrying the standard [`ErrorData`] payload
+    pub fn with_error_data(code: ErrorCode, message: impl Into<String>, data: ErrorData) -> Self {
+        let error = Self::new(code, message);
+        match serde_json::to_value(data) {
+            Ok(data) => error.with_data(data),
+            Err(_) => error,
+        }
+    }
+
+    /// The standard [`ErrorData`] payload, `None` when the peer sent none or something else
+    pub fn error_data(&self) -> Option<ErrorData> {
+        serde_json::from_value(self.data.clone()?).ok()
+    }
+
+    /// `tools/call` targeted a tool the server doesn't have, the client should refresh its tool list
+    pub fn tool_not_found(name: &str) -> Self {
+        Self::with_error_data(
+            ErrorCode::InvalidParams,
+            format!("Unknown tool: {}", name),
+            ErrorData::new(ErrorData::TOOL_NOT_FOUND, false)
+                .details(serde_json::json!({ "name": name })),
+        )
+    }
+
+    /// `prompts/get` named a prompt the server doesn't have
+    pub fn prompt_not_found(name: &str) -> Self {
+        Self::with_error_data(
+            ErrorCode::InvalidParams,
+            format!("Unknown prompt: {}", name),
+            ErrorData::new(ErrorData::PROMPT_NOT_FOUND, false)
+                .details(serde_json::json!({ "name": name })),
+        )
+    }
+
+    /// The server answers `method` only when it advertises `capability`
+    pub fn capability_not_advertised(method: &str, capability: &str) -> Self {
+        Self::with_error_data(
+            ErrorCode::MethodNotFound,
+            format!("Method not found: {}", method),
+            ErrorData::new(ErrorData::CAPABILITY_NOT_ADVERTISED, false)
+                .details(serde_json::json!({ "method": method, "capability": capability })),
+        )
+    }
+
+    /// Arguments the prompt declares as required weren't given
+    pub fn missing_arguments(prompt: &str, missing: &[&str]) -> Self {
+        Self::with_error_data(
+            ErrorCode::InvalidParams,
+            format!(
+                "Missing required arguments for prompt {}: {}",
+                prompt,
+                missing.join(", ")
+            ),
+            ErrorData::new(ErrorData::MISSING_ARGUMENTS, false)
+                .details(serde_json::json!({ "name": prompt, "missing": missing })),
+        )
+    }
+
+    /// The caller isn't allowed to make this request, e.g. rejected by a request interceptor
+    pub fn forbidden(message: impl Into<String>) -> Self {
+        Self::with_error_data(
+            ErrorCode::InvalidRequest,
+            message,
+            ErrorData::new(ErrorData::FORBIDDEN, false),
+        )
+    }
+
+    /// The tool can't take more calls right now, retry after `retry_after`
+    pub fn tool_busy(name: &str, retry_after: Duration) -> Self {
+        Self::with_error_data(
+            ErrorCode::InternalError,
+            format!("Tool busy: {}", name),
+            ErrorData::new(ErrorData::TOOL_BUSY, true)
+                .details(serde_json::json!({ "name": name }))
+                .retry_after(retry_after),
+        )
+    }
+
+    /// The transport didn't open within `limit`
+    pub fn connection_timeout(limit: Duration) -> Self {
+        Self::with_error_data(
+            ErrorCode::ConnectionTimeout,
+            format!("Connection not established within {:?}", limit),
+            ErrorData::new(ErrorData::CONNECTION_TIMEOUT, true),
+        )
+    }
+
+    /// Nothing was received from the peer for `limit`, the connection is presumed dead
+    pub fn idle_timeout(limit: Duration) -> Self {
+        Self::with_error_data(
+            ErrorCode::IdleTimeout,
+            format!("Nothing received for {:?}, disconnected", limit),
+            ErrorData::new(ErrorData::IDLE_TIMEOUT, true),
+        )
+    }
+
+    pub fn is_tool_not_found(&self) -> bool {
+        self.error_data()
+            .is_some_and(|data| data.kind == ErrorData::TOOL_NOT_FOUND)
+    }
+}
+
+impl std::fmt::Display for JsonRpcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "JSON-RPC error {}: {}", self.code, self.message)
+    }
+}
+
+impl std::error::Error for JsonRpcError {}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    #[test]
+    fn test_decode_limits() {
+        let kind = |err: anyhow::Error| {
+            let err = err.downcast::<JsonRpcError>().unwrap();
+            assert_eq!(err.code, ErrorCode::ParseError as i32);
+            err.error_data().unwrap().kind
+        };
+        let limits = DecodeLimits::default();
+
+        let json = r#"{"jsonrpc":"2.0","id":1,"method":"test","params":{"text":"[[[[{{"}}"#;
+        assert!(limits.decode(json).is_ok());
+
+        let nested = format!(
+            r#"{{"jsonrpc":"2.0","id":1,"method":"test","params":{{"a":{}{}}}}}"#,
+            "[".repeat(100_000),
+            "]".repeat(100_000)
+        );
+        assert_eq!(
+            kind(limits.decode(&nested).unwrap_err()),
+            ErrorData::INVALID_MESSAGE
+        );
+        let shallow = limits.max_depth(3);
+        assert!(shallow
+            .decode(r#"{"jsonrpc":"2.0","method":"a","params":{"b":[]}}"#)
+            .is_ok());
+        assert!(shallow
+            .decode(r#"{"jsonrpc":"2.0","method":"a","params":{"b":[[]]}}"#)
+            .is_err());
+
+        let small = limits.max_bytes(16);
+        assert_eq!(
+            kind(small.decode(json).unwrap_err()),
+            ErrorData::INVALID_MESSAGE
+        );
+        assert_eq!(
+            kind(limits.decode("{").unwrap_err()),
+            ErrorData::INVALID_MESSAGE
+        );
+    }
+
     #[test]
     fn test_deserialize_initialize_request() {
         let json = r#"{"method":"initialize","params":{"protocolVersion":"2024-11-05","capabilities":{},"clientInfo":{"name":"claude-ai","version":"0.1.0"}},"jsonrpc":"2.0","id":0}"#;
@@ -144,4 +566,46 @@ mod tests {
             _ => panic!("Expected Request variant"),
         }
     }
+
+    #[test]
+    fn test_response_result_as() {
+        let response = JsonRpcResponse {
+            id: 1,
+            result: Some(serde_json::json!({"protocolVersion": "2024-11-05"})),
+            ..Default::default()
+        };
+        let result: serde_json::Map<String, serde_json::Value> = response.result_as().unwrap();
+        assert_eq!(result["protocolVersion"], "2024-11-05");
+
+        let response = JsonRpcResponse {
+            id: 2,
+            error: Some(JsonRpcError {
+                code: -32601,
+                message: "Method not found: foo".to_string(),
+                data: None,
+            }),
+            ..Default::default()
+        };
+        let err = response.result_as::<serde_json::Value>().unwrap_err();
+        let err = err.downcast_ref::<JsonRpcError>().expect("typed error");
+        assert_eq!(err.code, -32601);
+        assert_eq!(err.message, "Method not found: foo");
+    }
+
+    #[test]
+    fn test_batch_roundtrip() {
+        let json = r#"[{"method":"ping","jsonrpc":"2.0","id":1},{"method":"notifications/initialized","jsonrpc":"2.0"}]"#;
+        let message: Message = serde_json::from_str(json).unwrap();
+        match &message {
+            JsonRpcMessage::Batch(messages) => {
+                assert!(matches!(messages[0], JsonRpcMessage::Request(_)));
+                assert!(matches!(messages[1], JsonRpcMessage::Notification(_)));
+            }
+            other => panic!("Expected batch, got {:?}", other),
+        }
+        assert_eq!(
+            serde_json::to_value(&message).unwrap(),
+            serde_json::from_str::<serde_json::Value>(json).unwrap()
+        );
+    }
 }
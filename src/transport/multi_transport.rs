@@ -0,0 +1,288 @@
+use super::{BoxedTransport, Message, RequestId, Transport};
+use anyhow::Result;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, Mutex};
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+
+/// Composite transport feeding one server from several transports, e.g. stdio and SSE
+/// request ids are rewritten on receive so peers can't collide, responses go back to the
+/// transport the request came from, notifications are broadcast to every transport and
+/// server-initiated requests go to the transport that was active last
+#[derive(Clone)]
+pub struct MultiTransport {
+    transports: Arc<Vec<BoxedTransport>>,
+    rx: Arc<Mutex<Option<mpsc::Receiver<Tagged>>>>,
+    readers: Arc<Mutex<Vec<JoinHandle<()>>>>,
+    routes: Arc<parking_lot::Mutex<Routes>>,
+    next_id: Arc<AtomicU64>,
+}
+
+// A received message with the index of the transport it came from
+type Tagged = (usize, Message);
+
+#[derive(Default)]
+struct Routes {
+    // Rewritten id of each pending peer request -> source transport and original id
+    requests: HashMap<RequestId, (usize, RequestId)>,
+    last_source: Option<usize>,
+}
+
+impl MultiTransport {
+    pub fn new<T: Into<BoxedTransport>>(transports: impl IntoIterator<Item = T>) -> Self {
+        Self {
+            transports: Arc::new(transports.into_iter().map(Into::into).collect()),
+            rx: Arc::new(Mutex::new(None)),
+            readers: Arc::new(Mutex::new(Vec::new())),
+            routes: Arc::new(parking_lot::Mutex::new(Routes::default())),
+            next_id: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    fn tag(&self, source: usize, message: Message) -> Message {
+        match message {
+            Message::Request(mut request) => {
+                let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+                self.routes.lock().requests.insert(id, (source, request.id));
+                request.id = id;
+                Message::Request(request)
+            }
+            Message::Batch(messages) => Message::Batch(
+                messages
+                    .into_iter()
+                    .map(|message| self.tag(source, message))
+                    .collect(),
+            ),
+            message => message,
+        }
+    }
+
+    /// Restore the original id of a response and find the transport it goes back to
+    fn route(&self, message: &Message) -> Option<(usize, Message)> {
+        match message {
+            Message::Response(response) => {
+                let (source, id) = self.routes.lock().requests.remove(&response.id)?;
+                let mut response = response.clone();
+                response.id = id;
+                Some((source, Message::Response(response)))
+            }
+            _ => None,
+        }
+    }
+
+    async fn send_to(&self, source: usize, message: &Message) -> Result<()> {
+        self.transports[source].send(message).await
+    }
+
+    async fn stop_readers(&self) {
+        for reader in self.readers.lock().await.drain(..) {
+            reader.abort();
+        }
+        *self.rx.lock().await = None;
+    }
+}
+
+#[async_trait]
+impl Transport for MultiTransport {
+    async fn send(&self, message: &Message) -> Result<()> {
+        match message {
+            Message::Response(_) => {
+                let (source, message) = self
+                    .route(message)
+                    .ok_or_else(|| anyhow::anyhow!("No transport for response {:?}", message))?;
+                self.send_to(source, &message).await
+            }
+            Message::Batch(messages) => {
+                // A batch reply is split per transport, each gets its own responses back
+                let mut batches: HashMap<usize, Vec<Message>> = HashMap::new();
+                for message in messages {
+                    match self.route(message) {
+                        Some((source, message)) => batches.entry(source).or_default().push(message),
+                        None => warn!("Dropping unroutable batch entry {:?}", message),
+                    }
+                }
+                for (source, messages) in batches {
+                    self.send_to(source, &Message::Batch(messages)).await?;
+                }
+                Ok(())
+            }
+            // An error without id answers the message just received
+            Message::Request(_) | Message::UnidentifiedError(_) => {
+                let source = self.routes.lock().last_source.unwrap_or(0);
+                self.send_to(source, message).await
+            }
+            Message::Notification(_) => {
+                for (source, transport) in self.transports.iter().enumerate() {
+                    if let Err(e) = transport.send(message).await {
+                        debug!("Failed to notify transport {}: {}", source, e);
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+
+    async fn receive(&self) -> Result<Option<Message>> {
+        let mut rx_guard = self.rx.lock().await;
+        let rx = rx_guard
+            .as_mut()
+            .ok_or_else(|| anyhow::anyhow!("Transport not opened"))?;
+        // Ends once every transport has ended
+        let Some((source, message)) = rx.recv().await else {
+            return Ok(None);
+        };
+        self.routes.lock().last_source = Some(source);
+        Ok(Some(self.tag(source, message)))
+    }
+
+    async fn open(&self) -> Result<()> {
+        let mut readers = self.readers.lock().await;
+        if !readers.is_empty() {
+            return Ok(());
+        }
+        let (tx, rx) = mpsc::channel(100);
+        for (source, transport) in self.transports.iter().enumerate() {
+            transport.open().await?;
+            let transport = transport.clone();
+            let tx = tx.clone();
+            readers.push(tokio::spawn(async move {
+                loop {
+                    match transport.receive().await {
+                        Ok(Some(message)) => {
+                            if tx.send((source, message)).await.is_err() {
+                                break;
+                            }
+                        }
+                        Ok(None) => break,
+                        // One failing transport doesn't take the others down
+                        Err(e) => {
+                            warn!("Transport {} failed: {}", source, e);
+                            break;
+                        }
+                    }
+                }
+            }));
+        }
+        *self.rx.lock().await = Some(rx);
+        Ok(())
+    }
+
+    async fn close(&self) -> Result<()> {
+        self.stop_readers().await;
+        for transport in self.transports.iter() {
+            transport.close().await?;
+        }
+        Ok(())
+    }
+
+    async fn close_with_timeout(&self, timeout: Duration) -> Result<()> {
+        self.stop_readers().await;
+        for transport in self.transports.iter() {
+            transport.close_with_timeout(timeout).await?;
+        }
+        Ok(())
+    }
+
+    // In-flight requests are only aborted once every peer is gone
+    async fn closed(&self) {
+        futures::future::join_all(self.transports.iter().map(|transport| transport.closed())).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{Client, ClientBuilder};
+    use crate::protocol::RequestOptions;
+    use crate::server::Server;
+    use crate::transport::{ClientInMemoryTransport, ServerInMemoryTransport};
+    use crate::types::{CallToolResponse, Tool};
+
+    async fn echo(client: &Client<ClientInMemoryTransport>, text: String) -> Result<()> {
+        let response: CallToolResponse = client
+            .request_typed(
+                "tools/call",
+                serde_json::json!({"name": "echo", "arguments": {"text": text}}),
+                RequestOptions::default(),
+            )
+            .await?;
+        assert_eq!(
+            serde_json::to_value(response)?,
+            serde_json::to_value(CallToolResponse::text(format!("{:?}", text)))?
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_multiple_peers_share_one_server() -> Result<()> {
+        let (server_tx, mut server_rx) = mpsc::unbounded_channel();
+        let clients: Vec<ClientInMemoryTransport> = (0..2)
+            .map(|_| {
+                let server_tx = server_tx.clone();
+                ClientInMemoryTransport::new(move |t: ServerInMemoryTransport| {
+                    server_tx.send(t).unwrap();
+                    tokio::spawn(async {})
+                })
+            })
+            .collect();
+        let mut server_transports = Vec::new();
+        for client in &clients {
+            client.open().await?;
+            server_transports.push(server_rx.recv().await.unwrap());
+        }
+
+        let transport = MultiTransport::new(server_transports);
+        transport.open().await?;
+        let mut builder = Server::builder(transport);
+        builder.register_tool(
+            Tool {
+                name: "echo".to_string(),
+                description: None,
+                input_schema: serde_json::json!({"type": "object"}),
+                output_schema: None,
+            },
+            |req| {
+                Box::pin(async move {
+                    let text = req.arguments.unwrap_or_default().remove("text");
+                    Ok(CallToolResponse::text(text.unwrap_or_default().to_string()))
+                })
+            },
+        );
+        let server = builder.build();
+        let server = tokio::spawn(async move { server.listen().await });
+
+        let mut handles = Vec::new();
+        for (i, transport) in clients.iter().enumerate() {
+            let client = ClientBuilder::new(transport.clone()).build();
+            let client_clone = client.clone();
+            tokio::spawn(async move { client_clone.start().await });
+            handles.push(tokio::spawn(async move {
+                // Both clients use the same request ids, each must get its own replies
+                for n in 0..5 {
+                    echo(&client, format!("client {} call {}", i, n)).await?;
+                }
+                anyhow::Ok(client)
+            }));
+        }
+        let mut peers = Vec::new();
+        for handle in handles {
+            peers.push(handle.await??);
+        }
+
+        // The server keeps serving the other peer after one disconnects
+        let first = clients[0].clone();
+        let first_closed = tokio::spawn(async move { first.close().await });
+        echo(&peers[1], "still there".to_string()).await?;
+        assert!(!server.is_finished());
+
+        drop(peers);
+        clients[1].close().await?;
+        first_closed.await??;
+        tokio::time::timeout(std::time::Duration::from_secs(1), server).await???;
+        Ok(())
+    }
+}
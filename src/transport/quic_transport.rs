@@ -0,0 +1,526 @@
+//! QUIC transport, each session is one bidirectional stream carrying newline delimited JSON
+//! like stdio. Streams of a connection are independent, a lost packet only stalls its own session
+use super::stdio_transport::LineBuffer;
+use super::{DecodeLimits, Message, Transport, DEFAULT_DRAIN_TIMEOUT};
+use anyhow::Result;
+use async_trait::async_trait;
+use quinn::rustls::pki_types::CertificateDer;
+use quinn::rustls::RootCertStore;
+use quinn::{Connection, Endpoint, RecvStream, SendStream, VarInt};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::sync::{mpsc, watch, Mutex};
+use tokio::task::JoinHandle;
+use tracing::debug;
+
+/// Certificate and private key, both PEM encoded, of a self-signed certificate for `names`
+/// meant for development, clients have to trust the certificate with
+/// [`ClientQuicTransportBuilder::with_root_certificate`]
+pub fn self_signed_certificate(names: &[&str]) -> Result<(String, String)> {
+    let names = names
+        .iter()
+        .map(|name| name.to_string())
+        .collect::<Vec<_>>();
+    let certified = rcgen::generate_simple_self_signed(names)?;
+    Ok((certified.cert.pem(), certified.key_pair.serialize_pem()))
+}
+
+fn parse_certificates(pem: &[u8]) -> Result<Vec<CertificateDer<'static>>> {
+    Ok(rustls_pemfile::certs(&mut &*pem).collect::<Result<_, _>>()?)
+}
+
+/// Accepts QUIC connections, every bidirectional stream a client opens is a session
+/// a stream only reaches the server with its first message, which is the client's `initialize`
+pub struct QuicServer {
+    endpoint: Endpoint,
+    sessions: Mutex<mpsc::Receiver<ServerQuicTransport>>,
+    acceptor: JoinHandle<()>,
+}
+
+impl QuicServer {
+    /// Listen on `addr` with a PEM encoded certificate chain and private key
+    /// must be called from within the tokio runtime
+    pub fn bind(addr: SocketAddr, cert_pem: &[u8], key_pem: &[u8]) -> Result<Self> {
+        let key = rustls_pemfile::private_key(&mut &*key_pem)?
+            .ok_or_else(|| anyhow::anyhow!("No private key in PEM"))?;
+        let config = quinn::ServerConfig::with_single_cert(parse_certificates(cert_pem)?, key)?;
+        let endpoint = Endpoint::server(config, addr)?;
+        let (sessions_tx, sessions_rx) = mpsc::channel(16);
+        let acceptor = tokio::spawn(accept_sessions(endpoint.clone(), sessions_tx));
+        Ok(Self {
+            endpoint,
+            sessions: Mutex::new(sessions_rx),
+            acceptor,
+        })
+    }
+
+    /// The bound address, e.g. the port picked for port 0
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.endpoint.local_addr()?)
+    }
+
+    /// The next session, `None` once the server is closed
+    pub async fn accept(&self) -> Option<ServerQuicTransport> {
+        self.sessions.lock().await.recv().await
+    }
+
+    /// Stop accepting and close every connection
+    pub fn close(&self) {
+        self.acceptor.abort();
+        self.endpoint.close(VarInt::from_u32(0), b"server closed");
+    }
+}
+
+impl Drop for QuicServer {
+    fn drop(&mut self) {
+        self.acceptor.abort();
+    }
+}
+
+async fn accept_sessions(endpoint: Endpoint, sessions: mpsc::Sender<ServerQuicTransport>) {
+    while let Some(incoming) = endpoint.accept().await {
+        let sessions = sessions.clone();
+        tokio::spawn(async move {
+            let connection = match incoming.await {
+                Ok(connection) => connection,
+                Err(e) => {
+                    debug!("QUIC handshake failed: {}", e);
+                    return;
+                }
+            };
+            debug!("QUIC connection from {}", connection.remote_address());
+            while let Ok((send, recv)) = connection.accept_bi().await {
+                let session = ServerQuicTransport::new(connection.clone(), send, recv);
+                if sessions.send(session).await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+}
+
+/// Both halves of a session's stream, `lost` is set once the stream can't carry messages
+/// anymore so that receive and `closed` return
+#[derive(Clone)]
+struct QuicStream {
+    send: Arc<Mutex<Option<SendStream>>>,
+    recv: Arc<Mutex<Option<BufReader<RecvStream>>>>,
+    lost: Arc<watch::Sender<bool>>,
+}
+
+impl QuicStream {
+    fn new(send: Option<SendStream>, recv: Option<RecvStream>) -> Self {
+        Self {
+            send: Arc::new(Mutex::new(send)),
+            recv: Arc::new(Mutex::new(recv.map(BufReader::new))),
+            lost: Arc::new(watch::Sender::new(false)),
+        }
+    }
+
+    async fn attach(&self, send: SendStream, recv: RecvStream) {
+        *self.send.lock().await = Some(send);
+        *self.recv.lock().await = Some(BufReader::new(recv));
+        self.lost.send_replace(false);
+    }
+
+    async fn is_usable(&self) -> bool {
+        self.send.lock().await.is_some() && !*self.lost.borrow()
+    }
+
+    async fn lost(&self) {
+        let mut lost = self.lost.subscribe();
+        let _ = lost.wait_for(|lost| *lost).await;
+    }
+
+    async fn receive(&self, limits: &DecodeLimits) -> Result<Option<Message>> {
+        let mut recv = self.recv.lock().await;
+        let reader = recv
+            .as_mut()
+            .ok_or_else(|| anyhow::anyhow!("Transport not opened"))?;
+        let mut line = LineBuffer::new(limits.max_bytes);
+        let read = async {
+            loop {
+                let available = reader.fill_buf().await?;
+                let (consumed, done) = line.push(available);
+                reader.consume(consumed);
+                if done {
+                    return Ok::<_, std::io::Error>(());
+                }
+            }
+        };
+        let read = tokio::select! {
+            read = read => read,
+            _ = self.lost() => return Ok(None),
+        };
+        if let Err(e) = read {
+            debug!("QUIC stream lost: {}", e);
+            self.lost.send_replace(true);
+            return Ok(None);
+        }
+        let Some(line) = line.finish()? else {
+            debug!("QUIC stream finished by the peer");
+            self.lost.send_replace(true);
+            return Ok(None);
+        };
+        Ok(Some(limits.decode(&line)?))
+    }
+
+    async fn send(&self, message: &Message) -> Result<()> {
+        let mut line = serde_json::to_string(message)?;
+        line.push('\n');
+        let mut send = self.send.lock().await;
+        let stream = send
+            .as_mut()
+            .ok_or_else(|| anyhow::anyhow!("Transport not opened"))?;
+        if let Err(e) = stream.write_all(line.as_bytes()).await {
+            self.lost.send_replace(true);
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    /// Finish the stream and wait at most `timeout` for the peer to acknowledge what was sent
+    async fn close(&self, timeout: Duration) {
+        if let Some(mut send) = self.send.lock().await.take() {
+            let _ = send.finish();
+            match tokio::time::timeout(timeout, send.stopped()).await {
+                Ok(Ok(Some(code))) => debug!("Peer stopped the QUIC stream with code {}", code),
+                Ok(Err(e)) => debug!("QUIC stream lost while closing: {}", e),
+                Err(_) => debug!("Peer didn't acknowledge the QUIC stream on close"),
+                Ok(Ok(None)) => {}
+            }
+        }
+        // Wakes a pending receive so the lock is released
+        self.lost.send_replace(true);
+        *self.recv.lock().await = None;
+    }
+}
+
+/// Server side of a session, one bidirectional stream of a client's connection
+#[derive(Clone)]
+pub struct ServerQuicTransport {
+    connection: Connection,
+    stream: QuicStream,
+    limits: DecodeLimits,
+}
+
+impl ServerQuicTransport {
+    fn new(connection: Connection, send: SendStream, recv: RecvStream) -> Self {
+        Self {
+            connection,
+            stream: QuicStream::new(Some(send), Some(recv)),
+            limits: DecodeLimits::default(),
+        }
+    }
+
+    pub fn decode_limits(mut self, limits: DecodeLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn remote_addr(&self) -> SocketAddr {
+        self.connection.remote_address()
+    }
+}
+
+#[async_trait]
+impl Transport for ServerQuicTransport {
+    async fn receive(&self) -> Result<Option<Message>> {
+        self.stream.receive(&self.limits).await
+    }
+
+    async fn send(&self, message: &Message) -> Result<()> {
+        self.stream.send(message).await
+    }
+
+    async fn open(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn close(&self) -> Result<()> {
+        self.close_with_timeout(DEFAULT_DRAIN_TIMEOUT).await
+    }
+
+    /// Finishes the session's stream, the connection stays up for the client's other sessions
+    async fn close_with_timeout(&self, timeout: Duration) -> Result<()> {
+        self.stream.close(timeout).await;
+        Ok(())
+    }
+
+    async fn closed(&self) {
+        tokio::select! {
+            _ = self.connection.closed() => {}
+            _ = self.stream.lost() => {}
+        }
+    }
+}
+
+/// Client side of a QUIC session
+/// once the stream or the connection is lost, receive returns `None` and `open` starts a new
+/// stream, reconnecting first if the connection is gone
+#[derive(Clone)]
+pub struct ClientQuicTransport {
+    server_addr: SocketAddr,
+    server_name: String,
+    root_certificates: Arc<Vec<Vec<u8>>>,
+    limits: DecodeLimits,
+    endpoint: Arc<Mutex<Option<Endpoint>>>,
+    connection: Arc<Mutex<Option<Connection>>>,
+    stream: QuicStream,
+}
+
+pub struct ClientQuicTransportBuilder {
+    server_addr: SocketAddr,
+    server_name: String,
+    root_certificates: Vec<Vec<u8>>,
+    limits: DecodeLimits,
+}
+
+impl ClientQuicTransportBuilder {
+    /// `server_name` is checked against the server's certificate
+    pub fn new(server_addr: SocketAddr, server_name: impl Into<String>) -> Self {
+        Self {
+            server_addr,
+            server_name: server_name.into(),
+            root_certificates: Vec::new(),
+            limits: DecodeLimits::default(),
+        }
+    }
+
+    /// Trust a PEM encoded CA certificate in addition to the webpki roots
+    /// the certificate is parsed when the connection opens
+    pub fn with_root_certificate(mut self, pem: &[u8]) -> Self {
+        self.root_certificates.push(pem.to_vec());
+        self
+    }
+
+    pub fn decode_limits(mut self, limits: DecodeLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn build(self) -> ClientQuicTransport {
+        ClientQuicTransport {
+            server_addr: self.server_addr,
+            server_name: self.server_name,
+            root_certificates: Arc::new(self.root_certificates),
+            limits: self.limits,
+            endpoint: Arc::new(Mutex::new(None)),
+            connection: Arc::new(Mutex::new(None)),
+            stream: QuicStream::new(None, None),
+        }
+    }
+}
+
+impl ClientQuicTransport {
+    pub fn builder(
+        server_addr: SocketAddr,
+        server_name: impl Into<String>,
+    ) -> ClientQuicTransportBuilder {
+        ClientQuicTransportBuilder::new(server_addr, server_name)
+    }
+
+    /// The connection if it is still up, otherwise a new one
+    async fn connection(&self) -> Result<Connection> {
+        let mut connection = self.connection.lock().await;
+        if let Some(live) = connection
+            .as_ref()
+            .filter(|live| live.close_reason().is_none())
+        {
+            return Ok(live.clone());
+        }
+        let mut endpoint = self.endpoint.lock().await;
+        let endpoint = match endpoint.as_mut() {
+            Some(endpoint) => endpoint,
+            None => endpoint.insert(self.client_endpoint()?),
+        };
+        debug!("Connecting to QUIC server {}", self.server_addr);
+        let connected = endpoint
+            .connect(self.server_addr, &self.server_name)?
+            .await?;
+        *connection = Some(connected.clone());
+        Ok(connected)
+    }
+
+    fn client_endpoint(&self) -> Result<Endpoint> {
+        let mut roots = RootCertStore::empty();
+        roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        for pem in self.root_certificates.iter() {
+            for certificate in parse_certificates(pem)? {
+                roots.add(certificate)?;
+            }
+        }
+        let config = quinn::ClientConfig::with_root_certificates(Arc::new(roots))?;
+        let bind: SocketAddr = if self.server_addr.is_ipv6() {
+            "[::]:0".parse()?
+        } else {
+            "0.0.0.0:0".parse()?
+        };
+        let mut endpoint = Endpoint::client(bind)?;
+        endpoint.set_default_client_config(config);
+        Ok(endpoint)
+    }
+}
+
+#[async_trait]
+impl Transport for ClientQuicTransport {
+    async fn receive(&self) -> Result<Option<Message>> {
+        self.stream.receive(&self.limits).await
+    }
+
+    async fn send(&self, message: &Message) -> Result<()> {
+        self.stream.send(message).await
+    }
+
+    /// Opens a stream, a no-op while the current one is usable
+    async fn open(&self) -> Result<()> {
+        if self.stream.is_usable().await {
+            debug!("QUIC stream already open");
+            return Ok(());
+        }
+        let connection = self.connection().await?;
+        let (send, recv) = connection.open_bi().await?;
+        self.stream.attach(send, recv).await;
+        Ok(())
+    }
+
+    async fn close(&self) -> Result<()> {
+        self.close_with_timeout(DEFAULT_DRAIN_TIMEOUT).await
+    }
+
+    async fn close_with_timeout(&self, timeout: Duration) -> Result<()> {
+        self.stream.close(timeout).await;
+        if let Some(connection) = self.connection.lock().await.take() {
+            connection.close(VarInt::from_u32(0), b"closed");
+        }
+        Ok(())
+    }
+
+    async fn closed(&self) {
+        let connection = self.connection.lock().await.clone();
+        match connection {
+            Some(connection) => tokio::select! {
+                _ = connection.closed() => {}
+                _ = self.stream.lost() => {}
+            },
+            None => self.stream.lost().await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{Client, ClientBuilder};
+    use crate::server::Server;
+    use crate::types::{
+        CallToolRequest, CallToolResponse, Implementation, ServerCapabilities, ToolBuilder,
+        ToolResponseContent,
+    };
+
+    // Every session's transport is also handed to the test
+    async fn echo_server() -> Result<(
+        Arc<QuicServer>,
+        String,
+        mpsc::UnboundedReceiver<ServerQuicTransport>,
+    )> {
+        let (cert, key) = self_signed_certificate(&["localhost"])?;
+        let quic = Arc::new(QuicServer::bind(
+            "127.0.0.1:0".parse()?,
+            cert.as_bytes(),
+            key.as_bytes(),
+        )?);
+        let (sessions_tx, sessions_rx) = mpsc::unbounded_channel();
+        let accepting = quic.clone();
+        tokio::spawn(async move {
+            while let Some(transport) = accepting.accept().await {
+                let _ = sessions_tx.send(transport.clone());
+                let mut builder = Server::builder(transport).capabilities(ServerCapabilities {
+                    tools: Some(serde_json::json!({})),
+                    ..Default::default()
+                });
+                builder.register_tool(ToolBuilder::new("echo").build(), |req| {
+                    Box::pin(async move {
+                        let text = req.arguments.unwrap_or_default()["text"].to_string();
+                        Ok(CallToolResponse::text(text))
+                    })
+                });
+                let server = builder.build();
+                tokio::spawn(async move { server.listen().await });
+            }
+        });
+        Ok((quic, cert, sessions_rx))
+    }
+
+    // A fresh client on the transport, initialized
+    async fn connect(
+        transport: &ClientQuicTransport,
+    ) -> Result<(Client<ClientQuicTransport>, JoinHandle<Result<()>>)> {
+        transport.open().await?;
+        let client = ClientBuilder::new(transport.clone()).build();
+        let started = client.clone();
+        let session = tokio::spawn(async move { started.start().await });
+        client
+            .initialize(Implementation {
+                name: "quic-test".to_string(),
+                version: "0.1.0".to_string(),
+            })
+            .await?;
+        Ok((client, session))
+    }
+
+    async fn call_echo(client: &Client<ClientQuicTransport>) -> Result<String> {
+        let response: CallToolResponse = client
+            .request_typed(
+                "tools/call",
+                CallToolRequest {
+                    name: "echo".to_string(),
+                    arguments: Some([("text".to_string(), "hi".into())].into()),
+                    meta: None,
+                },
+                Default::default(),
+            )
+            .await?;
+        match &response.content[0] {
+            ToolResponseContent::Text { text } => Ok(text.clone()),
+            other => Err(anyhow::anyhow!("Expected text, got {:?}", other)),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tool_call_and_reopen_after_loss() -> Result<()> {
+        let (quic, cert, mut sessions) = echo_server().await?;
+        let transport = ClientQuicTransport::builder(quic.local_addr()?, "localhost")
+            .with_root_certificate(cert.as_bytes())
+            .build();
+        let ended = |session: JoinHandle<Result<()>>| async {
+            tokio::time::timeout(Duration::from_secs(5), session).await??
+        };
+
+        let (client, session) = connect(&transport).await?;
+        assert_eq!(call_echo(&client).await?, r#""hi""#);
+        let first = transport.connection.lock().await.clone().unwrap();
+
+        // The server ends the session's stream, a new one is opened on the same connection
+        sessions.recv().await.unwrap().close().await?;
+        ended(session).await?;
+        let (client, session) = connect(&transport).await?;
+        assert_eq!(call_echo(&client).await?, r#""hi""#);
+        let second = transport.connection.lock().await.clone().unwrap();
+        assert_eq!(first.stable_id(), second.stable_id());
+
+        // The connection is lost, open reconnects
+        second.close(VarInt::from_u32(1), b"network gone");
+        ended(session).await?;
+        let (client, _session) = connect(&transport).await?;
+        assert_eq!(call_echo(&client).await?, r#""hi""#);
+        let third = transport.connection.lock().await.clone().unwrap();
+        assert_ne!(second.stable_id(), third.stable_id());
+
+        transport.close().await?;
+        quic.close();
+        Ok(())
+    }
+}
@@ -1,6 +1,11 @@
 use crate::sse::middleware::{AuthConfig, Claims};
+use crate::types::RESOURCE_CHUNK_METHOD;
+use crate::validation::{BuildError, BuildIssue, IssueCode};
 
-use super::{Message, Transport};
+use super::{
+    Clock, ConnectionActivity, DecodeLimits, Message, SystemClock, Transport,
+    DEFAULT_DRAIN_TIMEOUT, MESSAGE_HEADERS,
+};
 
 use actix_web::web::Bytes;
 use anyhow::Result;
@@ -9,73 +14,185 @@ use futures::StreamExt;
 use jsonwebtoken::{encode, EncodingKey, Header};
 
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
-use tokio::sync::{broadcast, mpsc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::{broadcast, mpsc, watch, Mutex, Notify};
+use tokio::task::JoinHandle;
 use tracing::debug;
 
+/// Messages the client hasn't read beyond which it counts as slow for
+/// [`SlowConsumerPolicy::DropNotificationsKeepResponses`], chunks of a streamed resource read
+/// wait for the client to read below it under every policy
+pub const SLOW_CONSUMER_BACKLOG: usize = 32;
+
+/// What to do about an SSE client that stops reading its event stream, e.g. a suspended tab
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlowConsumerPolicy {
+    /// Disconnect the session once a sent message went unread for this long
+    DisconnectAfter(Duration),
+    /// Drop notifications while more than [`SLOW_CONSUMER_BACKLOG`] messages are unread,
+    /// responses and server requests are always queued
+    DropNotificationsKeepResponses,
+    /// Queue everything for as long as the session lives
+    Unbounded,
+}
+
+/// What the slow consumer policies did since the server started
+#[derive(Debug, Default)]
+pub struct SlowConsumerStats {
+    disconnected_sessions: AtomicU64,
+    dropped_notifications: AtomicU64,
+}
+
+impl SlowConsumerStats {
+    pub fn disconnected_sessions(&self) -> u64 {
+        self.disconnected_sessions.load(Ordering::Relaxed)
+    }
+
+    pub fn dropped_notifications(&self) -> u64 {
+        self.dropped_notifications.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn record_disconnect(&self) {
+        self.disconnected_sessions.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Sent messages the event stream hasn't handed to the client yet
+#[derive(Debug)]
+struct Backlog {
+    unread: usize,
+    // Last delivery, or when the oldest unread message was sent if it came after
+    since: SystemTime,
+}
+
 #[derive(Clone)]
 pub struct ServerSseTransport {
     // For receiving messages from HTTP POST requests
     message_rx: Arc<Mutex<mpsc::Receiver<Message>>>,
     message_tx: mpsc::Sender<Message>,
-    // For sending messages to SSE clients
-    sse_tx: broadcast::Sender<Message>,
+    // For sending messages to SSE clients, serialized once and shared by every subscriber
+    sse_tx: broadcast::Sender<Arc<str>>,
+    activity: Arc<ConnectionActivity>,
+    clock: Arc<dyn Clock>,
+    disconnected: Arc<watch::Sender<bool>>,
+    policy: SlowConsumerPolicy,
+    stats: Arc<SlowConsumerStats>,
+    backlog: Arc<parking_lot::Mutex<Backlog>>,
+    // Woken on every delivery
+    read: Arc<Notify>,
 }
 
 impl ServerSseTransport {
-    pub fn new(sse_tx: broadcast::Sender<Message>) -> Self {
+    /// `sse_tx` carries the JSON of every outgoing message
+    pub fn new(sse_tx: broadcast::Sender<Arc<str>>) -> Self {
         let (message_tx, message_rx) = mpsc::channel(100);
         Self {
             message_rx: Arc::new(Mutex::new(message_rx)),
             message_tx,
             sse_tx,
+            activity: Arc::new(ConnectionActivity::default()),
+            clock: Arc::new(SystemClock),
+            disconnected: Arc::new(watch::Sender::new(false)),
+            policy: SlowConsumerPolicy::Unbounded,
+            stats: Arc::new(SlowConsumerStats::default()),
+            backlog: Arc::new(parking_lot::Mutex::new(Backlog {
+                unread: 0,
+                since: SystemTime::now(),
+            })),
+            read: Arc::new(Notify::new()),
         }
     }
 
-    pub async fn send_message(&self, message: Message) -> Result<()> {
-        self.message_tx.send(message).await?;
-        Ok(())
+    /// Record activity times with `clock` instead of the system clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.activity = Arc::new(ConnectionActivity::with_clock(clock.clone()));
+        self.clock = clock;
+        self
     }
 
-    // Helper function to chunk message into SSE format
-    fn format_sse_message(message: &Message) -> Result<String> {
-        const CHUNK_SIZE: usize = 16 * 1024; // 16KB chunks
-        let json = serde_json::to_string(message)?;
-        let mut result = String::new();
+    /// How to treat a client that stops reading, `Unbounded` by default
+    /// deliveries must be reported with [`ServerSseTransport::delivered`], `stats` is shared
+    /// between the sessions of a server
+    pub fn slow_consumer_policy(
+        mut self,
+        policy: SlowConsumerPolicy,
+        stats: Arc<SlowConsumerStats>,
+    ) -> Self {
+        self.policy = policy;
+        self.stats = stats;
+        self
+    }
 
-        // Add event type
-        result.push_str("event: message\n");
+    pub fn policy(&self) -> SlowConsumerPolicy {
+        self.policy
+    }
 
-        // If small enough, send as single chunk
-        if json.len() <= CHUNK_SIZE {
-            result.push_str(&format!("data: {}\n\n", json));
-            return Ok(result);
-        }
+    /// The event stream yielded a message for the response body
+    /// counted when the stream pulls it, before it is flushed to the client
+    pub fn delivered(&self) {
+        self.forget_unread(1);
+    }
 
-        // For larger messages, split at proper boundaries (commas or spaces)
-        let mut start = 0;
-        while start < json.len() {
-            let mut end = (start + CHUNK_SIZE).min(json.len());
+    /// Messages the event stream lost before delivering them, they no longer hold up `close`
+    pub(crate) fn skipped(&self, count: u64) {
+        self.forget_unread(usize::try_from(count).unwrap_or(usize::MAX));
+    }
 
-            // If we're not at the end, find a good split point
-            if end < json.len() {
-                // Look back for a comma or space to split at
-                while end > start && !json[end..].starts_with([',', ' ']) {
-                    end -= 1;
-                }
-                // If we couldn't find a good split point, just use the max size
-                if end == start {
-                    end = (start + CHUNK_SIZE).min(json.len());
-                }
+    fn forget_unread(&self, count: usize) {
+        let mut backlog = self.backlog.lock();
+        backlog.unread = backlog.unread.saturating_sub(count);
+        backlog.since = self.clock.now();
+        self.read.notify_waiters();
+    }
+
+    /// Resolves once the event stream handed every sent message to the client
+    async fn drained(&self) {
+        self.unread_below(1).await
+    }
+
+    /// Resolves once fewer than `limit` sent messages wait for the client
+    async fn unread_below(&self, limit: usize) {
+        loop {
+            // Registered before checking, a delivery in between still wakes it
+            let read = self.read.notified();
+            if self.backlog.lock().unread < limit {
+                return;
             }
+            read.await;
+        }
+    }
 
-            result.push_str(&format!("data: {}\n", &json[start..end]));
-            start = end;
+    /// How long sent messages have been waiting for the client, zero when it is caught up
+    pub fn stalled_for(&self) -> Duration {
+        let backlog = self.backlog.lock();
+        if backlog.unread == 0 {
+            return Duration::ZERO;
         }
+        self.clock
+            .now()
+            .duration_since(backlog.since)
+            .unwrap_or_default()
+    }
+
+    /// The client's event stream ended, stops the session's server and aborts its requests
+    pub fn disconnect(&self) {
+        self.disconnected.send_replace(true);
+    }
+
+    pub fn is_disconnected(&self) -> bool {
+        *self.disconnected.borrow()
+    }
 
-        result.push('\n');
-        Ok(result)
+    pub async fn send_message(&self, message: Message) -> Result<()> {
+        self.activity.touch();
+        self.message_tx.send(message).await?;
+        Ok(())
+    }
+
+    pub fn activity(&self) -> &ConnectionActivity {
+        &self.activity
     }
 }
 
@@ -83,7 +200,11 @@ impl ServerSseTransport {
 impl Transport for ServerSseTransport {
     async fn receive(&self) -> Result<Option<Message>> {
         let mut rx = self.message_rx.lock().await;
-        match rx.recv().await {
+        let message = tokio::select! {
+            message = rx.recv() => message,
+            _ = self.closed() => None,
+        };
+        match message {
             Some(message) => {
                 debug!("Received message from POST request: {:?}", message);
                 Ok(Some(message))
@@ -93,17 +214,32 @@ impl Transport for ServerSseTransport {
     }
 
     async fn send(&self, message: &Message) -> Result<()> {
-        let formatted = Self::format_sse_message(message)?;
-        // Show first and last 500 characters for debugging
-        if formatted.len() > 1000 {
-            let first = &formatted[..500];
-            let last = &formatted[formatted.len() - 500..];
-            debug!("Sending chunked SSE message: {}...{}", first, last);
-        } else {
-            debug!("Sending chunked SSE message: {}", formatted);
+        let json: Arc<str> = serde_json::to_string(message)?.into();
+        if is_chunk(message) {
+            // A streamed read could queue a whole file, it goes at the pace of the client instead
+            tokio::select! {
+                _ = self.unread_below(SLOW_CONSUMER_BACKLOG) => {}
+                _ = self.closed() => return Ok(()),
+            }
+        }
+        {
+            let mut backlog = self.backlog.lock();
+            if self.policy == SlowConsumerPolicy::DropNotificationsKeepResponses
+                && backlog.unread >= SLOW_CONSUMER_BACKLOG
+                && is_notification(message)
+            {
+                self.stats
+                    .dropped_notifications
+                    .fetch_add(1, Ordering::Relaxed);
+                debug!("Dropped a notification for a slow SSE client");
+                return Ok(());
+            }
+            if backlog.unread == 0 {
+                backlog.since = self.clock.now();
+            }
+            backlog.unread += 1;
         }
-        
-        self.sse_tx.send(message.clone())?;
+        self.sse_tx.send(json)?;
         Ok(())
     }
 
@@ -112,14 +248,50 @@ impl Transport for ServerSseTransport {
     }
 
     async fn close(&self) -> Result<()> {
+        self.close_with_timeout(DEFAULT_DRAIN_TIMEOUT).await
+    }
+
+    /// Ends the event stream once the client read the messages sent so far
+    async fn close_with_timeout(&self, timeout: Duration) -> Result<()> {
+        let drained = tokio::time::timeout(timeout, async {
+            tokio::select! {
+                _ = self.drained() => {}
+                _ = self.closed() => {}
+            }
+        })
+        .await;
+        if drained.is_err() {
+            debug!("SSE client left messages unread on close");
+        }
+        self.disconnect();
         Ok(())
     }
+
+    async fn closed(&self) {
+        let mut disconnected = self.disconnected.subscribe();
+        let _ = disconnected.wait_for(|disconnected| *disconnected).await;
+    }
+}
+
+/// Notifications and batches of only notifications, nothing waits for them
+/// chunks of a streamed resource read are part of its response and never dropped
+fn is_notification(message: &Message) -> bool {
+    match message {
+        Message::Notification(notification) => notification.method != RESOURCE_CHUNK_METHOD,
+        Message::Batch(messages) => messages.iter().all(is_notification),
+        Message::Request(_) | Message::Response(_) | Message::UnidentifiedError(_) => false,
+    }
+}
+
+fn is_chunk(message: &Message) -> bool {
+    matches!(message, Message::Notification(notification) if notification.method == RESOURCE_CHUNK_METHOD)
 }
 
 #[derive(Debug)]
 pub enum SseEvent {
     Message(Message),
-    SessionId(String),
+    /// Where to POST messages, absolute or relative to the server URL
+    Endpoint(String),
 }
 
 /// Client-side SSE transport that sends messages via HTTP POST
@@ -131,12 +303,21 @@ pub struct ClientSseTransport {
     server_url: String,
     client: reqwest::Client,
     auth_config: Option<AuthConfig>,
-    session_id: Arc<Mutex<Option<String>>>,
+    // Message URL from the `endpoint` event, resolved against `server_url`
+    endpoint: Arc<Mutex<Option<String>>>,
+    // Task reading the event stream, finished once the stream ended
+    stream: Arc<Mutex<Option<JoinHandle<Result<()>>>>>,
     headers: HashMap<String, String>,
+    limits: DecodeLimits,
     buffer: Arc<Mutex<String>>, // Add buffer for partial messages
 }
 
 impl ClientSseTransport {
+    /// No auth and no extra headers, see [`ClientSseTransport::builder`] for those
+    pub fn new(url: String) -> Self {
+        Self::builder(url).build()
+    }
+
     pub fn builder(url: String) -> ClientSseTransportBuilder {
         ClientSseTransportBuilder::new(url)
     }
@@ -173,7 +354,44 @@ impl ClientSseTransport {
         }
     }
 
-    fn parse_sse_message(event: &str) -> Option<SseEvent> {
+    /// Send a message with extra headers on the POST request
+    /// these override the connection-level headers configured on the builder
+    pub async fn send_with_headers(
+        &self,
+        message: &Message,
+        headers: &HashMap<String, String>,
+    ) -> Result<()> {
+        let endpoint = self
+            .endpoint
+            .lock()
+            .await
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("No session ID available"))?
+            .clone();
+
+        let mut request = self.client.post(endpoint).json(message);
+
+        let mut merged = self.headers.clone();
+        merged.extend(headers.iter().map(|(k, v)| (k.clone(), v.clone())));
+        for (key, value) in &merged {
+            request = request.header(key, value);
+        }
+
+        let request = self.add_auth_header(request).await?;
+        let response = request.send().await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await?;
+            return Err(anyhow::anyhow!(
+                "Failed to send message, status: {status}, body: {text}",
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn parse_sse_message(event: &str, limits: DecodeLimits) -> Option<SseEvent> {
         let mut event_type = None;
         let mut current_data = String::new();
 
@@ -186,9 +404,9 @@ impl ClientSseTransport {
 
             if line.starts_with("event:") {
                 event_type = Some(line.trim_start_matches("event:").trim().to_string());
-            } else if line.starts_with("data:") {
+            } else if let Some(data) = line.strip_prefix("data:") {
                 // Strip the "data:" prefix and any leading/trailing whitespace
-                let data = line["data:".len()..].trim();
+                let data = data.trim();
                 // For chunked messages, we just concatenate the data
                 current_data.push_str(data);
             }
@@ -197,29 +415,24 @@ impl ClientSseTransport {
         // If we have data, try to parse it
         if !current_data.is_empty() {
             let result = match (event_type.as_ref(), Some(&current_data)) {
-                (Some(endpoint), Some(url)) if endpoint == "endpoint" => Some(SseEvent::SessionId(
-                    url.split("sessionId=")
-                        .nth(1)
-                        .unwrap_or_default()
-                        .to_string(),
-                )),
-                (None, Some(data)) | (Some(_), Some(data)) => {
-                    match serde_json::from_str::<Message>(data) {
-                        Ok(msg) => Some(SseEvent::Message(msg)),
-                        Err(e) => {
-                            debug!(
-                                "Failed to parse SSE message: {}. Content preview: {}",
-                                e,
-                                if data.len() > 100 {
-                                    format!("{}... (truncated)", &data[..100])
-                                } else {
-                                    data.to_string()
-                                }
-                            );
-                            None
-                        }
-                    }
+                (Some(endpoint), Some(url)) if endpoint == "endpoint" => {
+                    Some(SseEvent::Endpoint(url.to_string()))
                 }
+                (None, Some(data)) | (Some(_), Some(data)) => match limits.decode(data) {
+                    Ok(msg) => Some(SseEvent::Message(msg)),
+                    Err(e) => {
+                        debug!(
+                            "Failed to parse SSE message: {}. Content preview: {}",
+                            e,
+                            if data.len() > 100 {
+                                format!("{}... (truncated)", &data[..100])
+                            } else {
+                                data.to_string()
+                            }
+                        );
+                        None
+                    }
+                },
                 _ => None,
             };
 
@@ -237,11 +450,20 @@ impl ClientSseTransport {
         }
     }
 
+    /// Resolves the endpoint against the URL of the event stream as RFC 3986 references are,
+    /// absolute endpoints are used as sent
+    fn resolve_endpoint(server_url: &str, endpoint: &str) -> Result<String> {
+        let events = url::Url::parse(&format!("{}/sse", server_url.trim_end_matches('/')))?;
+        Ok(events.join(endpoint)?.to_string())
+    }
+
     async fn handle_sse_chunk(
         chunk: Bytes,
+        server_url: &str,
         tx: &mpsc::Sender<Message>,
-        session_id: &Arc<Mutex<Option<String>>>,
+        endpoint: &Arc<Mutex<Option<String>>>,
         buffer: &Arc<Mutex<String>>,
+        limits: DecodeLimits,
     ) -> Result<()> {
         let chunk_str = String::from_utf8(chunk.to_vec())?;
         let mut buffer = buffer.lock().await;
@@ -254,15 +476,16 @@ impl ClientSseTransport {
             let complete_event = buffer[..pos + 2].to_string();
             buffer.replace_range(..pos + 2, "");
 
-            if let Some(sse_event) = Self::parse_sse_message(&complete_event) {
+            if let Some(sse_event) = Self::parse_sse_message(&complete_event, limits) {
                 match sse_event {
                     SseEvent::Message(message) => {
                         debug!("Received SSE message: {:?}", message);
                         tx.send(message).await?;
                     }
-                    SseEvent::SessionId(id) => {
-                        debug!("Received session ID: {}", id);
-                        *session_id.lock().await = Some(id);
+                    SseEvent::Endpoint(url) => {
+                        let url = Self::resolve_endpoint(server_url, &url)?;
+                        debug!("Received message endpoint: {}", url);
+                        *endpoint.lock().await = Some(url);
                     }
                 }
             }
@@ -277,6 +500,9 @@ pub struct ClientSseTransportBuilder {
     server_url: String,
     auth_config: Option<AuthConfig>,
     headers: HashMap<String, String>,
+    limits: DecodeLimits,
+    #[cfg(any(feature = "tls-rustls", feature = "tls-native"))]
+    root_certificates: Vec<reqwest::Certificate>,
 }
 
 impl ClientSseTransportBuilder {
@@ -285,11 +511,36 @@ impl ClientSseTransportBuilder {
             server_url,
             auth_config: None,
             headers: HashMap::new(),
+            limits: DecodeLimits::default(),
+            #[cfg(any(feature = "tls-rustls", feature = "tls-native"))]
+            root_certificates: Vec::new(),
         }
     }
 
+    /// Limits for messages from the server, larger or deeper ones are skipped
+    pub fn decode_limits(mut self, limits: DecodeLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Trust a PEM encoded CA certificate in addition to the backend's default roots
+    #[cfg(any(feature = "tls-rustls", feature = "tls-native"))]
+    pub fn with_root_certificate(mut self, pem: &[u8]) -> Result<Self> {
+        self.root_certificates
+            .push(reqwest::Certificate::from_pem(pem)?);
+        Ok(self)
+    }
+
+    /// Fails, there is no TLS backend to trust the certificate
+    #[cfg(not(any(feature = "tls-rustls", feature = "tls-native")))]
+    pub fn with_root_certificate(self, _pem: &[u8]) -> Result<Self> {
+        Err(anyhow::anyhow!(
+            "Root certificates need the `tls-rustls` or `tls-native` feature"
+        ))
+    }
+
     pub fn with_auth(mut self, jwt_secret: String) -> Self {
-        self.auth_config = Some(AuthConfig { jwt_secret });
+        self.auth_config = Some(AuthConfig::new(jwt_secret));
         self
     }
 
@@ -298,16 +549,67 @@ impl ClientSseTransportBuilder {
         self
     }
 
+    /// Like [`build`](Self::build), failing when the URL doesn't parse or the auth token would
+    /// go over plain HTTP to a host other than localhost
+    pub fn try_build(self) -> std::result::Result<ClientSseTransport, BuildError> {
+        let mut issues = Vec::new();
+        match url::Url::parse(&self.server_url) {
+            Ok(url) => {
+                let loopback = match url.host() {
+                    Some(url::Host::Domain(domain)) => domain == "localhost",
+                    Some(url::Host::Ipv4(ip)) => ip.is_loopback(),
+                    Some(url::Host::Ipv6(ip)) => ip.is_loopback(),
+                    None => false,
+                };
+                if self.auth_config.is_some() && url.scheme() == "http" && !loopback {
+                    issues.push(BuildIssue::error(
+                        IssueCode::InsecureAuth,
+                        format!("The auth token would be sent in the clear to {}", url),
+                        "use an https:// URL",
+                    ));
+                }
+            }
+            Err(e) => issues.push(BuildIssue::error(
+                IssueCode::InvalidUrl,
+                format!("Server URL `{}` is invalid: {}", self.server_url, e),
+                "pass an absolute http:// or https:// URL",
+            )),
+        }
+        BuildError::check(issues, false)?;
+        Ok(self.build())
+    }
+
+    #[cfg(any(feature = "tls-rustls", feature = "tls-native"))]
+    fn client_builder(&self) -> reqwest::ClientBuilder {
+        self.root_certificates
+            .iter()
+            .fold(reqwest::Client::builder(), |builder, certificate| {
+                builder.add_root_certificate(certificate.clone())
+            })
+    }
+
+    #[cfg(not(any(feature = "tls-rustls", feature = "tls-native")))]
+    fn client_builder(&self) -> reqwest::ClientBuilder {
+        reqwest::Client::builder()
+    }
+
     pub fn build(self) -> ClientSseTransport {
         let (tx, rx) = mpsc::channel(100);
+        let client = self
+            .client_builder()
+            .build()
+            // Same failure as `reqwest::Client::new`, the TLS backend can't be initialized
+            .expect("Failed to build HTTP client");
         ClientSseTransport {
             tx,
             rx: Arc::new(Mutex::new(rx)),
             server_url: self.server_url,
-            client: reqwest::Client::new(),
+            client,
             auth_config: self.auth_config,
-            session_id: Arc::new(Mutex::new(None)),
+            endpoint: Arc::new(Mutex::new(None)),
+            stream: Arc::new(Mutex::new(None)),
             headers: self.headers,
+            limits: self.limits,
             buffer: Arc::new(Mutex::new(String::new())), // Initialize buffer
         }
     }
@@ -327,46 +629,34 @@ impl Transport for ClientSseTransport {
     }
 
     async fn send(&self, message: &Message) -> Result<()> {
-        let session_id = self
-            .session_id
-            .lock()
-            .await
-            .as_ref()
-            .ok_or_else(|| anyhow::anyhow!("No session ID available"))?
-            .clone();
-
-        let request = self
-            .client
-            .post(format!(
-                "{}/message?sessionId={}",
-                self.server_url, session_id
-            ))
-            .json(message);
-
-        let request = self.add_auth_header(request).await?;
-        let response = request.send().await?;
-
-        if !response.status().is_success() {
-            let status = response.status();
-            let text = response.text().await?;
-            return Err(anyhow::anyhow!(
-                "Failed to send message, status: {status}, body: {text}",
-            ));
-        }
-
-        Ok(())
+        let headers = MESSAGE_HEADERS
+            .try_with(|headers| headers.clone())
+            .unwrap_or_default();
+        self.send_with_headers(message, &headers).await
     }
 
     async fn open(&self) -> Result<()> {
+        // Held until the new stream is stored, concurrent opens connect once
+        let mut stream_guard = self.stream.lock().await;
+        if stream_guard
+            .as_ref()
+            .is_some_and(|stream| !stream.is_finished())
+        {
+            return Ok(());
+        }
+        // The endpoint of an ended stream belongs to its session, wait for the new one
+        *self.endpoint.lock().await = None;
         let tx = self.tx.clone();
         let server_url = self.server_url.clone();
         let auth_config = self.auth_config.clone();
-        let session_id = self.session_id.clone();
+        let endpoint = self.endpoint.clone();
         let headers = self.headers.clone();
         let buffer = self.buffer.clone();
+        let client = self.client.clone();
+        let limits = self.limits;
 
         let handle = tokio::spawn(async move {
-            let mut request = reqwest::Client::new().get(format!("{}/sse", server_url));
+            let mut request = client.get(format!("{}/sse", server_url));
 
             // Add custom headers
             for (key, value) in &headers {
@@ -394,7 +684,10 @@ impl Transport for ClientSseTransport {
             // Handle first message to get session ID
             if let Some(first_chunk) = event_stream.next().await {
                 match first_chunk {
-                    Ok(bytes) => Self::handle_sse_chunk(bytes, &tx, &session_id, &buffer).await?,
+                    Ok(bytes) => {
+                        Self::handle_sse_chunk(bytes, &server_url, &tx, &endpoint, &buffer, limits)
+                            .await?
+                    }
                     Err(e) => {
                         return Err(anyhow::anyhow!("Failed to get initial SSE message: {}", e))
                     }
@@ -408,7 +701,10 @@ impl Transport for ClientSseTransport {
             // Handle remaining messages
             while let Some(chunk) = event_stream.next().await {
                 if let Ok(bytes) = chunk {
-                    if let Err(e) = Self::handle_sse_chunk(bytes, &tx, &session_id, &buffer).await {
+                    if let Err(e) =
+                        Self::handle_sse_chunk(bytes, &server_url, &tx, &endpoint, &buffer, limits)
+                            .await
+                    {
                         debug!("Error handling SSE message: {:?}", e);
                     }
                 }
@@ -417,10 +713,11 @@ impl Transport for ClientSseTransport {
             Ok::<_, anyhow::Error>(())
         });
 
-        // Wait for the session ID to be set
+        // Wait for the endpoint to be set
         let mut attempts = 0;
         while attempts < 10 {
-            if self.session_id.lock().await.is_some() {
+            if self.endpoint.lock().await.is_some() {
+                *stream_guard = Some(handle);
                 return Ok(());
             }
             tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
@@ -439,6 +736,150 @@ impl Transport for ClientSseTransport {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::protocol::{Protocol, RequestOptions};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn test_try_build_checks_url() {
+        use crate::validation::IssueCode;
+
+        let codes = |url: &str| {
+            ClientSseTransport::builder(url.to_string())
+                .with_auth("secret".to_string())
+                .try_build()
+                .err()
+                .map(|e| e.codes())
+        };
+        assert_eq!(codes("http://localhost:3004"), None);
+        assert_eq!(codes("https://mcp.example.com"), None);
+        assert_eq!(
+            codes("http://mcp.example.com"),
+            Some(vec![IssueCode::InsecureAuth])
+        );
+        assert_eq!(codes("mcp.example.com"), Some(vec![IssueCode::InvalidUrl]));
+    }
+
+    // Minimal HTTP server accepting POSTs and recording the request headers
+    async fn mock_message_server() -> Result<(String, mpsc::Receiver<HashMap<String, String>>)> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("http://{}", listener.local_addr()?);
+        let (tx, rx) = mpsc::channel(10);
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut raw = Vec::new();
+                let mut buf = [0u8; 4096];
+                while !raw.windows(4).any(|w| w == b"\r\n\r\n") {
+                    let n = stream.read(&mut buf).await.unwrap();
+                    if n == 0 {
+                        break;
+                    }
+                    raw.extend_from_slice(&buf[..n]);
+                }
+                let head = String::from_utf8_lossy(&raw).to_string();
+                let headers = head
+                    .lines()
+                    .skip(1)
+                    .filter_map(|line| line.split_once(':'))
+                    .map(|(k, v)| (k.trim().to_lowercase(), v.trim().to_string()))
+                    .collect();
+                stream
+                    .write_all(
+                        b"HTTP/1.1 202 Accepted\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                    )
+                    .await
+                    .unwrap();
+                let _ = tx.send(headers).await;
+            }
+        });
+        Ok((url, rx))
+    }
+
+    #[tokio::test]
+    async fn test_per_message_headers() -> Result<()> {
+        let (url, mut rx) = mock_message_server().await?;
+        let transport = ClientSseTransportBuilder::new(url.clone())
+            .with_header("x-tenant-id", "default")
+            .with_header("x-client", "async-mcp")
+            .build();
+        *transport.endpoint.lock().await = Some(format!("{}/message?sessionId=test", url));
+
+        let message = Message::Notification(Default::default());
+        let headers = HashMap::from([("x-tenant-id".to_string(), "acme".to_string())]);
+        transport.send_with_headers(&message, &headers).await?;
+        let recorded = rx.recv().await.unwrap();
+        assert_eq!(recorded["x-tenant-id"], "acme");
+        assert_eq!(recorded["x-client"], "async-mcp");
+
+        // Headers set on RequestOptions reach the POST through the protocol
+        let protocol = Protocol::builder(transport).build();
+        let _ = protocol
+            .request(
+                "ping",
+                None,
+                RequestOptions::default()
+                    .timeout(std::time::Duration::from_millis(100))
+                    .header("x-trace-id", "trace-1"),
+            )
+            .await;
+        let recorded = rx.recv().await.unwrap();
+        assert_eq!(recorded["x-trace-id"], "trace-1");
+        assert_eq!(recorded["x-tenant-id"], "default");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_chunks_wait_for_slow_client() -> Result<()> {
+        let (sse_tx, _sse_rx) = broadcast::channel(SLOW_CONSUMER_BACKLOG * 2);
+        let transport = ServerSseTransport::new(sse_tx);
+        let chunk = Message::Notification(crate::transport::JsonRpcNotification {
+            method: RESOURCE_CHUNK_METHOD.to_string(),
+            ..Default::default()
+        });
+        for _ in 0..SLOW_CONSUMER_BACKLOG {
+            transport.send(&chunk).await?;
+        }
+        // The client hasn't read any of them
+        let next = transport.send(&chunk);
+        tokio::pin!(next);
+        assert!(tokio::time::timeout(Duration::from_millis(50), &mut next)
+            .await
+            .is_err());
+        transport.delivered();
+        next.await?;
+
+        // A client that left doesn't hold the stream up
+        transport.disconnect();
+        transport.send(&chunk).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_close_after_lagging() -> Result<()> {
+        let (sse_tx, mut sse_rx) = broadcast::channel(1);
+        let transport = ServerSseTransport::new(sse_tx);
+        let ping = Message::Request(crate::transport::JsonRpcRequest {
+            method: "ping".to_string(),
+            ..Default::default()
+        });
+        for _ in 0..3 {
+            transport.send(&ping).await?;
+        }
+        match sse_rx.recv().await {
+            Err(broadcast::error::RecvError::Lagged(skipped)) => transport.skipped(skipped),
+            other => panic!("Expected a lag, got {:?}", other),
+        }
+        sse_rx.recv().await?;
+        transport.delivered();
+
+        // Nothing is left for the client to read
+        tokio::time::timeout(
+            Duration::from_secs(1),
+            transport.close_with_timeout(Duration::from_secs(10)),
+        )
+        .await??;
+        Ok(())
+    }
 
     #[test]
     fn test_parse_large_sse_message() {
@@ -459,7 +900,7 @@ mod tests {
         sse_message.push('\n');
 
         // Try to parse it
-        let result = ClientSseTransport::parse_sse_message(&sse_message);
+        let result = ClientSseTransport::parse_sse_message(&sse_message, DecodeLimits::default());
         assert!(result.is_some(), "Failed to parse SSE message");
 
         if let Some(SseEvent::Message(msg)) = result {
@@ -479,7 +920,7 @@ mod tests {
             "data: ired\":[\"path\",\"pattern\"],\"type\":\"object\"},\"name\":\"search_files\"},{\"description\":\"Retrieve detailed metadata about a file or directory. Returns comprehensive information including size, creation time, last modified time, permissions, and type. This tool is perfect for understanding file characteristics without reading the actual content. Only works within allowed directories.\",\"inputSchema\":{\"$schema\":\"http: //json-schema.org/draft-07/schema#\",\"additionalProperties\":false,\"properties\":{\"path\":{\"type\":\"string\"}},\"required\":[\"path\"],\"type\":\"object\"},\"name\":\"get_file_info\"},{\"description\":\"Returns the list of directories that this server is allowed to access. Use this to understand which directories are available before trying to access files.\",\"inputSchema\":{\"properties\":{},\"required\":[],\"type\":\"object\"},\"name\":\"list_allowed_directories\"}]},\"jsonrpc\":\"2.0\"}"
         );
 
-        let result = ClientSseTransport::parse_sse_message(sse_message);
+        let result = ClientSseTransport::parse_sse_message(sse_message, DecodeLimits::default());
         assert!(result.is_some(), "Failed to parse real SSE message");
 
         // Verify we can parse the message into valid JSON
@@ -490,4 +931,88 @@ mod tests {
             panic!("Expected Message event");
         }
     }
+
+    #[tokio::test]
+    async fn test_sse_skips_garbage() -> Result<()> {
+        let (tx, mut rx) = mpsc::channel(10);
+        let endpoint = Arc::new(Mutex::new(None));
+        let buffer = Arc::new(Mutex::new(String::new()));
+
+        let valid = r#"{"id":1,"method":"test","jsonrpc":"2.0"}"#;
+        let truncated = &valid[..valid.len() / 2];
+        let chunk =
+            format!("event: message\ndata: {truncated}\n\nrandom noise\n\ndata: {valid}\n\n");
+        ClientSseTransport::handle_sse_chunk(
+            Bytes::from(chunk),
+            "http://localhost",
+            &tx,
+            &endpoint,
+            &buffer,
+            DecodeLimits::default(),
+        )
+        .await?;
+
+        match rx.try_recv()? {
+            Message::Request(request) => assert_eq!(request.id, 1),
+            other => panic!("Unexpected message: {:?}", other),
+        }
+        assert!(rx.try_recv().is_err());
+        assert!(buffer.lock().await.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_respects_limits() {
+        let event = "data: {\"jsonrpc\":\"2.0\",\"method\":\"a\",\"params\":[[[1]]]}\n\n";
+        let parse = |limits| ClientSseTransport::parse_sse_message(event, limits);
+        assert!(parse(DecodeLimits::default()).is_some());
+        assert!(parse(DecodeLimits::default().max_depth(2)).is_none());
+        assert!(parse(DecodeLimits::default().max_bytes(16)).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_endpoint_resolution() -> Result<()> {
+        let (tx, _rx) = mpsc::channel(10);
+        let endpoint = Arc::new(Mutex::new(None));
+        let buffer = Arc::new(Mutex::new(String::new()));
+        let resolved = |data: &'static str| {
+            let (tx, endpoint, buffer) = (tx.clone(), endpoint.clone(), buffer.clone());
+            async move {
+                let chunk = format!("event: endpoint\ndata: {data}\n\n");
+                ClientSseTransport::handle_sse_chunk(
+                    Bytes::from(chunk),
+                    "https://proxy.example.com/mcp/",
+                    &tx,
+                    &endpoint,
+                    &buffer,
+                    DecodeLimits::default(),
+                )
+                .await?;
+                let url = endpoint.lock().await.clone();
+                Ok::<_, anyhow::Error>(url)
+            }
+        };
+
+        // Relative to the event stream, which keeps a prefix the proxy strips
+        assert_eq!(
+            resolved("message?sessionId=abc").await?.as_deref(),
+            Some("https://proxy.example.com/mcp/message?sessionId=abc")
+        );
+        // A server mounted under the prefix advertises it in an absolute path
+        assert_eq!(
+            resolved("/mcp/message?sessionId=abc").await?.as_deref(),
+            Some("https://proxy.example.com/mcp/message?sessionId=abc")
+        );
+        assert_eq!(
+            resolved("/message?sessionId=abc").await?.as_deref(),
+            Some("https://proxy.example.com/message?sessionId=abc")
+        );
+        assert_eq!(
+            resolved("https://edge.example.com/message?sessionId=def")
+                .await?
+                .as_deref(),
+            Some("https://edge.example.com/message?sessionId=def")
+        );
+        Ok(())
+    }
 }
@@ -1,41 +1,185 @@
-use super::{Message, Transport};
+use super::compression::{decode_frame, Compression};
+use super::{
+    invalid_message, ConnectionActivity, DecodeLimits, JsonRpcRequest, JsonRpcVersion, Message,
+    RequestId, Transport, DEFAULT_DRAIN_TIMEOUT,
+};
 use anyhow::Result;
 use async_trait::async_trait;
+use std::borrow::Cow;
 use std::collections::HashMap;
-use std::io::{self, BufRead, Write};
+use std::io::{self, Write};
 use std::process::Stdio;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, SystemTime};
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, BufWriter};
 use tokio::process::Child;
 use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
 use tracing::debug;
 
-/// Stdio transport for server with json serialization
+// Liveness pings use ids far above the protocol's own so replies can't be mistaken
+const PING_ID_START: RequestId = 1 << 62;
+
+/// How long [`ClientStdioTransport::close`] waits for the child to exit after closing its
+/// stdin, and on unix again after SIGTERM
+const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[cfg(windows)]
+const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+
+/// Accumulates one line from a buffered reader, bytes past `max_bytes` aren't kept
+/// so a runaway peer can't grow memory, the oversized line is skipped up to its newline
+pub(super) struct LineBuffer {
+    line: Vec<u8>,
+    max_bytes: usize,
+    oversized: Option<usize>,
+    eof: bool,
+}
+
+impl LineBuffer {
+    pub(super) fn new(max_bytes: usize) -> Self {
+        Self {
+            line: Vec::new(),
+            max_bytes,
+            oversized: None,
+            eof: false,
+        }
+    }
+
+    /// Take bytes from the reader's buffer, returns how many were used and whether the line ended
+    pub(super) fn push(&mut self, available: &[u8]) -> (usize, bool) {
+        if available.is_empty() {
+            self.eof = true;
+            return (0, true);
+        }
+        let (chunk, done) = match available.iter().position(|&b| b == b'\n') {
+            Some(end) => (&available[..=end], true),
+            None => (available, false),
+        };
+        // The newline ending the line doesn't count towards the limit
+        let content = chunk.len() - usize::from(done);
+        match &mut self.oversized {
+            Some(skipped) => *skipped += content,
+            None if self.line.len() + content > self.max_bytes => {
+                self.oversized = Some(self.line.len() + content);
+                self.line = Vec::new();
+            }
+            None => self.line.extend_from_slice(chunk),
+        }
+        (chunk.len(), done)
+    }
+
+    /// The line without its `\n` or `\r\n`, `None` at the end of input
+    pub(super) fn finish(mut self) -> Result<Option<String>> {
+        if let Some(bytes) = self.oversized {
+            return Err(invalid_message(format!(
+                "line of {} bytes exceeds the limit of {} bytes",
+                bytes, self.max_bytes
+            ))
+            .into());
+        }
+        if self.eof && self.line.is_empty() {
+            return Ok(None);
+        }
+        if self.line.last() == Some(&b'\n') {
+            self.line.pop();
+            if self.line.last() == Some(&b'\r') {
+                self.line.pop();
+            }
+        }
+        Ok(Some(String::from_utf8(self.line)?))
+    }
+}
+
+/// Stdio transport for server with json serialization, built with [`ServerStdioTransport::new`]
+/// or `default()` since it has settings
 /// TODO: support for other binary serialzation formats
 #[derive(Default, Clone)]
-pub struct ServerStdioTransport;
+pub struct ServerStdioTransport {
+    limits: DecodeLimits,
+    compression: Option<Arc<Compression>>,
+}
+
+impl ServerStdioTransport {
+    /// Default limits and no compression
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Lines longer than `max_bytes` are skipped and fail with an `invalid_message` error
+    pub fn with_limits(limits: DecodeLimits) -> Self {
+        Self {
+            limits,
+            compression: None,
+        }
+    }
+
+    /// Gzip messages longer than `threshold` bytes once the client advertised support in `initialize`
+    /// compressed messages from the client are always accepted
+    pub fn compression(mut self, threshold: usize) -> Self {
+        self.compression = Some(Arc::new(Compression::new(threshold)));
+        self
+    }
+}
+
+/// The process has one stdin, every server transport reads through the same buffer.
+/// Reading is async so a receive can wait while the protocol handles a request
+fn stdin() -> &'static Mutex<BufReader<tokio::io::Stdin>> {
+    static STDIN: OnceLock<Mutex<BufReader<tokio::io::Stdin>>> = OnceLock::new();
+    STDIN.get_or_init(|| Mutex::new(BufReader::new(tokio::io::stdin())))
+}
+
+/// The message as sent, with our side of the compression negotiation
+fn outgoing<'a>(compression: &Option<Arc<Compression>>, message: &'a Message) -> Cow<'a, Message> {
+    match compression {
+        Some(compression) => compression.outgoing(message),
+        None => Cow::Borrowed(message),
+    }
+}
+
+/// The line to write for `serialized`
+fn compress(compression: &Option<Arc<Compression>>, serialized: String) -> Result<String> {
+    match compression {
+        Some(compression) => compression.encode(serialized),
+        None => Ok(serialized),
+    }
+}
+
 #[async_trait]
 impl Transport for ServerStdioTransport {
     async fn receive(&self) -> Result<Option<Message>> {
-        let stdin = io::stdin();
-        let mut reader = stdin.lock();
-        let mut line = String::new();
-        reader.read_line(&mut line)?;
-        if line.is_empty() {
-            return Ok(None);
+        let mut reader = stdin().lock().await;
+        let mut line = LineBuffer::new(self.limits.max_bytes);
+        loop {
+            let available = reader.fill_buf().await?;
+            let (consumed, done) = line.push(available);
+            reader.consume(consumed);
+            if done {
+                break;
+            }
         }
+        let Some(line) = line.finish()? else {
+            return Ok(None);
+        };
 
+        let line = decode_frame(&line, self.limits.max_bytes)?;
         debug!("Received: {line}");
-        let message: Message = serde_json::from_str(&line)?;
+        let message = self.limits.decode(&line)?;
+        if let Some(compression) = &self.compression {
+            compression.incoming(&message);
+        }
         Ok(Some(message))
     }
 
     async fn send(&self, message: &Message) -> Result<()> {
         let stdout = io::stdout();
         let mut writer = stdout.lock();
-        let serialized = serde_json::to_string(message)?;
+        let message = outgoing(&self.compression, message);
+        let serialized = serde_json::to_string(&*message)?;
         debug!("Sending: {serialized}");
-        writer.write_all(serialized.as_bytes())?;
+        let line = compress(&self.compression, serialized)?;
+        writer.write_all(line.as_bytes())?;
         writer.write_all(b"\n")?;
         writer.flush()?;
         Ok(())
@@ -45,7 +189,9 @@ impl Transport for ServerStdioTransport {
         Ok(())
     }
 
+    // Every send is flushed, this only catches writes made around the transport
     async fn close(&self) -> Result<()> {
+        io::stdout().flush()?;
         Ok(())
     }
 }
@@ -59,9 +205,20 @@ pub struct ClientStdioTransport {
     program: String,
     args: Vec<String>,
     env: Option<HashMap<String, String>>,
+    limits: DecodeLimits,
+    compression: Option<Arc<Compression>>,
+    activity: Arc<ConnectionActivity>,
+    ping_interval: Option<Duration>,
+    next_ping_id: Arc<AtomicU64>,
+    pinger: Arc<Mutex<Option<JoinHandle<()>>>>,
+    shutdown_timeout: Duration,
+    no_window: bool,
 }
 
 impl ClientStdioTransport {
+    /// `program` is started directly, without a shell, and each of `args` reaches it as one
+    /// argument, spaces included. On Windows they are quoted for the child's command line
+    /// parser, scripts such as `npx.cmd` need their extension
     pub fn new(program: &str, args: &[&str], env: Option<HashMap<String, String>>) -> Result<Self> {
         Ok(ClientStdioTransport {
             stdin: Arc::new(Mutex::new(None)),
@@ -70,9 +227,90 @@ impl ClientStdioTransport {
             program: program.to_string(),
             args: args.iter().map(|&s| s.to_string()).collect(),
             env,
+            limits: DecodeLimits::default(),
+            compression: None,
+            activity: Arc::new(ConnectionActivity::default()),
+            ping_interval: None,
+            next_ping_id: Arc::new(AtomicU64::new(PING_ID_START)),
+            pinger: Arc::new(Mutex::new(None)),
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+            no_window: false,
         })
     }
+
+    /// Limits for messages from the child, longer lines are skipped with an `invalid_message` error
+    pub fn decode_limits(mut self, limits: DecodeLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Gzip messages longer than `threshold` bytes once the child advertised support in `initialize`
+    /// compressed messages from the child are always accepted
+    pub fn compression(mut self, threshold: usize) -> Self {
+        self.compression = Some(Arc::new(Compression::new(threshold)));
+        self
+    }
+
+    /// How long `close` waits for the child to exit on its own, once after closing its stdin
+    /// and on unix once more after SIGTERM, before killing it. One second by default
+    pub fn shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.shutdown_timeout = timeout;
+        self
+    }
+
+    /// Start the child without a console window on Windows, e.g. from a GUI host
+    /// no effect on other platforms
+    pub fn no_window(mut self, enabled: bool) -> Self {
+        self.no_window = enabled;
+        self
+    }
+
+    /// Send an MCP `ping` whenever nothing was received for `interval`
+    /// replies are consumed by the transport and only refresh `last_activity`
+    pub fn ping_interval(mut self, interval: Duration) -> Self {
+        self.ping_interval = Some(interval);
+        self
+    }
+
+    /// When the child last wrote a message, the open time until it does
+    pub fn last_activity(&self) -> SystemTime {
+        self.activity.last_activity()
+    }
+
+    /// Whether the child wrote something within `max_idle`
+    /// with a ping interval below `max_idle` a live child always is, a wedged one stops being
+    pub fn is_healthy(&self, max_idle: Duration) -> bool {
+        self.last_activity().elapsed().unwrap_or_default() <= max_idle
+    }
+
+    async fn ping(&self) -> Result<()> {
+        let id = self.next_ping_id.fetch_add(1, Ordering::Relaxed);
+        self.send(&Message::Request(JsonRpcRequest {
+            id,
+            method: "ping".to_string(),
+            params: None,
+            jsonrpc: JsonRpcVersion::default(),
+        }))
+        .await
+    }
+}
+fn decode_line(line: &str, limits: &DecodeLimits) -> Result<Message> {
+    let row = if line.len() > 1000 {
+        let start = &line[..100];
+        let end = &line[line.len() - 100..];
+        format!("{}...{}", start, end)
+    } else {
+        line.to_string()
+    };
+
+    debug!("ClientStdioTransport: Received from process: {}", row);
+    let line = decode_frame(line, limits.max_bytes)?;
+    limits.decode(&line).map_err(|e| {
+        tracing::error!("Failed to parse message: {}", e);
+        e
+    })
 }
+
 #[async_trait]
 impl Transport for ClientStdioTransport {
     async fn receive(&self) -> Result<Option<Message>> {
@@ -82,29 +320,35 @@ impl Transport for ClientStdioTransport {
             .as_mut()
             .ok_or_else(|| anyhow::anyhow!("Transport not opened"))?;
 
-        let mut line = String::new();
-        debug!("ClientStdioTransport: Reading line from process");
-        let bytes_read = stdout.read_line(&mut line).await?;
-        debug!("ClientStdioTransport: Read {} bytes", bytes_read);
-
-        if bytes_read == 0 {
-            debug!("ClientStdioTransport: Received EOF from process");
-            return Ok(None);
-        }
-
-        let row = if line.len() > 1000 {
-            let start = &line[..100];
-            let end = &line[line.len() - 100..];
-            format!("{}...{}", start, end)
-        } else {
-            line.clone()
+        let message = loop {
+            debug!("ClientStdioTransport: Reading line from process");
+            let mut line = LineBuffer::new(self.limits.max_bytes);
+            loop {
+                let available = stdout.fill_buf().await?;
+                let (consumed, done) = line.push(available);
+                stdout.consume(consumed);
+                if done {
+                    break;
+                }
+            }
+            let Some(line) = line.finish()? else {
+                debug!("ClientStdioTransport: Received EOF from process");
+                return Ok(None);
+            };
+            self.activity.touch();
+            let message = decode_line(&line, &self.limits)?;
+            if let Some(compression) = &self.compression {
+                compression.incoming(&message);
+            }
+            // Replies to liveness pings stay inside the transport, late ones included
+            if let Message::Response(response) = &message {
+                let pings = PING_ID_START..self.next_ping_id.load(Ordering::Relaxed);
+                if pings.contains(&response.id) {
+                    continue;
+                }
+            }
+            break message;
         };
-        
-        debug!("ClientStdioTransport: Received from process: {}", row);
-        let message: Message = serde_json::from_str(&line).map_err(|e| {
-            tracing::error!("Failed to parse message: {}", e);
-            e
-        })?;
         debug!("ClientStdioTransport: Successfully parsed message");
         Ok(Some(message))
     }
@@ -116,9 +360,11 @@ impl Transport for ClientStdioTransport {
             .as_mut()
             .ok_or_else(|| anyhow::anyhow!("Transport not opened"))?;
 
-        let serialized = serde_json::to_string(message)?;
+        let message = outgoing(&self.compression, message);
+        let serialized = serde_json::to_string(&*message)?;
         debug!("ClientStdioTransport: Sending to process: {serialized}");
-        stdin.write_all(serialized.as_bytes()).await?;
+        let line = compress(&self.compression, serialized)?;
+        stdin.write_all(line.as_bytes()).await?;
         stdin.write_all(b"\n").await?;
         stdin.flush().await?;
         debug!("ClientStdioTransport: Successfully sent and flushed message");
@@ -127,6 +373,15 @@ impl Transport for ClientStdioTransport {
 
     async fn open(&self) -> Result<()> {
         debug!("ClientStdioTransport: Opening transport");
+        // Held until the new child is stored, concurrent opens spawn it once
+        let mut child_guard = self.child.lock().await;
+        if let Some(child) = child_guard.as_mut() {
+            if child.try_wait()?.is_none() {
+                debug!("ClientStdioTransport: Already open");
+                return Ok(());
+            }
+            debug!("ClientStdioTransport: Child exited, respawning");
+        }
         let mut command = tokio::process::Command::new(&self.program);
 
         // Set up the command with args and stdio
@@ -141,6 +396,10 @@ impl Transport for ClientStdioTransport {
                 command.env(key, value);
             }
         }
+        #[cfg(windows)]
+        if self.no_window {
+            command.creation_flags(CREATE_NO_WINDOW);
+        }
 
         let mut child = command.spawn()?;
 
@@ -156,21 +415,57 @@ impl Transport for ClientStdioTransport {
 
         *self.stdin.lock().await = Some(BufWriter::new(stdin));
         *self.stdout.lock().await = Some(BufReader::new(stdout));
-        *self.child.lock().await = Some(child);
+        *child_guard = Some(child);
+        drop(child_guard);
+        self.activity.touch();
+
+        if let Some(interval) = self.ping_interval {
+            let transport = self.clone();
+            *self.pinger.lock().await = Some(tokio::spawn(async move {
+                let mut ticker = tokio::time::interval(interval);
+                ticker.tick().await;
+                loop {
+                    ticker.tick().await;
+                    // At most one ping per interval, a wedged child's pipe isn't flooded
+                    // and one lost reply doesn't stop the pings
+                    if transport.is_healthy(interval) {
+                        continue;
+                    }
+                    if let Err(e) = transport.ping().await {
+                        debug!("ClientStdioTransport: Ping failed: {}", e);
+                        break;
+                    }
+                }
+            }));
+        }
 
         Ok(())
     }
 
+    /// Closes the child's stdin and waits for it to exit, which an MCP server does at EOF.
+    /// A child still running after the shutdown timeout gets SIGTERM on unix and another
+    /// timeout, then it is killed, with `TerminateProcess` on Windows
     async fn close(&self) -> Result<()> {
-        const GRACEFUL_TIMEOUT_MS: u64 = 1000;
-        const SIGTERM_TIMEOUT_MS: u64 = 500;
+        self.close_with_timeout(DEFAULT_DRAIN_TIMEOUT).await
+    }
+
+    async fn close_with_timeout(&self, timeout: Duration) -> Result<()> {
         debug!("Starting graceful shutdown");
+        if let Some(pinger) = self.pinger.lock().await.take() {
+            pinger.abort();
+        }
         {
             let mut stdin_guard = self.stdin.lock().await;
             if let Some(stdin) = stdin_guard.as_mut() {
                 debug!("Flushing stdin");
-                stdin.flush().await?;
+                // A child that already exited still has to be reaped below
+                match tokio::time::timeout(timeout, stdin.flush()).await {
+                    Ok(Err(e)) => debug!("Failed to flush stdin: {}", e),
+                    Err(_) => debug!("Child stopped reading, dropping unflushed messages"),
+                    Ok(Ok(())) => {}
+                }
             }
+            // Dropping the pipe is the child's EOF
             *stdin_guard = None;
         }
 
@@ -180,31 +475,20 @@ impl Transport for ClientStdioTransport {
             return Ok(());
         };
 
-        debug!("Attempting graceful shutdown");
-        match child.try_wait()? {
-            Some(status) => {
-                debug!("Process already exited with status: {}", status);
-                *child_guard = None;
-                return Ok(());
-            }
+        debug!("Waiting for process to exit after EOF");
+        let status = match exit_within(child, self.shutdown_timeout).await {
+            Some(status) => Some(status),
+            None => terminate(child, self.shutdown_timeout).await,
+        };
+        let status = match status {
+            Some(status) => Ok(status),
             None => {
-                debug!("Waiting for process to exit gracefully");
-                tokio::time::sleep(tokio::time::Duration::from_millis(GRACEFUL_TIMEOUT_MS)).await;
+                debug!("Process still running, killing it");
+                child.kill().await?;
+                child.wait().await
             }
-        }
-
-        if child.try_wait()?.is_none() {
-            debug!("Process still running, sending SIGTERM");
-            child.kill().await?;
-            tokio::time::sleep(tokio::time::Duration::from_millis(SIGTERM_TIMEOUT_MS)).await;
-        }
-
-        if child.try_wait()?.is_none() {
-            debug!("Process not responding to SIGTERM, forcing kill");
-            child.kill().await?;
-        }
-
-        match child.wait().await {
+        };
+        match status {
             Ok(status) => debug!("Process exited with status: {}", status),
             Err(e) => debug!("Error waiting for process exit: {}", e),
         }
@@ -215,114 +499,323 @@ impl Transport for ClientStdioTransport {
     }
 }
 
+/// SIGTERM, the child's exit status if it exits within `timeout`
+#[cfg(unix)]
+async fn terminate(child: &mut Child, timeout: Duration) -> Option<std::process::ExitStatus> {
+    let pid = child.id()?;
+    debug!("Process still running, sending SIGTERM");
+    // SAFETY: plain syscall, the child isn't reaped yet so the pid is still its own
+    unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM) };
+    exit_within(child, timeout).await
+}
+
+/// Windows has no SIGTERM for a child without its own console, it is killed right away
+#[cfg(not(unix))]
+async fn terminate(_child: &mut Child, _timeout: Duration) -> Option<std::process::ExitStatus> {
+    None
+}
+
+/// The child's exit status if it exits within `timeout`
+async fn exit_within(child: &mut Child, timeout: Duration) -> Option<std::process::ExitStatus> {
+    match tokio::time::timeout(timeout, child.wait()).await {
+        Ok(Ok(status)) => Some(status),
+        Ok(Err(e)) => {
+            debug!("Error waiting for process exit: {}", e);
+            None
+        }
+        Err(_) => None,
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::transport::{JsonRpcMessage, JsonRpcRequest, JsonRpcVersion};
+    use crate::transport::{JsonRpcMessage, JsonRpcNotification, JsonRpcRequest, JsonRpcVersion};
 
     use super::*;
     use std::time::Duration;
     #[tokio::test]
     #[cfg(unix)]
-    async fn test_stdio_transport() -> Result<()> {
-        // Create transport connected to cat command which will stay alive
-        let transport = ClientStdioTransport::new("cat", &[], None)?;
-
-        // Create a test message
-        let test_message = JsonRpcMessage::Request(JsonRpcRequest {
-            id: 1,
+    async fn test_compression_negotiated_at_initialize() -> Result<()> {
+        // cat echoes our own initialize back, so the advertisement reaches us as the peer's
+        let transport = ClientStdioTransport::new("cat", &[], None)?.compression(64);
+        transport.open().await?;
+        let large = JsonRpcMessage::Request(JsonRpcRequest {
+            id: 2,
             method: "test".to_string(),
-            params: Some(serde_json::json!({"hello": "world"})),
+            params: Some(serde_json::json!({"text": "x".repeat(1000)})),
             jsonrpc: JsonRpcVersion::default(),
         });
+        transport.send(&large).await?;
+        assert_eq!(transport.receive().await?, Some(large.clone()));
+        assert!(!transport.compression.as_ref().unwrap().is_negotiated());
+
+        transport
+            .send(&JsonRpcMessage::Request(JsonRpcRequest {
+                id: 1,
+                method: "initialize".to_string(),
+                params: Some(serde_json::json!({"capabilities": {}})),
+                jsonrpc: JsonRpcVersion::default(),
+            }))
+            .await?;
+        let Some(JsonRpcMessage::Request(initialize)) = transport.receive().await? else {
+            panic!("Expected the echoed initialize");
+        };
+        assert_eq!(
+            initialize.params.unwrap()["capabilities"]["experimental"]["stdioCompression"],
+            serde_json::json!({"encodings": ["gzip"]})
+        );
+        assert!(transport.compression.as_ref().unwrap().is_negotiated());
 
-        // Open transport
-        transport.open().await?;
+        // Sent as a gzip frame and decoded on the way back
+        transport.send(&large).await?;
+        assert_eq!(transport.receive().await?, Some(large));
 
-        // Send message
-        transport.send(&test_message).await?;
+        transport.close().await?;
+        Ok(())
+    }
 
-        // Receive echoed message
-        let response = transport.receive().await?;
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn test_oversized_line_is_skipped() -> Result<()> {
+        let script = r#"head -c 100000 /dev/zero | tr '\0' 'a'; echo
+            echo '{"jsonrpc":"2.0","method":"ok"}'"#;
+        let transport = ClientStdioTransport::new("sh", &["-c", script], None)?
+            .decode_limits(DecodeLimits::default().max_bytes(1024));
+        transport.open().await?;
 
-        // Verify the response matches
-        assert_eq!(Some(test_message), response);
+        let err = transport.receive().await.unwrap_err();
+        let err = err
+            .downcast_ref::<crate::transport::JsonRpcError>()
+            .unwrap();
+        assert_eq!(
+            err.error_data().unwrap().kind,
+            crate::types::ErrorData::INVALID_MESSAGE
+        );
+        // The next line is read normally
+        match transport.receive().await? {
+            Some(JsonRpcMessage::Notification(notification)) => {
+                assert_eq!(notification.method, "ok")
+            }
+            other => panic!("Expected notification, got {:?}", other),
+        }
+        assert_eq!(transport.receive().await?, None);
 
-        // Clean up
         transport.close().await?;
-
         Ok(())
     }
 
     #[tokio::test]
     #[cfg(unix)]
-    async fn test_graceful_shutdown() -> Result<()> {
-        // Create transport with a sleep command that runs for 5 seconds
-        let transport = ClientStdioTransport::new("sleep", &["5"], None)?;
+    async fn test_health_check_flags_stopped_child() -> Result<()> {
+        // Answers every request with an empty result, like a server does for `ping`
+        let script = r#"while read -r line; do
+            id=$(echo "$line" | sed -n 's/.*"id":\([0-9]*\).*/\1/p')
+            echo "{\"jsonrpc\":\"2.0\",\"id\":$id,\"result\":{}}"
+        done"#;
+        let transport = ClientStdioTransport::new("sh", &["-c", script], None)?
+            .ping_interval(Duration::from_millis(50));
         transport.open().await?;
+        let reader = transport.clone();
+        tokio::spawn(async move { while let Ok(Some(_)) = reader.receive().await {} });
+
+        let max_idle = Duration::from_millis(300);
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert!(transport.is_healthy(max_idle));
+
+        let pid = transport.child.lock().await.as_ref().unwrap().id().unwrap();
+        std::process::Command::new("kill")
+            .args(["-STOP", &pid.to_string()])
+            .status()?;
+        tokio::time::sleep(max_idle + Duration::from_millis(200)).await;
+        assert!(!transport.is_healthy(max_idle));
+
+        std::process::Command::new("kill")
+            .args(["-CONT", &pid.to_string()])
+            .status()?;
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(transport.is_healthy(max_idle));
 
-        // Spawn a task that will read from the transport
-        let transport_clone = transport.clone();
-        let read_handle = tokio::spawn(async move {
-            let result = transport_clone.receive().await;
-            debug!("Receive returned: {:?}", result);
-            result
-        });
+        transport.close().await?;
+        Ok(())
+    }
 
-        // Wait a bit to ensure the process is running
-        tokio::time::sleep(Duration::from_millis(100)).await;
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn test_pings_continue_after_lost_reply() -> Result<()> {
+        // Ignores the first ping, then answers like a server does
+        let script = r#"read -r first
+        while read -r line; do
+            id=$(echo "$line" | sed -n 's/.*"id":\([0-9]*\).*/\1/p')
+            echo "{\"jsonrpc\":\"2.0\",\"id\":$id,\"result\":{}}"
+        done"#;
+        let transport = ClientStdioTransport::new("sh", &["-c", script], None)?
+            .ping_interval(Duration::from_millis(50));
+        transport.open().await?;
+        let reader = transport.clone();
+        let received = tokio::spawn(async move { reader.receive().await });
 
-        // Initiate graceful shutdown
-        let start = std::time::Instant::now();
-        transport.close().await?;
-        let shutdown_duration = start.elapsed();
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert!(transport.is_healthy(Duration::from_millis(300)));
+        // Ping replies never reach the caller
+        assert!(!received.is_finished());
 
-        // Verify that:
-        // 1. The read operation was cancelled (returned None)
-        // 2. The shutdown completed in less than 5 seconds (didn't wait for sleep)
-        // 3. The process was properly terminated
-        let read_result = read_handle.await?;
-        assert!(read_result.is_ok());
-        assert_eq!(read_result.unwrap(), None);
-        assert!(shutdown_duration < Duration::from_secs(5));
+        received.abort();
+        transport.close().await?;
+        Ok(())
+    }
 
-        // Verify process is no longer running
-        let child_guard = transport.child.lock().await;
-        assert!(child_guard.is_none());
+    #[test]
+    fn test_crlf_line_endings() -> Result<()> {
+        let mut line = LineBuffer::new(1024);
+        assert_eq!(line.push(b"{\"a\":1}\r\n{\"b\":2}\n"), (9, true));
+        assert_eq!(line.finish()?.as_deref(), Some("{\"a\":1}"));
+        let mut line = LineBuffer::new(1024);
+        line.push(b"{\"b\":2}\n");
+        assert_eq!(line.finish()?.as_deref(), Some("{\"b\":2}"));
+        Ok(())
+    }
 
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn test_open_respawns_exited_child() -> Result<()> {
+        let script = r#"echo '{"jsonrpc":"2.0","method":"hello"}'"#;
+        let transport = ClientStdioTransport::new("sh", &["-c", script], None)?;
+        for _ in 0..2 {
+            transport.open().await?;
+            assert!(matches!(
+                transport.receive().await?,
+                Some(JsonRpcMessage::Notification(_))
+            ));
+            assert!(transport.receive().await?.is_none());
+            while let Some(child) = transport.child.lock().await.as_mut() {
+                if child.try_wait()?.is_some() {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }
+        transport.close().await?;
         Ok(())
     }
 
     #[tokio::test]
     #[cfg(unix)]
-    async fn test_shutdown_with_pending_io() -> Result<()> {
-        // Use 'read' command which will wait for input without echoing
-        let transport = ClientStdioTransport::new("read", &[], None)?;
+    async fn test_arguments_keep_spaces() -> Result<()> {
+        let script = r#"printf '{"jsonrpc":"2.0","method":"%s"}\r\n' "$1""#;
+        let transport = ClientStdioTransport::new("sh", &["-c", script, "sh", "two words"], None)?;
         transport.open().await?;
+        match transport.receive().await? {
+            Some(JsonRpcMessage::Notification(notification)) => {
+                assert_eq!(notification.method, "two words")
+            }
+            other => panic!("Expected notification, got {:?}", other),
+        }
+        transport.close().await?;
+        Ok(())
+    }
 
-        // Start a receive operation that will be pending
-        let transport_clone = transport.clone();
-        let read_handle = tokio::spawn(async move { transport_clone.receive().await });
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn test_close_escalates() -> Result<()> {
+        let timeout = Duration::from_millis(100);
+
+        // Exits at EOF, close doesn't wait out the timeout
+        let cat = ClientStdioTransport::new("cat", &[], None)?.shutdown_timeout(timeout * 10);
+        cat.open().await?;
+        let started = std::time::Instant::now();
+        cat.close().await?;
+        assert!(started.elapsed() < timeout * 10);
+
+        // Ignores EOF and SIGTERM, killed after both timeouts
+        let script = "trap '' TERM; while :; do sleep 0.01; done";
+        let stubborn =
+            ClientStdioTransport::new("sh", &["-c", script], None)?.shutdown_timeout(timeout);
+        stubborn.open().await?;
+        let started = std::time::Instant::now();
+        stubborn.close().await?;
+        assert!(started.elapsed() >= timeout * 2);
+        assert!(stubborn.child.lock().await.is_none());
+        Ok(())
+    }
 
-        // Give some time for read operation to start
-        tokio::time::sleep(Duration::from_millis(100)).await;
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn test_close_delivers_last_notification() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("async-mcp-drain-{}", std::process::id()));
+        let transport = ClientStdioTransport::new(
+            "sh",
+            &["-c", r#"cat > "$1""#, "sh", path.to_str().unwrap()],
+            None,
+        )?;
+        transport.open().await?;
+        let notification = JsonRpcMessage::Notification(JsonRpcNotification {
+            method: "notifications/cancelled".to_string(),
+            ..Default::default()
+        });
+        transport.send(&notification).await?;
+        transport.close_with_timeout(Duration::from_secs(1)).await?;
+
+        let written = std::fs::read_to_string(&path)?;
+        std::fs::remove_file(&path)?;
+        assert_eq!(
+            serde_json::from_str::<JsonRpcMessage>(written.trim())?,
+            notification
+        );
+        Ok(())
+    }
 
-        // Send a message (will be pending since 'read' won't echo)
-        let test_message = JsonRpcMessage::Request(JsonRpcRequest {
+    #[tokio::test]
+    #[cfg(windows)]
+    async fn test_windows_echo_child() -> Result<()> {
+        // findstr prints every line it reads, with CRLF endings, and exits at EOF
+        let transport = ClientStdioTransport::new("findstr", &["^"], None)?
+            .no_window(true)
+            .shutdown_timeout(Duration::from_secs(5));
+        transport.open().await?;
+        let request = JsonRpcMessage::Request(JsonRpcRequest {
             id: 1,
-            method: "test".to_string(),
-            params: Some(serde_json::json!({"hello": "world"})),
+            method: "with spaces".to_string(),
+            params: Some(serde_json::json!({"path": "C:\\Program Files\\tool"})),
             jsonrpc: JsonRpcVersion::default(),
         });
-        transport.send(&test_message).await?;
+        transport.send(&request).await?;
+        assert_eq!(transport.receive().await?, Some(request));
+
+        let started = std::time::Instant::now();
+        transport.close().await?;
+        assert!(started.elapsed() < Duration::from_secs(5));
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn test_receive_malformed_line() -> Result<()> {
+        let valid = r#"{"id":1,"method":"test","jsonrpc":"2.0"}"#;
+        let script = format!("printf '%s\\n%s\\n' 'not json' '{valid}'");
+        let transport = ClientStdioTransport::new("sh", &["-c", &script], None)?;
+        transport.open().await?;
+
+        // A bad line is reported as an error without poisoning the stream
+        assert!(transport.receive().await.is_err());
+        match transport.receive().await? {
+            Some(JsonRpcMessage::Request(request)) => assert_eq!(request.method, "test"),
+            other => panic!("Unexpected message: {:?}", other),
+        }
+        assert_eq!(transport.receive().await?, None);
 
-        // Initiate shutdown
         transport.close().await?;
+        Ok(())
+    }
 
-        // Verify the read operation was cancelled cleanly
-        let read_result = read_handle.await?;
-        assert!(read_result.is_ok());
-        assert_eq!(read_result.unwrap(), None);
+    #[test]
+    fn test_limit_excludes_newline() -> Result<()> {
+        let mut line = LineBuffer::new(4);
+        assert_eq!(line.push(b"abcd\nnext"), (5, true));
+        assert_eq!(line.finish()?.as_deref(), Some("abcd"));
 
+        let mut line = LineBuffer::new(4);
+        line.push(b"abcde\n");
+        assert!(line.finish().is_err());
         Ok(())
     }
 }
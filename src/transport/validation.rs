@@ -0,0 +1,458 @@
+//! Deterministic mapping of JSON to [`JsonRpcMessage`] variants
+//! rules are applied in this order, the first one that matches decides:
+//!
+//! | JSON                                       | Result                          |
+//! |--------------------------------------------|---------------------------------|
+//! | `[]`                                       | empty `Batch`, answered with Invalid Request and counted as [`MessageViolation::EmptyBatch`] |
+//! | array inside a batch                       | [`MessageViolation::NestedBatch`] |
+//! | other array                                | `Batch`, each element by these rules |
+//! | not an object                              | [`MessageViolation::NotAnObject`] |
+//! | `jsonrpc` other than `"2.0"`               | [`MessageViolation::UnsupportedVersion`] |
+//! | `id: null` with `error`, no `method` nor `result` | `UnidentifiedError`, the answer to a message without usable id |
+//! | other `id: null`                           | [`MessageViolation::NullId`]      |
+//! | `method` with `result` or `error`          | [`MessageViolation::MethodWithResult`] |
+//! | `method` and `id`                          | `Request`, an `id` always makes it one |
+//! | `method` without `id`                      | `Notification`                  |
+//! | no `method` nor `id`                       | [`MessageViolation::MissingId`]   |
+//! | `result` and `error`                       | [`MessageViolation::ResultAndError`] |
+//! | neither `result` nor `error`               | [`MessageViolation::NoResultOrError`] |
+//! | otherwise                                  | `Response`, `result: null` is a result |
+//!
+//! the chosen variant is then deserialized as before, unknown fields and ids that aren't
+//! numbers are still rejected
+use super::{JsonRpcError, JsonRpcMessage, JsonRpcUnidentifiedError, JsonRpcVersion};
+use serde::{de::Error as _, Deserialize, Deserializer};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// A way a message breaks JSON-RPC 2.0 or MCP
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MessageViolation {
+    EmptyBatch,
+    NestedBatch,
+    NotAnObject,
+    UnsupportedVersion(String),
+    /// A null id on a request or result, only errors to a message without usable id have one
+    NullId,
+    /// A request or notification also carrying `result` or `error`
+    MethodWithResult,
+    /// Neither `method` nor `id`, so neither a notification nor a response
+    MissingId,
+    ResultAndError,
+    NoResultOrError,
+}
+
+impl fmt::Display for MessageViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MessageViolation::EmptyBatch => write!(f, "empty batch"),
+            MessageViolation::NestedBatch => write!(f, "batch inside a batch"),
+            MessageViolation::NotAnObject => write!(f, "message is not an object"),
+            MessageViolation::UnsupportedVersion(version) => {
+                write!(f, "unsupported jsonrpc version {:?}", version)
+            }
+            MessageViolation::NullId => write!(f, "id is null"),
+            MessageViolation::MethodWithResult => {
+                write!(f, "request or notification with a result or error")
+            }
+            MessageViolation::MissingId => write!(f, "message has neither method nor id"),
+            MessageViolation::ResultAndError => write!(f, "response with both result and error"),
+            MessageViolation::NoResultOrError => {
+                write!(f, "response with neither result nor error")
+            }
+        }
+    }
+}
+
+/// Messages rejected by each rule since the process started
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MessageAnomalies {
+    pub empty_batch: u64,
+    pub nested_batch: u64,
+    pub not_an_object: u64,
+    pub unsupported_version: u64,
+    pub null_id: u64,
+    pub method_with_result: u64,
+    pub missing_id: u64,
+    pub result_and_error: u64,
+    pub no_result_or_error: u64,
+}
+
+static ANOMALIES: [AtomicU64; 9] = [const { AtomicU64::new(0) }; 9];
+
+impl MessageViolation {
+    fn counter(&self) -> usize {
+        match self {
+            MessageViolation::EmptyBatch => 0,
+            MessageViolation::NestedBatch => 1,
+            MessageViolation::NotAnObject => 2,
+            MessageViolation::UnsupportedVersion(_) => 3,
+            MessageViolation::NullId => 4,
+            MessageViolation::MethodWithResult => 5,
+            MessageViolation::MissingId => 6,
+            MessageViolation::ResultAndError => 7,
+            MessageViolation::NoResultOrError => 8,
+        }
+    }
+}
+
+/// Counts of the messages each rule rejected, across all transports
+pub fn message_anomalies() -> MessageAnomalies {
+    let count = |i: usize| ANOMALIES[i].load(Ordering::Relaxed);
+    MessageAnomalies {
+        empty_batch: count(0),
+        nested_batch: count(1),
+        not_an_object: count(2),
+        unsupported_version: count(3),
+        null_id: count(4),
+        method_with_result: count(5),
+        missing_id: count(6),
+        result_and_error: count(7),
+        no_result_or_error: count(8),
+    }
+}
+
+enum ParseError {
+    Violation(MessageViolation),
+    Invalid(serde_json::Error),
+}
+
+fn parse(value: serde_json::Value, in_batch: bool) -> Result<JsonRpcMessage, ParseError> {
+    let violation = |violation| Err(ParseError::Violation(violation));
+    let object = match value {
+        serde_json::Value::Array(_) if in_batch => return violation(MessageViolation::NestedBatch),
+        serde_json::Value::Array(messages) => {
+            return messages
+                .into_iter()
+                .map(|message| parse(message, true))
+                .collect::<Result<_, _>>()
+                .map(JsonRpcMessage::Batch)
+        }
+        serde_json::Value::Object(object) => object,
+        _ => return violation(MessageViolation::NotAnObject),
+    };
+    match object.get("jsonrpc") {
+        Some(serde_json::Value::String(version)) if version == "2.0" => {}
+        None => {}
+        Some(version) => {
+            let version = version
+                .as_str()
+                .map_or_else(|| version.to_string(), str::to_string);
+            return violation(MessageViolation::UnsupportedVersion(version));
+        }
+    }
+    let has = |key| object.contains_key(key);
+    let variant = if object.get("id").is_some_and(|id| id.is_null()) {
+        // JSON-RPC answers parse errors and invalid requests with `id: null`
+        if has("error") && !has("method") && !has("result") {
+            Variant::UnidentifiedError
+        } else {
+            return violation(MessageViolation::NullId);
+        }
+    } else if has("method") {
+        if has("result") || has("error") {
+            return violation(MessageViolation::MethodWithResult);
+        }
+        if has("id") {
+            Variant::Request
+        } else {
+            Variant::Notification
+        }
+    } else if !has("id") {
+        return violation(MessageViolation::MissingId);
+    } else if has("result") && has("error") {
+        return violation(MessageViolation::ResultAndError);
+    } else if !has("result") && !has("error") {
+        return violation(MessageViolation::NoResultOrError);
+    } else {
+        Variant::Response
+    };
+    let value = serde_json::Value::Object(object);
+    match variant {
+        Variant::Request => serde_json::from_value(value).map(JsonRpcMessage::Request),
+        Variant::Notification => serde_json::from_value(value).map(JsonRpcMessage::Notification),
+        Variant::Response => serde_json::from_value(value).map(JsonRpcMessage::Response),
+        Variant::UnidentifiedError => {
+            serde_json::from_value(value).map(|error: UnidentifiedError| {
+                JsonRpcMessage::UnidentifiedError(JsonRpcUnidentifiedError {
+                    error: error.error,
+                    jsonrpc: error.jsonrpc,
+                })
+            })
+        }
+    }
+    .map_err(ParseError::Invalid)
+}
+
+enum Variant {
+    Request,
+    Notification,
+    Response,
+    UnidentifiedError,
+}
+
+/// Wire form of [`JsonRpcUnidentifiedError`], `()` only accepts the null id
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct UnidentifiedError {
+    #[serde(rename = "id")]
+    _id: (),
+    error: JsonRpcError,
+    #[serde(default)]
+    jsonrpc: JsonRpcVersion,
+}
+
+impl<'de> Deserialize<'de> for JsonRpcMessage {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = serde_json::Value::deserialize(deserializer)?;
+        match parse(value, false) {
+            Ok(JsonRpcMessage::Batch(messages)) if messages.is_empty() => {
+                // Kept so the protocol can answer it, there is no id to answer a failed decode
+                ANOMALIES[MessageViolation::EmptyBatch.counter()].fetch_add(1, Ordering::Relaxed);
+                Ok(JsonRpcMessage::Batch(messages))
+            }
+            Ok(message) => Ok(message),
+            Err(ParseError::Violation(violation)) => {
+                ANOMALIES[violation.counter()].fetch_add(1, Ordering::Relaxed);
+                Err(D::Error::custom(format!(
+                    "invalid JSON-RPC message: {}",
+                    violation
+                )))
+            }
+            Err(ParseError::Invalid(e)) => Err(D::Error::custom(e)),
+        }
+    }
+}
+
+impl JsonRpcMessage {
+    /// Rule violations of a message built in code, what deserializing it back would reject
+    pub fn validate(&self) -> Result<(), Vec<MessageViolation>> {
+        let mut violations = Vec::new();
+        self.collect_violations(false, &mut violations);
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+
+    fn collect_violations(&self, in_batch: bool, violations: &mut Vec<MessageViolation>) {
+        let version = match self {
+            JsonRpcMessage::Batch(_) if in_batch => {
+                violations.push(MessageViolation::NestedBatch);
+                return;
+            }
+            JsonRpcMessage::Batch(messages) if messages.is_empty() => {
+                violations.push(MessageViolation::EmptyBatch);
+                return;
+            }
+            JsonRpcMessage::Batch(messages) => {
+                for message in messages {
+                    message.collect_violations(true, violations);
+                }
+                return;
+            }
+            JsonRpcMessage::Request(request) => &request.jsonrpc,
+            JsonRpcMessage::Notification(notification) => &notification.jsonrpc,
+            JsonRpcMessage::UnidentifiedError(error) => &error.jsonrpc,
+            JsonRpcMessage::Response(response) => {
+                match (&response.result, &response.error) {
+                    (Some(_), Some(_)) => violations.push(MessageViolation::ResultAndError),
+                    (None, None) => violations.push(MessageViolation::NoResultOrError),
+                    _ => {}
+                }
+                &response.jsonrpc
+            }
+        };
+        if version.as_str() != "2.0" {
+            violations.push(MessageViolation::UnsupportedVersion(
+                version.as_str().to_string(),
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::{JsonRpcError, JsonRpcResponse};
+
+    #[derive(Debug, PartialEq)]
+    enum Expected {
+        Request,
+        Notification,
+        Response,
+        UnidentifiedError,
+        Batch,
+        Violation(MessageViolation),
+        Invalid,
+    }
+
+    #[test]
+    fn test_message_mapping() {
+        use Expected::*;
+        use MessageViolation::*;
+        let cases = [
+            (r#"{"jsonrpc":"2.0","id":1,"method":"ping"}"#, Request),
+            (
+                r#"{"jsonrpc":"2.0","method":"notifications/initialized"}"#,
+                Notification,
+            ),
+            (r#"{"method":"notifications/initialized"}"#, Notification),
+            // An id makes it a request, even for a notifications/ method
+            (
+                r#"{"jsonrpc":"2.0","id":3,"method":"notifications/cancelled"}"#,
+                Request,
+            ),
+            (
+                r#"{"jsonrpc":"2.0","id":null,"method":"ping"}"#,
+                Violation(NullId),
+            ),
+            (r#"{"jsonrpc":"2.0","id":1,"result":{}}"#, Response),
+            (r#"{"jsonrpc":"2.0","id":1,"result":null}"#, Response),
+            (
+                r#"{"jsonrpc":"2.0","id":1,"error":{"code":-32601,"message":"no"}}"#,
+                Response,
+            ),
+            (
+                r#"{"jsonrpc":"2.0","id":null,"error":{"code":-32700,"message":"parse"}}"#,
+                UnidentifiedError,
+            ),
+            (
+                r#"{"jsonrpc":"2.0","id":null,"result":{}}"#,
+                Violation(NullId),
+            ),
+            (
+                r#"{"jsonrpc":"2.0","id":null,"result":{},"error":{"code":1,"message":"x"}}"#,
+                Violation(NullId),
+            ),
+            (
+                r#"{"jsonrpc":"2.0","id":null,"method":"ping","error":{"code":1,"message":"x"}}"#,
+                Violation(NullId),
+            ),
+            (
+                r#"{"jsonrpc":"2.0","id":null,"error":{"code":-32700,"message":"parse"},"extra":1}"#,
+                Invalid,
+            ),
+            (
+                r#"{"jsonrpc":"2.0","id":1,"result":{},"error":{"code":1,"message":"x"}}"#,
+                Violation(ResultAndError),
+            ),
+            (r#"{"jsonrpc":"2.0","id":1}"#, Violation(NoResultOrError)),
+            (r#"{"jsonrpc":"2.0","result":{}}"#, Violation(MissingId)),
+            (r#"{}"#, Violation(MissingId)),
+            (
+                r#"{"jsonrpc":"2.0","id":1,"method":"ping","result":{}}"#,
+                Violation(MethodWithResult),
+            ),
+            (
+                r#"{"jsonrpc":"1.0","id":1,"method":"ping"}"#,
+                Violation(UnsupportedVersion("1.0".to_string())),
+            ),
+            (
+                r#"{"jsonrpc":2,"method":"ping"}"#,
+                Violation(UnsupportedVersion("2".to_string())),
+            ),
+            (
+                r#"[{"jsonrpc":"2.0","id":1,"method":"ping"},{"method":"a"}]"#,
+                Batch,
+            ),
+            (r#"[]"#, Batch),
+            (r#"[[{"method":"a"}]]"#, Violation(NestedBatch)),
+            (r#"[{"jsonrpc":"2.0","id":1}]"#, Violation(NoResultOrError)),
+            (r#""ping""#, Violation(NotAnObject)),
+            (r#"{"jsonrpc":"2.0","id":"abc","method":"ping"}"#, Invalid),
+            (
+                r#"{"jsonrpc":"2.0","id":1,"method":"ping","extra":true}"#,
+                Invalid,
+            ),
+            (
+                r#"{"jsonrpc":"2.0","id":1,"method":"ping"}"#.trim_end_matches('}'),
+                Invalid,
+            ),
+        ];
+        for (json, expected) in cases {
+            let actual = match serde_json::from_str::<serde_json::Value>(json) {
+                Ok(value) => match parse(value, false) {
+                    Ok(JsonRpcMessage::Request(_)) => Request,
+                    Ok(JsonRpcMessage::Notification(_)) => Notification,
+                    Ok(JsonRpcMessage::Response(_)) => Response,
+                    Ok(JsonRpcMessage::Batch(_)) => Batch,
+                    Ok(JsonRpcMessage::UnidentifiedError(_)) => UnidentifiedError,
+                    Err(ParseError::Violation(violation)) => Violation(violation),
+                    Err(ParseError::Invalid(_)) => Invalid,
+                },
+                Err(_) => Invalid,
+            };
+            assert_eq!(actual, expected, "{}", json);
+            // Deserializing directly agrees
+            assert_eq!(
+                serde_json::from_str::<JsonRpcMessage>(json).is_ok(),
+                matches!(
+                    expected,
+                    Request | Notification | Response | UnidentifiedError | Batch
+                ),
+                "{}",
+                json
+            );
+        }
+    }
+
+    #[test]
+    fn test_anomaly_counters() {
+        let before = message_anomalies();
+        assert!(serde_json::from_str::<JsonRpcMessage>(r#"{"id":null,"method":"a"}"#).is_err());
+        assert!(serde_json::from_str::<JsonRpcMessage>(r#"{"id":1}"#).is_err());
+        // Decoded to be answered, still counted
+        assert!(serde_json::from_str::<JsonRpcMessage>("[]").is_ok());
+        let after = message_anomalies();
+        // Other tests may count too
+        assert!(after.null_id > before.null_id);
+        assert!(after.no_result_or_error > before.no_result_or_error);
+        assert!(after.empty_batch > before.empty_batch);
+    }
+
+    #[test]
+    fn test_validate() {
+        let response = |result: Option<serde_json::Value>, error: Option<JsonRpcError>| {
+            JsonRpcMessage::Response(JsonRpcResponse {
+                id: 1,
+                result,
+                error,
+                ..Default::default()
+            })
+        };
+        let ok = response(Some(serde_json::Value::Null), None);
+        assert_eq!(ok.validate(), Ok(()));
+        // `result: null` survives a roundtrip
+        let roundtrip: JsonRpcMessage =
+            serde_json::from_str(&serde_json::to_string(&ok).unwrap()).unwrap();
+        assert_eq!(roundtrip, ok);
+
+        let batch = JsonRpcMessage::Batch(vec![
+            response(None, None),
+            response(Some(serde_json::json!({})), Some(JsonRpcError::default())),
+            JsonRpcMessage::Batch(vec![ok]),
+        ]);
+        assert_eq!(
+            batch.validate(),
+            Err(vec![
+                MessageViolation::NoResultOrError,
+                MessageViolation::ResultAndError,
+                MessageViolation::NestedBatch,
+            ])
+        );
+        assert_eq!(
+            JsonRpcMessage::Batch(Vec::new()).validate(),
+            Err(vec![MessageViolation::EmptyBatch])
+        );
+        // The error answering a message without usable id is valid
+        let unidentified = JsonRpcMessage::UnidentifiedError(JsonRpcUnidentifiedError::new(
+            JsonRpcError::default(),
+        ));
+        assert_eq!(unidentified.validate(), Ok(()));
+        let roundtrip: JsonRpcMessage =
+            serde_json::from_str(&serde_json::to_string(&unidentified).unwrap()).unwrap();
+        assert_eq!(roundtrip, unidentified);
+    }
+}
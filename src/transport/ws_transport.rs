@@ -1,28 +1,94 @@
-use super::{Message, Transport};
+use super::{
+    Clock, ConnectionActivity, DecodeLimits, JsonRpcError, Message, Transport,
+    DEFAULT_DRAIN_TIMEOUT,
+};
+use crate::types::{ErrorCode, ErrorData};
 use actix_ws::{Message as WsMessage, Session};
 use anyhow::Result;
 use async_trait::async_trait;
 use futures::{SinkExt, StreamExt};
 use reqwest::header::{HeaderName, HeaderValue};
 use std::sync::Arc;
+use std::time::Duration;
 use std::{collections::HashMap, str::FromStr};
-use tokio::sync::{broadcast, Mutex};
+use tokio::sync::{broadcast, mpsc, watch, Mutex};
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
 use tokio_tungstenite::tungstenite::{client::IntoClientRequest, Message as TungsteniteMessage};
+#[cfg(any(feature = "tls-rustls", feature = "tls-native"))]
+use tokio_tungstenite::Connector;
 use tracing::{debug, info};
 
+/// Frames written to a server WebSocket session
+#[derive(Debug, Clone)]
+pub enum WsOutbound {
+    Message(Message),
+    Close(Option<actix_ws::CloseReason>),
+}
+
+/// Server side of a WebSocket connection
+/// `handle_ws_connection` owns the actix `Session` and is its only writer, the transport
+/// hands outbound frames to it through `tx` so concurrent sends can't interleave
 #[derive(Clone)]
 pub struct ServerWsTransport {
-    session: Arc<Mutex<Option<Session>>>,
     rx: Arc<Mutex<Option<broadcast::Receiver<Message>>>>,
+    tx: mpsc::Sender<WsOutbound>,
+    activity: Arc<ConnectionActivity>,
 }
 
 impl ServerWsTransport {
-    pub fn new(session: Session, rx: broadcast::Receiver<Message>) -> Self {
+    /// `rx` yields messages received from the client, `tx` feeds the connection writer
+    pub fn new(rx: broadcast::Receiver<Message>, tx: mpsc::Sender<WsOutbound>) -> Self {
         Self {
-            session: Arc::new(Mutex::new(Some(session))),
             rx: Arc::new(Mutex::new(Some(rx))),
+            tx,
+            activity: Arc::new(ConnectionActivity::default()),
         }
     }
+
+    /// Record activity times with `clock` instead of the system clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.activity = Arc::new(ConnectionActivity::with_clock(clock));
+        self
+    }
+
+    /// Wire a transport to an upgraded actix connection, spawning its connection handler
+    /// must be called from within the actix runtime
+    pub fn spawn(
+        session: Session,
+        stream: actix_ws::MessageStream,
+        limits: DecodeLimits,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        let (inbound_tx, inbound_rx) = broadcast::channel(100);
+        let (outbound_tx, outbound_rx) = mpsc::channel(100);
+        let transport = Self::new(inbound_rx, outbound_tx).with_clock(clock);
+        let activity = transport.activity.clone();
+        actix_web::rt::spawn(async move {
+            if let Err(e) =
+                handle_ws_connection(session, stream, inbound_tx, outbound_rx, limits, activity)
+                    .await
+            {
+                debug!("WebSocket connection error: {}", e);
+            }
+        });
+        transport
+    }
+
+    pub fn activity(&self) -> &ConnectionActivity {
+        &self.activity
+    }
+
+    /// Close the connection with a specific close code, e.g. 1008 after a policy violation
+    pub async fn close_with(&self, code: u16, reason: impl Into<String>) -> Result<()> {
+        let reason = actix_ws::CloseReason {
+            code: code.into(),
+            description: Some(reason.into()),
+        };
+        self.tx
+            .send(WsOutbound::Close(Some(reason)))
+            .await
+            .map_err(|_| anyhow::anyhow!("WebSocket connection closed"))
+    }
 }
 
 #[derive(Clone)]
@@ -31,30 +97,52 @@ pub struct ClientWsTransport {
     ws_rx: Arc<Mutex<Option<broadcast::Receiver<Message>>>>,
     url: String,
     headers: HashMap<String, String>,
-    ws_write: Arc<
-        Mutex<
-            Option<
-                futures::stream::SplitSink<
-                    tokio_tungstenite::WebSocketStream<
-                        tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
-                    >,
-                    TungsteniteMessage,
-                >,
-            >,
-        >,
-    >,
+    root_certificates: Arc<Vec<Vec<u8>>>,
+    ws_write: Arc<Mutex<Option<WsWriter>>>,
+    closed: Arc<watch::Sender<Option<(u16, String)>>>,
+    limits: DecodeLimits,
 }
 
+type WsWriter = futures::stream::SplitSink<
+    tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>,
+    TungsteniteMessage,
+>;
+
 impl ClientWsTransport {
     pub fn builder(url: String) -> ClientWsTransportBuilder {
         ClientWsTransportBuilder::new(url)
     }
+
+    /// Close code and reason of the connection once the server closed it
+    /// 1006 with an empty reason when the connection dropped without a close frame
+    pub fn last_close(&self) -> Option<(u16, String)> {
+        self.closed.borrow().clone()
+    }
+
+    /// `ConnectionClosed` carrying the close frame, retriable unless it was a policy violation
+    fn closed_error(&self) -> Option<JsonRpcError> {
+        let (code, reason) = self.last_close()?;
+        let message = if reason.is_empty() {
+            format!("WebSocket closed by server ({})", code)
+        } else {
+            format!("WebSocket closed by server ({}): {}", code, reason)
+        };
+        let retriable = code != u16::from(CloseCode::Policy);
+        Some(JsonRpcError::with_error_data(
+            ErrorCode::ConnectionClosed,
+            message,
+            ErrorData::new(ErrorData::CONNECTION_CLOSED, retriable)
+                .details(serde_json::json!({ "code": code, "reason": reason })),
+        ))
+    }
 }
 
 #[derive(Default)]
 pub struct ClientWsTransportBuilder {
     url: String,
     headers: HashMap<String, String>,
+    root_certificates: Vec<Vec<u8>>,
+    limits: DecodeLimits,
 }
 
 impl ClientWsTransportBuilder {
@@ -62,9 +150,24 @@ impl ClientWsTransportBuilder {
         Self {
             url,
             headers: HashMap::new(),
+            root_certificates: Vec::new(),
+            limits: DecodeLimits::default(),
         }
     }
 
+    /// Limits for messages from the server, larger or deeper ones are skipped
+    pub fn decode_limits(mut self, limits: DecodeLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Trust a PEM encoded CA certificate in addition to the backend's default roots
+    /// the certificate is parsed when the connection opens
+    pub fn with_root_certificate(mut self, pem: &[u8]) -> Self {
+        self.root_certificates.push(pem.to_vec());
+        self
+    }
+
     pub fn with_header(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
         self.headers.insert(key.into(), value.into());
         self
@@ -77,9 +180,69 @@ impl ClientWsTransportBuilder {
             ws_rx: Arc::new(Mutex::new(Some(rx))),
             url: self.url,
             headers: self.headers,
+            root_certificates: Arc::new(self.root_certificates),
             ws_write: Arc::new(Mutex::new(None)),
+            closed: Arc::new(watch::channel(None).0),
+            limits: self.limits,
+        }
+    }
+}
+
+type WsConnection = (
+    tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>,
+    tokio_tungstenite::tungstenite::handshake::client::Response,
+);
+
+#[cfg(any(feature = "tls-rustls", feature = "tls-native"))]
+async fn connect(
+    request: tokio_tungstenite::tungstenite::handshake::client::Request,
+    root_certificates: &[Vec<u8>],
+) -> Result<WsConnection> {
+    let connector = tls_connector(root_certificates)?;
+    Ok(tokio_tungstenite::connect_async_tls_with_config(request, None, false, connector).await?)
+}
+
+/// Without a TLS backend only `ws://` URLs connect
+#[cfg(not(any(feature = "tls-rustls", feature = "tls-native")))]
+async fn connect(
+    request: tokio_tungstenite::tungstenite::handshake::client::Request,
+    root_certificates: &[Vec<u8>],
+) -> Result<WsConnection> {
+    if !root_certificates.is_empty() {
+        anyhow::bail!("Root certificates need the `tls-rustls` or `tls-native` feature");
+    }
+    Ok(tokio_tungstenite::connect_async(request).await?)
+}
+
+/// TLS connector trusting the extra roots, `None` keeps tungstenite's default
+#[cfg(feature = "tls-rustls")]
+fn tls_connector(root_certificates: &[Vec<u8>]) -> Result<Option<Connector>> {
+    if root_certificates.is_empty() {
+        return Ok(None);
+    }
+    let mut roots = rustls::RootCertStore::empty();
+    roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    for pem in root_certificates {
+        for certificate in rustls_pemfile::certs(&mut pem.as_slice()) {
+            roots.add(certificate?)?;
         }
     }
+    let config = rustls::ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ok(Some(Connector::Rustls(Arc::new(config))))
+}
+
+#[cfg(all(feature = "tls-native", not(feature = "tls-rustls")))]
+fn tls_connector(root_certificates: &[Vec<u8>]) -> Result<Option<Connector>> {
+    if root_certificates.is_empty() {
+        return Ok(None);
+    }
+    let mut builder = native_tls::TlsConnector::builder();
+    for pem in root_certificates {
+        builder.add_root_certificate(native_tls::Certificate::from_pem(pem)?);
+    }
+    Ok(Some(Connector::NativeTls(builder.build()?)))
 }
 
 #[async_trait]
@@ -103,14 +266,11 @@ impl Transport for ServerWsTransport {
     }
 
     async fn send(&self, message: &Message) -> Result<()> {
-        let text = serde_json::to_string(message)?;
-        if let Some(session) = self.session.lock().await.as_mut() {
-            debug!("Server sending message: {}", text);
-            session.text(text).await?;
-        } else {
-            debug!("Server send called but session is None");
-        }
-        Ok(())
+        debug!("Server sending message: {:?}", message);
+        self.tx
+            .send(WsOutbound::Message(message.clone()))
+            .await
+            .map_err(|_| anyhow::anyhow!("WebSocket connection closed"))
     }
 
     async fn open(&self) -> Result<()> {
@@ -118,26 +278,52 @@ impl Transport for ServerWsTransport {
     }
 
     async fn close(&self) -> Result<()> {
+        self.close_with_timeout(DEFAULT_DRAIN_TIMEOUT).await
+    }
+
+    async fn close_with_timeout(&self, timeout: Duration) -> Result<()> {
         info!("Server WebSocket connection closing");
-        if let Some(session) = self.session.lock().await.take() {
-            session.close(None).await?;
+        // Frames are written in order, the close frame follows the queued messages and the
+        // connection handler ends once it wrote it
+        let drained = tokio::time::timeout(timeout, async {
+            // The connection may already be gone
+            if self.tx.send(WsOutbound::Close(None)).await.is_ok() {
+                self.tx.closed().await;
+            }
+        })
+        .await;
+        if drained.is_err() {
+            debug!("WebSocket frames still queued on close");
         }
         Ok(())
     }
+
+    // The connection handler drops its receiver when the connection ends
+    async fn closed(&self) {
+        self.tx.closed().await
+    }
 }
 
 #[async_trait]
 impl Transport for ClientWsTransport {
     async fn receive(&self) -> Result<Option<Message>> {
         if let Some(rx) = self.ws_rx.lock().await.as_mut() {
-            match rx.recv().await {
-                Ok(msg) => {
-                    debug!("Client received message: {:?}", msg);
-                    Ok(Some(msg))
-                }
-                Err(e) => {
-                    debug!("Client receive error: {}", e);
-                    Ok(None)
+            let mut closed = self.closed.subscribe();
+            tokio::select! {
+                // Deliver what arrived before the close frame first
+                biased;
+                msg = rx.recv() => match msg {
+                    Ok(msg) => {
+                        debug!("Client received message: {:?}", msg);
+                        Ok(Some(msg))
+                    }
+                    Err(e) => {
+                        debug!("Client receive error: {}", e);
+                        Ok(None)
+                    }
+                },
+                _ = closed.wait_for(Option::is_some) => {
+                    Err(self.closed_error().expect("close recorded").into())
                 }
             }
         } else {
@@ -146,7 +332,11 @@ impl Transport for ClientWsTransport {
         }
     }
 
+    // Per-message headers (MESSAGE_HEADERS) are ignored, frames carry no headers
     async fn send(&self, message: &Message) -> Result<()> {
+        if let Some(error) = self.closed_error() {
+            return Err(error.into());
+        }
         let text = serde_json::to_string(message)?;
         if let Some(write) = self.ws_write.lock().await.as_mut() {
             debug!("Client sending message: {}", text);
@@ -158,6 +348,12 @@ impl Transport for ClientWsTransport {
     }
 
     async fn open(&self) -> Result<()> {
+        // Held until the new writer is stored, concurrent opens connect once
+        let mut write_guard = self.ws_write.lock().await;
+        if write_guard.is_some() && self.closed.borrow().is_none() {
+            debug!("WebSocket connection already open");
+            return Ok(());
+        }
         info!("Opening WebSocket connection to {}", self.url);
 
         let mut request = self.url.clone().into_client_request().unwrap();
@@ -172,7 +368,7 @@ impl Transport for ClientWsTransport {
                 HeaderValue::from_str(v).unwrap(),
             );
         }
-        let (ws_stream, response) = tokio_tungstenite::connect_async(request).await?;
+        let (ws_stream, response) = connect(request, &self.root_certificates).await?;
 
         info!(
             "WebSocket connection established. Response status: {}",
@@ -181,7 +377,10 @@ impl Transport for ClientWsTransport {
         debug!("WebSocket response headers: {:?}", response.headers());
 
         let (write, read) = ws_stream.split();
-        *self.ws_write.lock().await = Some(write);
+        *write_guard = Some(write);
+        drop(write_guard);
+        // A new connection, the previous close no longer applies
+        self.closed.send_replace(None);
 
         // Get channels for WebSocket communication
         let ws_tx = self
@@ -189,32 +388,45 @@ impl Transport for ClientWsTransport {
             .lock()
             .await
             .as_ref()
-            .expect("sender should exist")
+            .ok_or_else(|| anyhow::anyhow!("WebSocket transport was closed"))?
             .clone();
 
         // Handle receiving messages from WebSocket
+        let closed = self.closed.clone();
+        let limits = self.limits;
         tokio::spawn(async move {
             let mut read = read;
+            // 1006, the connection dropped without a close frame
+            let mut close = (u16::from(CloseCode::Abnormal), String::new());
             while let Some(result) = read.next().await {
                 match result {
-                    Ok(msg) => {
-                        if let TungsteniteMessage::Text(text) = msg {
-                            match serde_json::from_str::<Message>(&text) {
-                                Ok(message) => {
-                                    debug!("Received WebSocket message: {:?}", message);
-                                    // Send to the broadcast channel for the transport to receive
-                                    let _ = ws_tx.send(message);
-                                }
-                                Err(e) => debug!("Failed to parse WebSocket message: {}", e),
+                    Ok(TungsteniteMessage::Text(text)) => {
+                        match limits.decode(&text) {
+                            Ok(message) => {
+                                debug!("Received WebSocket message: {:?}", message);
+                                // Send to the broadcast channel for the transport to receive
+                                let _ = ws_tx.send(message);
                             }
+                            Err(e) => debug!("Failed to parse WebSocket message: {}", e),
                         }
                     }
+                    Ok(TungsteniteMessage::Close(frame)) => {
+                        info!("WebSocket closed by server: {:?}", frame);
+                        if let Some(frame) = frame {
+                            close = (u16::from(frame.code), frame.reason.into_owned());
+                        } else {
+                            close = (u16::from(CloseCode::Status), String::new());
+                        }
+                        break;
+                    }
+                    Ok(_) => {}
                     Err(e) => {
                         info!("WebSocket read error: {}", e);
                         break;
                     }
                 }
             }
+            closed.send_replace(Some(close));
             info!("WebSocket read loop terminated");
         });
 
@@ -222,39 +434,74 @@ impl Transport for ClientWsTransport {
     }
 
     async fn close(&self) -> Result<()> {
+        self.close_with_timeout(DEFAULT_DRAIN_TIMEOUT).await
+    }
+
+    async fn close_with_timeout(&self, timeout: Duration) -> Result<()> {
         info!("Closing WebSocket connection");
+        if let Some(mut write) = self.ws_write.lock().await.take() {
+            // Flushes the frames sent so far, then sends the close frame
+            match tokio::time::timeout(timeout, write.close()).await {
+                Ok(Err(e)) => debug!("Failed to close WebSocket: {}", e),
+                Err(_) => debug!("WebSocket frames still queued on close"),
+                Ok(Ok(())) => {}
+            }
+        }
         self.ws_tx.lock().await.take();
         self.ws_rx.lock().await.take();
         Ok(())
     }
 }
 
+/// Pump a server WebSocket connection
+/// messages from the client are decoded within `limits`, recorded in `activity` and published on
+/// `tx`, frames from `rx` are written to the session, this is the only place writing to the session
 pub async fn handle_ws_connection(
     mut session: Session,
     mut stream: actix_ws::MessageStream,
     tx: broadcast::Sender<Message>,
-    mut rx: broadcast::Receiver<Message>,
+    mut rx: mpsc::Receiver<WsOutbound>,
+    limits: DecodeLimits,
+    activity: Arc<ConnectionActivity>,
 ) -> Result<()> {
     info!("New WebSocket connection established");
 
     loop {
         tokio::select! {
             Some(Ok(msg)) = stream.next() => {
-                if let WsMessage::Text(text) = msg {
-                    match serde_json::from_str::<Message>(&text) {
+                activity.touch();
+                match msg {
+                    WsMessage::Text(text) => match limits.decode(&text) {
                         Ok(message) => {
                             debug!("Handler received message: {:?}", message);
                             tx.send(message)?;
                         }
                         Err(e) => debug!("Failed to parse message in handler: {}", e),
+                    },
+                    WsMessage::Ping(bytes) => session.pong(&bytes).await?,
+                    WsMessage::Close(reason) => {
+                        info!("WebSocket closed by client: {:?}", reason);
+                        break;
                     }
+                    _ => {}
                 }
             }
-            Ok(message) = rx.recv() => {
-                debug!("Handler sending message: {:?}", message);
-                let text = serde_json::to_string(&message)?;
-                session.text(text).await?;
-            }
+            outbound = rx.recv() => match outbound {
+                Some(WsOutbound::Message(message)) => {
+                    debug!("Handler sending message: {:?}", message);
+                    let text = serde_json::to_string(&message)?;
+                    session.text(text).await?;
+                }
+                Some(WsOutbound::Close(reason)) => {
+                    session.close(reason).await?;
+                    return Ok(());
+                }
+                // Every transport handle was dropped
+                None => {
+                    session.close(None).await?;
+                    return Ok(());
+                }
+            },
             else => {
                 info!("WebSocket connection terminated");
                 break
@@ -263,3 +510,161 @@ pub async fn handle_ws_connection(
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::{
+        JsonRpcMessage, JsonRpcRequest, JsonRpcResponse, JsonRpcVersion, SystemClock,
+    };
+    use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+    use std::collections::HashSet;
+
+    // Answers every request from its own task so the writes race each other
+    async fn respond_ws(req: HttpRequest, body: web::Payload) -> actix_web::Result<HttpResponse> {
+        let (response, session, stream) = actix_ws::handle(&req, body)?;
+        let transport = ServerWsTransport::spawn(
+            session,
+            stream,
+            DecodeLimits::default(),
+            Arc::new(SystemClock),
+        );
+        actix_web::rt::spawn(async move {
+            while let Ok(Some(JsonRpcMessage::Request(request))) = transport.receive().await {
+                let transport = transport.clone();
+                tokio::spawn(async move {
+                    let response = JsonRpcMessage::Response(JsonRpcResponse {
+                        id: request.id,
+                        result: Some(serde_json::json!({"method": request.method})),
+                        error: None,
+                        jsonrpc: JsonRpcVersion::default(),
+                    });
+                    transport.send(&response).await.unwrap();
+                });
+            }
+        });
+        Ok(response)
+    }
+
+    // Closes the connection with the code and reason of the first request
+    async fn close_ws(req: HttpRequest, body: web::Payload) -> actix_web::Result<HttpResponse> {
+        let (response, session, stream) = actix_ws::handle(&req, body)?;
+        let transport = ServerWsTransport::spawn(
+            session,
+            stream,
+            DecodeLimits::default(),
+            Arc::new(SystemClock),
+        );
+        actix_web::rt::spawn(async move {
+            if let Ok(Some(JsonRpcMessage::Request(request))) = transport.receive().await {
+                let params = request.params.unwrap();
+                let code = params["code"].as_u64().unwrap() as u16;
+                let reason = params["reason"].as_str().unwrap();
+                transport.close_with(code, reason).await.unwrap();
+            }
+        });
+        Ok(response)
+    }
+
+    #[tokio::test]
+    async fn test_close_frame_is_surfaced() -> Result<()> {
+        use crate::error::McpError;
+
+        let server = HttpServer::new(|| App::new().route("/ws", web::get().to(close_ws)))
+            .workers(1)
+            .bind(("127.0.0.1", 0))?;
+        let port = server.addrs()[0].port();
+        let server = server.run();
+        let handle = server.handle();
+        tokio::spawn(server);
+
+        for (code, reason, retriable) in
+            [(1008, "token expired", false), (1001, "restarting", true)]
+        {
+            let transport =
+                ClientWsTransport::builder(format!("ws://127.0.0.1:{}/ws", port)).build();
+            transport.open().await?;
+            transport
+                .send(&JsonRpcMessage::Request(JsonRpcRequest {
+                    id: 1,
+                    method: "close".to_string(),
+                    params: Some(serde_json::json!({"code": code, "reason": reason})),
+                    jsonrpc: JsonRpcVersion::default(),
+                }))
+                .await?;
+
+            let err = tokio::time::timeout(std::time::Duration::from_secs(5), transport.receive())
+                .await?
+                .unwrap_err();
+            assert_eq!(transport.last_close(), Some((code, reason.to_string())));
+            let error = err.json_rpc_error().unwrap();
+            assert_eq!(error.code, ErrorCode::ConnectionClosed as i32);
+            assert!(error.message.contains(reason));
+            assert_eq!(err.is_retriable(), retriable);
+
+            // Later sends fail with the same reason instead of timing out
+            let err = transport
+                .send(&JsonRpcMessage::Request(JsonRpcRequest {
+                    id: 2,
+                    method: "ping".to_string(),
+                    params: None,
+                    jsonrpc: JsonRpcVersion::default(),
+                }))
+                .await
+                .unwrap_err();
+            assert!(err.to_string().contains(reason));
+        }
+
+        handle.stop(false).await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_server_sends() -> Result<()> {
+        let server = HttpServer::new(|| App::new().route("/ws", web::get().to(respond_ws)))
+            .workers(1)
+            .bind(("127.0.0.1", 0))?;
+        let port = server.addrs()[0].port();
+        let server = server.run();
+        let handle = server.handle();
+        tokio::spawn(server);
+
+        let transport = ClientWsTransport::builder(format!("ws://127.0.0.1:{}/ws", port)).build();
+        transport.open().await?;
+        for id in 0..50 {
+            transport
+                .send(&JsonRpcMessage::Request(JsonRpcRequest {
+                    id,
+                    method: format!("test_{}", id),
+                    params: None,
+                    jsonrpc: JsonRpcVersion::default(),
+                }))
+                .await?;
+        }
+
+        // Every frame is a whole response, requests are not echoed back
+        let mut ids = HashSet::new();
+        for _ in 0..50 {
+            let message =
+                tokio::time::timeout(std::time::Duration::from_secs(5), transport.receive())
+                    .await?
+                    .unwrap()
+                    .unwrap();
+            match message {
+                JsonRpcMessage::Response(response) => {
+                    assert_eq!(
+                        response.result,
+                        Some(serde_json::json!({"method": format!("test_{}", response.id)}))
+                    );
+                    ids.insert(response.id);
+                }
+                other => panic!("Expected a response, got {:?}", other),
+            }
+        }
+        assert_eq!(ids, (0..50).collect());
+
+        transport.close().await?;
+        handle.stop(false).await;
+        Ok(())
+    }
+}
@@ -0,0 +1,81 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CompleteRequest {
+    #[serde(rename = "ref")]
+    pub reference: Reference,
+    pub argument: CompletionArgument,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum Reference {
+    #[serde(rename = "ref/prompt")]
+    Prompt { name: String },
+    #[serde(rename = "ref/resource")]
+    Resource { uri: String },
+}
+
+impl Reference {
+    /// Identifier of the referenced prompt or resource, e.g. `ref/prompt:code_review`
+    pub fn key(&self) -> String {
+        match self {
+            Reference::Prompt { name } => format!("ref/prompt:{name}"),
+            Reference::Resource { uri } => format!("ref/resource:{uri}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CompletionArgument {
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CompletionResult {
+    pub completion: CompletionOptions,
+    #[serde(rename = "_meta", skip_serializing_if = "Option::is_none")]
+    pub meta: Option<serde_json::Value>,
+}
+
+/// Maximum number of completion values allowed in a single response
+pub const MAX_COMPLETION_VALUES: usize = 100;
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct CompletionOptions {
+    pub values: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub has_more: Option<bool>,
+}
+
+impl CompletionOptions {
+    /// Build completion options that respect the spec limits
+    /// values are truncated to [`MAX_COMPLETION_VALUES`], `total` is the full count
+    /// and `has_more` is set when values were truncated
+    pub fn new(all_values: Vec<String>) -> Self {
+        let total = all_values.len();
+        let mut values = all_values;
+        values.truncate(MAX_COMPLETION_VALUES);
+        Self {
+            has_more: Some(total > values.len()),
+            total: Some(total as u32),
+            values,
+        }
+    }
+}
+
+impl From<CompletionOptions> for CompletionResult {
+    fn from(completion: CompletionOptions) -> Self {
+        Self {
+            completion,
+            meta: None,
+        }
+    }
+}
@@ -0,0 +1,68 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use url::Url;
+
+use super::{ResourceContent, ResourceContents};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum ToolResponseContent {
+    #[serde(rename = "text")]
+    Text { text: String },
+    #[serde(rename = "image")]
+    Image {
+        data: String,
+        #[serde(rename = "mimeType")]
+        mime_type: String,
+    },
+    #[serde(rename = "resource")]
+    Resource { resource: ResourceContents },
+}
+
+/// Text as is, images and resources as a short placeholder so logs don't get base64 data
+impl fmt::Display for ToolResponseContent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ToolResponseContent::Text { text } => f.write_str(text),
+            ToolResponseContent::Image { mime_type, .. } => write!(f, "[image {}]", mime_type),
+            ToolResponseContent::Resource { resource } => write!(f, "[resource {}]", resource.uri),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    User,
+    Assistant,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "type")]
+pub enum MessageContent {
+    #[serde(rename = "text")]
+    Text { text: String },
+    #[serde(rename = "image")]
+    Image {
+        data: String,
+        #[serde(rename = "mimeType")]
+        mime_type: String,
+    },
+    #[serde(rename = "resource")]
+    Resource { resource: ResourceContent },
+    /// Reference to a registered resource, `prompts/get` replaces it with the resource contents
+    #[serde(skip)]
+    ResourceRef { uri: Url },
+}
+
+/// Text as is, images and resources as a short placeholder so logs don't get base64 data
+impl fmt::Display for MessageContent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MessageContent::Text { text } => f.write_str(text),
+            MessageContent::Image { mime_type, .. } => write!(f, "[image {}]", mime_type),
+            MessageContent::Resource { resource } => write!(f, "[resource {}]", resource.uri()),
+            MessageContent::ResourceRef { uri } => write!(f, "[resource {}]", uri),
+        }
+    }
+}
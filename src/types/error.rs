@@ -0,0 +1,62 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+/// Standard `data` payload of error responses, lets hosts decide whether to retry
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ErrorData {
+    pub retriable: bool,
+    /// Machine-readable error kind, e.g. `tool_not_found`
+    pub kind: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<serde_json::Value>,
+    /// Link to documentation about the error
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub docs: Option<String>,
+    /// Suggested delay before retrying
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_after_ms: Option<u64>,
+}
+
+impl ErrorData {
+    pub const INTERNAL: &'static str = "internal";
+    pub const METHOD_NOT_FOUND: &'static str = "method_not_found";
+    pub const TOOL_NOT_FOUND: &'static str = "tool_not_found";
+    pub const PROMPT_NOT_FOUND: &'static str = "prompt_not_found";
+    pub const MISSING_ARGUMENTS: &'static str = "missing_arguments";
+    pub const TOOL_BUSY: &'static str = "tool_busy";
+    pub const TIMEOUT: &'static str = "timeout";
+    pub const CONNECTION_TIMEOUT: &'static str = "connection_timeout";
+    pub const IDLE_TIMEOUT: &'static str = "idle_timeout";
+    pub const CONNECTION_CLOSED: &'static str = "connection_closed";
+    pub const INVALID_MESSAGE: &'static str = "invalid_message";
+    pub const RESULT_TOO_LARGE: &'static str = "result_too_large";
+    pub const FORBIDDEN: &'static str = "forbidden";
+    pub const CAPABILITY_NOT_ADVERTISED: &'static str = "capability_not_advertised";
+
+    pub fn new(kind: impl Into<String>, retriable: bool) -> Self {
+        Self {
+            retriable,
+            kind: kind.into(),
+            details: None,
+            docs: None,
+            retry_after_ms: None,
+        }
+    }
+
+    pub fn details(mut self, details: serde_json::Value) -> Self {
+        self.details = Some(details);
+        self
+    }
+
+    pub fn docs(mut self, docs: impl Into<String>) -> Self {
+        self.docs = Some(docs.into());
+        self
+    }
+
+    pub fn retry_after(mut self, delay: Duration) -> Self {
+        self.retry_after_ms = Some(delay.as_millis() as u64);
+        self
+    }
+}
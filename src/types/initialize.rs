@@ -0,0 +1,164 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+#[serde(default)]
+pub struct Implementation {
+    pub name: String,
+    pub version: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+#[serde(default)]
+pub struct InitializeRequest {
+    pub protocol_version: String,
+    pub capabilities: ClientCapabilities,
+    pub client_info: Implementation,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+#[serde(default)]
+pub struct InitializeResponse {
+    pub protocol_version: String,
+    pub capabilities: ServerCapabilities,
+    pub server_info: Implementation,
+    /// How to use the server, e.g. added to the model's system prompt
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instructions: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+#[serde(default)]
+pub struct ServerCapabilities {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tools: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub experimental: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logging: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompts: Option<PromptCapabilities>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resources: Option<ResourceCapabilities>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub completions: Option<serde_json::Value>,
+}
+
+impl ServerCapabilities {
+    /// Capability a standard request method belongs to, by its prefix, `None` for other methods
+    pub fn capability_for(method: &str) -> Option<&'static str> {
+        let (prefix, _) = method.split_once('/')?;
+        match prefix {
+            "tools" => Some("tools"),
+            "prompts" => Some("prompts"),
+            "resources" => Some("resources"),
+            "logging" => Some("logging"),
+            "completion" => Some("completions"),
+            _ => None,
+        }
+    }
+
+    /// Whether the capability named by [`ServerCapabilities::capability_for`] is advertised
+    pub fn advertises(&self, capability: &str) -> bool {
+        match capability {
+            "tools" => self.tools.is_some(),
+            "prompts" => self.prompts.is_some(),
+            "resources" => self.resources.is_some(),
+            "logging" => self.logging.is_some(),
+            "completions" => self.completions.is_some(),
+            _ => false,
+        }
+    }
+
+    /// Capabilities set in either are kept, where both set one the fields of `other` win
+    pub fn merge(self, other: ServerCapabilities) -> Self {
+        Self {
+            tools: merge_option(self.tools, other.tools, merge_json),
+            experimental: merge_option(self.experimental, other.experimental, merge_json),
+            logging: merge_option(self.logging, other.logging, merge_json),
+            prompts: merge_option(self.prompts, other.prompts, |a, b| PromptCapabilities {
+                list_changed: b.list_changed.or(a.list_changed),
+            }),
+            resources: merge_option(self.resources, other.resources, |a, b| {
+                ResourceCapabilities {
+                    subscribe: b.subscribe.or(a.subscribe),
+                    list_changed: b.list_changed.or(a.list_changed),
+                }
+            }),
+            completions: merge_option(self.completions, other.completions, merge_json),
+        }
+    }
+}
+
+fn merge_option<T>(a: Option<T>, b: Option<T>, merge: impl FnOnce(T, T) -> T) -> Option<T> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(merge(a, b)),
+        (a, b) => b.or(a),
+    }
+}
+
+/// Keys of both objects, anything that isn't an object is replaced
+fn merge_json(a: serde_json::Value, b: serde_json::Value) -> serde_json::Value {
+    match (a, b) {
+        (serde_json::Value::Object(mut a), serde_json::Value::Object(b)) => {
+            a.extend(b);
+            serde_json::Value::Object(a)
+        }
+        (_, b) => b,
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+#[serde(default)]
+pub struct PromptCapabilities {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub list_changed: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+#[serde(default)]
+pub struct ResourceCapabilities {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subscribe: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub list_changed: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+#[serde(default)]
+pub struct ClientCapabilities {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub experimental: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sampling: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub roots: Option<RootCapabilities>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+#[serde(default)]
+pub struct RootCapabilities {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub list_changed: Option<bool>,
+}
+
+/// A directory or file the client lets the server work in
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Root {
+    pub uri: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+/// Result of a `roots/list` request sent to the client
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListRootsResult {
+    pub roots: Vec<Root>,
+}
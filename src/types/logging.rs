@@ -0,0 +1,48 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum LoggingLevel {
+    Debug,
+    Info,
+    Notice,
+    Warning,
+    Error,
+    Critical,
+    Alert,
+    Emergency,
+}
+
+/// Params of `notifications/message`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LoggingMessageParams {
+    pub level: LoggingLevel,
+    /// Name of the logger emitting the message
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logger: Option<String>,
+    /// Arbitrary JSON payload, a string or a structured record
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<serde_json::Value>,
+}
+
+impl LoggingMessageParams {
+    pub fn new(level: LoggingLevel, data: impl Into<serde_json::Value>) -> Self {
+        Self {
+            level,
+            logger: None,
+            data: Some(data.into()),
+        }
+    }
+
+    pub fn logger(mut self, logger: impl Into<String>) -> Self {
+        self.logger = Some(logger.into());
+        self
+    }
+}
+
+/// Params of `logging/setLevel`, the client receives records of `level` and more severe
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SetLevelRequest {
+    pub level: LoggingLevel,
+}
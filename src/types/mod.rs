@@ -0,0 +1,505 @@
+use serde::{Deserialize, Serialize};
+
+mod completion;
+mod content;
+mod error;
+mod initialize;
+mod logging;
+mod progress;
+mod prompts;
+mod resources;
+mod sampling;
+mod schema;
+mod tools;
+
+pub use completion::*;
+pub use content::*;
+pub use error::*;
+pub use initialize::*;
+pub use logging::*;
+pub use progress::*;
+pub use prompts::*;
+pub use resources::*;
+pub use sampling::*;
+pub use schema::*;
+pub use tools::*;
+
+pub const LATEST_PROTOCOL_VERSION: &str = "2024-11-05";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<String>,
+    #[serde(rename = "_meta", skip_serializing_if = "Option::is_none")]
+    pub meta: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    // SDK error codes
+    ConnectionClosed = -1,
+    RequestTimeout = -2,
+    ConnectionTimeout = -3,
+    IdleTimeout = -4,
+
+    // Standard JSON-RPC error codes
+    ParseError = -32700,
+    InvalidRequest = -32600,
+    MethodNotFound = -32601,
+    InvalidParams = -32602,
+    InternalError = -32603,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_call_tool_response_constructors() {
+        let response = CallToolResponse::json(serde_json::json!({"a": [1, 2]}));
+        assert_eq!(
+            serde_json::to_value(&response).unwrap(),
+            serde_json::json!({"content": [{"type": "text", "text": "{\"a\":[1,2]}"}]})
+        );
+        assert!(CallToolResponse::text("done").is_error.is_none());
+
+        let error = CallToolResponse::error("narrow the query");
+        assert_eq!(error.is_error, Some(true));
+        assert!(matches!(
+            &error.content[0],
+            ToolResponseContent::Text { text } if text == "narrow the query"
+        ));
+
+        // Maps with non-string keys can't be serialized as JSON
+        let unserializable: std::collections::HashMap<(u8, u8), u8> = [((1, 2), 3)].into();
+        assert_eq!(CallToolResponse::json(unserializable).is_error, Some(true));
+    }
+
+    #[test]
+    fn test_content_display() {
+        let uri = url::Url::parse("file:///notes.md").unwrap();
+        let image = ToolResponseContent::Image {
+            data: "iVBORw0KGgo=".to_string(),
+            mime_type: "image/png".to_string(),
+        };
+        assert_eq!(image.to_string(), "[image image/png]");
+        assert_eq!(
+            ToolResponseContent::Text {
+                text: "done".to_string()
+            }
+            .to_string(),
+            "done"
+        );
+        let resource = ToolResponseContent::Resource {
+            resource: ResourceContents {
+                uri: uri.clone(),
+                mime_type: None,
+            },
+        };
+        assert_eq!(resource.to_string(), "[resource file:///notes.md]");
+
+        let blob = MessageContent::Resource {
+            resource: ResourceContent::blob(uri.clone(), "image/png", b"png"),
+        };
+        assert_eq!(format!("{}", blob), "[resource file:///notes.md]");
+        assert_eq!(
+            MessageContent::ResourceRef { uri }.to_string(),
+            "[resource file:///notes.md]"
+        );
+        let sampled = SamplingContent::Image {
+            data: "iVBORw0KGgo=".to_string(),
+            mime_type: "image/jpeg".to_string(),
+        };
+        assert_eq!(sampled.to_string(), "[image image/jpeg]");
+    }
+
+    #[test]
+    fn test_server_capabilities() {
+        let capabilities = ServerCapabilities::default();
+        let json = serde_json::to_string(&capabilities).unwrap();
+        assert_eq!(json, "{}");
+    }
+
+    #[test]
+    fn test_merge_server_capabilities() {
+        let tools = ServerCapabilities {
+            tools: Some(serde_json::json!({"listChanged": true})),
+            resources: Some(ResourceCapabilities {
+                subscribe: Some(true),
+                list_changed: None,
+            }),
+            ..Default::default()
+        };
+        let resources = ServerCapabilities {
+            tools: Some(serde_json::json!({"x-vendor": 1})),
+            resources: Some(ResourceCapabilities {
+                subscribe: None,
+                list_changed: Some(false),
+            }),
+            prompts: Some(PromptCapabilities::default()),
+            ..Default::default()
+        };
+        assert_eq!(
+            serde_json::to_value(tools.merge(resources)).unwrap(),
+            serde_json::json!({
+                "tools": {"listChanged": true, "x-vendor": 1},
+                "prompts": {},
+                "resources": {"subscribe": true, "listChanged": false}
+            })
+        );
+    }
+
+    #[test]
+    fn test_completion_options_limits() {
+        let values: Vec<String> = (0..150).map(|i| format!("value-{i}")).collect();
+        let options = CompletionOptions::new(values);
+        assert_eq!(options.values.len(), MAX_COMPLETION_VALUES);
+        assert_eq!(options.total, Some(150));
+        assert_eq!(options.has_more, Some(true));
+
+        let options = CompletionOptions::new(vec!["a".to_string(), "b".to_string()]);
+        assert_eq!(options.values.len(), 2);
+        assert_eq!(options.total, Some(2));
+        assert_eq!(options.has_more, Some(false));
+
+        let result: CompletionResult = options.into();
+        let json = serde_json::to_value(&result).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({"completion": {"values": ["a", "b"], "total": 2, "hasMore": false}})
+        );
+    }
+
+    #[test]
+    fn test_logging_message_params() {
+        let params = LoggingMessageParams::new(
+            LoggingLevel::Warning,
+            serde_json::json!({"event": "cache_miss", "key": "users"}),
+        )
+        .logger("cache");
+        let json = serde_json::to_value(&params).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "level": "warning",
+                "logger": "cache",
+                "data": {"event": "cache_miss", "key": "users"}
+            })
+        );
+        let parsed: LoggingMessageParams = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed.level, LoggingLevel::Warning);
+    }
+
+    #[test]
+    fn test_progress_and_cancelled_wire_names() {
+        let progress = ProgressParams {
+            progress_token: ProgressToken::Number(1),
+            progress: 0.5,
+            total: None,
+            message: Some("halfway".to_string()),
+        };
+        assert_eq!(
+            serde_json::to_value(&progress).unwrap(),
+            serde_json::json!({"progressToken": 1, "progress": 0.5, "message": "halfway"})
+        );
+        let cancelled = CancelledParams {
+            request_id: 9,
+            reason: None,
+        };
+        assert_eq!(
+            serde_json::to_value(&cancelled).unwrap(),
+            serde_json::json!({"requestId": 9})
+        );
+
+        // Only the spec names are accepted, no release of this crate sent snake_case
+        assert!(
+            serde_json::from_value::<CancelledParams>(serde_json::json!({"request_id": 9}))
+                .is_err()
+        );
+        assert!(serde_json::from_value::<ProgressParams>(
+            serde_json::json!({"progress_token": 1, "progress": 0.5})
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_tool_builder_schema() {
+        let tool = ToolBuilder::new("search")
+            .description("Search the web")
+            .arg_string("query", "the query", true)
+            .arg_number("max", "max results", false)
+            .arg_array(
+                "domains",
+                "domains to search",
+                serde_json::json!({"type": "string"}),
+                false,
+            )
+            .build();
+        assert_eq!(tool.description.as_deref(), Some("Search the web"));
+        assert_eq!(
+            tool.input_schema,
+            serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "query": {"type": "string", "description": "the query"},
+                    "max": {"type": "number", "description": "max results"},
+                    "domains": {
+                        "type": "array",
+                        "items": {"type": "string"},
+                        "description": "domains to search"
+                    }
+                },
+                "required": ["query"]
+            })
+        );
+
+        let empty = ToolBuilder::new("ping").build();
+        assert_eq!(
+            empty.input_schema,
+            serde_json::json!({"type": "object", "properties": {}})
+        );
+
+        let raw = serde_json::json!({"type": "object", "additionalProperties": true});
+        let tool = ToolBuilder::new("raw")
+            .arg_string("ignored", "", true)
+            .input_schema(raw.clone())
+            .build();
+        assert_eq!(tool.input_schema, raw);
+
+        let tool = ToolBuilder::new("typed")
+            .input_schema(Schema::object().property("query", Schema::string()))
+            .build();
+        assert_eq!(
+            tool.input_schema,
+            serde_json::json!({"type": "object", "properties": {"query": {"type": "string"}}})
+        );
+        let invalid = ToolBuilder::new("invalid")
+            .input_schema(Schema::object().required(["query"]))
+            .try_build();
+        assert!(matches!(
+            invalid,
+            Err(SchemaError::UnknownRequired { name, .. }) if name == "query"
+        ));
+    }
+
+    #[test]
+    fn test_create_message_request_builder() {
+        let request = CreateMessageRequest::builder()
+            .system_prompt("Be brief")
+            .user_message("What is MCP?")
+            .assistant_message("A protocol")
+            .user_message("Longer please")
+            .temperature(0.7)
+            .max_tokens(200)
+            .model_hint("claude-3-sonnet")
+            .model_hint("claude")
+            .stop_sequence("\n\n")
+            .build()
+            .unwrap();
+        assert_eq!(
+            serde_json::to_value(&request).unwrap(),
+            serde_json::json!({
+                "messages": [
+                    {"role": "user", "content": {"type": "text", "text": "What is MCP?"}},
+                    {"role": "assistant", "content": {"type": "text", "text": "A protocol"}},
+                    {"role": "user", "content": {"type": "text", "text": "Longer please"}}
+                ],
+                "modelPreferences": {"hints": [{"name": "claude-3-sonnet"}, {"name": "claude"}]},
+                "systemPrompt": "Be brief",
+                "temperature": 0.7,
+                "maxTokens": 200,
+                "stopSequences": ["\n\n"]
+            })
+        );
+
+        let valid = || {
+            CreateMessageRequest::builder()
+                .user_message("hi")
+                .max_tokens(10)
+        };
+        assert!(valid().build().is_ok());
+        assert!(valid().max_tokens(0).build().is_err());
+        assert!(CreateMessageRequest::builder()
+            .user_message("hi")
+            .build()
+            .is_err());
+        assert!(CreateMessageRequest::builder()
+            .max_tokens(10)
+            .build()
+            .is_err());
+        assert!(valid().temperature(2.0).build().is_ok());
+        assert!(valid().temperature(2.1).build().is_err());
+        assert!(valid().temperature(-0.1).build().is_err());
+        assert!(valid().temperature(f64::NAN).build().is_err());
+        let priorities = |cost| ModelPreferences {
+            cost_priority: Some(cost),
+            speed_priority: Some(0.5),
+            ..Default::default()
+        };
+        assert!(valid().model_preferences(priorities(1.0)).build().is_ok());
+        let error = valid()
+            .model_preferences(priorities(1.5))
+            .build()
+            .unwrap_err();
+        assert!(error.to_string().contains("cost_priority"));
+
+        // Hints survive setting the priorities, in the order they were added
+        let request = valid()
+            .model_hint("claude-3-sonnet")
+            .model_preferences(ModelPreferences {
+                hints: Some(vec![ModelHint {
+                    name: Some("claude".to_string()),
+                }]),
+                ..priorities(0.3)
+            })
+            .model_hint("gpt")
+            .build()
+            .unwrap();
+        assert_eq!(
+            serde_json::to_value(&request.model_preferences).unwrap(),
+            serde_json::json!({
+                "hints": [{"name": "claude-3-sonnet"}, {"name": "claude"}, {"name": "gpt"}],
+                "costPriority": 0.3,
+                "speedPriority": 0.5
+            })
+        );
+    }
+
+    #[test]
+    fn test_sampling_conversions() {
+        let reason: StopReason =
+            serde_json::from_value(serde_json::json!("contentFilter")).unwrap();
+        assert_eq!(reason, StopReason::Other("contentFilter".to_string()));
+        let reason: StopReason = serde_json::from_value(serde_json::json!("maxTokens")).unwrap();
+        assert_eq!(reason, StopReason::MaxTokens);
+        assert!(serde_json::from_value::<StopReason>(serde_json::json!(3)).is_err());
+
+        let prompt: GetPromptResult = serde_json::from_value(serde_json::json!({
+            "messages": [
+                {"role": "user", "content": {"type": "text", "text": "Summarize"}},
+                {"role": "user", "content": {"type": "resource", "resource": {
+                    "uri": "file:///notes.txt", "text": "notes"
+                }}}
+            ]
+        }))
+        .unwrap();
+        let request = CreateMessageRequest::from_prompt(prompt, 100).unwrap();
+        assert_eq!(
+            serde_json::to_value(&request).unwrap(),
+            serde_json::json!({
+                "messages": [
+                    {"role": "user", "content": {"type": "text", "text": "Summarize"}},
+                    {"role": "user", "content": {"type": "text", "text": "notes"}}
+                ],
+                "maxTokens": 100
+            })
+        );
+
+        let blob: PromptMessage = serde_json::from_value(serde_json::json!({
+            "role": "user",
+            "content": {"type": "resource", "resource": {"uri": "file:///a.png", "blob": "aGk="}}
+        }))
+        .unwrap();
+        assert!(SamplingMessage::try_from(blob).is_err());
+    }
+
+    const FIXTURES: &str = include_str!("fixtures.json");
+
+    /// Round-trip every fixture of each wire type and check unknown fields are tolerated
+    macro_rules! check_fixtures {
+        ($($ty:ty),* $(,)?) => {{
+            let fixtures: serde_json::Map<String, serde_json::Value> =
+                serde_json::from_str(FIXTURES).unwrap();
+            let mut checked = Vec::new();
+            $(
+                let name = stringify!($ty);
+                let cases = fixtures[name].as_array().unwrap();
+                assert!(!cases.is_empty(), "no fixtures for {}", name);
+                for case in cases {
+                    let value: $ty = serde_json::from_value(case.clone())
+                        .unwrap_or_else(|e| panic!("{} failed to parse {}: {}", name, case, e));
+                    assert_eq!(&serde_json::to_value(&value).unwrap(), case, "{}", name);
+
+                    if let serde_json::Value::Object(map) = case {
+                        let mut extended = map.clone();
+                        extended.insert("unknownField".to_string(), serde_json::json!(1));
+                        serde_json::from_value::<$ty>(extended.into()).unwrap_or_else(|e| {
+                            panic!("{} rejected an unknown field: {}", name, e)
+                        });
+                    }
+                }
+                checked.push(name);
+            )*
+            let mut missing: Vec<_> = fixtures
+                .keys()
+                .filter(|key| !checked.contains(&key.as_str()))
+                .collect();
+            missing.sort();
+            assert!(missing.is_empty(), "fixtures without a type: {:?}", missing);
+        }};
+    }
+
+    #[test]
+    fn test_wire_type_fixtures() {
+        check_fixtures!(
+            Implementation,
+            InitializeRequest,
+            InitializeResponse,
+            ServerCapabilities,
+            PromptCapabilities,
+            ResourceCapabilities,
+            ClientCapabilities,
+            RootCapabilities,
+            Root,
+            ListRootsResult,
+            Tool,
+            CallToolRequest,
+            CallToolResponse,
+            ToolsListResponse,
+            ToolResponseContent,
+            ResourceContents,
+            ReadResourceRequest,
+            ResourceRange,
+            ResourceRangeResult,
+            ReadResourceResponse,
+            ResourceContent,
+            TextResourceContents,
+            BlobResourceContents,
+            ResourcesListResponse,
+            Resource,
+            ResourceTemplate,
+            ResourceTemplatesListResponse,
+            SubscribeRequest,
+            ResourceUpdatedParams,
+            ListRequest,
+            PromptsListResponse,
+            Prompt,
+            PromptArgument,
+            GetPromptRequest,
+            GetPromptResult,
+            PromptMessage,
+            Role,
+            MessageContent,
+            CompleteRequest,
+            Reference,
+            CompletionArgument,
+            CompletionResult,
+            CompletionOptions,
+            LoggingLevel,
+            LoggingMessageParams,
+            ProgressToken,
+            ProgressParams,
+            CancelledParams,
+            ErrorData,
+            CreateMessageRequest,
+            IncludeContext,
+            ModelPreferences,
+            ModelHint,
+            SamplingMessage,
+            SamplingContent,
+            CreateMessageResult,
+            StopReason,
+        );
+    }
+}
@@ -0,0 +1,252 @@
+use std::collections::HashMap;
+
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use super::ProgressToken;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResourceContents {
+    pub uri: Url,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mime_type: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReadResourceRequest {
+    pub uri: Url,
+    #[serde(rename = "_meta", skip_serializing_if = "Option::is_none")]
+    pub meta: Option<serde_json::Value>,
+}
+
+impl ReadResourceRequest {
+    pub fn new(uri: Url) -> Self {
+        Self { uri, meta: None }
+    }
+
+    /// Request only `length` bytes starting at `offset`
+    pub fn range(mut self, offset: u64, length: u64) -> Self {
+        let range = ResourceRange { offset, length };
+        let mut meta = match self.meta.take() {
+            Some(serde_json::Value::Object(map)) => map,
+            _ => serde_json::Map::new(),
+        };
+        if let Ok(serde_json::Value::Object(range)) = serde_json::to_value(range) {
+            meta.extend(range);
+        }
+        self.meta = Some(meta.into());
+        self
+    }
+
+    /// Byte range requested through the `offset`/`length` vendor extension in `_meta`
+    pub fn requested_range(&self) -> Option<ResourceRange> {
+        serde_json::from_value(self.meta.clone()?).ok()
+    }
+
+    /// Ask for the contents as `notifications/resources/chunk` instead of inline,
+    /// needs a progress token in `_meta` which [`crate::protocol::RequestOptions::on_resource_chunk`] sets
+    pub fn stream(mut self) -> Self {
+        let mut meta = match self.meta.take() {
+            Some(serde_json::Value::Object(map)) => map,
+            _ => serde_json::Map::new(),
+        };
+        meta.insert("stream".to_string(), true.into());
+        self.meta = Some(meta.into());
+        self
+    }
+
+    /// Progress token of a read that asked to be streamed
+    pub fn stream_token(&self) -> Option<ProgressToken> {
+        let meta = self.meta.as_ref()?;
+        if meta.get("stream") != Some(&serde_json::Value::Bool(true)) {
+            return None;
+        }
+        serde_json::from_value(meta.get("progressToken")?.clone()).ok()
+    }
+}
+
+/// Params of `resources/subscribe` and `resources/unsubscribe`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SubscribeRequest {
+    pub uri: Url,
+}
+
+/// Method of the notification sent to subscribers when a resource changes
+pub const RESOURCE_UPDATED_METHOD: &str = "notifications/resources/updated";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResourceUpdatedParams {
+    pub uri: Url,
+}
+
+/// Method of the notifications carrying a streamed read
+pub const RESOURCE_CHUNK_METHOD: &str = "notifications/resources/chunk";
+
+/// One piece of a streamed `resources/read`, sent before the response in `offset` order
+/// the bytes are base64 encoded whatever the resource type, chunks can split UTF-8 characters
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResourceChunk {
+    pub progress_token: ProgressToken,
+    pub uri: Url,
+    pub offset: u64,
+    pub blob: String,
+}
+
+impl ResourceChunk {
+    pub fn bytes(&self) -> Result<Vec<u8>, base64::DecodeError> {
+        base64::engine::general_purpose::STANDARD.decode(&self.blob)
+    }
+}
+
+/// Returned in the response `_meta` of a streamed read, whose `contents` are empty
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResourceStreamResult {
+    pub length: u64,
+    pub chunks: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mime_type: Option<String>,
+}
+
+/// Byte range of a `resources/read`, carried in the request `_meta`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResourceRange {
+    pub offset: u64,
+    pub length: u64,
+}
+
+/// Range actually served, returned in the response `_meta` of a ranged read
+/// `eof` is set when the read reached the end of the resource
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResourceRangeResult {
+    pub offset: u64,
+    pub length: u64,
+    pub eof: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReadResourceResponse {
+    pub contents: Vec<ResourceContent>,
+    #[serde(rename = "_meta", skip_serializing_if = "Option::is_none")]
+    pub meta: Option<serde_json::Value>,
+}
+
+impl ReadResourceResponse {
+    /// One content per representation of the resource, e.g. its source text and a rendered image
+    pub fn new(contents: Vec<ResourceContent>) -> Self {
+        Self {
+            contents,
+            meta: None,
+        }
+    }
+
+    /// Sets `_meta`, e.g. the [`ResourceRangeResult`] of a ranged read
+    pub fn meta(mut self, meta: serde_json::Value) -> Self {
+        self.meta = Some(meta);
+        self
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ResourceContent {
+    Text(TextResourceContents),
+    Blob(BlobResourceContents),
+}
+
+impl ResourceContent {
+    pub fn text(uri: Url, mime_type: impl Into<String>, text: impl Into<String>) -> Self {
+        Self::Text(TextResourceContents {
+            uri,
+            mime_type: Some(mime_type.into()),
+            text: text.into(),
+        })
+    }
+
+    pub fn uri(&self) -> &Url {
+        match self {
+            Self::Text(contents) => &contents.uri,
+            Self::Blob(contents) => &contents.uri,
+        }
+    }
+
+    /// `data` is base64 encoded
+    pub fn blob(uri: Url, mime_type: impl Into<String>, data: &[u8]) -> Self {
+        Self::Blob(BlobResourceContents {
+            uri,
+            mime_type: Some(mime_type.into()),
+            blob: base64::engine::general_purpose::STANDARD.encode(data),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TextResourceContents {
+    pub uri: Url,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mime_type: Option<String>,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlobResourceContents {
+    pub uri: Url,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mime_type: Option<String>,
+    /// base64 encoded data
+    pub blob: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResourcesListResponse {
+    pub resources: Vec<Resource>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+    #[serde(rename = "_meta", skip_serializing_if = "Option::is_none")]
+    pub meta: Option<HashMap<String, serde_json::Value>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Resource {
+    pub uri: Url,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mime_type: Option<String>,
+}
+
+/// Parameterized resource, `uri_template` is an RFC 6570 URI template
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResourceTemplate {
+    pub uri_template: String,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mime_type: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResourceTemplatesListResponse {
+    pub resource_templates: Vec<ResourceTemplate>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+    #[serde(rename = "_meta", skip_serializing_if = "Option::is_none")]
+    pub meta: Option<HashMap<String, serde_json::Value>>,
+}
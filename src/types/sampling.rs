@@ -0,0 +1,325 @@
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+
+use super::{GetPromptResult, MessageContent, PromptMessage, ResourceContent, Role};
+
+/// Params of a `sampling/createMessage` request sent from the server to the client
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateMessageRequest {
+    pub messages: Vec<SamplingMessage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model_preferences: Option<ModelPreferences>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system_prompt: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub include_context: Option<IncludeContext>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    pub max_tokens: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop_sequences: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<serde_json::Value>,
+}
+
+impl CreateMessageRequest {
+    pub fn new(messages: Vec<SamplingMessage>, max_tokens: u32) -> Self {
+        Self {
+            messages,
+            model_preferences: None,
+            system_prompt: None,
+            include_context: None,
+            temperature: None,
+            max_tokens,
+            stop_sequences: None,
+            metadata: None,
+        }
+    }
+
+    /// Build a request from the messages of a `prompts/get` result
+    pub fn from_prompt(prompt: GetPromptResult, max_tokens: u32) -> anyhow::Result<Self> {
+        let messages = prompt
+            .messages
+            .into_iter()
+            .map(SamplingMessage::try_from)
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self::new(messages, max_tokens))
+    }
+
+    pub fn builder() -> CreateMessageRequestBuilder {
+        CreateMessageRequestBuilder::default()
+    }
+}
+
+/// Fluent construction of a [`CreateMessageRequest`], `build` checks the ranges the spec
+/// gives: temperature in 0..=2, model priorities in 0..=1 and a positive `max_tokens`
+#[derive(Debug, Clone, Default)]
+pub struct CreateMessageRequestBuilder {
+    messages: Vec<SamplingMessage>,
+    system_prompt: Option<String>,
+    include_context: Option<IncludeContext>,
+    temperature: Option<f64>,
+    max_tokens: Option<u32>,
+    model_preferences: Option<ModelPreferences>,
+    stop_sequences: Vec<String>,
+    metadata: Option<serde_json::Value>,
+}
+
+impl CreateMessageRequestBuilder {
+    pub fn message(mut self, role: Role, content: SamplingContent) -> Self {
+        self.messages.push(SamplingMessage { role, content });
+        self
+    }
+
+    pub fn user_message<S: Into<String>>(self, text: S) -> Self {
+        self.message(Role::User, SamplingContent::Text { text: text.into() })
+    }
+
+    pub fn assistant_message<S: Into<String>>(self, text: S) -> Self {
+        self.message(Role::Assistant, SamplingContent::Text { text: text.into() })
+    }
+
+    pub fn system_prompt<S: Into<String>>(mut self, prompt: S) -> Self {
+        self.system_prompt = Some(prompt.into());
+        self
+    }
+
+    pub fn include_context(mut self, context: IncludeContext) -> Self {
+        self.include_context = Some(context);
+        self
+    }
+
+    pub fn temperature(mut self, temperature: f64) -> Self {
+        self.temperature = Some(temperature);
+        self
+    }
+
+    pub fn max_tokens(mut self, max_tokens: u32) -> Self {
+        self.max_tokens = Some(max_tokens);
+        self
+    }
+
+    /// Replaces earlier priorities, hints added before come first, then those of `preferences`
+    pub fn model_preferences(mut self, mut preferences: ModelPreferences) -> Self {
+        if let Some(mut hints) = self.model_preferences.take().and_then(|p| p.hints) {
+            hints.extend(preferences.hints.take().unwrap_or_default());
+            preferences.hints = Some(hints);
+        }
+        self.model_preferences = Some(preferences);
+        self
+    }
+
+    /// Add a model name hint, hints are in order of preference
+    pub fn model_hint<S: Into<String>>(mut self, name: S) -> Self {
+        self.model_preferences
+            .get_or_insert_with(ModelPreferences::default)
+            .hints
+            .get_or_insert_with(Vec::new)
+            .push(ModelHint {
+                name: Some(name.into()),
+            });
+        self
+    }
+
+    pub fn stop_sequence<S: Into<String>>(mut self, stop: S) -> Self {
+        self.stop_sequences.push(stop.into());
+        self
+    }
+
+    pub fn metadata(mut self, metadata: serde_json::Value) -> Self {
+        self.metadata = Some(metadata);
+        self
+    }
+
+    pub fn build(self) -> anyhow::Result<CreateMessageRequest> {
+        if self.messages.is_empty() {
+            bail!("Sampling needs at least one message");
+        }
+        let max_tokens = match self.max_tokens {
+            Some(0) => bail!("max_tokens must be greater than 0"),
+            Some(max_tokens) => max_tokens,
+            None => bail!("max_tokens is required"),
+        };
+        if let Some(temperature) = self.temperature {
+            if !(0.0..=2.0).contains(&temperature) {
+                bail!("temperature must be between 0 and 2, got {}", temperature);
+            }
+        }
+        if let Some(preferences) = &self.model_preferences {
+            for (name, priority) in [
+                ("cost_priority", preferences.cost_priority),
+                ("speed_priority", preferences.speed_priority),
+                ("intelligence_priority", preferences.intelligence_priority),
+            ] {
+                if let Some(priority) = priority.filter(|p| !(0.0..=1.0).contains(p)) {
+                    bail!("{} must be between 0 and 1, got {}", name, priority);
+                }
+            }
+        }
+        Ok(CreateMessageRequest {
+            messages: self.messages,
+            model_preferences: self.model_preferences,
+            system_prompt: self.system_prompt,
+            include_context: self.include_context,
+            temperature: self.temperature,
+            max_tokens,
+            stop_sequences: (!self.stop_sequences.is_empty()).then_some(self.stop_sequences),
+            metadata: self.metadata,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum IncludeContext {
+    None,
+    ThisServer,
+    AllServers,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModelPreferences {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hints: Option<Vec<ModelHint>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cost_priority: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub speed_priority: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub intelligence_priority: Option<f64>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ModelHint {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SamplingMessage {
+    pub role: Role,
+    pub content: SamplingContent,
+}
+
+/// Sampling messages only carry text or images, unlike prompt messages
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(tag = "type")]
+pub enum SamplingContent {
+    #[serde(rename = "text")]
+    Text { text: String },
+    #[serde(rename = "image")]
+    Image {
+        data: String,
+        #[serde(rename = "mimeType")]
+        mime_type: String,
+    },
+}
+
+/// Text as is, images as `[image mime]` so logs don't get base64 data
+impl std::fmt::Display for SamplingContent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SamplingContent::Text { text } => f.write_str(text),
+            SamplingContent::Image { mime_type, .. } => write!(f, "[image {}]", mime_type),
+        }
+    }
+}
+
+/// Result of a `sampling/createMessage` request
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateMessageResult {
+    pub role: Role,
+    pub content: SamplingContent,
+    pub model: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop_reason: Option<StopReason>,
+}
+
+/// Why sampling stopped, values the spec doesn't name are kept as `Other`
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(from = "String", into = "String")]
+pub enum StopReason {
+    EndTurn,
+    StopSequence,
+    MaxTokens,
+    Other(String),
+}
+
+impl From<String> for StopReason {
+    fn from(value: String) -> Self {
+        match value.as_str() {
+            "endTurn" => Self::EndTurn,
+            "stopSequence" => Self::StopSequence,
+            "maxTokens" => Self::MaxTokens,
+            _ => Self::Other(value),
+        }
+    }
+}
+
+impl From<StopReason> for String {
+    fn from(value: StopReason) -> Self {
+        match value {
+            StopReason::EndTurn => "endTurn".to_string(),
+            StopReason::StopSequence => "stopSequence".to_string(),
+            StopReason::MaxTokens => "maxTokens".to_string(),
+            StopReason::Other(value) => value,
+        }
+    }
+}
+
+impl From<SamplingContent> for MessageContent {
+    fn from(content: SamplingContent) -> Self {
+        match content {
+            SamplingContent::Text { text } => MessageContent::Text { text },
+            SamplingContent::Image { data, mime_type } => MessageContent::Image { data, mime_type },
+        }
+    }
+}
+
+/// Embedded text resources become text, blobs and unresolved references can't be sampled
+impl TryFrom<MessageContent> for SamplingContent {
+    type Error = anyhow::Error;
+
+    fn try_from(content: MessageContent) -> anyhow::Result<Self> {
+        match content {
+            MessageContent::Text { text } => Ok(SamplingContent::Text { text }),
+            MessageContent::Image { data, mime_type } => {
+                Ok(SamplingContent::Image { data, mime_type })
+            }
+            MessageContent::Resource {
+                resource: ResourceContent::Text(resource),
+            } => Ok(SamplingContent::Text {
+                text: resource.text,
+            }),
+            MessageContent::Resource {
+                resource: ResourceContent::Blob(resource),
+            } => bail!("Blob resource {} can't be used for sampling", resource.uri),
+            MessageContent::ResourceRef { uri } => {
+                bail!("Unresolved resource {} can't be used for sampling", uri)
+            }
+        }
+    }
+}
+
+impl From<SamplingMessage> for PromptMessage {
+    fn from(message: SamplingMessage) -> Self {
+        PromptMessage {
+            role: message.role,
+            content: message.content.into(),
+        }
+    }
+}
+
+impl TryFrom<PromptMessage> for SamplingMessage {
+    type Error = anyhow::Error;
+
+    fn try_from(message: PromptMessage) -> anyhow::Result<Self> {
+        Ok(SamplingMessage {
+            role: message.role,
+            content: message.content.try_into()?,
+        })
+    }
+}
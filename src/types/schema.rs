@@ -0,0 +1,361 @@
+use std::fmt;
+
+use serde_json::Value;
+
+/// JSON Schema assembled from typed parts instead of nested `json!` literals, e.g. for
+/// [`ToolBuilder::input_schema`](super::ToolBuilder::input_schema)
+/// mistakes such as a required property that isn't declared are caught by [`Schema::build`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schema {
+    kind: Kind,
+    description: Option<String>,
+    enum_values: Vec<Value>,
+    // Properties or required names were given to a schema that isn't an object
+    not_an_object: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Kind {
+    String,
+    Number,
+    Integer,
+    Boolean,
+    Array(Box<Schema>),
+    Object {
+        properties: Vec<(String, Schema)>,
+        required: Vec<String>,
+    },
+    /// A hand-written schema, taken as is
+    Raw(Value),
+}
+
+impl Kind {
+    fn name(&self) -> &'static str {
+        match self {
+            Kind::String => "string",
+            Kind::Number => "number",
+            Kind::Integer => "integer",
+            Kind::Boolean => "boolean",
+            Kind::Array(_) => "array",
+            Kind::Object { .. } => "object",
+            Kind::Raw(_) => "raw",
+        }
+    }
+
+    fn accepts(&self, value: &Value) -> bool {
+        match self {
+            Kind::String => value.is_string(),
+            Kind::Number => value.is_number(),
+            Kind::Integer => value.is_i64() || value.is_u64(),
+            Kind::Boolean => value.is_boolean(),
+            Kind::Array(_) => value.is_array(),
+            Kind::Object { .. } => value.is_object(),
+            Kind::Raw(_) => true,
+        }
+    }
+}
+
+/// A mistake in a [`Schema`], `path` leads to the offending schema, e.g. `entities.items`,
+/// and is empty for the root
+#[derive(Debug, Clone, PartialEq)]
+pub enum SchemaError {
+    /// `required` names a property the object doesn't declare
+    UnknownRequired { path: String, name: String },
+    /// An enum value doesn't have the schema's type
+    EnumTypeMismatch {
+        path: String,
+        value: Value,
+        expected: &'static str,
+    },
+    /// Properties or required names on a schema that isn't an object
+    NotAnObject { path: String },
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let at = |path: &str| {
+            if path.is_empty() {
+                "the root".to_string()
+            } else {
+                format!("`{}`", path)
+            }
+        };
+        match self {
+            SchemaError::UnknownRequired { path, name } => write!(
+                f,
+                "Required property `{}` of {} is not declared",
+                name,
+                at(path)
+            ),
+            SchemaError::EnumTypeMismatch {
+                path,
+                value,
+                expected,
+            } => write!(
+                f,
+                "Enum value {} of {} is not of type {}",
+                value,
+                at(path),
+                expected
+            ),
+            SchemaError::NotAnObject { path } => {
+                write!(f, "{} has properties but is not an object", at(path))
+            }
+        }
+    }
+}
+
+impl std::error::Error for SchemaError {}
+
+impl Schema {
+    fn new(kind: Kind) -> Self {
+        Self {
+            kind,
+            description: None,
+            enum_values: Vec::new(),
+            not_an_object: false,
+        }
+    }
+
+    pub fn object() -> Self {
+        Self::new(Kind::Object {
+            properties: Vec::new(),
+            required: Vec::new(),
+        })
+    }
+
+    pub fn string() -> Self {
+        Self::new(Kind::String)
+    }
+
+    pub fn number() -> Self {
+        Self::new(Kind::Number)
+    }
+
+    pub fn integer() -> Self {
+        Self::new(Kind::Integer)
+    }
+
+    pub fn boolean() -> Self {
+        Self::new(Kind::Boolean)
+    }
+
+    /// Array whose elements match `items`
+    pub fn array_of(items: Schema) -> Self {
+        Self::new(Kind::Array(Box::new(items)))
+    }
+
+    pub fn description<S: Into<String>>(mut self, description: S) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    /// Declare a property of an object, replacing an earlier one of the same name
+    pub fn property<S: Into<String>>(mut self, name: S, schema: Schema) -> Self {
+        let name = name.into();
+        match &mut self.kind {
+            Kind::Object { properties, .. } => {
+                properties.retain(|(existing, _)| *existing != name);
+                properties.push((name, schema));
+            }
+            _ => self.not_an_object = true,
+        }
+        self
+    }
+
+    /// Properties of an object that must be present, each has to be declared with
+    /// [`property`](Self::property)
+    pub fn required<I, S>(mut self, names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        match &mut self.kind {
+            Kind::Object { required, .. } => {
+                for name in names {
+                    let name = name.into();
+                    if !required.contains(&name) {
+                        required.push(name);
+                    }
+                }
+            }
+            _ => self.not_an_object = true,
+        }
+        self
+    }
+
+    /// The values allowed, each has to have the schema's type
+    pub fn enum_values<I, V>(mut self, values: I) -> Self
+    where
+        I: IntoIterator<Item = V>,
+        V: Into<Value>,
+    {
+        self.enum_values.extend(values.into_iter().map(Into::into));
+        self
+    }
+
+    /// The schema as JSON, failing on the first mistake found
+    pub fn build(&self) -> Result<Value, SchemaError> {
+        self.to_value("")
+    }
+
+    fn to_value(&self, path: &str) -> Result<Value, SchemaError> {
+        let join = |name: &str| {
+            if path.is_empty() {
+                name.to_string()
+            } else {
+                format!("{}.{}", path, name)
+            }
+        };
+        if self.not_an_object {
+            return Err(SchemaError::NotAnObject {
+                path: path.to_string(),
+            });
+        }
+        let mut schema = match &self.kind {
+            Kind::Raw(value) => value.clone(),
+            Kind::Array(items) => {
+                serde_json::json!({"type": "array", "items": items.to_value(&join("items"))?})
+            }
+            Kind::Object {
+                properties,
+                required,
+            } => {
+                let mut declared = serde_json::Map::new();
+                for (name, property) in properties {
+                    declared.insert(name.clone(), property.to_value(&join(name))?);
+                }
+                if let Some(name) = required.iter().find(|name| !declared.contains_key(*name)) {
+                    return Err(SchemaError::UnknownRequired {
+                        path: path.to_string(),
+                        name: name.clone(),
+                    });
+                }
+                let mut schema = serde_json::json!({"type": "object", "properties": declared});
+                if !required.is_empty() {
+                    schema["required"] = required.clone().into();
+                }
+                schema
+            }
+            kind => serde_json::json!({"type": kind.name()}),
+        };
+        if let Some(value) = self.enum_values.iter().find(|v| !self.kind.accepts(v)) {
+            return Err(SchemaError::EnumTypeMismatch {
+                path: path.to_string(),
+                value: value.clone(),
+                expected: self.kind.name(),
+            });
+        }
+        if let Some(schema) = schema.as_object_mut() {
+            if let Some(description) = &self.description {
+                schema.insert("description".to_string(), description.clone().into());
+            }
+            if !self.enum_values.is_empty() {
+                schema.insert("enum".to_string(), self.enum_values.clone().into());
+            }
+        }
+        Ok(schema)
+    }
+}
+
+/// A hand-written schema, used verbatim
+impl From<Value> for Schema {
+    fn from(value: Value) -> Self {
+        Self::new(Kind::Raw(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_nested_schema_output() {
+        let entity = Schema::object()
+            .property("name", Schema::string().description("Unique name"))
+            .property("priority", Schema::integer().enum_values([1, 2, 3]))
+            .property("observations", Schema::array_of(Schema::string()))
+            .required(["name", "observations"]);
+        let schema = Schema::object()
+            .property("entities", Schema::array_of(entity))
+            .property("dryRun", Schema::boolean())
+            .required(["entities"]);
+        assert_eq!(
+            schema.build().unwrap(),
+            json!({
+                "type": "object",
+                "properties": {
+                    "entities": {
+                        "type": "array",
+                        "items": {
+                            "type": "object",
+                            "properties": {
+                                "name": {"type": "string", "description": "Unique name"},
+                                "priority": {"type": "integer", "enum": [1, 2, 3]},
+                                "observations": {"type": "array", "items": {"type": "string"}}
+                            },
+                            "required": ["name", "observations"]
+                        }
+                    },
+                    "dryRun": {"type": "boolean"}
+                },
+                "required": ["entities"]
+            })
+        );
+
+        // Hand-written parts are kept as they are
+        let raw = json!({"type": "string", "format": "uri"});
+        assert_eq!(
+            Schema::object()
+                .property("link", raw.clone().into())
+                .build()
+                .unwrap(),
+            json!({"type": "object", "properties": {"link": raw}})
+        );
+    }
+
+    #[test]
+    fn test_mistakes_are_reported_with_their_path() {
+        let missing = Schema::object().property(
+            "entities",
+            Schema::array_of(
+                Schema::object()
+                    .property("name", Schema::string())
+                    .required(["name", "entityType"]),
+            ),
+        );
+        assert_eq!(
+            missing.build(),
+            Err(SchemaError::UnknownRequired {
+                path: "entities.items".to_string(),
+                name: "entityType".to_string(),
+            })
+        );
+
+        let mismatched = Schema::object().property(
+            "mode",
+            Schema::string().enum_values([json!("merge"), json!(1)]),
+        );
+        let error = mismatched.build().unwrap_err();
+        assert_eq!(
+            error,
+            SchemaError::EnumTypeMismatch {
+                path: "mode".to_string(),
+                value: json!(1),
+                expected: "string",
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "Enum value 1 of `mode` is not of type string"
+        );
+
+        assert_eq!(
+            Schema::string().required(["name"]).build(),
+            Err(SchemaError::NotAnObject {
+                path: String::new()
+            })
+        );
+    }
+}
@@ -0,0 +1,212 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::{Schema, SchemaError, ToolResponseContent};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Tool {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub input_schema: serde_json::Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_schema: Option<serde_json::Value>,
+}
+
+/// Fluent construction of a [`Tool`], assembling `inputSchema` from its arguments
+#[derive(Debug, Clone)]
+pub struct ToolBuilder {
+    name: String,
+    description: Option<String>,
+    properties: serde_json::Map<String, serde_json::Value>,
+    required: Vec<String>,
+    input_schema: Option<Schema>,
+    output_schema: Option<serde_json::Value>,
+}
+
+impl ToolBuilder {
+    pub fn new<S: Into<String>>(name: S) -> Self {
+        Self {
+            name: name.into(),
+            description: None,
+            properties: serde_json::Map::new(),
+            required: Vec::new(),
+            input_schema: None,
+            output_schema: None,
+        }
+    }
+
+    pub fn description<S: Into<String>>(mut self, description: S) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    /// Argument with an arbitrary property schema, `description` is added to it
+    pub fn arg<S: Into<String>>(
+        mut self,
+        name: S,
+        description: &str,
+        mut schema: serde_json::Value,
+        required: bool,
+    ) -> Self {
+        let name = name.into();
+        if let Some(schema) = schema.as_object_mut() {
+            schema.insert("description".to_string(), description.into());
+        }
+        if required && !self.required.contains(&name) {
+            self.required.push(name.clone());
+        }
+        self.properties.insert(name, schema);
+        self
+    }
+
+    pub fn arg_string<S: Into<String>>(self, name: S, description: &str, required: bool) -> Self {
+        self.arg(
+            name,
+            description,
+            serde_json::json!({"type": "string"}),
+            required,
+        )
+    }
+
+    pub fn arg_number<S: Into<String>>(self, name: S, description: &str, required: bool) -> Self {
+        self.arg(
+            name,
+            description,
+            serde_json::json!({"type": "number"}),
+            required,
+        )
+    }
+
+    pub fn arg_integer<S: Into<String>>(self, name: S, description: &str, required: bool) -> Self {
+        self.arg(
+            name,
+            description,
+            serde_json::json!({"type": "integer"}),
+            required,
+        )
+    }
+
+    pub fn arg_boolean<S: Into<String>>(self, name: S, description: &str, required: bool) -> Self {
+        self.arg(
+            name,
+            description,
+            serde_json::json!({"type": "boolean"}),
+            required,
+        )
+    }
+
+    /// Array argument whose elements match the `items` schema
+    pub fn arg_array<S: Into<String>>(
+        self,
+        name: S,
+        description: &str,
+        items: serde_json::Value,
+        required: bool,
+    ) -> Self {
+        let schema = serde_json::json!({"type": "array", "items": items});
+        self.arg(name, description, schema, required)
+    }
+
+    /// Use `schema` as `inputSchema`, arguments added with `arg_*` are ignored
+    /// a [`Schema`] is checked when the tool is built, a `Value` is used verbatim
+    pub fn input_schema(mut self, schema: impl Into<Schema>) -> Self {
+        self.input_schema = Some(schema.into());
+        self
+    }
+
+    pub fn output_schema(mut self, schema: serde_json::Value) -> Self {
+        self.output_schema = Some(schema);
+        self
+    }
+
+    /// Like [`build`](Self::build), failing when the input [`Schema`] has a mistake
+    pub fn try_build(self) -> Result<Tool, SchemaError> {
+        let input_schema = match self.input_schema {
+            Some(schema) => schema.build()?,
+            None => {
+                let mut schema = serde_json::json!({
+                    "type": "object",
+                    "properties": self.properties,
+                });
+                if !self.required.is_empty() {
+                    schema["required"] = self.required.into();
+                }
+                schema
+            }
+        };
+        Ok(Tool {
+            name: self.name,
+            description: self.description,
+            input_schema,
+            output_schema: self.output_schema,
+        })
+    }
+
+    /// # Panics
+    /// When the input [`Schema`] has a mistake, see [`try_build`](Self::try_build)
+    pub fn build(self) -> Tool {
+        let name = self.name.clone();
+        self.try_build()
+            .unwrap_or_else(|e| panic!("Invalid input schema of tool {}: {}", name, e))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CallToolRequest {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub arguments: Option<HashMap<String, serde_json::Value>>,
+    #[serde(rename = "_meta", skip_serializing_if = "Option::is_none")]
+    pub meta: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CallToolResponse {
+    pub content: Vec<ToolResponseContent>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub is_error: Option<bool>,
+    #[serde(rename = "_meta", skip_serializing_if = "Option::is_none")]
+    pub meta: Option<serde_json::Value>,
+}
+
+impl CallToolResponse {
+    /// Single text content
+    pub fn text(text: impl Into<String>) -> Self {
+        Self {
+            content: vec![ToolResponseContent::Text { text: text.into() }],
+            is_error: None,
+            meta: None,
+        }
+    }
+
+    /// `value` serialized as JSON text, an error response if it can't be serialized
+    pub fn json(value: impl Serialize) -> Self {
+        match serde_json::to_string(&value) {
+            Ok(text) => Self::text(text),
+            Err(e) => Self::error(format!("Failed to serialize result: {}", e)),
+        }
+    }
+
+    /// Tool-level failure reported to the model, `isError` set
+    pub fn error(message: impl Into<String>) -> Self {
+        Self {
+            is_error: Some(true),
+            ..Self::text(message)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ToolsListResponse {
+    pub tools: Vec<Tool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+    #[serde(rename = "_meta", skip_serializing_if = "Option::is_none")]
+    pub meta: Option<serde_json::Value>,
+}
@@ -0,0 +1,148 @@
+//! Configuration problems found by [`ServerBuilder::try_build`](crate::server::ServerBuilder::try_build)
+//! and [`ClientBuilder::try_build`](crate::client::ClientBuilder::try_build), all of them are
+//! reported at once instead of surfacing one by one at runtime
+use std::fmt;
+use tracing::warn;
+
+/// More codes may be added, match with a wildcard arm
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum IssueCode {
+    /// A second tool with the same name replaced the first
+    DuplicateTool,
+    /// A second prompt with the same name replaced the first
+    DuplicatePrompt,
+    /// A second resource with the same URI replaced the first
+    DuplicateResource,
+    /// `list_page_size(0)`, pages are clamped to one item
+    ZeroPageSize,
+    /// A capability is advertised but a request it implies has no handler
+    MissingHandler,
+    /// The tools capability is advertised without any tool or tool source
+    NoTools,
+    /// A setting names a resource URI that isn't registered
+    UnknownResource,
+    /// A timeout of zero, which fails every attempt
+    ZeroTimeout,
+    /// The server URL doesn't parse
+    InvalidUrl,
+    /// Credentials would be sent over plain HTTP to a remote host
+    InsecureAuth,
+}
+
+impl IssueCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IssueCode::DuplicateTool => "duplicate_tool",
+            IssueCode::DuplicatePrompt => "duplicate_prompt",
+            IssueCode::DuplicateResource => "duplicate_resource",
+            IssueCode::ZeroPageSize => "zero_page_size",
+            IssueCode::MissingHandler => "missing_handler",
+            IssueCode::NoTools => "no_tools",
+            IssueCode::UnknownResource => "unknown_resource",
+            IssueCode::ZeroTimeout => "zero_timeout",
+            IssueCode::InvalidUrl => "invalid_url",
+            IssueCode::InsecureAuth => "insecure_auth",
+        }
+    }
+}
+
+/// Warnings are logged and building goes on, errors fail `try_build`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuildIssue {
+    pub code: IssueCode,
+    pub severity: Severity,
+    pub message: String,
+    /// How to fix it
+    pub suggestion: String,
+}
+
+impl BuildIssue {
+    pub(crate) fn warning(
+        code: IssueCode,
+        message: impl Into<String>,
+        suggestion: impl Into<String>,
+    ) -> Self {
+        Self {
+            code,
+            severity: Severity::Warning,
+            message: message.into(),
+            suggestion: suggestion.into(),
+        }
+    }
+
+    pub(crate) fn error(
+        code: IssueCode,
+        message: impl Into<String>,
+        suggestion: impl Into<String>,
+    ) -> Self {
+        Self {
+            severity: Severity::Error,
+            ..Self::warning(code, message, suggestion)
+        }
+    }
+}
+
+impl fmt::Display for BuildIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(
+            f,
+            "{}[{}]: {}, {}",
+            severity,
+            self.code.as_str(),
+            self.message,
+            self.suggestion
+        )
+    }
+}
+
+/// Every issue found, warnings included, when at least one is an error
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuildError {
+    pub issues: Vec<BuildIssue>,
+}
+
+impl BuildError {
+    /// Fails when any issue is an error, turning all of them into errors when `strict`,
+    /// otherwise logs the warnings
+    pub(crate) fn check(mut issues: Vec<BuildIssue>, strict: bool) -> Result<(), BuildError> {
+        if strict {
+            for issue in &mut issues {
+                issue.severity = Severity::Error;
+            }
+        }
+        if issues.iter().any(|issue| issue.severity == Severity::Error) {
+            return Err(BuildError { issues });
+        }
+        for issue in &issues {
+            warn!("{}", issue);
+        }
+        Ok(())
+    }
+
+    pub fn codes(&self) -> Vec<IssueCode> {
+        self.issues.iter().map(|issue| issue.code).collect()
+    }
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid configuration")?;
+        for issue in &self.issues {
+            write!(f, "\n  {}", issue)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for BuildError {}
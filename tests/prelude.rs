@@ -0,0 +1,109 @@
+//! The prelude alone is enough to write a server and a client, see `src/prelude.rs`
+use anyhow::Result;
+use async_mcp::prelude::*;
+
+// Names every prelude item, removing or renaming one fails to compile
+#[allow(dead_code, clippy::type_complexity)]
+type Surface = (
+    (
+        Client<BoxedTransport>,
+        ClientBuilder<BoxedTransport>,
+        RequestOptions,
+        ServerContext,
+        State<()>,
+        DynServer,
+        Server<ServerStdioTransport>,
+        ServerBuilder<ServerStdioTransport>,
+    ),
+    (
+        ClientInMemoryTransport,
+        ClientSseTransport,
+        ClientSseTransportBuilder,
+        ClientStdioTransport,
+        ClientWsTransport,
+        ClientWsTransportBuilder,
+        JsonRpcError,
+        ServerInMemoryTransport,
+    ),
+    (
+        CallToolRequest,
+        CallToolResponse,
+        ClientCapabilities,
+        ErrorCode,
+        GetPromptRequest,
+        GetPromptResult,
+        Implementation,
+        ListRequest,
+        MessageContent,
+        Prompt,
+        PromptArgument,
+        PromptCapabilities,
+    ),
+    (
+        PromptMessage,
+        ReadResourceRequest,
+        ReadResourceResponse,
+        Resource,
+        ResourceCapabilities,
+        ResourceContent,
+        ResourcesListResponse,
+        Role,
+        ServerCapabilities,
+        Tool,
+        ToolBuilder,
+        ToolResponseContent,
+    ),
+    ToolsListResponse,
+);
+
+#[allow(dead_code)]
+fn transport_trait<T: Transport>(transport: T) -> T {
+    transport
+}
+
+#[allow(dead_code)]
+async fn http_server() -> Result<()> {
+    run_http_server(0, None, |transport, _, _| async move {
+        Ok(Server::builder(transport).build())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn test_prelude_round_trip() -> Result<()> {
+    let transport = ClientInMemoryTransport::new(|t: ServerInMemoryTransport| {
+        let mut builder = Server::builder(t).capabilities(ServerCapabilities {
+            tools: Some(serde_json::json!({})),
+            ..Default::default()
+        });
+        builder.register_tool(ToolBuilder::new("hello").build(), |_: CallToolRequest| {
+            Box::pin(async move { Ok(CallToolResponse::text("hello")) })
+        });
+        tokio::spawn(async move { builder.build().listen().await.unwrap() })
+    });
+    transport.open().await?;
+    let client = ClientBuilder::new(transport.clone()).build();
+    let listener = client.clone();
+    tokio::spawn(async move { listener.start().await });
+
+    client
+        .initialize(Implementation {
+            name: "prelude".to_string(),
+            version: "0.1.0".to_string(),
+        })
+        .await?;
+    let response: CallToolResponse = client
+        .request_typed(
+            "tools/call",
+            serde_json::json!({"name": "hello"}),
+            RequestOptions::default(),
+        )
+        .await?;
+    assert!(matches!(
+        &response.content[..],
+        [ToolResponseContent::Text { text }] if text == "hello"
+    ));
+
+    transport.close().await?;
+    Ok(())
+}
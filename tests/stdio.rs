@@ -0,0 +1,189 @@
+//! Client and protocol over stdio against a real server process, see `examples/echo_mcp.rs`
+use anyhow::Result;
+use async_mcp::client::{Client, ClientBuilder};
+use async_mcp::protocol::RequestOptions;
+use async_mcp::transport::{ClientStdioTransport, JsonRpcMessage, Transport};
+use async_mcp::types::{CallToolResponse, ErrorCode, Implementation, ToolResponseContent};
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+/// The echo server example, a full `cargo test` builds it next to the test binaries
+fn echo_mcp() -> std::path::PathBuf {
+    let exe = std::env::current_exe().expect("test binary path");
+    // target/<profile>/deps/stdio-<hash> -> target/<profile>/examples/echo_mcp
+    let path = exe
+        .parent()
+        .and_then(|deps| deps.parent())
+        .expect("target directory")
+        .join("examples")
+        .join(format!("echo_mcp{}", std::env::consts::EXE_SUFFIX));
+    assert!(
+        path.exists(),
+        "{} is missing, build it with `cargo build --example echo_mcp`",
+        path.display()
+    );
+    path
+}
+
+/// Spawn the echo server with `env` and start a client listening on it
+async fn connect(
+    env: &[(&str, &str)],
+) -> Result<(
+    ClientStdioTransport,
+    Client<ClientStdioTransport>,
+    JoinHandle<Result<()>>,
+)> {
+    let env: HashMap<_, _> = env
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+    let transport = ClientStdioTransport::new(&echo_mcp().to_string_lossy(), &[], Some(env))?;
+    transport.open().await?;
+    let client = ClientBuilder::new(transport.clone()).build();
+    let listener = client.clone();
+    let listen = tokio::spawn(async move { listener.start().await });
+    Ok((transport, client, listen))
+}
+
+fn client_info() -> Implementation {
+    Implementation {
+        name: "stdio-test".to_string(),
+        version: "0.1.0".to_string(),
+    }
+}
+
+async fn echo(
+    client: &Client<ClientStdioTransport>,
+    text: &str,
+    options: RequestOptions,
+) -> Result<String> {
+    let params = json!({"name": "echo", "arguments": {"text": text}});
+    let response: CallToolResponse = client.request_typed("tools/call", params, options).await?;
+    match &response.content[..] {
+        [ToolResponseContent::Text { text }] => Ok(text.clone()),
+        other => anyhow::bail!("Unexpected content: {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_initialize_call_shutdown() -> Result<()> {
+    let (transport, client, listen) = connect(&[]).await?;
+
+    let initialized = client.initialize(client_info()).await?;
+    assert_eq!(initialized.server_info.name, "echo_mcp");
+    let tools = client
+        .request("tools/list", Some(json!({})), RequestOptions::default())
+        .await?;
+    assert_eq!(tools["tools"][0]["name"], "echo");
+    assert_eq!(
+        echo(&client, "hello", RequestOptions::default()).await?,
+        "hello"
+    );
+
+    // Closing stdin stops the server, the client's listen loop sees the end of its output
+    transport.close().await?;
+    listen.await??;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_progress_notification() -> Result<()> {
+    let (transport, client, _listen) = connect(&[("ECHO_MCP_PROGRESS", "1")]).await?;
+    client.initialize(client_info()).await?;
+
+    let progress = Arc::new(Mutex::new(Vec::new()));
+    let progress_handle = progress.clone();
+    let options = RequestOptions::default().on_progress(move |p| {
+        progress_handle
+            .lock()
+            .unwrap()
+            .push((p.progress, p.message));
+    });
+    assert_eq!(
+        echo(&client, "with progress", options).await?,
+        "with progress"
+    );
+    assert_eq!(
+        *progress.lock().unwrap(),
+        vec![(1.0, Some("echoing".to_string()))]
+    );
+
+    transport.close().await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_close_with_pending_request() -> Result<()> {
+    let (transport, client, listen) = connect(&[("ECHO_MCP_DELAY_MS", "30000")]).await?;
+    client.initialize(client_info()).await?;
+
+    let pending =
+        tokio::spawn(
+            async move { echo(&client, "never answered", RequestOptions::default()).await },
+        );
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // The pending read ends with the server instead of waiting for the delayed response
+    let started = Instant::now();
+    transport.close().await?;
+    listen.await??;
+    assert!(started.elapsed() < Duration::from_secs(5));
+    pending.abort();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_server_crash() -> Result<()> {
+    // initialize and one call are served, the next request kills the server
+    let (transport, client, listen) = connect(&[("ECHO_MCP_CRASH_AFTER", "2")]).await?;
+    client.initialize(client_info()).await?;
+    assert_eq!(
+        echo(&client, "first", RequestOptions::default()).await?,
+        "first"
+    );
+
+    let options = RequestOptions::default().timeout(Duration::from_millis(500));
+    assert!(echo(&client, "second", options).await.is_err());
+    listen.await??;
+    assert!(echo(&client, "third", RequestOptions::default())
+        .await
+        .is_err());
+
+    transport.close().await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_garbage_lines_are_skipped() -> Result<()> {
+    let (transport, client, _listen) = connect(&[("ECHO_MCP_GARBAGE", "1")]).await?;
+    client.initialize(client_info()).await?;
+    assert_eq!(
+        echo(&client, "still works", RequestOptions::default()).await?,
+        "still works"
+    );
+
+    transport.close().await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_empty_batch_error_reaches_client() -> Result<()> {
+    let transport = ClientStdioTransport::new(&echo_mcp().to_string_lossy(), &[], None)?;
+    transport.open().await?;
+    transport.send(&JsonRpcMessage::Batch(Vec::new())).await?;
+
+    // The `id: null` answer decodes instead of being skipped as an invalid line
+    let reply = tokio::time::timeout(Duration::from_secs(5), transport.receive()).await??;
+    match reply {
+        Some(JsonRpcMessage::UnidentifiedError(error)) => {
+            assert_eq!(error.error.code, ErrorCode::InvalidRequest as i32)
+        }
+        other => panic!("Unexpected message: {:?}", other),
+    }
+
+    transport.close().await?;
+    Ok(())
+}